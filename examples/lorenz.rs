@@ -0,0 +1,112 @@
+// runs Wolfgang Lorenz's test programs headless and prints a pass/fail table;
+// exits with 1 if any test didn't pass, so it can be used as a regression gate
+extern crate rust64;
+
+use rust64::c64::roms::{RomPaths, RomSet};
+use rust64::lorenz;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage: lorenz [--cycles <N>] [--verbose] <DIR|FILE>...
+
+Runs every test program given (all files of a directory, in alphabetical order).
+
+Options:
+  --cycles <N>   give up on a test after N clock cycles (default: 120000000)
+  --verbose      print the output of failed tests";
+
+
+fn main() {
+    let mut cycle_limit = lorenz::DEFAULT_CYCLE_LIMIT;
+    let mut verbose = false;
+    let mut tests = Vec::new();
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--cycles" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => cycle_limit = n,
+                None    => exit_with_usage("Invalid cycle limit"),
+            },
+            "--verbose" => verbose = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => add_tests(&mut tests, &arg),
+        }
+    }
+
+    if tests.is_empty() {
+        exit_with_usage("No test programs given");
+    }
+
+    let roms = match RomSet::load(&RomPaths::default()) {
+        Ok(roms) => roms,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+
+    for test in &tests {
+        let name = Path::new(test).file_stem().map_or(test.clone(), |s| s.to_string_lossy().into_owned());
+
+        match lorenz::run_test(&roms, test, cycle_limit) {
+            Ok(result) => {
+                println!("{:<12} {:<20} {:>10} cycles", name, result.outcome, result.cycles);
+
+                if result.outcome != lorenz::Outcome::Passed {
+                    failed += 1;
+                    if verbose {
+                        println!("{}", result.output.trim_end());
+                    }
+                }
+            },
+            Err(e) => {
+                println!("{:<12} {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("\n{} of {} tests passed", tests.len() - failed, tests.len());
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+
+fn add_tests(tests: &mut Vec<String>, path: &str) {
+    if !Path::new(path).is_dir() {
+        tests.push(path.to_string());
+        return;
+    }
+
+    match fs::read_dir(path) {
+        Ok(entries) => {
+            let mut files: Vec<String> = entries.filter_map(|e| e.ok())
+                                                .map(|e| e.path())
+                                                .filter(|p| p.is_file())
+                                                .map(|p| p.to_string_lossy().into_owned())
+                                                .collect();
+            files.sort();
+            tests.extend(files);
+        },
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+
+fn exit_with_usage(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,659 @@
+// server for the VICE binary remote monitor protocol, which debuggers and IDE integrations use to
+// control the emulator over TCP. Commands are only run while the machine is stopped: a command sent
+// while running (or a breakpoint hit) stops it until the client sends "exit".
+use c64::breakpoints;
+use c64::media;
+use c64::memory;
+use c64::prg;
+use c64::C64;
+use error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const STX: u8 = 0x02;
+const API_VERSION: u8 = 0x02;
+const COMMAND_HEADER_LEN: usize = 11;
+const EVENT_ID: u32 = 0xFFFF_FFFF; // request id of responses that weren't asked for
+
+// command types - responses have the same type as their command
+const CMD_MEMORY_GET: u8          = 0x01;
+const CMD_MEMORY_SET: u8          = 0x02;
+const CMD_CHECKPOINT_GET: u8      = 0x11;
+const CMD_CHECKPOINT_SET: u8      = 0x12;
+const CMD_CHECKPOINT_DELETE: u8   = 0x13;
+const CMD_CHECKPOINT_LIST: u8     = 0x14;
+const CMD_CHECKPOINT_TOGGLE: u8   = 0x15;
+const CMD_CONDITION_SET: u8       = 0x22;
+const CMD_REGISTERS_GET: u8       = 0x31;
+const CMD_REGISTERS_SET: u8       = 0x32;
+const CMD_DUMP: u8                = 0x41;
+const CMD_UNDUMP: u8              = 0x42;
+const CMD_ADVANCE: u8             = 0x71;
+const CMD_KEYBOARD_FEED: u8       = 0x72;
+const CMD_EXECUTE_UNTIL_RETURN: u8 = 0x73;
+const CMD_PING: u8                = 0x81;
+const CMD_BANKS_AVAILABLE: u8     = 0x82;
+const CMD_REGISTERS_AVAILABLE: u8 = 0x83;
+const CMD_VICE_INFO: u8           = 0x85;
+const CMD_EXIT: u8                = 0xAA;
+const CMD_QUIT: u8                = 0xBB;
+const CMD_RESET: u8               = 0xCC;
+const CMD_AUTOSTART: u8           = 0xDD;
+
+// events
+const EVENT_JAM: u8      = 0x61;
+const EVENT_STOPPED: u8  = 0x62;
+const EVENT_RESUMED: u8  = 0x63;
+
+// error codes
+const ERR_OK: u8                = 0x00;
+const ERR_OBJECT_MISSING: u8    = 0x01;
+const ERR_INVALID_MEMSPACE: u8  = 0x02;
+const ERR_LENGTH: u8            = 0x80;
+const ERR_INVALID_PARAMETER: u8 = 0x81;
+const ERR_API_VERSION: u8       = 0x82;
+const ERR_INVALID_COMMAND: u8   = 0x83;
+const ERR_FAILED: u8            = 0x8F;
+
+// only the main computer's memory space is available, no drives
+const MEMSPACE_MAIN: u8 = 0x00;
+
+// memory banks: what the CPU currently sees, or plain RAM
+const BANK_CPU: u16 = 0;
+const BANK_RAM: u16 = 1;
+const BANKS: [(u16, &str); 2] = [(BANK_CPU, "cpu"), (BANK_RAM, "ram")];
+
+// register ids as used by VICE for the 6510
+const REG_A: u8   = 0x00;
+const REG_X: u8   = 0x01;
+const REG_Y: u8   = 0x02;
+const REG_PC: u8  = 0x03;
+const REG_SP: u8  = 0x04;
+const REG_FL: u8  = 0x05;
+const REG_LIN: u8 = 0x35; // raster line, read only
+const REG_00: u8  = 0x37;
+const REG_01: u8  = 0x38;
+const REGISTERS: [(u8, &str, u8); 9] = [(REG_A, "A", 8), (REG_X, "X", 8), (REG_Y, "Y", 8), (REG_PC, "PC", 16), (REG_SP, "SP", 8),
+                                        (REG_FL, "FL", 8), (REG_LIN, "LIN", 16), (REG_00, "00", 8), (REG_01, "01", 8)];
+
+// checkpoint operations
+const OP_LOAD: u8  = 0x01;
+const OP_STORE: u8 = 0x02;
+const OP_EXEC: u8  = 0x04;
+
+// version reported to clients, which some of them check for protocol features
+const VICE_VERSION: [u8; 4] = [3, 7, 0, 0];
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+
+struct Command {
+    api_version: u8,
+    request_id: u32,
+    kind: u8,
+    body: Vec<u8>,
+}
+
+
+pub struct BinaryMonitor {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>, // received bytes not yet run as commands
+}
+
+impl BinaryMonitor {
+    // listen on localhost; port 0 picks a free one
+    pub fn bind(port: u16) -> Result<BinaryMonitor, Error> {
+        let address = format!("127.0.0.1:{}", port);
+        let io_error = |e| Error::Io(address.clone(), e);
+
+        let listener = TcpListener::bind(&address[..]).map_err(io_error)?;
+        listener.set_nonblocking(true).map_err(io_error)?;
+
+        Ok(BinaryMonitor {
+            listener,
+            client: None,
+            input: Vec::new(),
+        })
+    }
+
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+
+    // called while the machine runs: accepts a new client (replacing the old one) and reads what
+    // it sent so far; returns true if a complete command is waiting
+    pub fn poll(&mut self) -> bool {
+        if let Ok((stream, _)) = self.listener.accept() {
+            let _ = stream.set_nodelay(true);
+            self.client = Some(stream);
+            self.input.clear();
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            let result = match self.client {
+                Some(ref mut stream) => stream.set_nonblocking(true).and_then(|_| stream.read(&mut buffer)),
+                None => return false,
+            };
+
+            match result {
+                Ok(0) => self.disconnect(),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => self.disconnect(),
+            }
+        }
+
+        self.has_command()
+    }
+
+
+    // the machine has stopped at an instruction boundary, possibly because of a breakpoint: report
+    // it to the client and run its commands until it lets the machine continue or disconnects
+    pub fn session(&mut self, c64: &mut C64, hit: Option<breakpoints::Hit>) {
+        if self.report_stop(c64, hit).is_err() {
+            self.disconnect();
+            return;
+        }
+
+        while let Some(command) = self.next_command() {
+            match self.execute(c64, command) {
+                Ok(true) => (),
+                Ok(false) => {
+                    let pc = c64.cpu.pc;
+                    let _ = self.send(EVENT_RESUMED, ERR_OK, EVENT_ID, &pc.to_le_bytes());
+                    return;
+                },
+                Err(_) => break,
+            }
+        }
+
+        self.disconnect();
+    }
+
+
+    // *** private functions *** //
+
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.input.clear();
+    }
+
+
+    fn has_command(&self) -> bool {
+        self.input.len() >= COMMAND_HEADER_LEN && self.input.len() >= COMMAND_HEADER_LEN + body_length(&self.input)
+    }
+
+
+    // wait for the next command; None once the client is gone
+    fn next_command(&mut self) -> Option<Command> {
+        let mut buffer = [0; 4096];
+
+        loop {
+            // resynchronize on garbage
+            while !self.input.is_empty() && self.input[0] != STX {
+                self.input.remove(0);
+            }
+
+            if self.has_command() {
+                let len = COMMAND_HEADER_LEN + body_length(&self.input);
+                let packet: Vec<u8> = self.input.drain(..len).collect();
+
+                return Some(Command {
+                    api_version: packet[1],
+                    request_id: read_u32(&packet, 6),
+                    kind: packet[10],
+                    body: packet[COMMAND_HEADER_LEN..].to_vec(),
+                });
+            }
+
+            let stream = self.client.as_mut()?;
+            match stream.set_nonblocking(false).and_then(|_| stream.read(&mut buffer)) {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+            }
+        }
+    }
+
+
+    fn send(&mut self, kind: u8, error: u8, request_id: u32, body: &[u8]) -> io::Result<()> {
+        let mut packet = vec![STX, API_VERSION];
+        packet.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        packet.push(kind);
+        packet.push(error);
+        packet.extend_from_slice(&request_id.to_le_bytes());
+        packet.extend_from_slice(body);
+
+        match self.client {
+            Some(ref mut stream) => stream.set_nonblocking(false).and_then(|_| stream.write_all(&packet)),
+            None => Err(io::Error::new(ErrorKind::NotConnected, "no client")),
+        }
+    }
+
+
+    // checkpoint info (if one was hit), registers and the stop event
+    fn report_stop(&mut self, c64: &mut C64, hit: Option<breakpoints::Hit>) -> io::Result<()> {
+        if let Some(hit) = hit {
+            if let Some(info) = checkpoint_info(c64, hit.id, true) {
+                self.send(CMD_CHECKPOINT_GET, ERR_OK, EVENT_ID, &info)?;
+            }
+        }
+
+        let registers = registers(c64);
+        self.send(CMD_REGISTERS_GET, ERR_OK, EVENT_ID, &registers)?;
+
+        match c64.cpu.jam_address() {
+            Some(addr) => self.send(EVENT_JAM, ERR_OK, EVENT_ID, &addr.to_le_bytes()),
+            None => {
+                let pc = c64.cpu.pc;
+                self.send(EVENT_STOPPED, ERR_OK, EVENT_ID, &pc.to_le_bytes())
+            }
+        }
+    }
+
+
+    // run a command and send its response; returns false if the machine should continue
+    fn execute(&mut self, c64: &mut C64, command: Command) -> io::Result<bool> {
+        let id = command.request_id;
+        let kind = command.kind;
+
+        if command.api_version != 0x01 && command.api_version != API_VERSION {
+            self.send(kind, ERR_API_VERSION, id, &[])?;
+            return Ok(true);
+        }
+
+        let body = &command.body[..];
+        let result = match kind {
+            CMD_MEMORY_GET          => memory_get(c64, body),
+            CMD_MEMORY_SET          => memory_set(c64, body),
+            CMD_CHECKPOINT_GET      => parse_id(body).and_then(|cp| checkpoint_info(c64, cp, false).ok_or(ERR_OBJECT_MISSING)),
+            CMD_CHECKPOINT_SET      => checkpoint_set(c64, body),
+            CMD_CHECKPOINT_DELETE   => parse_id(body).and_then(|cp| found(c64.cpu.breakpoints.remove(cp))),
+            CMD_CHECKPOINT_TOGGLE   => {
+                expect_length(body, 5).and_then(|_| found(c64.cpu.breakpoints.set_enabled(read_u32(body, 0), body[4] != 0)))
+            },
+            CMD_CONDITION_SET       => condition_set(c64, body),
+            CMD_REGISTERS_GET       => expect_memspace(body).map(|_| registers(c64)),
+            CMD_REGISTERS_SET       => registers_set(c64, body),
+            CMD_DUMP                => string_at(body, 2).and_then(|file| c64.save_snapshot_file(&file).map(|_| Vec::new()).map_err(|_| ERR_FAILED)),
+            CMD_UNDUMP              => {
+                string_at(body, 0).and_then(|file| c64.load_snapshot_file(&file).map(|_| c64.cpu.pc.to_le_bytes().to_vec()).map_err(|_| ERR_FAILED))
+            },
+            CMD_KEYBOARD_FEED       => string_at(body, 0).map(|text| {
+                c64.type_text(text.as_bytes());
+                Vec::new()
+            }),
+            CMD_PING                => Ok(Vec::new()),
+            CMD_BANKS_AVAILABLE     => Ok(banks_available()),
+            CMD_REGISTERS_AVAILABLE => expect_memspace(body).map(|_| registers_available()),
+            CMD_VICE_INFO           => {
+                let mut info = vec![VICE_VERSION.len() as u8];
+                info.extend_from_slice(&VICE_VERSION);
+                info.push(4);
+                info.extend_from_slice(&[0; 4]); // no SVN revision
+                Ok(info)
+            },
+            CMD_RESET               => expect_length(body, 1).and_then(|_| match body[0] {
+                0 | 1 => {
+                    c64.reset();
+                    Ok(Vec::new())
+                },
+                _ => Err(ERR_INVALID_PARAMETER), // drives
+            }),
+            CMD_AUTOSTART           => autostart(c64, body),
+            CMD_CHECKPOINT_LIST     => {
+                let ids: Vec<u32> = c64.cpu.breakpoints.list().iter().map(|b| b.id).collect();
+                for cp in &ids {
+                    let info = checkpoint_info(c64, *cp, false).unwrap();
+                    self.send(CMD_CHECKPOINT_GET, ERR_OK, id, &info)?;
+                }
+                Ok((ids.len() as u32).to_le_bytes().to_vec())
+            },
+            CMD_ADVANCE             => expect_length(body, 3).map(|_| Vec::new()),
+            CMD_EXECUTE_UNTIL_RETURN | CMD_EXIT | CMD_QUIT => Ok(Vec::new()),
+            _ => Err(ERR_INVALID_COMMAND),
+        };
+
+        // setting registers or a checkpoint is answered like getting them, with the new values
+        let response_kind = match kind {
+            CMD_REGISTERS_SET  => CMD_REGISTERS_GET,
+            CMD_CHECKPOINT_SET => CMD_CHECKPOINT_GET,
+            _ => kind,
+        };
+        match result {
+            Ok(ref response) => self.send(response_kind, ERR_OK, id, response)?,
+            Err(error) => {
+                self.send(response_kind, error, id, &[])?;
+                return Ok(true);
+            },
+        }
+
+        match kind {
+            CMD_ADVANCE => {
+                let hit = advance(c64, body[0] != 0, read_u16(body, 1));
+                self.report_stop(c64, hit)?;
+            },
+            CMD_EXECUTE_UNTIL_RETURN => {
+                let hit = run_until_return(c64);
+                self.report_stop(c64, hit)?;
+            },
+            CMD_EXIT => return Ok(false),
+            CMD_QUIT => {
+                c64.quit_requested = true;
+                return Ok(false);
+            },
+            _ => (),
+        }
+
+        Ok(true)
+    }
+}
+
+
+// *** private functions *** //
+
+fn body_length(packet: &[u8]) -> usize {
+    read_u32(packet, 2) as usize
+}
+
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    (data[offset + 1] as u16) << 8 | data[offset] as u16
+}
+
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (read_u16(data, offset + 2) as u32) << 16 | read_u16(data, offset) as u32
+}
+
+
+fn expect_length(body: &[u8], len: usize) -> Result<(), u8> {
+    if body.len() >= len { Ok(()) } else { Err(ERR_LENGTH) }
+}
+
+
+fn expect_memspace(body: &[u8]) -> Result<(), u8> {
+    expect_length(body, 1)?;
+    if body[0] == MEMSPACE_MAIN { Ok(()) } else { Err(ERR_INVALID_MEMSPACE) }
+}
+
+
+fn found(found: bool) -> Result<Vec<u8>, u8> {
+    if found { Ok(Vec::new()) } else { Err(ERR_OBJECT_MISSING) }
+}
+
+
+fn parse_id(body: &[u8]) -> Result<u32, u8> {
+    expect_length(body, 4).map(|_| read_u32(body, 0))
+}
+
+
+// length-prefixed string at offset
+fn string_at(body: &[u8], offset: usize) -> Result<String, u8> {
+    expect_length(body, offset + 1)?;
+    let len = body[offset] as usize;
+    expect_length(body, offset + 1 + len)?;
+    Ok(String::from_utf8_lossy(&body[offset + 1..offset + 1 + len]).into_owned())
+}
+
+
+// side effects (1), start (2), end (2), memspace (1), bank (2)
+fn memory_range(body: &[u8]) -> Result<(bool, u16, u16, u16), u8> {
+    expect_length(body, 8)?;
+    let (start, end) = (read_u16(body, 1), read_u16(body, 3));
+
+    if body[5] != MEMSPACE_MAIN {
+        return Err(ERR_INVALID_MEMSPACE);
+    }
+    if end < start || !BANKS.iter().any(|b| b.0 == read_u16(body, 6)) {
+        return Err(ERR_INVALID_PARAMETER);
+    }
+
+    Ok((body[0] != 0, start, end, read_u16(body, 6)))
+}
+
+
+fn memory_get(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    let (side_effects, start, end, bank) = memory_range(body)?;
+    let len = end as usize - start as usize + 1;
+
+    let mut response = (len as u16).to_le_bytes().to_vec(); // 0 for all 64K
+    for addr in start..=end {
+        let value = match bank {
+            BANK_RAM => c64.bus.memory.get_ram_bank(memory::MemType::Ram).read(addr),
+            _ if side_effects => c64.cpu.read_byte(addr, &mut c64.bus),
+            _ => c64.bus.memory.read_byte(addr),
+        };
+        response.push(value);
+    }
+
+    Ok(response)
+}
+
+
+fn memory_set(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    let (side_effects, start, end, bank) = memory_range(body)?;
+    let data = &body[8..];
+    if data.len() != end as usize - start as usize + 1 {
+        return Err(ERR_LENGTH);
+    }
+
+    for (addr, value) in (start..=end).zip(data.iter()) {
+        match bank {
+            BANK_RAM => { c64.bus.memory.get_ram_bank(memory::MemType::Ram).write(addr, *value); },
+            _ if side_effects => { c64.cpu.write_byte(addr, *value, &mut c64.bus); },
+            _ => { c64.bus.memory.write_byte(addr, *value); },
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+
+// number (4), currently hit (1), start (2), end (2), stop when hit (1), enabled (1), operation (1), temporary (1),
+// hit count (4), ignore count (4), has condition (1), memspace (1)
+fn checkpoint_info(c64: &C64, id: u32, hit: bool) -> Option<Vec<u8>> {
+    let breakpoint = c64.cpu.breakpoints.list().iter().find(|b| b.id == id)?;
+    let operation = if breakpoint.load { OP_LOAD } else { 0 } |
+                    if breakpoint.store { OP_STORE } else { 0 } |
+                    if breakpoint.exec { OP_EXEC } else { 0 };
+
+    let mut info = id.to_le_bytes().to_vec();
+    info.push(hit as u8);
+    info.extend_from_slice(&breakpoint.start.to_le_bytes());
+    info.extend_from_slice(&breakpoint.end.to_le_bytes());
+    info.extend_from_slice(&[breakpoint.stop as u8, breakpoint.enabled as u8, operation, breakpoint.temporary as u8]);
+    info.extend_from_slice(&breakpoint.hits.to_le_bytes());
+    info.extend_from_slice(&breakpoint.ignore_count.to_le_bytes());
+    info.extend_from_slice(&[breakpoint.condition.is_some() as u8, MEMSPACE_MAIN]);
+    Some(info)
+}
+
+
+// start (2), end (2), stop when hit (1), enabled (1), operation (1), temporary (1), [memspace (1)]
+fn checkpoint_set(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    expect_length(body, 8)?;
+    if body.len() > 8 && body[8] != MEMSPACE_MAIN {
+        return Err(ERR_INVALID_MEMSPACE);
+    }
+
+    let (start, end, operation) = (read_u16(body, 0), read_u16(body, 2), body[6]);
+    if end < start || operation & (OP_LOAD | OP_STORE | OP_EXEC) == 0 {
+        return Err(ERR_INVALID_PARAMETER);
+    }
+
+    let breakpoints = &mut c64.cpu.breakpoints;
+    let id = breakpoints.add(start, end, operation & OP_EXEC != 0, operation & OP_LOAD != 0, operation & OP_STORE != 0, None);
+    breakpoints.set_stop(id, body[4] != 0);
+    breakpoints.set_enabled(id, body[5] != 0);
+    breakpoints.set_temporary(id, body[7] != 0);
+
+    checkpoint_info(c64, id, false).ok_or(ERR_FAILED)
+}
+
+
+// number (4), expression length (1), expression - register comparisons like "A == $01" (VICE's ".A" works too)
+fn condition_set(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    let id = parse_id(body)?;
+    let text = string_at(body, 4)?.replace('.', "");
+    let condition = breakpoints::Condition::parse(&text).map_err(|_| ERR_INVALID_PARAMETER)?;
+
+    found(c64.cpu.breakpoints.set_condition(id, Some(condition)))
+}
+
+
+// count (2), then for each: item size (1), id (1), value (2)
+fn registers(c64: &mut C64) -> Vec<u8> {
+    let mut response = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+
+    for &(id, _, _) in REGISTERS.iter() {
+        let value = match id {
+            REG_A   => c64.cpu.a as u16,
+            REG_X   => c64.cpu.x as u16,
+            REG_Y   => c64.cpu.y as u16,
+            REG_PC  => c64.cpu.pc,
+            REG_SP  => c64.cpu.sp as u16,
+            REG_FL  => c64.cpu.p as u16,
+            REG_LIN => c64.bus.vic.raster_cnt,
+            REG_00  => c64.bus.memory.read_byte(0x0000) as u16,
+            _       => c64.bus.memory.read_byte(0x0001) as u16,
+        };
+
+        response.extend_from_slice(&[3, id]);
+        response.extend_from_slice(&value.to_le_bytes());
+    }
+
+    response
+}
+
+
+// memspace (1), count (2), then for each: item size (1), id (1), value (2)
+fn registers_set(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    expect_memspace(body)?;
+    expect_length(body, 3)?;
+
+    let count = read_u16(body, 1);
+    let mut offset = 3;
+
+    for _ in 0..count {
+        expect_length(body, offset + 4)?;
+        let (id, value) = (body[offset + 1], read_u16(body, offset + 2));
+
+        match id {
+            REG_A   => c64.cpu.a = value as u8,
+            REG_X   => c64.cpu.x = value as u8,
+            REG_Y   => c64.cpu.y = value as u8,
+            REG_PC  => c64.cpu.pc = value,
+            REG_SP  => c64.cpu.sp = value as u8,
+            REG_FL  => c64.cpu.p = value as u8,
+            REG_00  => { c64.bus.memory.write_byte(0x0000, value as u8); },
+            REG_01  => { c64.bus.memory.write_byte(0x0001, value as u8); },
+            REG_LIN => (),
+            _ => return Err(ERR_OBJECT_MISSING),
+        }
+
+        offset += body[offset] as usize + 1;
+    }
+
+    Ok(registers(c64))
+}
+
+
+// count (2), then for each: item size (1), id (2), name length (1), name
+fn banks_available() -> Vec<u8> {
+    let mut response = (BANKS.len() as u16).to_le_bytes().to_vec();
+
+    for &(id, name) in BANKS.iter() {
+        response.push(3 + name.len() as u8);
+        response.extend_from_slice(&id.to_le_bytes());
+        response.push(name.len() as u8);
+        response.extend_from_slice(name.as_bytes());
+    }
+
+    response
+}
+
+
+// count (2), then for each: item size (1), id (1), size in bits (1), name length (1), name
+fn registers_available() -> Vec<u8> {
+    let mut response = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+
+    for &(id, name, bits) in REGISTERS.iter() {
+        response.extend_from_slice(&[3 + name.len() as u8, id, bits, name.len() as u8]);
+        response.extend_from_slice(name.as_bytes());
+    }
+
+    response
+}
+
+
+// run after loading (1), file index (2), file name length (1), file name - a program file, a T64 archive
+// the file at the index is loaded from, a disk image which is attached and the file at the index loaded from,
+// or a TAP image which the kernal loads the first file from
+fn autostart(c64: &mut C64, body: &[u8]) -> Result<Vec<u8>, u8> {
+    expect_length(body, 3)?;
+    let filename = string_at(body, 3)?;
+    c64.autostart = body[0] != 0;
+
+    match media::MediaType::detect(&filename) {
+        Ok(media::MediaType::Disk) => {
+            c64.attach_disk(&filename).map_err(|_| ERR_FAILED)?;
+            let name = c64.disk_image().ok_or(ERR_FAILED)?.directory().get(read_u16(body, 1) as usize).map(|e| e.name.clone()).ok_or(ERR_INVALID_PARAMETER)?;
+            c64.load_disk_program(&name).map_err(|_| ERR_FAILED)?;
+        },
+        Ok(media::MediaType::T64) => c64.load_t64_entry(&filename, read_u16(body, 1) as usize).map_err(|_| ERR_FAILED)?,
+        Ok(media::MediaType::Tap) => c64.attach_tape(&filename).and_then(|_| c64.load_tape()).map_err(|_| ERR_FAILED)?,
+        _ => c64.queue_prg(prg::Prg::from_filename(&filename).map_err(|_| ERR_FAILED)?),
+    }
+
+    Ok(Vec::new())
+}
+
+
+// run count instructions, stepping over subroutine calls if asked to; stops early on a breakpoint
+fn advance(c64: &mut C64, step_over: bool, count: u16) -> Option<breakpoints::Hit> {
+    for _ in 0..count {
+        let pc = c64.cpu.pc;
+        let is_call = step_over && c64.bus.memory.read_byte(pc) == JSR;
+
+        c64.run_until_fetch();
+        while is_call && c64.cpu.pc != pc.wrapping_add(3) && c64.cpu.breakpoints.hit.is_none() && c64.cpu.jam_address().is_none() {
+            c64.run_until_fetch();
+        }
+
+        if let Some(hit) = c64.cpu.breakpoints.hit.take() {
+            return Some(hit);
+        }
+    }
+
+    None
+}
+
+
+// run until an RTS or RTI leaves the current subroutine
+fn run_until_return(c64: &mut C64) -> Option<breakpoints::Hit> {
+    let sp = c64.cpu.sp;
+
+    loop {
+        let opcode = c64.bus.memory.read_byte(c64.cpu.pc);
+        c64.run_until_fetch();
+
+        if let Some(hit) = c64.cpu.breakpoints.hit.take() {
+            return Some(hit);
+        }
+
+        if ((opcode == RTS || opcode == RTI) && c64.cpu.sp > sp) || c64.cpu.jam_address().is_some() {
+            return None;
+        }
+    }
+}
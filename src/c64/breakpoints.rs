@@ -0,0 +1,342 @@
+// execution breakpoints and memory watchpoints, checked by the CPU on every instruction and bus access
+use c64::cpu;
+use std::fmt;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Exec,  // instruction about to be executed
+    Load,  // memory read (including dummy reads and I/O registers)
+    Store, // memory write
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Access::Exec  => "exec",
+            Access::Load  => "load",
+            Access::Store => "store",
+        })
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    PC, A, X, Y, SP, P,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal, NotEqual, Less, Greater, LessOrEqual, GreaterOrEqual,
+}
+
+
+// register comparison which has to hold for a breakpoint to stop, e.g. "A == $01"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    // "<register> <op> <value>" with registers pc, a, x, y, sp and p, ops ==, !=, <, >, <=, >= and a hex value
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        let operators = [("==", Comparison::Equal), ("!=", Comparison::NotEqual), ("<=", Comparison::LessOrEqual),
+                         (">=", Comparison::GreaterOrEqual), ("<", Comparison::Less), (">", Comparison::Greater)];
+
+        let (idx, op, comparison) = operators.iter()
+                                             .filter_map(|&(op, comparison)| text.find(op).map(|idx| (idx, op, comparison)))
+                                             .next()
+                                             .ok_or_else(|| format!("invalid condition: {}", text))?;
+
+        let register = match &text[..idx] {
+            "pc" => Register::PC,
+            "a"  => Register::A,
+            "x"  => Register::X,
+            "y"  => Register::Y,
+            "sp" => Register::SP,
+            "p"  => Register::P,
+            name => return Err(format!("unknown register: {}", name)),
+        };
+
+        let value_text = &text[idx + op.len()..];
+        let value = u16::from_str_radix(value_text.trim_start_matches('$'), 16).map_err(|_| format!("invalid value: {}", value_text))?;
+        if register != Register::PC && value > 0xFF {
+            return Err(format!("${:04X} doesn't fit in an 8-bit register", value));
+        }
+
+        Ok(Condition { register, comparison, value })
+    }
+
+
+    pub fn is_met(&self, cpu: &cpu::CPU) -> bool {
+        let register = match self.register {
+            Register::PC => cpu.pc,
+            Register::A  => cpu.a as u16,
+            Register::X  => cpu.x as u16,
+            Register::Y  => cpu.y as u16,
+            Register::SP => cpu.sp as u16,
+            Register::P  => cpu.p as u16,
+        };
+
+        match self.comparison {
+            Comparison::Equal          => register == self.value,
+            Comparison::NotEqual       => register != self.value,
+            Comparison::Less           => register <  self.value,
+            Comparison::Greater        => register >  self.value,
+            Comparison::LessOrEqual    => register <= self.value,
+            Comparison::GreaterOrEqual => register >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.comparison {
+            Comparison::Equal          => "==",
+            Comparison::NotEqual       => "!=",
+            Comparison::Less           => "<",
+            Comparison::Greater        => ">",
+            Comparison::LessOrEqual    => "<=",
+            Comparison::GreaterOrEqual => ">=",
+        };
+
+        match self.register {
+            Register::PC => write!(f, "PC {} ${:04X}", op, self.value),
+            _ => write!(f, "{:?} {} ${:02X}", self.register, op, self.value),
+        }
+    }
+}
+
+
+pub struct Breakpoint {
+    pub id: u32,
+    pub start: u16,
+    pub end: u16,       // inclusive
+    pub exec: bool,
+    pub load: bool,
+    pub store: bool,
+    pub condition: Option<Condition>,
+    pub ignore_count: u32, // hits left which don't stop the emulation
+    pub hits: u32,         // all hits so far, ignored ones included
+    pub enabled: bool,
+    pub stop: bool,        // false: only count hits
+    pub temporary: bool,   // removed once it has stopped the emulation
+}
+
+impl Breakpoint {
+    fn matches(&self, access: Access, addr: u16) -> bool {
+        let kind = match access {
+            Access::Exec  => self.exec,
+            Access::Load  => self.load,
+            Access::Store => self.store,
+        };
+
+        self.enabled && kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: Vec<&str> = [(self.exec, "exec"), (self.load, "load"), (self.store, "store")].iter()
+                                                                                               .filter(|k| k.0)
+                                                                                               .map(|k| k.1)
+                                                                                               .collect();
+        let range = if self.start == self.end { format!("${:04X}", self.start) } else { format!("${:04X}-${:04X}", self.start, self.end) };
+
+        write!(f, "#{:<3} {:<15} {:<11} hits: {}", self.id, kinds.join("/"), range, self.hits)?;
+
+        if let Some(ref condition) = self.condition {
+            write!(f, "  if {}", condition)?;
+        }
+        if self.ignore_count > 0 {
+            write!(f, "  ignore: {}", self.ignore_count)?;
+        }
+        if !self.stop {
+            write!(f, "  (no stop)")?;
+        }
+        if self.temporary {
+            write!(f, "  (temporary)")?;
+        }
+        if !self.enabled {
+            write!(f, "  (disabled)")?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// the breakpoint which stopped the emulation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub id: u32,
+    pub access: Access,
+    pub addr: u16,
+    pub value: u8, // data read or written (0 for exec)
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Exec => write!(f, "#{} (stop on exec ${:04X})", self.id, self.addr),
+            _ => write!(f, "#{} (stop on {} ${:04X}: ${:02X})", self.id, self.access, self.addr, self.value),
+        }
+    }
+}
+
+
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: u32,
+    exec_active: bool,   // any enabled exec breakpoints? (saves searching the list on every instruction)
+    memory_active: bool, // any enabled watchpoints? (saves searching the list on every bus access)
+    pub hit: Option<Hit>, // set on a hit, until taken by whoever pauses the emulation
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            list: Vec::new(),
+            next_id: 1,
+            exec_active: false,
+            memory_active: false,
+            hit: None,
+        }
+    }
+
+
+    // returns the id of the new breakpoint
+    pub fn add(&mut self, start: u16, end: u16, exec: bool, load: bool, store: bool, condition: Option<Condition>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.list.push(Breakpoint {
+            id,
+            start,
+            end,
+            exec,
+            load,
+            store,
+            condition,
+            ignore_count: 0,
+            hits: 0,
+            enabled: true,
+            stop: true,
+            temporary: false,
+        });
+
+        self.update_active();
+        id
+    }
+
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let count = self.list.len();
+        self.list.retain(|b| b.id != id);
+        self.update_active();
+        self.list.len() != count
+    }
+
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.update_active();
+    }
+
+
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.list
+    }
+
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> bool {
+        let found = self.with_breakpoint(id, |b| b.enabled = enabled);
+        self.update_active();
+        found
+    }
+
+
+    pub fn set_ignore_count(&mut self, id: u32, count: u32) -> bool {
+        self.with_breakpoint(id, |b| b.ignore_count = count)
+    }
+
+
+    pub fn set_condition(&mut self, id: u32, condition: Option<Condition>) -> bool {
+        self.with_breakpoint(id, |b| b.condition = condition)
+    }
+
+
+    pub fn set_stop(&mut self, id: u32, stop: bool) -> bool {
+        self.with_breakpoint(id, |b| b.stop = stop)
+    }
+
+
+    pub fn set_temporary(&mut self, id: u32, temporary: bool) -> bool {
+        self.with_breakpoint(id, |b| b.temporary = temporary)
+    }
+
+
+    pub fn is_active(&self, access: Access) -> bool {
+        match access {
+            Access::Exec => self.exec_active,
+            _ => self.memory_active,
+        }
+    }
+
+
+    // count hits of all matching breakpoints; the first one which isn't ignored is recorded as the hit
+    pub fn check(&mut self, access: Access, addr: u16, value: u8, cpu: &cpu::CPU) {
+        for breakpoint in self.list.iter_mut().filter(|b| b.matches(access, addr)) {
+            if let Some(ref condition) = breakpoint.condition {
+                if !condition.is_met(cpu) {
+                    continue;
+                }
+            }
+
+            breakpoint.hits += 1;
+
+            if breakpoint.ignore_count > 0 {
+                breakpoint.ignore_count -= 1;
+            }
+            else if breakpoint.stop && self.hit.is_none() {
+                self.hit = Some(Hit { id: breakpoint.id, access, addr, value });
+            }
+        }
+
+        if let Some(hit) = self.hit {
+            if self.list.iter().any(|b| b.id == hit.id && b.temporary) {
+                self.remove(hit.id);
+            }
+        }
+    }
+
+
+    // *** private functions *** //
+
+    fn with_breakpoint<F: FnOnce(&mut Breakpoint)>(&mut self, id: u32, f: F) -> bool {
+        match self.list.iter_mut().find(|b| b.id == id) {
+            Some(breakpoint) => {
+                f(breakpoint);
+                true
+            },
+            None => false,
+        }
+    }
+
+
+    fn update_active(&mut self) {
+        self.exec_active = self.list.iter().any(|b| b.enabled && b.exec);
+        self.memory_active = self.list.iter().any(|b| b.enabled && (b.load || b.store));
+    }
+}
+
+impl Default for Breakpoints {
+    fn default() -> Breakpoints {
+        Breakpoints::new()
+    }
+}
@@ -0,0 +1,158 @@
+// system bus: owns the memory and all the chips the CPU talks to
+use c64::cia;
+use c64::cpu;
+use c64::memory;
+use c64::roms;
+use c64::sid;
+use c64::vic;
+use error::Error;
+
+// everything the CPU needs from the system it's plugged into
+pub trait Bus {
+    // returns whether RAM was written (true) or RAM under ROM (false)
+    fn write_byte(&mut self, addr: u16, value: u8, on_write: &mut cpu::Callback) -> bool;
+    fn read_byte(&mut self, addr: u16, on_read: &mut cpu::Callback) -> u8;
+    fn read_word_le(&mut self, addr: u16) -> u16;
+
+    // unconnected address where the CPU reads back its own toggling value
+    fn is_open_bus(&self, _addr: u16) -> bool {
+        false
+    }
+}
+
+
+pub struct SystemBus {
+    pub memory: memory::Memory,
+    pub vic:    vic::VIC,
+    pub cia1:   cia::CIA,
+    pub cia2:   cia::CIA,
+    pub sid:    sid::SID,
+}
+
+impl SystemBus {
+    // fails if there's no audio device to play the SID on
+    pub fn new(audio_on: bool, roms: &roms::RomSet) -> Result<SystemBus, Error> {
+        Ok(SystemBus {
+            memory: memory::Memory::new(roms),
+            vic:    vic::VIC::new(),
+            cia1:   cia::CIA::new(true),
+            cia2:   cia::CIA::new(false),
+            sid:    sid::SID::new(audio_on)?,
+        })
+    }
+}
+
+impl Bus for SystemBus {
+    // Write a byte to memory or chip register - returns whether RAM was written (true) or RAM under ROM (false)
+    fn write_byte(&mut self, addr: u16, value: u8, on_write: &mut cpu::Callback) -> bool {
+        let mut mem_write_ok = true;
+
+        if self.memory.io_on {
+            match addr {
+ /*   VIC-II  */ 0xD000..=0xD3FF => self.vic.write_register(addr, value, on_write, &mut self.memory),
+ /*    SID    */ 0xD400..=0xD7FF => self.sid.write_register(addr, value, &mut self.memory),
+ /* color RAM */ 0xD800..=0xDBFF => mem_write_ok = self.memory.write_byte(addr, value & 0x0F),
+ /*    CIA1   */ 0xDC00..=0xDCFF => self.cia1.write_register(addr, value, on_write, &mut self.memory, &mut self.vic),
+ /*    CIA2   */ 0xDD00..=0xDDFF => self.cia2.write_register(addr, value, on_write, &mut self.memory, &mut self.vic),
+                 _               => mem_write_ok = self.memory.write_byte(addr, value),
+            }
+        }
+        else {
+            mem_write_ok = self.memory.write_byte(addr, value);
+        }
+
+        mem_write_ok
+    }
+
+
+    fn read_byte(&mut self, addr: u16, on_read: &mut cpu::Callback) -> u8 {
+        if self.memory.io_on {
+            match addr {
+   /*  VIC-II   */ 0xD000..=0xD3FF => self.vic.read_register(addr, &mut self.memory),
+   /*   SID     */ 0xD400..=0xD7FF => self.sid.read_register(addr, &mut self.memory),
+   /* color RAM */ 0xD800..=0xDBFF => (self.memory.read_byte(addr) & 0x0F) | (self.vic.last_byte & 0xF0),
+   /*   CIA1    */ 0xDC00..=0xDCFF => self.cia1.read_register(addr, on_read),
+   /*   CIA2    */ 0xDD00..=0xDDFF => self.cia2.read_register(addr, on_read),
+                   0xDF00..=0xDF9F => self.vic.last_byte,
+                   _ => self.memory.read_byte(addr)
+            }
+        }
+        else {
+            self.memory.read_byte(addr)
+        }
+    }
+
+
+    fn read_word_le(&mut self, addr: u16) -> u16 {
+        self.memory.read_word_le(addr)
+    }
+
+
+    fn is_open_bus(&self, addr: u16) -> bool {
+        addr == 0xDFFF && self.memory.io_on
+    }
+}
+
+
+// a single CPU bus cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusCycle {
+    Read(u16),
+    Write(u16, u8),
+}
+
+
+// flat 64K of RAM without any chips - for running CPU test suites
+pub struct RamBus {
+    pub ram: Vec<u8>,
+    pub trace: Option<Vec<BusCycle>>, // every read and write the CPU does, if enabled
+}
+
+impl RamBus {
+    pub fn new() -> RamBus {
+        RamBus {
+            ram: vec![0; 0x10000],
+            trace: None,
+        }
+    }
+
+
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        let end = (start + data.len()).min(0x10000);
+        self.ram[start..end].copy_from_slice(&data[..end - start]);
+    }
+}
+
+impl Default for RamBus {
+    fn default() -> RamBus {
+        RamBus::new()
+    }
+}
+
+impl Bus for RamBus {
+    fn write_byte(&mut self, addr: u16, value: u8, _on_write: &mut cpu::Callback) -> bool {
+        if let Some(ref mut trace) = self.trace {
+            trace.push(BusCycle::Write(addr, value));
+        }
+
+        self.ram[addr as usize] = value;
+        true
+    }
+
+
+    fn read_byte(&mut self, addr: u16, _on_read: &mut cpu::Callback) -> u8 {
+        if let Some(ref mut trace) = self.trace {
+            trace.push(BusCycle::Read(addr));
+        }
+
+        self.ram[addr as usize]
+    }
+
+
+    fn read_word_le(&mut self, addr: u16) -> u16 {
+        let lo = self.ram[addr as usize] as u16;
+        let hi = self.ram[addr.wrapping_add(1) as usize] as u16;
+        (hi << 8) | lo
+    }
+}
@@ -1,20 +1,24 @@
 // CIA chip
 use c64::cpu;
 use c64::memory;
+use c64::snapshot;
 use c64::vic;
-use std::rc::Rc;
-use std::cell::RefCell;
-
-pub type CIAShared = Rc<RefCell<CIA>>;
-
-enum TimerState {
-    Stop,
-    WaitCount,
-    LoadStop,
-    LoadCount,
-    LoadWaitCount,
-    Count,
-    CountStop
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use enum_primitive::FromPrimitive;
+use std::io;
+use std::io::{Read, Write};
+
+enum_from_primitive! {
+    #[derive(Clone, Copy)]
+    enum TimerState {
+        Stop,
+        WaitCount,
+        LoadStop,
+        LoadCount,
+        LoadWaitCount,
+        Count,
+        CountStop
+    }
 }
 
 
@@ -65,6 +69,33 @@ impl CIATimer {
     }
 
 
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u8(self.state as u8)?;
+        w.write_u16::<LittleEndian>(self.value)?;
+        w.write_u16::<LittleEndian>(self.latch)?;
+        w.write_all(&[self.ctrl, self.new_ctrl])?;
+        snapshot::write_bools(w, &[self.has_new_ctrl, self.is_cnt_phi2, self.irq_next_cycle, self.underflow, self.cnt_ta_underflow])
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.state = TimerState::from_u8(r.read_u8()?).ok_or(snapshot::invalid_data("Invalid CIA timer state"))?;
+        self.value = r.read_u16::<LittleEndian>()?;
+        self.latch = r.read_u16::<LittleEndian>()?;
+        self.ctrl     = r.read_u8()?;
+        self.new_ctrl = r.read_u8()?;
+
+        let mut flags = [false; 5];
+        snapshot::read_bools(r, &mut flags)?;
+        self.has_new_ctrl     = flags[0];
+        self.is_cnt_phi2      = flags[1];
+        self.irq_next_cycle   = flags[2];
+        self.underflow        = flags[3];
+        self.cnt_ta_underflow = flags[4];
+        Ok(())
+    }
+
+
     pub fn update(&mut self, cia_icr: &mut u8, ta_underflow: bool) {
         match self.state {
             TimerState::Stop => (),
@@ -195,10 +226,6 @@ impl CIATimer {
 
 // the actual CIA chip including both timers
 pub struct CIA {
-    mem_ref: Option<memory::MemShared>,
-    cpu_ref: Option<cpu::CPUShared>,
-    vic_ref: Option<vic::VICShared>,
-
     is_cia1: bool,  // is this CIA1 or CIA2 chip?
     
     timer_a: CIATimer,
@@ -210,6 +237,7 @@ pub struct CIA {
     ddra: u8,
     ddrb: u8,
     sdr:  u8,
+    flag: bool, // level of the FLAG input; a falling edge sets bit 4 of the ICR
     
     // TOD timer
     tod_halt: bool,
@@ -237,12 +265,8 @@ pub struct CIA {
 }
 
 impl CIA {
-    pub fn new_shared(is_cia1: bool) -> CIAShared {
-        Rc::new(RefCell::new(CIA {
-            mem_ref: None,
-            cpu_ref: None,
-            vic_ref: None,
-
+    pub fn new(is_cia1: bool) -> CIA {
+        CIA {
             is_cia1: is_cia1,
             timer_a: CIATimer::new(true),
             timer_b: CIATimer::new(false),
@@ -253,6 +277,7 @@ impl CIA {
             ddra: 0,
             ddrb: 0,
             sdr: 0,
+            flag: true,
 
             tod_halt: false,
             tod_freq_div: 0,
@@ -274,14 +299,7 @@ impl CIA {
 
             // CIA2 only
             iec_lines: 0xD0
-        }))
-    }
-
-
-    pub fn set_references(&mut self, memref: memory::MemShared, cpuref: cpu::CPUShared, vicref: vic::VICShared) {
-        self.mem_ref = Some(memref);
-        self.cpu_ref = Some(cpuref);
-        self.vic_ref = Some(vicref);
+        }
     }
 
 
@@ -295,6 +313,7 @@ impl CIA {
         self.ddra = 0;
         self.ddrb = 0;
         self.sdr = 0;
+        self.flag = true;
         self.tod_halt = false;
         self.tod_freq_div = 0;
         self.tod_hour = 0;
@@ -321,6 +340,67 @@ impl CIA {
     }
 
 
+    // timers, ports and TOD clock; keyboard and joystick state comes from the host and is not saved
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.timer_a.save_state(w)?;
+        self.timer_b.save_state(w)?;
+        w.write_all(&[self.irq_mask, self.icr, self.pra, self.prb, self.ddra, self.ddrb, self.sdr])?;
+        snapshot::write_bools(w, &[self.flag, self.tod_halt])?;
+        w.write_u16::<LittleEndian>(self.tod_freq_div)?;
+        w.write_all(&[self.tod_hour, self.tod_min, self.tod_sec, self.tod_dsec])?;
+        w.write_all(&[self.alarm_hour, self.alarm_min, self.alarm_sec, self.alarm_dsec])?;
+        w.write_all(&[self.prev_lp, self.iec_lines])
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.timer_a.load_state(r)?;
+        self.timer_b.load_state(r)?;
+
+        let mut regs = [0; 7];
+        r.read_exact(&mut regs)?;
+        self.irq_mask = regs[0];
+        self.icr  = regs[1];
+        self.pra  = regs[2];
+        self.prb  = regs[3];
+        self.ddra = regs[4];
+        self.ddrb = regs[5];
+        self.sdr  = regs[6];
+
+        let mut flags = [false; 2];
+        snapshot::read_bools(r, &mut flags)?;
+        self.flag     = flags[0];
+        self.tod_halt = flags[1];
+        self.tod_freq_div = r.read_u16::<LittleEndian>()?;
+        let mut tod = [0; 8];
+        r.read_exact(&mut tod)?;
+        self.tod_hour = tod[0];
+        self.tod_min  = tod[1];
+        self.tod_sec  = tod[2];
+        self.tod_dsec = tod[3];
+        self.alarm_hour = tod[4];
+        self.alarm_min  = tod[5];
+        self.alarm_sec  = tod[6];
+        self.alarm_dsec = tod[7];
+
+        self.prev_lp   = r.read_u8()?;
+        self.iec_lines = r.read_u8()?;
+        Ok(())
+    }
+
+
+    // CIA2 port A as seen on its pins: VIC bank, serial bus ATN, CLK and DATA outputs
+    pub fn port_a_out(&self) -> u8 {
+        self.pra | !self.ddra
+    }
+
+
+    // serial bus CLK and DATA inputs of CIA2, true while the line is high
+    pub fn set_iec_inputs(&mut self, clk: bool, data: bool) {
+        self.iec_lines = (self.iec_lines & 0x3F) | if clk { 0x40 } else { 0 } | if data { 0x80 } else { 0 };
+    }
+
+
     pub fn update(&mut self) {
         self.timer_a.update(&mut self.icr, false);
         let ta_underflow = self.timer_a.underflow;
@@ -370,29 +450,32 @@ impl CIA {
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
+    pub fn write_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback, mem: &mut memory::Memory, vic: &mut vic::VIC) {
+        // registers repeat every 16 bytes
+        let addr = (addr & 0xFF00) + (addr % 0x0010);
+
         match addr & 0x00FF {
             0x04 => {
                 self.timer_a.latch = (self.timer_a.latch & 0xFF00) | value as u16;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x05 => {
                 self.timer_a.latch = (self.timer_a.latch & 0x00FF) | ((value as u16) << 8);
                 if (self.timer_a.ctrl & 1) == 0 {
                     self.timer_a.value = self.timer_a.latch;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x06 => {
                 self.timer_b.latch = (self.timer_b.latch & 0xFF00) | value as u16;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x07 => {
                 self.timer_b.latch = (self.timer_b.latch & 0x00FF) | ((value as u16) << 8);
                 if (self.timer_b.ctrl & 1) == 0 {
                     self.timer_b.value = self.timer_b.latch;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x08 => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -401,7 +484,7 @@ impl CIA {
                 else {
                     self.tod_dsec = value & 0x0F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x09 => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -410,7 +493,7 @@ impl CIA {
                 else {
                     self.tod_sec = value & 0x7F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0A => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -419,7 +502,7 @@ impl CIA {
                 else {
                     self.tod_min = value & 0x7F;
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
              0x0B => {
                 if (self.timer_b.ctrl & 0x80) != 0 {
@@ -428,7 +511,7 @@ impl CIA {
                 else {
                     self.tod_hour = value & 0x9F;
                 }
-                 as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                 mem.get_ram_bank(memory::MemType::Io).write(addr, value);
              },
             0x0C => {
                 self.sdr = value;
@@ -449,41 +532,41 @@ impl CIA {
                     self.icr |= 0x80;
                     *on_cia_write = if self.is_cia1 { cpu::Callback::TriggerCIAIrq } else { cpu::Callback::TriggerNMI };
                 }
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0E => {
                 self.timer_a.has_new_ctrl = true;
                 self.timer_a.new_ctrl = value;
                 self.timer_a.is_cnt_phi2 = (value & 0x20) == 0;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0x0F => {
                 self.timer_b.has_new_ctrl = true;
                 self.timer_b.new_ctrl = value;
                 self.timer_b.is_cnt_phi2 = (value & 0x60) == 0;
                 self.timer_b.cnt_ta_underflow = (value & 0x60) == 0x40;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             _ => {
                 if self.is_cia1 {
-                    self.write_cia1_register(addr, value, on_cia_write);
+                    self.write_cia1_register(addr, value, on_cia_write, mem, vic);
                 }
                 else {
-                    self.write_cia2_register(addr, value, on_cia_write);
+                    self.write_cia2_register(addr, value, mem, vic);
                 }
             }
         }
     }
 
 
-    pub fn process_irq(&mut self) {
+    pub fn process_irq(&mut self, cpu: &mut cpu::CPU) {
         if self.timer_a.irq_next_cycle {
             if self.trigger_irq(1) {
                 if self.is_cia1 {
-                    as_mut!(self.cpu_ref).set_cia_irq(true);
+                    cpu.set_cia_irq(true);
                 }
                 else {
-                    as_mut!(self.cpu_ref).set_nmi(true);
+                    cpu.set_nmi(true);
                 }
             }
             
@@ -492,10 +575,10 @@ impl CIA {
         if self.timer_a.irq_next_cycle {
             if self.trigger_irq(2) {
                 if self.is_cia1 {
-                    as_mut!(self.cpu_ref).set_cia_irq(true);
+                    cpu.set_cia_irq(true);
                 }
                 else {
-                    as_mut!(self.cpu_ref).set_nmi(true);
+                    cpu.set_nmi(true);
                 }
             }
             
@@ -504,7 +587,7 @@ impl CIA {
     }
 
 
-    pub fn count_tod(&mut self) {
+    pub fn count_tod(&mut self, cpu: &mut cpu::CPU) {
         let mut lo: u8;
         let mut hi: u8;
 
@@ -576,10 +659,10 @@ impl CIA {
                (self.tod_hour == self.alarm_hour) {
                 if self.trigger_irq(4) {
                     if self.is_cia1 {
-                        as_mut!(self.cpu_ref).set_cia_irq(true);
+                        cpu.set_cia_irq(true);
                     }
                     else {
-                        as_mut!(self.cpu_ref).set_nmi(true);
+                        cpu.set_nmi(true);
                     };
                 }
             }
@@ -587,6 +670,21 @@ impl CIA {
     }
 
 
+    // the FLAG pin: the datasette's read line on CIA1, the user port on CIA2
+    pub fn set_flag(&mut self, level: bool, cpu: &mut cpu::CPU) {
+        if self.flag && !level && self.trigger_irq(0x10) {
+            if self.is_cia1 {
+                cpu.set_cia_irq(true);
+            }
+            else {
+                cpu.set_nmi(true);
+            }
+        }
+
+        self.flag = level;
+    }
+
+
     // true - irq triggered; false - not
     pub fn trigger_irq(&mut self, mask: u8) -> bool {
         self.icr |= mask;
@@ -603,9 +701,10 @@ impl CIA {
 
     // *** private functions *** //
 
+    // only the port registers (0x00-0x03) differ between CIA1 and CIA2
     fn read_cia1_register(&mut self, addr: u16) -> u8 {
-        match addr {
-            0xDC00 => {
+        match addr & 0x000F {
+            0x00 => {
                 let mut retval = self.pra | !self.ddra;
                 let tst = (self.prb | !self.ddrb) & self.joystick_1;
 
@@ -620,7 +719,7 @@ impl CIA {
                 
                 retval & self.joystick_2
             },
-            0xDC01 => {
+            _    => { // 0x01
                 let mut retval = !self.ddrb;
                 let tst = (self.pra | !self.ddra) & self.joystick_2;
 
@@ -635,78 +734,66 @@ impl CIA {
 
                 (retval | (self.prb & self.ddrb)) & self.joystick_1
             },
-            0xDC10..=0xDCFF => self.read_cia1_register(0xDC00 + (addr % 0x0010)),
-            _ => panic!("Address out of CIA1 memory range: ${:04X}", addr),
         }
     }
 
 
-    fn write_cia1_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
-        match addr {
-            0xDC00 => {
+    fn write_cia1_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback, mem: &mut memory::Memory, vic: &mut vic::VIC) {
+        match addr & 0x000F {
+            0x00 => {
                 self.pra = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDC01 => {
+            0x01 => {
                 self.prb = value;
-                self.check_lp();
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                self.check_lp(on_cia_write, mem, vic);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDC02 => {
+            0x02 => {
                 self.ddra = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDC03 => {
+            _    => { // 0x03
                 self.ddrb = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
-                self.check_lp();
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
+                self.check_lp(on_cia_write, mem, vic);
             },
-            0xDC10..=0xDCFF => self.write_cia1_register(0xDC00 + (addr % 0x0010), value, on_cia_write),
-            _ => panic!("Address out of CIA1 memory range"),
         }
     }
 
 
     fn read_cia2_register(&mut self, addr: u16) -> u8 {
-        match addr {
-            0xDD00 => {
-                // TODO
-                (self.pra | !self.ddra) & 0x3f | self.iec_lines
-            },
-            0xDD01 => self.prb | !self.ddrb,
-            0xDD10..=0xDDFF => self.read_cia2_register(0xDD00 + (addr % 0x0010)),
-            _ => panic!("Address out of CIA2 memory range ${:04X}", addr),
+        match addr & 0x000F {
+            0x00 => (self.pra | !self.ddra) & 0x3f | self.iec_lines,
+            _    => self.prb | !self.ddrb, // 0x01
         }
     }
 
 
-    fn write_cia2_register(&mut self, addr: u16, value: u8, on_cia_write: &mut cpu::Callback) {
-        match addr {
-            0xDD00 => {
-                // TODO
+    fn write_cia2_register(&mut self, addr: u16, value: u8, mem: &mut memory::Memory, vic: &mut vic::VIC) {
+        match addr & 0x000F {
+            0x00 => {
                 self.pra = value;
-                as_mut!(self.vic_ref).on_va_change(!(self.pra | !self.ddra) & 3);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                vic.on_va_change(!(self.pra | !self.ddra) & 3, mem);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDD01 => {
+            0x01 => {
                 self.prb = value;
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDD02 => {
+            0x02 => {
                 self.ddra = value;
-                as_mut!(self.vic_ref).on_va_change(!(self.pra | !self.ddra) & 3);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                vic.on_va_change(!(self.pra | !self.ddra) & 3, mem);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xDD03 => { self.ddrb = value; as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value); },
-            0xDD10..=0xDDFF => self.write_cia2_register(0xDD00 + (addr % 0x0010), value, on_cia_write),
-            _ => panic!("Address out of CIA2 memory range"),
+            _    => { self.ddrb = value; mem.get_ram_bank(memory::MemType::Io).write(addr, value); }, // 0x03
         }
     }
 
 
-    fn check_lp(&mut self) {
+    fn check_lp(&mut self, on_cia_write: &mut cpu::Callback, mem: &mut memory::Memory, vic: &mut vic::VIC) {
         if ((self.prb | !self.ddrb) & 0x10) != self.prev_lp {
-            as_mut!(self.vic_ref).trigger_lp_irq();
+            *on_cia_write = vic.trigger_lp_irq(mem);
         }
 
         self.prev_lp = (self.prb | !self.ddrb) & 0x10;
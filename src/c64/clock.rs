@@ -19,6 +19,11 @@ impl Clock {
         clock
     }
 
+    pub fn set_freq(&mut self, freq: f64) {
+        self.clock_period = 1.0 / freq;
+    }
+
+
     pub fn tick(&mut self) -> bool {
         self.curr_time = time::OffsetDateTime::now_utc().unix_timestamp_nanos() as f64 / 1E09;
 
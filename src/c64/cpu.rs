@@ -1,19 +1,23 @@
 // The CPU
-use c64::cia;
-use c64::memory;
+use c64::breakpoints;
+use c64::bus;
 use c64::opcodes;
-use c64::sid;
-use c64::vic;
-use std::cell::RefCell;
-use std::rc::Rc;
+use c64::snapshot;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use enum_primitive::FromPrimitive;
+use std::io;
+use std::mem;
+use std::io::{Read, Write};
 use utils;
 
-pub type CPUShared = Rc<RefCell<CPU>>;
-
 pub const NMI_VECTOR:   u16 = 0xFFFA;
 pub const RESET_VECTOR: u16 = 0xFFFC;
 pub const IRQ_VECTOR:   u16 = 0xFFFE;
 
+// default values of the chip-dependent constants mixed into the ANE and LXA results
+pub const ANE_MAGIC: u8 = 0xEF;
+pub const LXA_MAGIC: u8 = 0xEE;
+
 
 // status flags for P register
 pub enum StatusFlag {
@@ -38,13 +42,13 @@ pub enum Callback {
     ClearNMI
 }
 
-pub enum CPUState {
-    FetchOp,
-    FetchOperandAddr,
-    PerformRMW,
-    ProcessIRQ,
-    ProcessNMI,
-    ExecuteOp
+enum_from_primitive! {
+    #[derive(Clone, Copy)]
+    pub enum CPUState {
+        FetchOp,    // next cycle fetches an opcode, or starts an interrupt sequence
+        ExecuteOp,  // running the bus cycles of the current instruction
+        Jammed      // KIL opcode executed - nothing but a reset gets the CPU going again
+    }
 }
 
 
@@ -55,68 +59,51 @@ pub struct CPU {
     pub a:  u8,  // accumulator
     pub x:  u8,  // index register
     pub y:  u8,  // index register
-    pub mem_ref:  Option<memory::MemShared>, // reference to shared system memory
-    pub vic_ref:  Option<vic::VICShared>,
-    pub cia1_ref: Option<cia::CIAShared>,
-    pub cia2_ref: Option<cia::CIAShared>,
-    pub sid_ref:  Option<sid::SIDShared>,
     pub instruction: opcodes::Instruction,
     pub ba_low:  bool,  // is BA low?
     pub cia_irq: bool,
     pub vic_irq: bool,
-    pub irq_cycles_left: u8,
-    pub nmi_cycles_left: u8,
-    pub first_nmi_cycle: u32,
-    pub first_irq_cycle: u32,
+    pub int_line: bool,   // IRQ (if not masked) or NMI seen at the end of the previous cycle
+    pub int_polled: bool, // interrupt polled during the current instruction - taken instead of the next opcode
     pub state: CPUState,
     pub nmi: bool,
     pub debug_instr: bool,
     pub prev_pc: u16, // previous program counter - used for debugging
     pub op_debugger: utils::OpDebugger,
+    pub breakpoints: breakpoints::Breakpoints,
+    pub ane_magic: u8,
+    pub lxa_magic: u8,
     dfff_byte: u8
 }
 
 impl CPU {
-    pub fn new_shared() -> CPUShared {
-        Rc::new(RefCell::new(CPU {
+    pub fn new() -> CPU {
+        CPU {
             pc: 0,
             sp: 0xFF,
             p:  0,
             a:  0,
             x:  0,
             y:  0,
-            mem_ref:  None,
-            vic_ref:  None,
-            cia1_ref: None,
-            cia2_ref: None,
-            sid_ref:  None,
             ba_low:  false,
             cia_irq: false,
             vic_irq: false,
-            irq_cycles_left: 0,
-            nmi_cycles_left: 0,
-            first_nmi_cycle: 0,
-            first_irq_cycle: 0,
+            int_line: false,
+            int_polled: false,
             state: CPUState::FetchOp,
             instruction: opcodes::Instruction::new(),
             nmi: false,
             debug_instr: false,
             prev_pc: 0,
             op_debugger: utils::OpDebugger::new(),
+            breakpoints: breakpoints::Breakpoints::new(),
+            ane_magic: ANE_MAGIC,
+            lxa_magic: LXA_MAGIC,
             dfff_byte: 0x55
-        }))
+        }
     }
 
 
-    pub fn set_references(&mut self, memref: memory::MemShared, vicref: vic::VICShared, cia1ref: cia::CIAShared, cia2ref: cia::CIAShared, sidref: sid::SIDShared) {
-        self.mem_ref = Some(memref);
-        self.vic_ref = Some(vicref);
-        self.cia1_ref = Some(cia1ref);
-        self.cia2_ref = Some(cia2ref);
-        self.sid_ref  = Some(sidref);
-    }    
-    
-
     pub fn set_status_flag(&mut self, flag: StatusFlag, value: bool) {
         if value { self.p |=   flag as u8;  }
         else     { self.p &= !(flag as u8); }
@@ -135,170 +122,166 @@ impl CPU {
     }
     
 
-    pub fn reset(&mut self) {
-        let pc = self.read_word_le(RESET_VECTOR);
+    pub fn reset<B: bus::Bus>(&mut self, bus: &mut B) {
+        let pc = self.read_word_le(RESET_VECTOR, bus);
         self.pc = pc;
+        self.state = CPUState::FetchOp;
+        self.int_line = false;
+        self.int_polled = false;
+        self.set_status_flag(StatusFlag::InterruptDisable, true);
 
         // I'm only doing this to avoid dead code warning :)
         self.set_status_flag(StatusFlag::Unused, false);
     }
 
 
-    pub fn update(&mut self, c64_cycle_cnt: u32) {
-        // check for irq and nmi
+    // run a single bus cycle
+    pub fn update<B: bus::Bus>(&mut self, bus: &mut B) {
         match self.state {
             CPUState::FetchOp => {
-                if self.nmi && self.nmi_cycles_left == 0 && (c64_cycle_cnt - (self.first_nmi_cycle as u32) >= 2) {
-                    self.nmi_cycles_left = 7;
-                    self.state = CPUState::ProcessNMI;
-                }
-                else if !self.get_status_flag(StatusFlag::InterruptDisable) {
-                    let irq_ready = (self.cia_irq || self.vic_irq) && self.irq_cycles_left == 0;
+                if self.ba_low { return; }
 
-                    if irq_ready && (c64_cycle_cnt - (self.first_irq_cycle as u32) >= 2) {
-                        self.irq_cycles_left = 7;
-                        self.state = CPUState::ProcessIRQ;
-                    }
+                if self.int_polled {
+                    // an interrupt forces a BRK instead of the next opcode, which is read but discarded
+                    let pc = self.pc;
+                    self.read_byte(pc, bus);
+                    self.instruction.decode(0x00);
+                    self.instruction.interrupt = true;
+                    self.int_polled = false;
                 }
-            },
-            _ => {}
-        }
-        
-        match self.state {
-            CPUState::FetchOp => {
-                if self.ba_low { return; }
-                let next_op = self.next_byte();
-                match opcodes::get_instruction(next_op) {
-                    Some((opcode, total_cycles, is_rmw, addr_mode)) => {
-                        self.instruction.opcode = opcode;
-                        self.instruction.addr_mode = addr_mode;
-                        self.instruction.is_rmw = is_rmw;
-                        self.instruction.calculate_cycles(total_cycles, is_rmw);
-                        if self.debug_instr { utils::debug_instruction(next_op, self); }
+                else {
+                    let next_op = self.next_byte(bus);
+                    if !self.instruction.decode(next_op) {
+                        panic!("Can't fetch instruction");
                     }
-                    None => panic!("Can't fetch instruction")
+                    if self.debug_instr { utils::debug_instruction(next_op, self, bus); }
                 }
 
-                // jump straight to op execution unless operand address needs to be fetched
-                match self.instruction.addr_mode {
-                    opcodes::AddrMode::Implied     => self.state = CPUState::ExecuteOp,
-                    opcodes::AddrMode::Accumulator => self.state = CPUState::ExecuteOp,
-                    opcodes::AddrMode::Immediate   => self.state = CPUState::ExecuteOp,
-                    opcodes::AddrMode::Relative    => {
-                        // TODO: inc PC only during op execution?
-                        let base = (self.pc + 1) as i16;
-                        let offset = self.next_byte() as i8;
-                        self.instruction.operand_addr = (base + offset as i16) as u16;
-                        self.state = CPUState::ExecuteOp;
-                    },
-                    _ => self.state = CPUState::FetchOperandAddr,
-                };
+                self.state = CPUState::ExecuteOp;
             },
-            CPUState::FetchOperandAddr => {
-                if self.ba_low { return; }
-                if opcodes::fetch_operand_addr(self) {
-                    if self.instruction.is_rmw {
-                        self.state = CPUState::PerformRMW;
-                    }
-                    else {
-                        self.state = CPUState::ExecuteOp;
-                    }
+            CPUState::ExecuteOp => {
+                // interrupts are polled one cycle before the end of an instruction, with the exception
+                // of taken branches that don't cross a page - these don't poll again in their last cycle
+                if !self.instruction.is_short_branch() {
+                    self.int_polled = self.int_line;
                 }
 
-                // TODO: odd case? Some instructions can be executed immediately after operand fetch
-                if self.instruction.cycles_to_run == 0 && self.instruction.cycles_to_fetch == 0 {
-                    //panic!("Not sure if this should happen - reinvestigate");
-                    opcodes::run(self);
-                    self.state = CPUState::FetchOp;
-                }
-            }
-            CPUState::ProcessIRQ => {
-                if self.process_irq(false) {
-                    self.cia_irq = false;
-                    self.vic_irq = false;
-                    self.state = CPUState::FetchOp;
-                }
-            },
-            CPUState::ProcessNMI => {
-                if self.process_irq(true) {
-                    self.nmi = false;
-                    self.state = CPUState::FetchOp;
+                if opcodes::run_cycle(self, bus) {
+                    if let CPUState::ExecuteOp = self.state {
+                        self.state = CPUState::FetchOp;
+
+                        // checked before the next instruction starts, unless an interrupt comes first
+                        if !self.int_polled && self.breakpoints.is_active(breakpoints::Access::Exec) {
+                            let pc = self.pc;
+                            self.check_breakpoints(breakpoints::Access::Exec, pc, 0);
+                        }
+                    }
                 }
             },
-            CPUState::PerformRMW => {
-                match self.instruction.cycles_to_rmw {
-                    2 => {
-                        if self.ba_low { return; }
-                        let addr = self.instruction.operand_addr;
-                        self.instruction.rmw_buffer = self.read_byte(addr);
-                    },
-                    1 => {
-                        let addr = self.instruction.operand_addr;
-                        let val = self.instruction.rmw_buffer;
-                        self.write_byte(addr, val);
-                        self.state = CPUState::ExecuteOp;
-                    },
-                     _ => panic!("Too many cycles in RMW stage! ({}) ", self.instruction.cycles_to_rmw)
-                }
+            CPUState::Jammed => {}
+        }
 
-                self.instruction.cycles_to_rmw -= 1;
-            },
-            CPUState::ExecuteOp => {
-                if opcodes::run(self) {
-                    self.state = CPUState::FetchOp;
-                }
-            }
+        // NMI is latched on its edge, IRQ is a level which only counts if it isn't masked
+        let irq = (self.cia_irq || self.vic_irq) && !self.get_status_flag(StatusFlag::InterruptDisable);
+        self.int_line = self.nmi || irq;
+    }
+
+
+    // address of the KIL opcode that locked up the CPU, if any
+    pub fn jam_address(&self) -> Option<u16> {
+        match self.state {
+            CPUState::Jammed => Some(self.pc.wrapping_sub(1)),
+            _ => None,
         }
     }
 
 
-    pub fn next_byte(&mut self) -> u8 {
-        let pc = self.pc;
-        let op = self.read_byte(pc);
-        self.pc += 1;
-        op
+    // registers, interrupt latches and progress of the current instruction
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u16::<LittleEndian>(self.pc)?;
+        w.write_all(&[self.sp, self.p, self.a, self.x, self.y])?;
+        w.write_u8(self.state as u8)?;
+        w.write_u8(self.instruction.op_byte)?;
+        w.write_u16::<LittleEndian>(self.instruction.operand_addr)?;
+        w.write_u16::<LittleEndian>(self.instruction.index_addr)?;
+        w.write_all(&[self.instruction.cycle, self.instruction.rmw_buffer])?;
+        snapshot::write_bools(w, &[self.instruction.zp_crossed, self.instruction.ba_stalled, self.instruction.interrupt,
+                                   self.ba_low, self.cia_irq, self.vic_irq, self.nmi, self.int_line, self.int_polled])?;
+        w.write_u16::<LittleEndian>(self.prev_pc)?;
+        w.write_u8(self.dfff_byte)
     }
 
 
-    // stack memory: $0100 - $01FF (256 byes)
-    pub fn push_byte(&mut self, value: u8) {
-        self.sp -= 0x01;
-        let new_sp = (self.sp + 0x01) as u16;
-        self.write_byte(0x0100 + new_sp, value);
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.pc = r.read_u16::<LittleEndian>()?;
+        let mut regs = [0; 5];
+        r.read_exact(&mut regs)?;
+        self.sp = regs[0];
+        self.p  = regs[1];
+        self.a  = regs[2];
+        self.x  = regs[3];
+        self.y  = regs[4];
+        self.state = CPUState::from_u8(r.read_u8()?).ok_or(snapshot::invalid_data("Invalid CPU state"))?;
+
+        // opcode and addressing mode are restored from the raw opcode, the rest is progress tracking
+        let op_byte = r.read_u8()?;
+        if !self.instruction.decode(op_byte) {
+            return Err(snapshot::invalid_data("Invalid CPU instruction"));
+        }
+
+        self.instruction.operand_addr = r.read_u16::<LittleEndian>()?;
+        self.instruction.index_addr = r.read_u16::<LittleEndian>()?;
+        let mut progress = [0; 2];
+        r.read_exact(&mut progress)?;
+        self.instruction.cycle = progress[0];
+        self.instruction.rmw_buffer = progress[1];
+
+        let mut flags = [false; 9];
+        snapshot::read_bools(r, &mut flags)?;
+        self.instruction.zp_crossed = flags[0];
+        self.instruction.ba_stalled = flags[1];
+        self.instruction.interrupt  = flags[2];
+        self.ba_low     = flags[3];
+        self.cia_irq    = flags[4];
+        self.vic_irq    = flags[5];
+        self.nmi        = flags[6];
+        self.int_line   = flags[7];
+        self.int_polled = flags[8];
+        self.prev_pc = r.read_u16::<LittleEndian>()?;
+        self.dfff_byte = r.read_u8()?;
+        Ok(())
+    }
+
+
+    pub fn next_byte<B: bus::Bus>(&mut self, bus: &mut B) -> u8 {
+        let pc = self.pc;
+        let op = self.read_byte(pc, bus);
+        self.pc = pc.wrapping_add(1);
+        op
     }
 
 
-    pub fn pop_byte(&mut self) -> u8 {
-        let addr = 0x0100 + (self.sp + 0x01) as u16;
-        let value = self.read_byte(addr);
-        self.sp += 0x01;
-        value
+    // stack memory: $0100 - $01FF (256 byes)
+    pub fn push_byte<B: bus::Bus>(&mut self, value: u8, bus: &mut B) {
+        let addr = 0x0100 | self.sp as u16;
+        self.sp = self.sp.wrapping_sub(0x01);
+        self.write_byte(addr, value, bus);
     }
 
 
-    pub fn push_word(&mut self, value: u16) {
-        self.push_byte(((value >> 8) & 0xFF) as u8);
-        self.push_byte((value & 0xFF) as u8);
+    pub fn pop_byte<B: bus::Bus>(&mut self, bus: &mut B) -> u8 {
+        self.sp = self.sp.wrapping_add(0x01);
+        let addr = 0x0100 | self.sp as u16;
+        self.read_byte(addr, bus)
     }
 
 
-    pub fn write_byte(&mut self, addr: u16, value: u8) -> bool {
+    pub fn write_byte<B: bus::Bus>(&mut self, addr: u16, value: u8, bus: &mut B) -> bool {
         let mut on_write = Callback::None;
-        let mut mem_write_ok = true;
-        let io_enabled = as_ref!(self.mem_ref).io_on;
-
-        if io_enabled {
-            match addr {
- /*   VIC-II  */ 0xD000..=0xD3FF => as_mut!(self.vic_ref).write_register(addr, value, &mut on_write),
- /*    SID    */ 0xD400..=0xD7FF => as_mut!(self.sid_ref).write_register(addr, value),
- /* color RAM */ 0xD800..=0xDBFF => mem_write_ok = as_mut!(self.mem_ref).write_byte(addr, value & 0x0F),
- /*    CIA1   */ 0xDC00..=0xDCFF => as_mut!(self.cia1_ref).write_register(addr, value, &mut on_write),
- /*    CIA2   */ 0xDD00..=0xDDFF => as_mut!(self.cia2_ref).write_register(addr, value, &mut on_write),
-                 _               => mem_write_ok = as_mut!(self.mem_ref).write_byte(addr, value),
-            }
-        }
-        else {
-            mem_write_ok = as_mut!(self.mem_ref).write_byte(addr, value);
+        let mem_write_ok = bus.write_byte(addr, value, &mut on_write);
+
+        if self.breakpoints.is_active(breakpoints::Access::Store) {
+            self.check_breakpoints(breakpoints::Access::Store, addr, value);
         }
 
         // on VIC/CIA register write perform necessary action on the CPU
@@ -316,29 +299,17 @@ impl CPU {
     }
     
 
-    pub fn read_byte(&mut self, addr: u16) -> u8 {
-        let byte: u8;
+    pub fn read_byte<B: bus::Bus>(&mut self, addr: u16, bus: &mut B) -> u8 {
         let mut on_read = Callback::None;
-        let io_enabled = as_ref!(self.mem_ref).io_on;
-
-        if io_enabled {
-            match addr {
-   /*  VIC-II   */ 0xD000..=0xD3FF => byte = as_mut!(self.vic_ref).read_register(addr),
-   /*   SID     */ 0xD400..=0xD7FF => byte = as_mut!(self.sid_ref).read_register(addr),
-   /* color RAM */ 0xD800..=0xDBFF => byte = (as_ref!(self.mem_ref).read_byte(addr) & 0x0F) | (as_ref!(self.vic_ref).last_byte & 0xF0),
-   /*   CIA1    */ 0xDC00..=0xDCFF => byte = as_mut!(self.cia1_ref).read_register(addr, &mut on_read),
-   /*   CIA2    */ 0xDD00..=0xDDFF => byte = as_mut!(self.cia2_ref).read_register(addr, &mut on_read),
-                   0xDF00..=0xDF9F => byte = as_ref!(self.vic_ref).last_byte,
-                   0xDFFF => {
-                       self.dfff_byte = !self.dfff_byte;
-                       byte = self.dfff_byte;
-                   },
-                   _ => byte = as_mut!(self.mem_ref).read_byte(addr)
-            }
+
+        // $DFFF is open bus with a toggling value - handled here since it's CPU-side state
+        let byte = if bus.is_open_bus(addr) {
+            self.dfff_byte = !self.dfff_byte;
+            self.dfff_byte
         }
         else {
-            byte = as_mut!(self.mem_ref).read_byte(addr);
-        }
+            bus.read_byte(addr, &mut on_read)
+        };
 
         match on_read {
             Callback::TriggerCIAIrq => self.set_cia_irq(true),
@@ -348,12 +319,16 @@ impl CPU {
             _ => (),
         }
 
+        if self.breakpoints.is_active(breakpoints::Access::Load) {
+            self.check_breakpoints(breakpoints::Access::Load, addr, byte);
+        }
+
         byte
     }
 
 
-    pub fn read_word_le(&self, addr: u16) -> u16 {
-        as_ref!(self.mem_ref).read_word_le(addr)
+    pub fn read_word_le<B: bus::Bus>(&self, addr: u16, bus: &mut B) -> u16 {
+        bus.read_word_le(addr)
     }
 
 
@@ -372,40 +347,6 @@ impl CPU {
     }
     
 
-    pub fn get_operand(&mut self) -> u8 {
-        // RMW instruction store pre-fetched operand value in internal buffer
-        if self.instruction.is_rmw {
-            return self.instruction.rmw_buffer;
-        }
-
-        let val = match self.instruction.addr_mode {
-            opcodes::AddrMode::Implied     => panic!("Can't get operand value!"),
-            opcodes::AddrMode::Accumulator => self.a,
-            opcodes::AddrMode::Immediate   => self.next_byte(),
-            _ => {
-                let addr = self.instruction.operand_addr;
-                self.read_byte(addr)
-            }
-        };
-
-        val
-    }
-
-
-    pub fn set_operand(&mut self, val: u8) {
-        match self.instruction.addr_mode {
-            opcodes::AddrMode::Implied     => panic!("Can't set implied operand value!"),
-            opcodes::AddrMode::Accumulator => self.a = val,
-            opcodes::AddrMode::Immediate   => panic!("Can't set immediate operand value!"),
-            opcodes::AddrMode::Relative    => panic!("Can't set relative operand value!"),
-            _ => {
-                let addr = self.instruction.operand_addr;
-                let _ = self.write_byte(addr, val);
-            }
-        }
-    }
-
-
     // perform add with carry
     pub fn adc(&mut self, value: u8) {
         let c = self.get_status_flag(StatusFlag::Carry);
@@ -435,9 +376,9 @@ impl CPU {
                 is_zero = is_zero.wrapping_add(0x01);
             }
             
-            self.set_status_flag(StatusFlag::Negative, (hi << 4) != 0); // TODO: is this ok?              
+            self.set_status_flag(StatusFlag::Negative, (hi << 4) & 0x80 != 0); // NMOS: taken before the high nibble fixup
             self.set_status_flag(StatusFlag::Overflow, is_overflow);
-            self.set_status_flag(StatusFlag::Zero,     is_zero == 0);
+            self.set_status_flag(StatusFlag::Zero,     is_zero & 0xFF == 0);
 
             if hi > 9 {
                 hi = hi.wrapping_add(0x06);
@@ -510,87 +451,43 @@ impl CPU {
     }
 
 
-    // perform a branch
-    pub fn branch(&mut self, flag_condition: bool, cycle: u8) -> bool {
-        match cycle {
-            3 => {
-                if self.ba_low { return false; }
-                if flag_condition {
-                    let addr = self.instruction.operand_addr;
-                    let pc = self.pc;
-                    self.instruction.zp_crossed = (addr >> 8) != (pc >> 8);
-                }
-                else {
-                    // no branching - finish instruction after only 2 cycles
-                    self.instruction.cycles_to_run = 1;
-                }
-            },
-            2 => {
-                if !self.instruction.zp_crossed {
-                    self.first_irq_cycle += 1;
-                    self.first_nmi_cycle += 1;
-                }
-                if self.ba_low { return false; }
-                
-                let addr = self.instruction.operand_addr;
-                self.pc = addr;
-
-                if !self.instruction.zp_crossed {
-                    // no page crossing - finish instruction after only 3 cycle
-                    self.instruction.cycles_to_run = 1;
-                }
-            },
-            1 => {
-                if self.ba_low { return false; }
-            },
-            _ => panic!("Wrong number of branching cycles"),
-        }
-
-        true
-    }
-
+    // undocumented ARR: AND followed by ROR, with flags taken from the adder and its own decimal mode fixup
+    pub fn arr(&mut self, value: u8) {
+        let c = self.get_status_flag(StatusFlag::Carry);
+        let t = self.a & value;
+        let mut res = (t >> 1) | if c { 0x80 } else { 0x00 };
 
-    // *** private functions *** //
+        if self.get_status_flag(StatusFlag::DecimalMode) {
+            self.set_status_flag(StatusFlag::Negative, c);
+            self.set_status_flag(StatusFlag::Zero, res == 0);
+            self.set_status_flag(StatusFlag::Overflow, ((t ^ res) & 0x40) != 0);
 
-    fn process_irq(&mut self, is_nmi: bool) -> bool {
-        let new_pc    = if is_nmi { NMI_VECTOR } else { IRQ_VECTOR };
-        let cycle_cnt = if is_nmi { self.nmi_cycles_left } else { self.irq_cycles_left };
-        
-        match cycle_cnt {
-            7 | 6 => {
-                if self.ba_low { return false; }
-            },
-            5 => {
-                let pc_hi = (self.pc >> 8) as u8;
-                self.push_byte(pc_hi);
-            },
-            4 => {
-                let pc_lo = self.pc as u8;
-                self.push_byte(pc_lo);
-            },
-            3 => {
-                self.set_status_flag(StatusFlag::Break, false);
-                let curr_p = self.p;
-                self.push_byte(curr_p);
-                self.set_status_flag(StatusFlag::InterruptDisable, true);
-            },
-            2 => {
-                if self.ba_low { return false; } // TODO: is reading whole word ok in cycle 1?
-            },
-            1 => {
-                if self.ba_low { return false; }
-                self.pc = as_ref!(self.mem_ref).read_word_le(new_pc);
+            if (t & 0x0F) + (t & 0x01) > 0x05 {
+                res = (res & 0xF0) | (res.wrapping_add(0x06) & 0x0F);
             }
-            _ => panic!("Invalid IRQ/NMI cycle")
-        }
 
-        if is_nmi {
-            self.nmi_cycles_left -= 1;
-            self.nmi_cycles_left == 0
+            let hi_fixup = (t as u16 & 0xF0) + (t as u16 & 0x10) > 0x50;
+            if hi_fixup {
+                res = res.wrapping_add(0x60);
+            }
+            self.set_status_flag(StatusFlag::Carry, hi_fixup);
         }
         else {
-            self.irq_cycles_left -= 1;
-            self.irq_cycles_left == 0
+            self.set_zn_flags(res);
+            self.set_status_flag(StatusFlag::Carry, (res & 0x40) != 0);
+            self.set_status_flag(StatusFlag::Overflow, ((res >> 6) ^ (res >> 5)) & 0x01 != 0);
         }
+
+        self.a = res;
+    }
+
+
+    // *** private functions *** //
+
+    fn check_breakpoints(&mut self, access: breakpoints::Access, addr: u16, value: u8) {
+        // moved out for the duration of the check, since conditions look at the registers
+        let mut breakpoints = mem::take(&mut self.breakpoints);
+        breakpoints.check(access, addr, value, self);
+        self.breakpoints = breakpoints;
     }
 }
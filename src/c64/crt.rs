@@ -1,13 +1,14 @@
-use std;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::str;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::fmt;
 use c64::memory;
+use error::Error;
+use utils;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use enum_primitive::FromPrimitive;
 
+pub const SIGNATURE: &[u8; 16] = b"C64 CARTRIDGE   ";
+
 #[derive(Debug)]
 pub struct Crt {
     header: Header,
@@ -15,45 +16,55 @@ pub struct Crt {
 }
 
 impl Crt {
-    pub fn from_filename(filename: &str) -> Result<Crt, String> {
-        let mut file = File::open(filename).map_err(|e| e.to_string())?;
+    pub fn from_filename(filename: &str) -> Result<Crt, Error> {
+        let data = utils::open_file(filename, 0)?;
+        Crt::from_data(&data).map_err(|e| Error::InvalidCrt(format!("Invalid cartridge {}: {}", filename, e)))
+    }
+
+
+    fn from_data(data: &[u8]) -> Result<Crt, String> {
+        let mut file = Cursor::new(data);
+        // the whole file is in memory already, so a failed read means it's cut short
+        let truncated = |_| "file is truncated".to_string();
 
         // Read Header
         let mut signature = [0u8; 16];
-        file.read(&mut signature).map_err(|e| e.to_string())?;
-        if &signature != b"C64 CARTRIDGE   " {
-            return Err("Invalid cartridge signature".to_string())
+        file.read_exact(&mut signature).map_err(truncated)?;
+        if &signature != SIGNATURE {
+            return Err("invalid signature".to_string())
         }
-        let header_len = file.read_u32::<BigEndian>().map_err(|e| e.to_string())?;
+        let header_len = file.read_u32::<BigEndian>().map_err(truncated)?;
         let mut version = [0u8;2];
-        file.read(&mut version).map_err(|e| e.to_string())?;
-        let hw_type = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
+        file.read_exact(&mut version).map_err(truncated)?;
+        let hw_type = file.read_u16::<BigEndian>().map_err(truncated)?;
         if hw_type != 0 {
-            return Err("Unsupported cartridge type".to_string())
+            return Err(format!("unsupported cartridge type {}", hw_type))
         }
-        let exrom = file.read_u8().map_err(|e| e.to_string())?;
-        let game = file.read_u8().map_err(|e| e.to_string())?;
-        file.seek(SeekFrom::Start(0x20)).map_err(|e| e.to_string())?;
+        let exrom = file.read_u8().map_err(truncated)?;
+        let game = file.read_u8().map_err(truncated)?;
+        file.seek(SeekFrom::Start(0x20)).map_err(truncated)?;
         let mut name = [0u8; 32];
-        file.read(&mut name).map_err(|e| e.to_string())?;
+        file.read_exact(&mut name).map_err(truncated)?;
         
         // Read Chips
-        file.seek(SeekFrom::Start(header_len as u64)).map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(header_len as u64)).map_err(truncated)?;
         let mut chips: Vec<Chip> = Vec::new();
         loop {
             let mut chip_signature = [0u8;4];
-            file.read(&mut chip_signature).map_err(|e| e.to_string())?;
-            if &chip_signature != b"CHIP" {
+            if file.read_exact(&mut chip_signature).is_err() || &chip_signature != b"CHIP" {
                 break;
             }
-            let length = file.read_u32::<BigEndian>().map_err(|e| e.to_string())?;
+            let length = file.read_u32::<BigEndian>().map_err(truncated)?;
             let chip_type = ChipType::from_u16(file.read_u16::<BigEndian>()
-                .map_err(|e| e.to_string())?).ok_or("Invalid chip type".to_string())?;
-            let bank_number = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-            let load_addr = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-            let data_size = file.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
+                .map_err(truncated)?).ok_or("invalid chip type".to_string())?;
+            let bank_number = file.read_u16::<BigEndian>().map_err(truncated)?;
+            let load_addr = file.read_u16::<BigEndian>().map_err(truncated)?;
+            let data_size = file.read_u16::<BigEndian>().map_err(truncated)?;
+            if load_addr as usize + data_size as usize > 0x10000 {
+                return Err(format!("chip at ${:04x} doesn't fit in memory ({} bytes)", load_addr, data_size))
+            }
             let mut data: Vec<u8> = vec![0u8; data_size as usize];
-            file.read(&mut data).map_err(|e| e.to_string())?;
+            file.read_exact(&mut data).map_err(truncated)?;
 
             chips.push(Chip {
                 signature: chip_signature,
@@ -81,7 +92,7 @@ impl Crt {
         })
     }
 
-    pub fn load_into_memory(&self, mut memory: std::cell::RefMut<memory::Memory>) {
+    pub fn load_into_memory(&self, memory: &mut memory::Memory) {
         memory.exrom = self.header.exrom == 1;
         memory.game = self.header.game == 1;
         for chip in self.chips.iter() {
@@ -116,14 +127,14 @@ impl fmt::Debug for Header {
     game: {},
     name: {}
 }}",
-            str::from_utf8(&self.signature).unwrap(),
+            String::from_utf8_lossy(&self.signature),
             self.header_len,
             self.version[0],
             self.version[1],
             self.hw_type,
             self.exrom,
             self.game,
-            str::from_utf8(&self.name).unwrap()
+            String::from_utf8_lossy(&self.name)
         )
     }
 }
@@ -150,7 +161,7 @@ impl fmt::Debug for Chip {
     data_size: {} bytes,
     data: (not shown)
 }}",
-            str::from_utf8(&self.signature).unwrap(),
+            String::from_utf8_lossy(&self.signature),
             self.length,
             self.chip_type,
             self.bank_number,
@@ -0,0 +1,100 @@
+// D64 disk images: the 256-byte sectors of a 1541 disk in track order, optionally followed by an error byte per sector
+use c64::disk_image::{DiskImage, Layout, Sectors};
+use error::Error;
+use utils;
+
+pub use c64::disk_image::{DirEntry, DosError, FileType, SECTOR_SIZE, matches_pattern};
+
+// sectors in a 35 and a 40 track image, which may be followed by as many error bytes
+const SECTORS_35: usize = 683;
+const SECTORS_40: usize = 768;
+pub const SIZES: [usize; 4] = [SECTORS_35 * SECTOR_SIZE, SECTORS_35 * (SECTOR_SIZE + 1), SECTORS_40 * SECTOR_SIZE, SECTORS_40 * (SECTOR_SIZE + 1)];
+
+
+pub struct D64 {
+    sectors: Sectors,
+}
+
+impl D64 {
+    pub fn from_filename(filename: &str) -> Result<D64, Error> {
+        let data = utils::open_file(filename, 0)?;
+        let mut image = D64::from_data(data).map_err(|e| Error::InvalidDisk(format!("Invalid disk image {}: {}", filename, e)))?;
+        image.set_filename(filename);
+        Ok(image)
+    }
+
+
+    pub fn from_data(data: Vec<u8>) -> Result<D64, String> {
+        Ok(D64 {
+            sectors: Sectors::from_data(Layout::Dos1541, &[35, 40], data)?,
+        })
+    }
+
+
+    // empty 35 track disk
+    pub fn blank(name: &[u8], id: &[u8]) -> D64 {
+        let mut image = D64 {
+            sectors: Sectors::new(Layout::Dos1541, 35),
+        };
+
+        image.format(name, Some(id));
+        image
+    }
+
+
+    // damage a sector the way the image's error bytes do
+    pub fn set_sector_error(&mut self, track: u8, sector: u8, error: Option<DosError>) {
+        self.sectors.set_sector_error(track, sector, error);
+    }
+}
+
+impl DiskImage for D64 {
+    fn layout(&self) -> Layout {
+        Layout::Dos1541
+    }
+
+
+    fn tracks(&self) -> u8 {
+        self.sectors.tracks()
+    }
+
+
+    fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        self.sectors.raw_sector(track, sector)
+    }
+
+
+    fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError> {
+        self.sectors.write_sector(track, sector, data)
+    }
+
+
+    fn sector_error(&self, track: u8, sector: u8) -> Option<DosError> {
+        self.sectors.sector_error(track, sector)
+    }
+
+
+    fn filename(&self) -> Option<&str> {
+        self.sectors.filename()
+    }
+
+
+    fn set_filename(&mut self, filename: &str) {
+        self.sectors.set_filename(filename);
+    }
+
+
+    fn save(&self) -> Result<(), Error> {
+        self.sectors.save()
+    }
+}
+
+
+pub fn sectors_per_track(track: u8) -> u8 {
+    match track {
+        1..=17  => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _       => 17,
+    }
+}
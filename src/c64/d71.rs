@@ -0,0 +1,80 @@
+// D71 disk images: both sides of a 1571 disk, 70 tracks of 256-byte sectors in track order, optionally followed by an error byte per sector
+use c64::disk_image::{DiskImage, DosError, Layout, SECTOR_SIZE, Sectors};
+use error::Error;
+use utils;
+
+const SECTORS: usize = 1366;
+pub const SIZES: [usize; 2] = [SECTORS * SECTOR_SIZE, SECTORS * (SECTOR_SIZE + 1)];
+
+
+pub struct D71 {
+    sectors: Sectors,
+}
+
+impl D71 {
+    pub fn from_filename(filename: &str) -> Result<D71, Error> {
+        let data = utils::open_file(filename, 0)?;
+        let mut image = D71::from_data(data).map_err(|e| Error::InvalidDisk(format!("Invalid disk image {}: {}", filename, e)))?;
+        image.set_filename(filename);
+        Ok(image)
+    }
+
+
+    pub fn from_data(data: Vec<u8>) -> Result<D71, String> {
+        Ok(D71 {
+            sectors: Sectors::from_data(Layout::Dos1571, &[70], data)?,
+        })
+    }
+
+
+    // empty double sided disk
+    pub fn blank(name: &[u8], id: &[u8]) -> D71 {
+        let mut image = D71 {
+            sectors: Sectors::new(Layout::Dos1571, 70),
+        };
+
+        image.format(name, Some(id));
+        image
+    }
+}
+
+impl DiskImage for D71 {
+    fn layout(&self) -> Layout {
+        Layout::Dos1571
+    }
+
+
+    fn tracks(&self) -> u8 {
+        self.sectors.tracks()
+    }
+
+
+    fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        self.sectors.raw_sector(track, sector)
+    }
+
+
+    fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError> {
+        self.sectors.write_sector(track, sector, data)
+    }
+
+
+    fn sector_error(&self, track: u8, sector: u8) -> Option<DosError> {
+        self.sectors.sector_error(track, sector)
+    }
+
+
+    fn filename(&self) -> Option<&str> {
+        self.sectors.filename()
+    }
+
+
+    fn set_filename(&mut self, filename: &str) {
+        self.sectors.set_filename(filename);
+    }
+
+
+    fn save(&self) -> Result<(), Error> {
+        self.sectors.save()
+    }
+}
@@ -0,0 +1,80 @@
+// D81 disk images: a 1581 3.5" disk, 80 tracks of 40 256-byte sectors in track order, optionally followed by an error byte per sector
+use c64::disk_image::{DiskImage, DosError, Layout, SECTOR_SIZE, Sectors};
+use error::Error;
+use utils;
+
+const SECTORS: usize = 3200;
+pub const SIZES: [usize; 2] = [SECTORS * SECTOR_SIZE, SECTORS * (SECTOR_SIZE + 1)];
+
+
+pub struct D81 {
+    sectors: Sectors,
+}
+
+impl D81 {
+    pub fn from_filename(filename: &str) -> Result<D81, Error> {
+        let data = utils::open_file(filename, 0)?;
+        let mut image = D81::from_data(data).map_err(|e| Error::InvalidDisk(format!("Invalid disk image {}: {}", filename, e)))?;
+        image.set_filename(filename);
+        Ok(image)
+    }
+
+
+    pub fn from_data(data: Vec<u8>) -> Result<D81, String> {
+        Ok(D81 {
+            sectors: Sectors::from_data(Layout::Dos1581, &[80], data)?,
+        })
+    }
+
+
+    // empty 3.5" disk
+    pub fn blank(name: &[u8], id: &[u8]) -> D81 {
+        let mut image = D81 {
+            sectors: Sectors::new(Layout::Dos1581, 80),
+        };
+
+        image.format(name, Some(id));
+        image
+    }
+}
+
+impl DiskImage for D81 {
+    fn layout(&self) -> Layout {
+        Layout::Dos1581
+    }
+
+
+    fn tracks(&self) -> u8 {
+        self.sectors.tracks()
+    }
+
+
+    fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        self.sectors.raw_sector(track, sector)
+    }
+
+
+    fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError> {
+        self.sectors.write_sector(track, sector, data)
+    }
+
+
+    fn sector_error(&self, track: u8, sector: u8) -> Option<DosError> {
+        self.sectors.sector_error(track, sector)
+    }
+
+
+    fn filename(&self) -> Option<&str> {
+        self.sectors.filename()
+    }
+
+
+    fn set_filename(&mut self, filename: &str) {
+        self.sectors.set_filename(filename);
+    }
+
+
+    fn save(&self) -> Result<(), Error> {
+        self.sectors.save()
+    }
+}
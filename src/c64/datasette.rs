@@ -0,0 +1,168 @@
+// the datasette: plays a TAP image into the FLAG input of CIA1 while PLAY is pressed and the 6510 port
+// has its motor running, so the kernal's tape routines and turbo loaders see the pulses just like on hardware
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use c64::model;
+use c64::snapshot;
+use c64::tap;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Play,
+    Stop,
+    Rewind, // all the way to the start of the tape, which stops it
+}
+
+
+pub struct Datasette {
+    tape: Option<tap::Tap>,
+    model: model::Model, // of the emulated machine, whose clock the pulses are converted to
+    playing: bool,    // PLAY is pressed
+    position: usize,  // next pulse on the tape
+    pulse_len: u32,   // cycles of the pulse under the head
+    pulse_left: u32,  // cycles until the next pulse starts
+    read_line: bool,  // level of the read line, low in the first half of a pulse
+}
+
+impl Datasette {
+    pub fn new() -> Datasette {
+        Datasette {
+            tape: None,
+            model: model::Model::Pal,
+            playing: false,
+            position: 0,
+            pulse_len: 0,
+            pulse_left: 0,
+            read_line: true,
+        }
+    }
+
+
+    pub fn set_model(&mut self, model: model::Model) {
+        self.model = model;
+    }
+
+
+    // a new tape is rewound and stopped
+    pub fn insert(&mut self, tape: tap::Tap) {
+        self.tape = Some(tape);
+        self.press(Button::Rewind);
+    }
+
+
+    pub fn eject(&mut self) -> Option<tap::Tap> {
+        self.press(Button::Rewind);
+        self.tape.take()
+    }
+
+
+    pub fn tape(&self) -> Option<&tap::Tap> {
+        self.tape.as_ref()
+    }
+
+
+    // without a tape PLAY doesn't stay down
+    pub fn press(&mut self, button: Button) {
+        match button {
+            Button::Play => self.playing = self.tape.is_some(),
+            Button::Stop => self.playing = false,
+            Button::Rewind => {
+                self.playing = false;
+                self.position = 0;
+                self.pulse_len = 0;
+                self.pulse_left = 0;
+            },
+        }
+    }
+
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+
+    // pulses played so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+
+    // what the FLAG input of CIA1 sees
+    pub fn read_line(&self) -> bool {
+        self.read_line
+    }
+
+
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        snapshot::write_bools(w, &[self.playing, self.read_line])?;
+        w.write_u32::<LittleEndian>(self.position as u32)?;
+        w.write_u32::<LittleEndian>(self.pulse_len)?;
+        w.write_u32::<LittleEndian>(self.pulse_left)
+    }
+
+
+    // the tape isn't part of the state; played with another one, it goes on from the same position
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut flags = [false; 2];
+        snapshot::read_bools(r, &mut flags)?;
+        self.playing    = flags[0];
+        self.read_line  = flags[1];
+        self.position   = r.read_u32::<LittleEndian>()? as usize;
+        self.pulse_len  = r.read_u32::<LittleEndian>()?;
+        self.pulse_left = r.read_u32::<LittleEndian>()?;
+        Ok(())
+    }
+
+
+    // one clock cycle; returns true if a pulse starts, which pulls the read line low. It comes back up halfway
+    // through the pulse, or right before the next one if the pulse is too short for that, so that every pulse
+    // is a falling edge on FLAG exactly one pulse length after the previous one.
+    pub fn update(&mut self, motor: bool) -> bool {
+        if !self.playing || !motor {
+            self.read_line = true;
+            return false;
+        }
+
+        self.pulse_left = self.pulse_left.saturating_sub(1);
+        if self.pulse_left <= self.pulse_len / 2 {
+            self.read_line = true;
+        }
+
+        if self.pulse_left > 0 {
+            return false;
+        }
+
+        let pulse = self.tape.as_ref().and_then(|t| t.pulses().get(self.position).map(|len| (*len, t.model)));
+        match pulse {
+            Some((len, recorded_on)) => {
+                self.position += 1;
+                self.pulse_len = self.convert(len, recorded_on);
+                self.pulse_left = self.pulse_len;
+                self.read_line = false;
+                true
+            },
+            // the end of the tape releases PLAY
+            None => {
+                self.playing = false;
+                false
+            },
+        }
+    }
+
+
+    // *** private functions *** //
+
+    // pulse length in cycles of the emulated machine, at least one
+    fn convert(&self, len: u32, recorded_on: model::Model) -> u32 {
+        let len = (len as f64 * self.model.clock_freq() / recorded_on.clock_freq()).round() as u32;
+        len.max(1)
+    }
+}
+
+impl Default for Datasette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
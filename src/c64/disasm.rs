@@ -0,0 +1,153 @@
+// 6502/6510 disassembler: decodes raw bytes into instructions using the CPU's opcode table,
+// independently of a running machine
+use c64::opcodes::{self, AddrMode, Op};
+use std::fmt;
+
+
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub op: Op,
+    pub addr_mode: AddrMode,
+    pub operand: u16,       // operand bytes as a little-endian value (0 if there are none)
+    pub length: u8,         // opcode and operand bytes
+    pub cycles: u8,         // without page crossing and taken branch penalties
+    pub extra_cycles: bool, // one more cycle on page crossing (branches: when taken, and again on page crossing)
+    pub illegal: bool,      // undocumented opcode
+}
+
+impl Instruction {
+    // decode the instruction at the start of data, located at address; None if data ends mid-instruction
+    pub fn decode(data: &[u8], address: u16) -> Option<Instruction> {
+        let opcode = *data.first()?;
+        let length = operand_length(opcode) + 1;
+
+        if data.len() < length as usize {
+            return None;
+        }
+
+        Some(Instruction::decode_with(address, |addr| data[addr.wrapping_sub(address) as usize]))
+    }
+
+
+    // decode the instruction at address, getting its bytes from read (e.g. a memory peek)
+    pub fn decode_with<F: FnMut(u16) -> u8>(address: u16, mut read: F) -> Instruction {
+        let opcode = read(address);
+        let (op, cycles, _, addr_mode) = opcodes::get_instruction(opcode).unwrap();
+        let length = operand_length(opcode) + 1;
+
+        let operand = match length {
+            2 => read(address.wrapping_add(1)) as u16,
+            3 => read(address.wrapping_add(1)) as u16 | (read(address.wrapping_add(2)) as u16) << 8,
+            _ => 0,
+        };
+
+        let extra_cycles = matches!(addr_mode, AddrMode::Relative |
+                                               AddrMode::AbsoluteIndexedX(true) |
+                                               AddrMode::AbsoluteIndexedY(true) |
+                                               AddrMode::IndirectIndexedY(true));
+
+        Instruction {
+            address,
+            opcode,
+            op,
+            addr_mode,
+            operand,
+            length,
+            // the opcode table counts the worst case
+            cycles: match addr_mode {
+                AddrMode::Relative => 2,
+                _ if extra_cycles  => cycles - 1,
+                _                  => cycles,
+            },
+            extra_cycles,
+            illegal: is_illegal(opcode, op),
+        }
+    }
+
+
+    // address the instruction refers to, if any (branch targets are resolved)
+    pub fn target(&self) -> Option<u16> {
+        match self.addr_mode {
+            AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate => None,
+            AddrMode::Relative => Some(self.address.wrapping_add(2).wrapping_add(self.operand as i8 as u16)),
+            _ => Some(self.operand),
+        }
+    }
+
+
+    // instruction bytes in hex, e.g. "BD 00 04"
+    pub fn hex(&self) -> String {
+        let bytes = [self.opcode, self.operand as u8, (self.operand >> 8) as u8];
+
+        bytes[..self.length as usize].iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+    }
+
+
+    // operand in assembler syntax, e.g. "$0400,X"
+    pub fn operand_text(&self) -> String {
+        match self.addr_mode {
+            AddrMode::Implied                => String::new(),
+            AddrMode::Accumulator            => "A".to_string(),
+            AddrMode::Immediate              => format!("#${:02X}", self.operand),
+            AddrMode::Zeropage               => format!("${:02X}", self.operand),
+            AddrMode::ZeropageIndexedX       => format!("${:02X},X", self.operand),
+            AddrMode::ZeropageIndexedY       => format!("${:02X},Y", self.operand),
+            AddrMode::Absolute               => format!("${:04X}", self.operand),
+            AddrMode::AbsoluteIndexedX(_)    => format!("${:04X},X", self.operand),
+            AddrMode::AbsoluteIndexedY(_)    => format!("${:04X},Y", self.operand),
+            AddrMode::Relative               => format!("${:04X}", self.target().unwrap()),
+            AddrMode::Indirect               => format!("(${:04X})", self.operand),
+            AddrMode::IndexedIndirectX       => format!("(${:02X},X)", self.operand),
+            AddrMode::IndirectIndexedY(_)    => format!("(${:02X}),Y", self.operand),
+        }
+    }
+}
+
+// assembler syntax, e.g. "LDA $0400,X"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self.addr_mode {
+            AddrMode::Implied => self.op.to_string(),
+            _ => format!("{} {}", self.op, self.operand_text()),
+        };
+
+        f.pad(&text)
+    }
+}
+
+
+// decode data, located at start, into consecutive instructions; bytes of a cut off instruction at the end are left out
+pub fn disassemble(data: &[u8], start: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while let Some(instruction) = Instruction::decode(&data[offset..], start.wrapping_add(offset as u16)) {
+        offset += instruction.length as usize;
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+
+// *** private functions *** //
+
+fn operand_length(opcode: u8) -> u8 {
+    match opcodes::get_instruction(opcode).unwrap().3 {
+        AddrMode::Implied | AddrMode::Accumulator => 0,
+        AddrMode::Absolute | AddrMode::AbsoluteIndexedX(_) | AddrMode::AbsoluteIndexedY(_) | AddrMode::Indirect => 2,
+        _ => 1,
+    }
+}
+
+
+fn is_illegal(opcode: u8, op: Op) -> bool {
+    match op {
+        Op::NOP => opcode != 0xEA,
+        Op::SBC => opcode == 0xEB,
+        Op::HLT | Op::SLO | Op::ANC | Op::RLA | Op::SRE | Op::RRA | Op::ALR | Op::SAX | Op::XAA |
+        Op::AHX | Op::TAS | Op::SHY | Op::SHX | Op::ARR | Op::LAX | Op::LAS | Op::DCP | Op::AXS | Op::ISC => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,886 @@
+// disk images: the CBM DOS filesystem of 1541, 1571 and 1581 disks, on top of the sector access each image format provides
+use c64::d64;
+use c64::d71;
+use c64::d81;
+use c64::drive1541::gcr;
+use c64::g64;
+use error::Error;
+use std::fs::File;
+use std::io::Write;
+use utils;
+
+pub const SECTOR_SIZE: usize = 256;
+const NAME_LEN: usize = 16;
+const PADDING: u8 = 0xA0;     // fills names and the disk id
+const ENTRY_SIZE: usize = 32; // directory entries, 8 per sector
+
+// track, sector and offset of some bytes in a sector
+type Location = (u8, u8, usize);
+
+
+// DOS errors which the disk itself can cause
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DosError {
+    Read(u8),           // damaged sector: 20-27 or 29, from the image's error bytes
+    IllegalTrackSector, // broken link in a block chain
+    FileNotFound,
+    FileExists,
+    DiskFull,
+}
+
+impl DosError {
+    pub fn code(&self) -> u8 {
+        match *self {
+            DosError::Read(code)         => code,
+            DosError::IllegalTrackSector => 66,
+            DosError::FileNotFound       => 62,
+            DosError::FileExists         => 63,
+            DosError::DiskFull           => 72,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Del, Seq, Prg, Usr, Rel,
+}
+
+impl FileType {
+    fn from_u8(value: u8) -> FileType {
+        match value & 0x07 {
+            1 => FileType::Seq,
+            2 => FileType::Prg,
+            3 => FileType::Usr,
+            4 => FileType::Rel,
+            _ => FileType::Del,
+        }
+    }
+
+
+    // as shown in directory listings
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FileType::Del => "DEL",
+            FileType::Seq => "SEQ",
+            FileType::Prg => "PRG",
+            FileType::Usr => "USR",
+            FileType::Rel => "REL",
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: Vec<u8>, // PETSCII, without padding
+    pub file_type: FileType,
+    pub closed: bool,  // not closed: left open while writing ("splat" file)
+    pub locked: bool,
+    pub track: u8,     // first block of the file
+    pub sector: u8,
+    pub blocks: u16,
+    location: Location, // directory sector and offset of the entry
+}
+
+
+// where the DOS of each drive keeps the header, the BAM and the directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Dos1541, // 35 tracks of 17-21 sectors, the directory on track 18
+    Dos1571, // both sides of a 1541 disk, with the rest of the BAM on track 53
+    Dos1581, // 80 tracks of 40 sectors, the directory on track 40
+}
+
+impl Layout {
+    pub fn sectors_per_track(&self, track: u8) -> u8 {
+        match *self {
+            Layout::Dos1541 => d64::sectors_per_track(track),
+            Layout::Dos1571 => d64::sectors_per_track(if track > 35 { track - 35 } else { track }),
+            Layout::Dos1581 => 40,
+        }
+    }
+
+
+    pub fn dir_track(&self) -> u8 {
+        match *self {
+            Layout::Dos1541 | Layout::Dos1571 => 18,
+            Layout::Dos1581 => 40,
+        }
+    }
+
+
+    // position of a sector in a file of sectors in track order
+    pub fn sector_index(&self, tracks: u8, track: u8, sector: u8) -> Option<usize> {
+        if track == 0 || track > tracks || sector >= self.sectors_per_track(track) {
+            return None;
+        }
+
+        Some((1..track).map(|t| self.sectors_per_track(t) as usize).sum::<usize>() + sector as usize)
+    }
+
+
+    pub fn total_sectors(&self, tracks: u8) -> usize {
+        (1..=tracks).map(|t| self.sectors_per_track(t) as usize).sum()
+    }
+
+
+    // *** private functions *** //
+
+    // tracks the BAM has room for
+    fn bam_tracks(&self) -> u8 {
+        match *self {
+            Layout::Dos1541 => 35,
+            Layout::Dos1571 => 70,
+            Layout::Dos1581 => 80,
+        }
+    }
+
+
+    fn first_dir_sector(&self) -> u8 {
+        match *self {
+            Layout::Dos1541 | Layout::Dos1571 => 1,
+            Layout::Dos1581 => 3,
+        }
+    }
+
+
+    // disk name in the header sector, followed by the id and the DOS type
+    fn name_offset(&self) -> usize {
+        match *self {
+            Layout::Dos1541 | Layout::Dos1571 => 0x90,
+            Layout::Dos1581 => 0x04,
+        }
+    }
+
+
+    // sector steps between the blocks of a file and of the directory
+    fn interleave(&self) -> (u8, u8) {
+        match *self {
+            Layout::Dos1541 => (10, 3),
+            Layout::Dos1571 => (6, 3),
+            Layout::Dos1581 => (1, 1),
+        }
+    }
+
+
+    // free block count and bitmap of a track: sector and offset on the directory track, or track 53 for the second side of a 1571
+    fn bam_entry(&self, track: u8) -> Option<(Location, Location)> {
+        if track == 0 || track > self.bam_tracks() {
+            return None;
+        }
+
+        let t = track as usize;
+        Some(match *self {
+            Layout::Dos1571 if track > 35 => ((18, 0, 0xDD + t - 36), (53, 0, 3 * (t - 36))),
+            Layout::Dos1541 | Layout::Dos1571 => ((18, 0, 4 * t), (18, 0, 4 * t + 1)),
+            Layout::Dos1581 if track > 40 => ((40, 2, 0x10 + 6 * (t - 41)), (40, 2, 0x11 + 6 * (t - 41))),
+            Layout::Dos1581 => ((40, 1, 0x10 + 6 * (t - 1)), (40, 1, 0x11 + 6 * (t - 1))),
+        })
+    }
+
+
+    // header and BAM sectors of a freshly formatted disk, with all blocks still marked as used
+    fn system_sectors(&self, name: &[u8], id: [u8; 2]) -> Vec<(u8, u8, [u8; SECTOR_SIZE])> {
+        let dir_track = self.dir_track();
+        let mut header = [0; SECTOR_SIZE];
+        header[0] = dir_track;
+        header[1] = self.first_dir_sector();
+
+        let offset = self.name_offset();
+        header[offset..offset + NAME_LEN].copy_from_slice(&pad_name(name));
+        header[offset + NAME_LEN..offset + NAME_LEN + 9].copy_from_slice(&[PADDING, PADDING, id[0], id[1], PADDING, 0, 0, PADDING, PADDING]);
+
+        match *self {
+            Layout::Dos1541 | Layout::Dos1571 => {
+                header[2] = 0x41; // DOS version 'A'
+                header[3] = if *self == Layout::Dos1571 { 0x80 } else { 0x00 }; // double sided
+                header[0xA5..0xA7].copy_from_slice(b"2A");
+                header[0xA9..0xAB].copy_from_slice(&[PADDING, PADDING]);
+
+                let mut sectors = vec![(dir_track, 0, header)];
+                if *self == Layout::Dos1571 {
+                    sectors.push((53, 0, [0; SECTOR_SIZE]));
+                }
+                sectors
+            },
+            Layout::Dos1581 => {
+                header[2] = 0x44; // DOS version 'D'
+                header[0x19..0x1B].copy_from_slice(b"3D");
+
+                // the two BAM sectors are linked, and carry the id and some flags
+                let mut bam = [0; SECTOR_SIZE];
+                bam[2..8].copy_from_slice(&[0x44, 0xBB, id[0], id[1], 0xC0, 0x00]);
+                let mut bam1 = bam;
+                bam1[0] = dir_track;
+                bam1[1] = 2;
+                bam[1] = 0xFF;
+                vec![(dir_track, 0, header), (dir_track, 1, bam1), (dir_track, 2, bam)]
+            },
+        }
+    }
+}
+
+
+// sector access is what an image format provides; the filesystem on top comes for free
+pub trait DiskImage {
+    fn layout(&self) -> Layout;
+    fn tracks(&self) -> u8;
+
+    // contents of a sector even if it's damaged, None if there's no such sector
+    fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]>;
+
+    // writing also clears the sector's read error
+    fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError>;
+
+    // file the image is written back to on changes
+    fn filename(&self) -> Option<&str>;
+    fn set_filename(&mut self, filename: &str);
+
+    // write the image back to the file it was loaded from, if any
+    fn save(&self) -> Result<(), Error>;
+
+
+    // read error a damaged sector gives, if any
+    fn sector_error(&self, _track: u8, _sector: u8) -> Option<DosError> {
+        None
+    }
+
+
+    // the bytes on each half track as a 1541 sees them; None if the disk doesn't fit into a 1541
+    fn gcr_tracks(&self) -> Option<Vec<Vec<u8>>> {
+        match self.layout() {
+            Layout::Dos1541 => Some(gcr::encode_disk(self)),
+            _ => None,
+        }
+    }
+
+
+    // take over what a 1541 wrote to the tracks from gcr_tracks()
+    fn write_gcr_tracks(&mut self, tracks: &[Vec<u8>]) {
+        for track in 1..=self.tracks() {
+            if let Some(data) = tracks.get((track as usize - 1) * 2) {
+                gcr::decode_track(data, track, self);
+            }
+        }
+    }
+
+
+    fn read_sector(&self, track: u8, sector: u8) -> Result<&[u8], DosError> {
+        let data = self.raw_sector(track, sector).ok_or(DosError::IllegalTrackSector)?;
+
+        match self.sector_error(track, sector) {
+            Some(error) => Err(error),
+            None => Ok(data),
+        }
+    }
+
+
+    // wipe the disk like the DOS "N" command; without an id the old one is kept
+    fn format(&mut self, name: &[u8], id: Option<&[u8]>) {
+        let layout = self.layout();
+        let old_id = self.disk_id();
+        let id = match id {
+            Some(id) => [id.first().cloned().unwrap_or(PADDING), id.get(1).cloned().unwrap_or(PADDING)],
+            None => old_id,
+        };
+
+        let empty = [0; SECTOR_SIZE];
+        for track in 1..=self.tracks() {
+            for sector in 0..layout.sectors_per_track(track) {
+                let _ = self.write_sector(track, sector, &empty);
+            }
+        }
+
+        let dir_track = layout.dir_track();
+        let mut directory = [0; SECTOR_SIZE];
+        directory[1] = 0xFF;
+        let _ = self.write_sector(dir_track, layout.first_dir_sector(), &directory);
+
+        let system_sectors = layout.system_sectors(name, id);
+        for &(track, sector, ref data) in &system_sectors {
+            let _ = self.write_sector(track, sector, data);
+        }
+
+        for track in 1..=layout.bam_tracks().min(self.tracks()) {
+            for sector in 0..layout.sectors_per_track(track) {
+                free(self, track, sector);
+            }
+        }
+
+        for &(track, sector, _) in &system_sectors {
+            allocate(self, track, sector);
+        }
+        allocate(self, dir_track, layout.first_dir_sector());
+
+        // the second directory track of a 1571 is kept free of files
+        if layout == Layout::Dos1571 && self.tracks() >= 53 {
+            for sector in 0..layout.sectors_per_track(53) {
+                allocate(self, 53, sector);
+            }
+        }
+    }
+
+
+    // PETSCII disk name without padding
+    fn disk_name(&self) -> Vec<u8> {
+        let offset = self.layout().name_offset();
+        header(self).map_or(Vec::new(), |h| unpad_name(&h[offset..offset + NAME_LEN]))
+    }
+
+
+    // the two id characters, which a 1541 also finds in every sector header
+    fn disk_id(&self) -> [u8; 2] {
+        let offset = self.layout().name_offset() + NAME_LEN + 2;
+        header(self).map_or([PADDING; 2], |h| [h[offset], h[offset + 1]])
+    }
+
+
+    // free blocks in the BAM, not counting the directory track
+    fn free_blocks(&self) -> u16 {
+        let layout = self.layout();
+        (1..=layout.bam_tracks()).filter(|t| *t != layout.dir_track())
+                                 .filter_map(|t| layout.bam_entry(t))
+                                 .map(|((track, sector, offset), _)| self.raw_sector(track, sector).map_or(0, |s| s[offset] as u16))
+                                 .sum()
+    }
+
+
+    // valid entries in the order of the directory
+    fn directory(&self) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+
+        for (track, sector) in directory_sectors(self) {
+            let data = match self.read_sector(track, sector) {
+                Ok(data) => data,
+                Err(_) => break,
+            };
+
+            for offset in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+                let entry = &data[offset..offset + ENTRY_SIZE];
+                if entry[2] == 0 {
+                    continue;
+                }
+
+                entries.push(DirEntry {
+                    name: unpad_name(&entry[5..5 + NAME_LEN]),
+                    file_type: FileType::from_u8(entry[2]),
+                    closed: entry[2] & 0x80 != 0,
+                    locked: entry[2] & 0x40 != 0,
+                    track: entry[3],
+                    sector: entry[4],
+                    blocks: (entry[0x1F] as u16) << 8 | entry[0x1E] as u16,
+                    location: (track, sector, offset),
+                });
+            }
+        }
+
+        entries
+    }
+
+
+    // first file with a name matching the pattern, in which '?' stands for any character and '*' for the rest of the name
+    fn find(&self, pattern: &[u8]) -> Option<DirEntry> {
+        self.directory().into_iter().find(|e| e.file_type != FileType::Del && matches_pattern(&e.name, pattern))
+    }
+
+
+    fn read_file(&self, entry: &DirEntry) -> Result<Vec<u8>, DosError> {
+        let mut data = Vec::new();
+        let (mut track, mut sector) = (entry.track, entry.sector);
+
+        // a chain can't be longer than the disk
+        for _ in 0..self.layout().total_sectors(self.tracks()) {
+            let block = self.read_sector(track, sector)?;
+
+            if block[0] == 0 {
+                // the last block tells the position of its last byte instead
+                let last = (block[1] as usize).max(1);
+                data.extend_from_slice(&block[2..=last]);
+                return Ok(data);
+            }
+
+            data.extend_from_slice(&block[2..]);
+            track = block[0];
+            sector = block[1];
+        }
+
+        Err(DosError::IllegalTrackSector)
+    }
+
+
+    // add a closed file to the directory; an existing one with the same name is replaced if asked to
+    fn write_file(&mut self, name: &[u8], file_type: FileType, data: &[u8], replace: bool) -> Result<(), DosError> {
+        let name = &name[..name.len().min(NAME_LEN)];
+        if let Some(existing) = self.directory().into_iter().find(|e| e.name == name) {
+            if !replace {
+                return Err(DosError::FileExists);
+            }
+            delete(self, &existing)?;
+        }
+
+        let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(SECTOR_SIZE - 2).collect() };
+        let (dir_track, dir_sector, offset) = free_entry(self)?;
+        let blocks = allocate_chain(self, chunks.len())?;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut block = [0; SECTOR_SIZE];
+            match blocks.get(i + 1) {
+                Some(&(track, sector)) => {
+                    block[0] = track;
+                    block[1] = sector;
+                },
+                None => block[1] = chunk.len() as u8 + 1,
+            }
+            block[2..2 + chunk.len()].copy_from_slice(chunk);
+            self.write_sector(blocks[i].0, blocks[i].1, &block)?;
+        }
+
+        let mut dir = self.read_sector(dir_track, dir_sector)?.to_vec();
+        let entry = &mut dir[offset..offset + ENTRY_SIZE];
+        entry[2..].copy_from_slice(&[0; ENTRY_SIZE - 2]);
+        entry[2] = 0x80 | file_type as u8;
+        entry[3] = blocks[0].0;
+        entry[4] = blocks[0].1;
+        entry[5..5 + NAME_LEN].copy_from_slice(&pad_name(name));
+        entry[0x1E] = blocks.len() as u8;
+        entry[0x1F] = (blocks.len() >> 8) as u8;
+        self.write_sector(dir_track, dir_sector, &dir)
+    }
+
+
+    // scratch all files matching the pattern; returns how many there were
+    fn scratch(&mut self, pattern: &[u8]) -> Result<u8, DosError> {
+        let entries: Vec<DirEntry> = self.directory().into_iter().filter(|e| !e.locked && matches_pattern(&e.name, pattern)).collect();
+
+        for entry in &entries {
+            delete(self, entry)?;
+        }
+
+        Ok(entries.len() as u8)
+    }
+
+
+    // the "$" file: a BASIC program listing the disk name, the matching files and the free blocks
+    fn listing(&self, pattern: &[u8]) -> Vec<u8> {
+        let mut program = vec![0x01, 0x04];
+        let offset = self.layout().name_offset();
+        let blank = [PADDING; SECTOR_SIZE];
+        let header = header(self).unwrap_or(&blank);
+
+        // reverse video header with the name, id and DOS type
+        let mut text = vec![0x12, b'"'];
+        text.extend(header[offset..offset + NAME_LEN].iter().map(|c| if *c == PADDING { b' ' } else { *c }));
+        text.extend_from_slice(b"\" ");
+        text.extend(header[offset + NAME_LEN + 2..offset + NAME_LEN + 7].iter().map(|c| if *c == PADDING { b' ' } else { *c }));
+        add_line(&mut program, 0, &text);
+
+        for entry in self.directory().iter().filter(|e| matches_pattern(&e.name, pattern)) {
+            let indent = match entry.blocks {
+                0..=9   => 3,
+                10..=99 => 2,
+                _       => 1,
+            };
+
+            let mut text = vec![b' '; indent];
+            text.push(b'"');
+            text.extend_from_slice(&entry.name);
+            text.push(b'"');
+            text.extend(vec![b' '; NAME_LEN - entry.name.len()]);
+            text.push(if entry.closed { b' ' } else { b'*' });
+            text.extend_from_slice(entry.file_type.name().as_bytes());
+            text.push(if entry.locked { b'<' } else { b' ' });
+            add_line(&mut program, entry.blocks, &text);
+        }
+
+        add_line(&mut program, self.free_blocks(), b"BLOCKS FREE.             ");
+        program.extend_from_slice(&[0, 0]);
+        program
+    }
+}
+
+
+// sectors in track order, optionally followed by an error byte per sector: how D64, D71 and D81 files store a disk
+pub struct Sectors {
+    layout: Layout,
+    tracks: u8,
+    data: Vec<u8>,
+    errors: Option<Vec<u8>>,
+    filename: Option<String>, // written back to on changes
+}
+
+impl Sectors {
+    pub fn new(layout: Layout, tracks: u8) -> Sectors {
+        Sectors {
+            layout,
+            tracks,
+            data: vec![0; layout.total_sectors(tracks) * SECTOR_SIZE],
+            errors: None,
+            filename: None,
+        }
+    }
+
+
+    // the size tells the number of tracks, of those that are possible, and whether there are error bytes
+    pub fn from_data(layout: Layout, track_counts: &[u8], mut data: Vec<u8>) -> Result<Sectors, String> {
+        let len = data.len();
+        let tracks = track_counts.iter().cloned()
+                                 .find(|t| [SECTOR_SIZE, SECTOR_SIZE + 1].iter().any(|size| layout.total_sectors(*t) * size == len))
+                                 .ok_or_else(|| format!("unexpected size of {} bytes", len))?;
+
+        let sectors = layout.total_sectors(tracks);
+        let errors = if len > sectors * SECTOR_SIZE { Some(data.split_off(sectors * SECTOR_SIZE)) } else { None };
+
+        Ok(Sectors {
+            layout,
+            tracks,
+            data,
+            errors,
+            filename: None,
+        })
+    }
+
+
+    // file sizes without and with error bytes
+    pub fn sizes(layout: Layout, tracks: u8) -> [usize; 2] {
+        let sectors = layout.total_sectors(tracks);
+        [sectors * SECTOR_SIZE, sectors * (SECTOR_SIZE + 1)]
+    }
+
+
+    pub fn tracks(&self) -> u8 {
+        self.tracks
+    }
+
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_ref().map(|f| &f[..])
+    }
+
+
+    pub fn set_filename(&mut self, filename: &str) {
+        self.filename = Some(filename.to_string());
+    }
+
+
+    pub fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        let index = self.layout.sector_index(self.tracks, track, sector)?;
+        Some(&self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE])
+    }
+
+
+    pub fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError> {
+        let index = self.layout.sector_index(self.tracks, track, sector).ok_or(DosError::IllegalTrackSector)?;
+        self.data[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE].copy_from_slice(&data[..SECTOR_SIZE]);
+
+        if let Some(ref mut errors) = self.errors {
+            errors[index] = 0x01;
+        }
+
+        Ok(())
+    }
+
+
+    pub fn sector_error(&self, track: u8, sector: u8) -> Option<DosError> {
+        let index = self.layout.sector_index(self.tracks, track, sector)?;
+
+        match self.errors {
+            Some(ref errors) => match errors[index] {
+                0x00 | 0x01 => None,
+                0x0B => Some(DosError::Read(29)),
+                // 2 to 9 stand for errors 20 to 27
+                code @ 0x02..=0x09 => Some(DosError::Read(code + 18)),
+                _ => Some(DosError::Read(20)),
+            },
+            None => None,
+        }
+    }
+
+
+    // error bytes are added to the image when the first error is set
+    pub fn set_sector_error(&mut self, track: u8, sector: u8, error: Option<DosError>) {
+        let index = match self.layout.sector_index(self.tracks, track, sector) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let code = match error {
+            None => 0x01,
+            Some(DosError::Read(29)) => 0x0B,
+            Some(DosError::Read(code)) if (20..=27).contains(&code) => code - 18,
+            Some(_) => 0x02,
+        };
+
+        if self.errors.is_none() {
+            if code == 0x01 {
+                return;
+            }
+            self.errors = Some(vec![0x01; self.data.len() / SECTOR_SIZE]);
+        }
+
+        if let Some(ref mut errors) = self.errors {
+            errors[index] = code;
+        }
+    }
+
+
+    pub fn save(&self) -> Result<(), Error> {
+        let filename = match self.filename {
+            Some(ref filename) => filename,
+            None => return Ok(()),
+        };
+
+        let io_error = |e| Error::Io(filename.clone(), e);
+        let mut file = File::create(filename).map_err(io_error)?;
+        file.write_all(&self.data).map_err(io_error)?;
+        if let Some(ref errors) = self.errors {
+            file.write_all(errors).map_err(io_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// disk image of any of the supported formats, told apart by size or signature
+pub fn open(filename: &str) -> Result<Box<dyn DiskImage>, Error> {
+    let data = utils::open_file(filename, 0)?;
+    let invalid = |e: String| Error::InvalidDisk(format!("Invalid disk image {}: {}", filename, e));
+
+    let mut image: Box<dyn DiskImage> = if data.starts_with(g64::SIGNATURE) {
+        Box::new(g64::G64::from_data(&data).map_err(invalid)?)
+    }
+    else if d71::SIZES.contains(&data.len()) {
+        Box::new(d71::D71::from_data(data).map_err(invalid)?)
+    }
+    else if d81::SIZES.contains(&data.len()) {
+        Box::new(d81::D81::from_data(data).map_err(invalid)?)
+    }
+    else {
+        Box::new(d64::D64::from_data(data).map_err(invalid)?)
+    };
+
+    image.set_filename(filename);
+    Ok(image)
+}
+
+
+// whether a file of this size starting with this header is one of the supported disk images
+pub fn is_disk_image(size: usize, header: &[u8]) -> bool {
+    header.starts_with(g64::SIGNATURE) || d64::SIZES.contains(&size) || d71::SIZES.contains(&size) || d81::SIZES.contains(&size)
+}
+
+
+pub fn matches_pattern(name: &[u8], pattern: &[u8]) -> bool {
+    for (i, p) in pattern.iter().enumerate() {
+        match *p {
+            b'*' => return true,
+            b'?' if i < name.len() => (),
+            c if i < name.len() && name[i] == c => (),
+            _ => return false,
+        }
+    }
+
+    pattern.len() == name.len()
+}
+
+
+// *** private functions *** //
+
+fn header<I: DiskImage + ?Sized>(image: &I) -> Option<&[u8]> {
+    image.raw_sector(image.layout().dir_track(), 0)
+}
+
+
+fn is_free<I: DiskImage + ?Sized>(image: &I, track: u8, sector: u8) -> bool {
+    match image.layout().bam_entry(track) {
+        Some((_, (t, s, offset))) => match image.raw_sector(t, s) {
+            Some(bam) => bam[offset + sector as usize / 8] & (1 << (sector % 8)) != 0,
+            None => false,
+        },
+        None => false,
+    }
+}
+
+
+// mark a block as used (or free) in the BAM, keeping the track's free count up to date
+fn set_used<I: DiskImage + ?Sized>(image: &mut I, track: u8, sector: u8, used: bool) {
+    if is_free(image, track, sector) != used {
+        return;
+    }
+
+    let ((count_track, count_sector, count_offset), (bits_track, bits_sector, bits_offset)) = match image.layout().bam_entry(track) {
+        Some(entry) => entry,
+        None => return,
+    };
+
+    if let Some(mut data) = image.raw_sector(count_track, count_sector).map(|s| s.to_vec()) {
+        data[count_offset] = if used { data[count_offset].wrapping_sub(1) } else { data[count_offset] + 1 };
+        let _ = image.write_sector(count_track, count_sector, &data);
+    }
+
+    if let Some(mut data) = image.raw_sector(bits_track, bits_sector).map(|s| s.to_vec()) {
+        data[bits_offset + sector as usize / 8] ^= 1 << (sector % 8);
+        let _ = image.write_sector(bits_track, bits_sector, &data);
+    }
+}
+
+
+fn allocate<I: DiskImage + ?Sized>(image: &mut I, track: u8, sector: u8) {
+    set_used(image, track, sector, true);
+}
+
+
+fn free<I: DiskImage + ?Sized>(image: &mut I, track: u8, sector: u8) {
+    set_used(image, track, sector, false);
+}
+
+
+// next free sector on a track, searching from the given one onwards
+fn free_sector<I: DiskImage + ?Sized>(image: &I, track: u8, from: u8) -> Option<u8> {
+    let sectors = image.layout().sectors_per_track(track);
+    (0..sectors).map(|i| (from + i) % sectors).find(|s| is_free(image, track, *s))
+}
+
+
+// blocks for a new file like the DOS picks them: starting next to the directory track and moving away from it,
+// with the sectors on each track interleaved
+fn allocate_chain<I: DiskImage + ?Sized>(image: &mut I, count: usize) -> Result<Vec<(u8, u8)>, DosError> {
+    let layout = image.layout();
+    let dir_track = layout.dir_track();
+    let last_track = layout.bam_tracks().min(image.tracks());
+    let mut blocks = Vec::new();
+
+    let first = (1..last_track).flat_map(|d| vec![dir_track.wrapping_sub(d), dir_track + d])
+                               .find(|t| *t >= 1 && *t <= last_track && free_sector(image, *t, 0).is_some())
+                               .ok_or(DosError::DiskFull)?;
+
+    let tracks: Vec<u8> = if first < dir_track {
+        (1..=first).rev().chain(dir_track + 1..=last_track).collect()
+    } else {
+        (first..=last_track).chain((1..dir_track).rev()).collect()
+    };
+
+    let mut sector = 0;
+    for track in tracks {
+        while blocks.len() < count {
+            match free_sector(image, track, sector) {
+                Some(s) => {
+                    allocate(image, track, s);
+                    blocks.push((track, s));
+                    sector = (s + layout.interleave().0) % layout.sectors_per_track(track);
+                },
+                None => break,
+            }
+        }
+        sector = 0;
+    }
+
+    if blocks.len() < count {
+        for &(track, sector) in &blocks {
+            free(image, track, sector);
+        }
+        return Err(DosError::DiskFull);
+    }
+
+    Ok(blocks)
+}
+
+
+fn directory_sectors<I: DiskImage + ?Sized>(image: &I) -> Vec<(u8, u8)> {
+    let mut sectors = Vec::new();
+    let layout = image.layout();
+    let (mut track, mut sector) = (layout.dir_track(), layout.first_dir_sector());
+
+    while track != 0 && !sectors.contains(&(track, sector)) {
+        sectors.push((track, sector));
+        match image.read_sector(track, sector) {
+            Ok(data) => {
+                track = data[0];
+                sector = data[1];
+            },
+            Err(_) => break,
+        }
+    }
+
+    sectors
+}
+
+
+// first unused directory slot, adding a directory sector if they're all taken
+fn free_entry<I: DiskImage + ?Sized>(image: &mut I) -> Result<Location, DosError> {
+    let sectors = directory_sectors(image);
+
+    for &(track, sector) in &sectors {
+        let data = image.read_sector(track, sector)?;
+        if let Some(offset) = (0..SECTOR_SIZE).step_by(ENTRY_SIZE).find(|o| data[o + 2] == 0) {
+            return Ok((track, sector, offset));
+        }
+    }
+
+    let layout = image.layout();
+    let dir_track = layout.dir_track();
+    let &(last_track, last_sector) = sectors.last().unwrap();
+    let next = (last_sector + layout.interleave().1) % layout.sectors_per_track(dir_track);
+    let sector = free_sector(image, dir_track, next).ok_or(DosError::DiskFull)?;
+    allocate(image, dir_track, sector);
+
+    let mut last = image.read_sector(last_track, last_sector)?.to_vec();
+    last[0] = dir_track;
+    last[1] = sector;
+    image.write_sector(last_track, last_sector, &last)?;
+
+    let mut data = [0; SECTOR_SIZE];
+    data[1] = 0xFF;
+    image.write_sector(dir_track, sector, &data)?;
+    Ok((dir_track, sector, 0))
+}
+
+
+// remove the entry from the directory and free its blocks
+fn delete<I: DiskImage + ?Sized>(image: &mut I, entry: &DirEntry) -> Result<(), DosError> {
+    let (track, sector, offset) = entry.location;
+    let mut dir = image.read_sector(track, sector)?.to_vec();
+    dir[offset + 2] = 0;
+    image.write_sector(track, sector, &dir)?;
+
+    let (mut track, mut sector) = (entry.track, entry.sector);
+    for _ in 0..image.layout().total_sectors(image.tracks()) {
+        let next = match image.read_sector(track, sector) {
+            Ok(block) => (block[0], block[1]),
+            Err(_) => break,
+        };
+
+        free(image, track, sector);
+        if next.0 == 0 {
+            break;
+        }
+        track = next.0;
+        sector = next.1;
+    }
+
+    Ok(())
+}
+
+
+fn pad_name(name: &[u8]) -> [u8; NAME_LEN] {
+    let mut padded = [PADDING; NAME_LEN];
+    for (i, c) in name.iter().take(NAME_LEN).enumerate() {
+        padded[i] = *c;
+    }
+    padded
+}
+
+
+fn unpad_name(name: &[u8]) -> Vec<u8> {
+    let len = name.iter().position(|c| *c == PADDING).unwrap_or(name.len());
+    name[..len].to_vec()
+}
+
+
+// BASIC line with a dummy link, which LOAD fixes up
+fn add_line(program: &mut Vec<u8>, number: u16, text: &[u8]) {
+    program.extend_from_slice(&[0x01, 0x01, number as u8, (number >> 8) as u8]);
+    program.extend_from_slice(text);
+    program.push(0);
+}
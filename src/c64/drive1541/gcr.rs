@@ -0,0 +1,243 @@
+// GCR encoding of 1541 disks: the bytes as they pass the read/write head, built from and decoded back into sectors
+use c64::d64;
+use c64::disk_image::{DiskImage, DosError};
+
+// 5-bit codes of the nybbles 0 to F; no code has more than two zeros in a row
+const GCR_CODES: [u8; 16] = [0x0A, 0x0B, 0x12, 0x13, 0x0E, 0x0F, 0x16, 0x17, 0x09, 0x19, 0x1A, 0x1B, 0x0D, 0x1D, 0x1E, 0x15];
+
+const SYNC_LEN: usize = 5;    // 0xFF bytes in front of every block
+const HEADER_GAP: usize = 9;  // between a sector header and its data block
+const HEADER_ID: u8 = 0x08;
+const DATA_ID: u8 = 0x07;
+const GAP_BYTE: u8 = 0x55;
+const HEADER_GCR_LEN: usize = 10; // 8 bytes
+const DATA_GCR_LEN: usize = 325;  // 260 bytes
+
+// GCR bytes per track in the speed zones 0 (outer tracks 31 and up) to 3 (tracks 1-17)
+const TRACK_SIZES: [usize; 4] = [6250, 6666, 7142, 7692];
+
+pub const HALF_TRACKS: usize = 84; // the head moves in half track steps, tracks 1 to 42
+
+
+// speed zone the DOS selects for a track
+pub fn speed_zone(track: u8) -> u8 {
+    match track {
+        1..=17  => 3,
+        18..=24 => 2,
+        25..=30 => 1,
+        _       => 0,
+    }
+}
+
+
+// 4 bytes become 5 GCR bytes
+pub fn encode(data: &[u8; 4]) -> [u8; 5] {
+    let mut bits: u64 = 0;
+    for byte in data {
+        bits = (bits << 10) | ((GCR_CODES[(byte >> 4) as usize] as u64) << 5) | GCR_CODES[(byte & 0x0F) as usize] as u64;
+    }
+
+    [(bits >> 32) as u8, (bits >> 24) as u8, (bits >> 16) as u8, (bits >> 8) as u8, bits as u8]
+}
+
+
+// 5 GCR bytes back into 4 bytes; None if any of the codes is invalid
+pub fn decode(gcr: &[u8; 5]) -> Option<[u8; 4]> {
+    let bits = gcr.iter().fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+    let mut data = [0; 4];
+
+    for (i, byte) in data.iter_mut().enumerate() {
+        let shift = 30 - i * 10;
+        let hi = decode_nybble((bits >> (shift + 5)) as u8 & 0x1F)?;
+        let lo = decode_nybble((bits >> shift) as u8 & 0x1F)?;
+        *byte = (hi << 4) | lo;
+    }
+
+    Some(data)
+}
+
+
+// the GCR tracks of a disk image, indexed by half track; the half tracks in between are empty
+pub fn encode_disk<I: DiskImage + ?Sized>(image: &I) -> Vec<Vec<u8>> {
+    let mut tracks = vec![Vec::new(); HALF_TRACKS];
+
+    for track in 1..=image.tracks() {
+        tracks[(track as usize - 1) * 2] = encode_track(image, track);
+    }
+
+    tracks
+}
+
+
+// write the sectors found on a GCR track back into the image; returns the number of sectors decoded
+pub fn decode_track<I: DiskImage + ?Sized>(gcr: &[u8], track: u8, image: &mut I) -> usize {
+    let sectors = decode_sectors(gcr, track);
+
+    for &(sector, ref data) in &sectors {
+        let _ = image.write_sector(track, sector, data);
+    }
+
+    sectors.len()
+}
+
+
+// the intact sectors on a GCR track, in the order they were found
+pub fn decode_sectors(gcr: &[u8], track: u8) -> Vec<(u8, Vec<u8>)> {
+    let mut sectors = Vec::new();
+    let mut pos = 0;
+
+    while let Some((sector, data_start)) = next_sector(gcr, track, pos) {
+        if let Some(data) = decode_block(gcr, data_start, DATA_GCR_LEN) {
+            let checksum = data[1..257].iter().fold(0, |sum, byte| sum ^ byte);
+            if data[0] == DATA_ID && data[257] == checksum && sector < d64::sectors_per_track(track) {
+                sectors.push((sector, data[1..257].to_vec()));
+            }
+        }
+
+        pos = data_start;
+    }
+
+    sectors
+}
+
+
+// replace the data block of a sector on a GCR track, like the DOS does: the header stays as it is;
+// returns false if the sector's header can't be found
+pub fn write_sector(gcr: &mut [u8], track: u8, sector: u8, data: &[u8]) -> bool {
+    let mut pos = 0;
+
+    while let Some((found, data_start)) = next_sector(gcr, track, pos) {
+        if found == sector && data_start + DATA_GCR_LEN <= gcr.len() {
+            let checksum = data[..256].iter().fold(0, |sum, byte| sum ^ byte);
+            let mut block = Vec::with_capacity(260);
+            block.push(DATA_ID);
+            block.extend_from_slice(&data[..256]);
+            block.extend_from_slice(&[checksum, 0x00, 0x00]);
+
+            let mut encoded = Vec::with_capacity(DATA_GCR_LEN);
+            encode_bytes(&block, &mut encoded);
+            gcr[data_start..data_start + DATA_GCR_LEN].copy_from_slice(&encoded);
+            return true;
+        }
+
+        pos = data_start;
+    }
+
+    false
+}
+
+
+// *** private functions *** //
+
+fn decode_nybble(code: u8) -> Option<u8> {
+    GCR_CODES.iter().position(|c| *c == code).map(|n| n as u8)
+}
+
+
+fn encode_bytes(data: &[u8], gcr: &mut Vec<u8>) {
+    for chunk in data.chunks(4) {
+        gcr.extend_from_slice(&encode(&[chunk[0], chunk[1], chunk[2], chunk[3]]));
+    }
+}
+
+
+// sector header and data block of every sector, with read errors from the image made real
+fn encode_track<I: DiskImage + ?Sized>(image: &I, track: u8) -> Vec<u8> {
+    let size = TRACK_SIZES[speed_zone(track) as usize];
+    let sectors = d64::sectors_per_track(track);
+    let id = image.disk_id();
+    let mut gcr = Vec::with_capacity(size);
+
+    // the gap after each sector spreads the sectors evenly around the track
+    let sector_len = 2 * SYNC_LEN + HEADER_GCR_LEN + HEADER_GAP + DATA_GCR_LEN;
+    let gap = (size - sectors as usize * sector_len) / sectors as usize;
+
+    // a track where no sector has a sync mark doesn't have any at all
+    let no_sync = (0..sectors).all(|s| image.sector_error(track, s) == Some(DosError::Read(21)));
+
+    for sector in 0..sectors {
+        let error = match image.sector_error(track, sector) {
+            Some(DosError::Read(code)) => code,
+            _ => 0,
+        };
+
+        let id = if error == 29 { [id[0] ^ 0xFF, id[1] ^ 0xFF] } else { id };
+        let mut header = [HEADER_ID, 0, sector, track, id[1], id[0], 0x0F, 0x0F];
+        header[1] = sector ^ track ^ id[1] ^ id[0] ^ if error == 27 { 0xFF } else { 0x00 };
+        if error == 20 {
+            header[0] = 0x00;
+        }
+
+        let data = image.raw_sector(track, sector).unwrap_or(&[0; 256]);
+        let checksum = data.iter().fold(0, |sum, byte| sum ^ byte) ^ if error == 23 { 0xFF } else { 0x00 };
+        let mut block = Vec::with_capacity(260);
+        block.push(if error == 22 { 0x00 } else { DATA_ID });
+        block.extend_from_slice(data);
+        block.extend_from_slice(&[checksum, 0x00, 0x00]);
+
+        let sync = if no_sync { GAP_BYTE } else { 0xFF };
+        gcr.extend_from_slice(&[sync; SYNC_LEN]);
+        encode_bytes(&header, &mut gcr);
+        gcr.extend_from_slice(&[GAP_BYTE; HEADER_GAP]);
+        gcr.extend_from_slice(&[sync; SYNC_LEN]);
+        encode_bytes(&block, &mut gcr);
+        let end = gcr.len() + gap;
+        gcr.resize(end, GAP_BYTE);
+    }
+
+    gcr.resize(size, GAP_BYTE);
+    gcr
+}
+
+
+// number of the next sector on the track with a valid header, and the start of its data block
+fn next_sector(gcr: &[u8], track: u8, pos: usize) -> Option<(u8, usize)> {
+    let mut pos = pos;
+
+    // a sector header followed by its data block, each behind a sync mark
+    while let Some(header_start) = next_block(gcr, pos) {
+        pos = header_start;
+        let header = match decode_block(gcr, header_start, HEADER_GCR_LEN) {
+            Some(header) => header,
+            None => continue,
+        };
+
+        if header[0] != HEADER_ID || header[3] != track {
+            continue;
+        }
+
+        return next_block(gcr, header_start + HEADER_GCR_LEN).map(|start| (header[2], start));
+    }
+
+    None
+}
+
+
+// start of the first block behind a sync mark at or after pos
+fn next_block(gcr: &[u8], pos: usize) -> Option<usize> {
+    let mut pos = pos;
+
+    while pos + 1 < gcr.len() {
+        if gcr[pos] == 0xFF && gcr[pos + 1] == 0xFF {
+            let start = (pos..gcr.len()).find(|i| gcr[*i] != 0xFF)?;
+            return Some(start);
+        }
+        pos += 1;
+    }
+
+    None
+}
+
+
+fn decode_block(gcr: &[u8], start: usize, len: usize) -> Option<Vec<u8>> {
+    if start + len > gcr.len() {
+        return None;
+    }
+
+    let mut data = Vec::with_capacity(len / 5 * 4);
+    for chunk in gcr[start..start + len].chunks(5) {
+        data.extend_from_slice(&decode(&[chunk[0], chunk[1], chunk[2], chunk[3], chunk[4]])?);
+    }
+
+    Some(data)
+}
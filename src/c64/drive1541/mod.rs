@@ -0,0 +1,372 @@
+// true drive emulation: a 1541 with its own 6502, two VIAs and the DOS ROM, connected to the C64's
+// serial bus through CIA2 and running in lockstep with the C64 clock
+pub mod gcr;
+pub mod via;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use c64::bus;
+use c64::cia;
+use c64::cpu;
+use c64::disk_image::DiskImage;
+use c64::roms;
+use c64::snapshot;
+use std::io;
+use std::io::{Read, Write};
+
+pub const CLOCK_FREQ: u32 = 1_000_000;
+const RAM_SIZE: usize = 0x0800;
+
+// VIA1 port B: serial bus
+const DATA_IN:  u8 = 0x01;
+const DATA_OUT: u8 = 0x02;
+const CLK_IN:   u8 = 0x04;
+const CLK_OUT:  u8 = 0x08;
+const ATN_ACK:  u8 = 0x10; // ATN auto acknowledge: DATA is pulled while ATN doesn't match it
+const ATN_IN:   u8 = 0x80; // bits 5 and 6 are the device number jumpers, both open for device 8
+
+// VIA2 port B: disk controller
+const STEPPER: u8 = 0x03;
+const MOTOR:   u8 = 0x04;
+const LED:     u8 = 0x08;
+const WRITE_ENABLE: u8 = 0x10; // write protect sense, low if the notch is covered
+const SYNC:    u8 = 0x80;      // low while the head is over a sync mark
+
+// CIA2 port A outputs, which pull their lines low through inverters
+const C64_ATN_OUT:  u8 = 0x08;
+const C64_CLK_OUT:  u8 = 0x10;
+const C64_DATA_OUT: u8 = 0x20;
+
+
+// what the drive CPU sees: 2K of RAM, the VIAs and the DOS ROM, all of them mirrored
+pub struct DriveBus {
+    pub ram:  Vec<u8>,
+    pub rom:  Vec<u8>,
+    pub via1: via::VIA, // serial bus
+    pub via2: via::VIA, // disk controller
+}
+
+impl bus::Bus for DriveBus {
+    fn write_byte(&mut self, addr: u16, value: u8, _on_write: &mut cpu::Callback) -> bool {
+        match addr & 0x9C00 {
+            0x0000 | 0x0400 => self.ram[addr as usize & (RAM_SIZE - 1)] = value,
+            0x1800 => self.via1.write_register(addr, value),
+            0x1C00 => self.via2.write_register(addr, value),
+            _ => (),
+        }
+
+        true
+    }
+
+
+    fn read_byte(&mut self, addr: u16, _on_read: &mut cpu::Callback) -> u8 {
+        match addr & 0x9C00 {
+            0x0000 | 0x0400 => self.ram[addr as usize & (RAM_SIZE - 1)],
+            0x1800 => self.via1.read_register(addr),
+            0x1C00 => self.via2.read_register(addr),
+            _ if addr & 0x8000 != 0 => self.rom[addr as usize & (roms::DRIVE_SIZE - 1)],
+            _ => (addr >> 8) as u8, // nothing there, the high address byte is what's left on the bus
+        }
+    }
+
+
+    fn read_word_le(&mut self, addr: u16) -> u16 {
+        let mut on_read = cpu::Callback::None;
+        let lo = self.read_byte(addr, &mut on_read) as u16;
+        let hi = self.read_byte(addr.wrapping_add(1), &mut on_read) as u16;
+        (hi << 8) | lo
+    }
+}
+
+
+pub struct Drive1541 {
+    cpu: cpu::CPU,
+    bus: DriveBus,
+    image: Option<Box<dyn DiskImage>>,
+    tracks: Vec<Vec<u8>>, // GCR data of the disk by half track, empty without a disk
+    modified: bool,       // tracks were written since the image was last updated
+    half_track: usize,    // head position, 0 is track 1
+    head_pos: usize,      // byte of the track under the head
+    byte_cycles: u8,      // cycles until the next byte passes the head
+    last_byte: u8,
+    sync: bool,           // the head is over a sync mark
+    stepper: u8,          // stepper motor phase
+    motor: bool,
+    clock_acc: u32,       // drive clock fraction, in C64 clock units
+}
+
+impl Drive1541 {
+    // the ROM must be roms::DRIVE_SIZE bytes
+    pub fn new(rom: Vec<u8>) -> Drive1541 {
+        let mut drive = Drive1541 {
+            cpu: cpu::CPU::new(),
+            bus: DriveBus {
+                ram:  vec![0; RAM_SIZE],
+                rom,
+                via1: via::VIA::new(),
+                via2: via::VIA::new(),
+            },
+            image: None,
+            tracks: Vec::new(),
+            modified: false,
+            half_track: 34, // track 18
+            head_pos: 0,
+            byte_cycles: 0,
+            last_byte: 0,
+            sync: false,
+            stepper: 0,
+            motor: false,
+            clock_acc: 0,
+        };
+
+        drive.reset();
+        drive
+    }
+
+
+    // shares the C64's reset line through the serial bus
+    pub fn reset(&mut self) {
+        self.bus.via1.reset();
+        self.bus.via2.reset();
+        self.cpu.reset(&mut self.bus);
+        self.clock_acc = 0;
+    }
+
+
+    // a disk of a 1571 or 1581 goes in, but a 1541 can't find anything on it
+    pub fn attach(&mut self, image: Box<dyn DiskImage>) {
+        self.detach();
+        self.tracks = image.gcr_tracks().unwrap_or_default();
+        self.image = Some(image);
+    }
+
+
+    // the image comes back with everything written to the disk
+    pub fn detach(&mut self) -> Option<Box<dyn DiskImage>> {
+        self.flush();
+        self.tracks.clear();
+        self.image.take()
+    }
+
+
+    pub fn image(&self) -> Option<&dyn DiskImage> {
+        self.image.as_deref()
+    }
+
+
+    pub fn cpu(&self) -> &cpu::CPU {
+        &self.cpu
+    }
+
+
+    pub fn bus(&mut self) -> &mut DriveBus {
+        &mut self.bus
+    }
+
+
+    // track under the head, in half tracks: 1.0, 1.5, 2.0...
+    pub fn track(&self) -> f32 {
+        1.0 + self.half_track as f32 / 2.0
+    }
+
+
+    pub fn led_on(&self) -> bool {
+        self.bus.via2.port_b_out() & LED != 0
+    }
+
+
+    // run the drive for one cycle of a C64 clocked at c64_freq, exchanging the serial bus lines with CIA2
+    pub fn update(&mut self, cia2: &mut cia::CIA, c64_freq: u32) {
+        self.clock_acc += CLOCK_FREQ;
+
+        while self.clock_acc >= c64_freq {
+            self.clock_acc -= c64_freq;
+            self.set_bus_inputs(cia2.port_a_out());
+            self.bus.via1.update();
+            self.bus.via2.update();
+            self.rotate_disk();
+
+            // both VIAs share the IRQ line
+            self.cpu.set_cia_irq(self.bus.via1.irq() || self.bus.via2.irq());
+            self.cpu.update(&mut self.bus);
+            self.move_head();
+        }
+
+        let (clk, data) = self.bus_lines(cia2.port_a_out());
+        cia2.set_iec_inputs(!clk, !data);
+    }
+
+
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.cpu.save_state(w)?;
+        w.write_all(&self.bus.ram)?;
+        self.bus.via1.save_state(w)?;
+        self.bus.via2.save_state(w)?;
+        w.write_all(&[self.half_track as u8, self.byte_cycles, self.last_byte, self.stepper])?;
+        w.write_u16::<LittleEndian>(self.head_pos as u16)?;
+        snapshot::write_bools(w, &[self.sync, self.motor])?;
+        w.write_u32::<LittleEndian>(self.clock_acc)
+    }
+
+
+    // the disk isn't part of the state, so it's whatever is in the drive right now
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.cpu.load_state(r)?;
+        r.read_exact(&mut self.bus.ram)?;
+        self.bus.via1.load_state(r)?;
+        self.bus.via2.load_state(r)?;
+
+        let mut head = [0; 4];
+        r.read_exact(&mut head)?;
+        if head[0] as usize >= gcr::HALF_TRACKS {
+            return Err(snapshot::invalid_data("Invalid drive head position"));
+        }
+        self.half_track  = head[0] as usize;
+        self.byte_cycles = head[1];
+        self.last_byte   = head[2];
+        self.stepper     = head[3];
+        self.head_pos  = r.read_u16::<LittleEndian>()? as usize;
+        let mut flags = [false; 2];
+        snapshot::read_bools(r, &mut flags)?;
+        self.sync  = flags[0];
+        self.motor = flags[1];
+        self.clock_acc = r.read_u32::<LittleEndian>()?;
+        Ok(())
+    }
+
+
+    // *** private functions *** //
+
+    // CLK and DATA, true if pulled low by either side
+    fn bus_lines(&self, c64_out: u8) -> (bool, bool) {
+        let drive_out = self.bus.via1.port_b_out();
+        let atn = c64_out & C64_ATN_OUT != 0;
+        let atn_ack = (drive_out & ATN_ACK != 0) != atn;
+
+        let clk  = c64_out & C64_CLK_OUT != 0 || drive_out & CLK_OUT != 0;
+        let data = c64_out & C64_DATA_OUT != 0 || drive_out & DATA_OUT != 0 || atn_ack;
+        (clk, data)
+    }
+
+
+    // the VIA1 inputs see the lines inverted, so a pulled line reads as 1
+    fn set_bus_inputs(&mut self, c64_out: u8) {
+        let (clk, data) = self.bus_lines(c64_out);
+        let atn = c64_out & C64_ATN_OUT != 0;
+
+        let mut inputs = 0;
+        if data { inputs |= DATA_IN; }
+        if clk  { inputs |= CLK_IN; }
+        if atn  { inputs |= ATN_IN; }
+        self.bus.via1.port_b_in = inputs;
+        self.bus.via1.set_ca1(atn);
+    }
+
+
+    // the disk passes the head one byte at a time, faster on the outer tracks; every byte that isn't
+    // part of a sync mark signals byte ready through the CPU's overflow flag and CA1
+    fn rotate_disk(&mut self) {
+        let mut inputs = SYNC;
+        if self.image.is_some() {
+            inputs |= WRITE_ENABLE;
+        }
+
+        if !self.motor || self.tracks.is_empty() || self.tracks[self.half_track].is_empty() {
+            self.sync = false;
+            self.bus.via2.port_b_in = inputs;
+            return;
+        }
+
+        if self.byte_cycles > 0 {
+            self.byte_cycles -= 1;
+            if self.sync {
+                inputs &= !SYNC;
+            }
+            self.bus.via2.port_b_in = inputs;
+            return;
+        }
+
+        let zone = (self.bus.via2.port_b_out() >> 5) & 0x03;
+        self.byte_cycles = 32 - 2 * zone - 1;
+
+        let track = &mut self.tracks[self.half_track];
+        self.head_pos = (self.head_pos + 1) % track.len();
+        let byte = if self.bus.via2.cb2_out() {
+            track[self.head_pos]
+        }
+        else {
+            // CB2 low is write mode: the byte latched in port A goes onto the disk
+            track[self.head_pos] = self.bus.via2.port_a_out();
+            self.modified = true;
+            0x00
+        };
+
+        self.sync = byte == 0xFF && self.last_byte == 0xFF;
+        self.last_byte = byte;
+
+        if self.sync {
+            inputs &= !SYNC;
+        }
+        else {
+            self.bus.via2.port_a_in = byte;
+            if self.bus.via2.ca2_out() {
+                self.cpu.set_status_flag(cpu::StatusFlag::Overflow, true);
+            }
+            self.bus.via2.set_ca1(false);
+            self.bus.via2.set_ca1(true);
+        }
+
+        self.bus.via2.port_b_in = inputs;
+    }
+
+
+    // the stepper moves the head half a track for each phase step; turning the motor off
+    // is when written tracks are put back into the image
+    fn move_head(&mut self) {
+        let controls = self.bus.via2.port_b_out();
+
+        let phase = controls & STEPPER;
+        if phase == (self.stepper + 1) & STEPPER && self.half_track + 1 < gcr::HALF_TRACKS {
+            self.step_to(self.half_track + 1);
+        }
+        else if phase == self.stepper.wrapping_sub(1) & STEPPER && self.half_track > 0 {
+            self.step_to(self.half_track - 1);
+        }
+        self.stepper = phase;
+
+        let motor = controls & MOTOR != 0;
+        if self.motor && !motor {
+            self.flush();
+        }
+        self.motor = motor;
+    }
+
+
+    // the angle of the disk stays the same when the head moves to a track of a different length
+    fn step_to(&mut self, half_track: usize) {
+        if !self.tracks.is_empty() {
+            let (old_len, new_len) = (self.tracks[self.half_track].len(), self.tracks[half_track].len());
+            if old_len > 0 && new_len > 0 {
+                self.head_pos = self.head_pos * new_len / old_len;
+            }
+        }
+
+        self.half_track = half_track;
+    }
+
+
+    // put the written tracks back into the image and save it to its file
+    fn flush(&mut self) {
+        if !self.modified {
+            return;
+        }
+        self.modified = false;
+
+        if let Some(ref mut image) = self.image {
+            image.write_gcr_tracks(&self.tracks);
+
+            if let Err(e) = image.save() {
+                println!("Couldn't write the disk back: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// MOS 6522 VIA: two 8-bit ports with handshake lines, two 16-bit timers and the interrupt logic
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use c64::snapshot;
+
+// interrupt flags
+pub const IRQ_CA2: u8 = 0x01;
+pub const IRQ_CA1: u8 = 0x02;
+pub const IRQ_SR:  u8 = 0x04;
+pub const IRQ_CB2: u8 = 0x08;
+pub const IRQ_CB1: u8 = 0x10;
+pub const IRQ_T2:  u8 = 0x20;
+pub const IRQ_T1:  u8 = 0x40;
+
+
+pub struct VIA {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool,  // one-shot timer 1 hasn't fired yet
+    t1_reload: bool, // free running timer 1 reloads in the cycle after its underflow
+    t2_counter: u16,
+    t2_latch_lo: u8,
+    t2_armed: bool,
+    sr: u8,
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+    ca1: bool,
+
+    // levels driven onto the port pins from outside, read wherever the pin is an input
+    pub port_a_in: u8,
+    pub port_b_in: u8,
+}
+
+impl VIA {
+    pub fn new() -> VIA {
+        VIA {
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            t1_reload: false,
+            t2_counter: 0xFFFF,
+            t2_latch_lo: 0xFF,
+            t2_armed: false,
+            sr: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+            ca1: true,
+            port_a_in: 0xFF,
+            port_b_in: 0xFF,
+        }
+    }
+
+
+    // the reset line clears the registers but not the timers and the shift register
+    pub fn reset(&mut self) {
+        self.ora = 0;
+        self.orb = 0;
+        self.ddra = 0;
+        self.ddrb = 0;
+        self.t1_armed = false;
+        self.t1_reload = false;
+        self.t2_armed = false;
+        self.acr = 0;
+        self.pcr = 0;
+        self.ifr = 0;
+        self.ier = 0;
+    }
+
+
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self.ora, self.orb, self.ddra, self.ddrb])?;
+        w.write_u16::<LittleEndian>(self.t1_counter)?;
+        w.write_u16::<LittleEndian>(self.t1_latch)?;
+        snapshot::write_bool(w, self.t1_armed)?;
+        snapshot::write_bool(w, self.t1_reload)?;
+        w.write_u16::<LittleEndian>(self.t2_counter)?;
+        w.write_all(&[self.t2_latch_lo])?;
+        snapshot::write_bool(w, self.t2_armed)?;
+        w.write_all(&[self.sr, self.acr, self.pcr, self.ifr, self.ier])?;
+        snapshot::write_bool(w, self.ca1)?;
+        w.write_all(&[self.port_a_in, self.port_b_in])
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut ports = [0; 4];
+        r.read_exact(&mut ports)?;
+        self.ora  = ports[0];
+        self.orb  = ports[1];
+        self.ddra = ports[2];
+        self.ddrb = ports[3];
+        self.t1_counter = r.read_u16::<LittleEndian>()?;
+        self.t1_latch   = r.read_u16::<LittleEndian>()?;
+        self.t1_armed   = snapshot::read_bool(r)?;
+        self.t1_reload  = snapshot::read_bool(r)?;
+        self.t2_counter  = r.read_u16::<LittleEndian>()?;
+        self.t2_latch_lo = r.read_u8()?;
+        self.t2_armed    = snapshot::read_bool(r)?;
+
+        let mut regs = [0; 5];
+        r.read_exact(&mut regs)?;
+        self.sr  = regs[0];
+        self.acr = regs[1];
+        self.pcr = regs[2];
+        self.ifr = regs[3];
+        self.ier = regs[4];
+        self.ca1 = snapshot::read_bool(r)?;
+        self.port_a_in = r.read_u8()?;
+        self.port_b_in = r.read_u8()?;
+        Ok(())
+    }
+
+
+    // count down the timers by one cycle
+    pub fn update(&mut self) {
+        if self.t1_reload {
+            self.t1_counter = self.t1_latch;
+            self.t1_reload = false;
+        }
+        else {
+            self.t1_counter = self.t1_counter.wrapping_sub(1);
+            if self.t1_counter == 0xFFFF {
+                let free_running = self.acr & 0x40 != 0;
+                if self.t1_armed {
+                    self.ifr |= IRQ_T1;
+                    self.t1_armed = free_running;
+                }
+                self.t1_reload = free_running;
+            }
+        }
+
+        // in pulse counting mode timer 2 counts PB6 edges, which aren't connected in the 1541
+        if self.acr & 0x20 == 0 {
+            self.t2_counter = self.t2_counter.wrapping_sub(1);
+            if self.t2_counter == 0xFFFF && self.t2_armed {
+                self.ifr |= IRQ_T2;
+                self.t2_armed = false;
+            }
+        }
+    }
+
+
+    pub fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+
+
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        match addr & 0x000F {
+            0x00 => {
+                self.ifr &= !(IRQ_CB1 | IRQ_CB2);
+                (self.orb & self.ddrb) | (self.port_b_in & !self.ddrb)
+            },
+            0x01 => {
+                self.ifr &= !(IRQ_CA1 | IRQ_CA2);
+                (self.ora & self.ddra) | (self.port_a_in & !self.ddra)
+            },
+            0x02 => self.ddrb,
+            0x03 => self.ddra,
+            0x04 => {
+                self.ifr &= !IRQ_T1;
+                self.t1_counter as u8
+            },
+            0x05 => (self.t1_counter >> 8) as u8,
+            0x06 => self.t1_latch as u8,
+            0x07 => (self.t1_latch >> 8) as u8,
+            0x08 => {
+                self.ifr &= !IRQ_T2;
+                self.t2_counter as u8
+            },
+            0x09 => (self.t2_counter >> 8) as u8,
+            0x0A => self.sr,
+            0x0B => self.acr,
+            0x0C => self.pcr,
+            0x0D => if self.irq() { self.ifr | 0x80 } else { self.ifr },
+            0x0E => self.ier | 0x80,
+            _    => (self.ora & self.ddra) | (self.port_a_in & !self.ddra), // 0x0F: port A without handshake
+        }
+    }
+
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr & 0x000F {
+            0x00 => {
+                self.ifr &= !(IRQ_CB1 | IRQ_CB2);
+                self.orb = value;
+            },
+            0x01 => {
+                self.ifr &= !(IRQ_CA1 | IRQ_CA2);
+                self.ora = value;
+            },
+            0x02 => self.ddrb = value,
+            0x03 => self.ddra = value,
+            0x04 | 0x06 => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            0x05 => {
+                // writing the high byte starts timer 1
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.ifr &= !IRQ_T1;
+            },
+            0x07 => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.ifr &= !IRQ_T1;
+            },
+            0x08 => self.t2_latch_lo = value,
+            0x09 => {
+                self.t2_counter = ((value as u16) << 8) | self.t2_latch_lo as u16;
+                self.t2_armed = true;
+                self.ifr &= !IRQ_T2;
+            },
+            0x0A => {
+                self.sr = value;
+                self.ifr &= !IRQ_SR;
+            },
+            0x0B => self.acr = value,
+            0x0C => self.pcr = value,
+            0x0D => self.ifr &= !value,
+            0x0E => {
+                if value & 0x80 != 0 { self.ier |= value & 0x7F; }
+                else                 { self.ier &= !value; }
+            },
+            _    => self.ora = value, // 0x0F
+        }
+    }
+
+
+    // port levels as seen from outside: pins set up as inputs are pulled high
+    pub fn port_a_out(&self) -> u8 {
+        self.ora | !self.ddra
+    }
+
+
+    pub fn port_b_out(&self) -> u8 {
+        self.orb | !self.ddrb
+    }
+
+
+    // CA1 is edge triggered, PCR bit 0 selects a rising (1) or falling (0) edge
+    pub fn set_ca1(&mut self, level: bool) {
+        if level != self.ca1 && level == (self.pcr & 0x01 != 0) {
+            self.ifr |= IRQ_CA1;
+        }
+
+        self.ca1 = level;
+    }
+
+
+    // CA2 and CB2 as outputs: only the manual modes drive them low
+    pub fn ca2_out(&self) -> bool {
+        self.pcr & 0x0E != 0x0C
+    }
+
+
+    pub fn cb2_out(&self) -> bool {
+        self.pcr & 0xE0 != 0xC0
+    }
+}
+
+impl Default for VIA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
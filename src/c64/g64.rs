@@ -0,0 +1,228 @@
+// G64 disk images: the raw GCR bytes of each half track, so copy protections and custom formats survive
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use c64::d64;
+use c64::disk_image::{DiskImage, DosError, Layout, Sectors};
+use c64::drive1541::gcr;
+use error::Error;
+use utils;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+pub const SIGNATURE: &[u8; 8] = b"GCR-1541";
+const MAX_TRACK_SIZE: u16 = 7928; // what the common tools write, leaving room above the 7692 bytes of zone 3
+
+
+pub struct G64 {
+    tracks: Vec<Vec<u8>>, // GCR bytes by half track, empty where there's no track
+    speeds: Vec<u8>,      // speed zone of each half track
+    max_track_size: u16,
+    sectors: Sectors,     // what the DOS finds on the tracks
+    filename: Option<String>,
+}
+
+impl G64 {
+    pub fn from_filename(filename: &str) -> Result<G64, Error> {
+        let data = utils::open_file(filename, 0)?;
+        let mut image = G64::from_data(&data).map_err(|e| Error::InvalidDisk(format!("Invalid disk image {}: {}", filename, e)))?;
+        image.set_filename(filename);
+        Ok(image)
+    }
+
+
+    pub fn from_data(data: &[u8]) -> Result<G64, String> {
+        let mut file = Cursor::new(data);
+        // the whole file is in memory already, so a failed read means it's cut short
+        let truncated = |_| "file is truncated".to_string();
+
+        let mut signature = [0u8; 8];
+        file.read_exact(&mut signature).map_err(truncated)?;
+        if &signature != SIGNATURE {
+            return Err("invalid signature".to_string())
+        }
+        let version = file.read_u8().map_err(truncated)?;
+        if version != 0 {
+            return Err(format!("unsupported version {}", version))
+        }
+        let half_tracks = file.read_u8().map_err(truncated)? as usize;
+        let max_track_size = file.read_u16::<LittleEndian>().map_err(truncated)?;
+
+        let mut offsets = Vec::with_capacity(half_tracks);
+        for _ in 0..half_tracks {
+            offsets.push(file.read_u32::<LittleEndian>().map_err(truncated)?);
+        }
+
+        // speeds above 3 point to a map of the speed of each byte, which a 1541 can't make use of
+        let mut speeds = vec![0; gcr::HALF_TRACKS];
+        for (i, speed) in speeds.iter_mut().enumerate().take(half_tracks) {
+            let value = file.read_u32::<LittleEndian>().map_err(truncated)?;
+            *speed = if value <= 3 { value as u8 } else { gcr::speed_zone(i as u8 / 2 + 1) };
+        }
+
+        let mut tracks = vec![Vec::new(); gcr::HALF_TRACKS];
+        for (i, offset) in offsets.into_iter().enumerate().take(gcr::HALF_TRACKS) {
+            if offset == 0 {
+                continue;
+            }
+
+            file.seek(SeekFrom::Start(offset as u64)).map_err(truncated)?;
+            let len = file.read_u16::<LittleEndian>().map_err(truncated)?;
+            let mut track = vec![0; len as usize];
+            file.read_exact(&mut track).map_err(truncated)?;
+            tracks[i] = track;
+        }
+
+        let mut image = G64 {
+            tracks,
+            speeds,
+            max_track_size,
+            sectors: Sectors::new(Layout::Dos1541, 0),
+            filename: None,
+        };
+        image.decode();
+        Ok(image)
+    }
+
+
+    // the GCR tracks a 1541 would write for a disk, which must fit into one
+    pub fn from_disk<I: DiskImage + ?Sized>(image: &I) -> Option<G64> {
+        let tracks = image.gcr_tracks()?;
+        let speeds = (0..gcr::HALF_TRACKS).map(|i| gcr::speed_zone(i as u8 / 2 + 1)).collect();
+
+        let mut image = G64 {
+            tracks,
+            speeds,
+            max_track_size: MAX_TRACK_SIZE,
+            sectors: Sectors::new(Layout::Dos1541, 0),
+            filename: None,
+        };
+        image.decode();
+        Some(image)
+    }
+
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let half_tracks = self.tracks.len();
+        let mut data = SIGNATURE.to_vec();
+        data.push(0);
+        data.push(half_tracks as u8);
+        data.write_u16::<LittleEndian>(self.max_track_size).unwrap();
+
+        // tracks follow the two tables, each in a slot of the maximum size
+        let slot = 2 + self.max_track_size as usize;
+        let mut offset = data.len() + 8 * half_tracks;
+        for track in &self.tracks {
+            let value = if track.is_empty() { 0 } else { offset };
+            data.write_u32::<LittleEndian>(value as u32).unwrap();
+            if !track.is_empty() {
+                offset += slot;
+            }
+        }
+
+        for speed in &self.speeds {
+            data.write_u32::<LittleEndian>(*speed as u32).unwrap();
+        }
+
+        for track in self.tracks.iter().filter(|t| !t.is_empty()) {
+            let len = track.len().min(self.max_track_size as usize);
+            data.write_u16::<LittleEndian>(len as u16).unwrap();
+            data.extend_from_slice(&track[..len]);
+            let end = data.len() + self.max_track_size as usize - len;
+            data.resize(end, 0);
+        }
+
+        data
+    }
+
+
+    // *** private functions *** //
+
+    // read the sectors off all full tracks, leaving those that can't be found damaged
+    fn decode(&mut self) {
+        let tracks = self.tracks.iter().rposition(|t| !t.is_empty()).map_or(0, |i| i / 2 + 1).max(35) as u8;
+        self.sectors = Sectors::new(Layout::Dos1541, tracks);
+
+        for track in 1..=tracks {
+            for sector in 0..d64::sectors_per_track(track) {
+                self.sectors.set_sector_error(track, sector, Some(DosError::Read(20)));
+            }
+
+            for (sector, data) in gcr::decode_sectors(&self.tracks[(track as usize - 1) * 2], track) {
+                let _ = self.sectors.write_sector(track, sector, &data);
+            }
+        }
+    }
+}
+
+impl DiskImage for G64 {
+    fn layout(&self) -> Layout {
+        Layout::Dos1541
+    }
+
+
+    fn tracks(&self) -> u8 {
+        self.sectors.tracks()
+    }
+
+
+    fn raw_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        self.sectors.raw_sector(track, sector)
+    }
+
+
+    // the new data goes into the sector's data block on the GCR track
+    fn write_sector(&mut self, track: u8, sector: u8, data: &[u8]) -> Result<(), DosError> {
+        let half_track = (track as usize).max(1) * 2 - 2;
+        let written = match self.tracks.get_mut(half_track) {
+            Some(gcr) => gcr::write_sector(gcr, track, sector, data),
+            None => false,
+        };
+
+        if !written {
+            return Err(self.sectors.sector_error(track, sector).unwrap_or(DosError::IllegalTrackSector));
+        }
+
+        self.sectors.write_sector(track, sector, data)
+    }
+
+
+    fn sector_error(&self, track: u8, sector: u8) -> Option<DosError> {
+        self.sectors.sector_error(track, sector)
+    }
+
+
+    fn filename(&self) -> Option<&str> {
+        self.filename.as_ref().map(|f| &f[..])
+    }
+
+
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = Some(filename.to_string());
+    }
+
+
+    fn save(&self) -> Result<(), Error> {
+        let filename = match self.filename {
+            Some(ref filename) => filename,
+            None => return Ok(()),
+        };
+
+        let io_error = |e| Error::Io(filename.clone(), e);
+        let mut file = File::create(filename).map_err(io_error)?;
+        file.write_all(&self.to_data()).map_err(io_error)
+    }
+
+
+    fn gcr_tracks(&self) -> Option<Vec<Vec<u8>>> {
+        Some(self.tracks.clone())
+    }
+
+
+    fn write_gcr_tracks(&mut self, tracks: &[Vec<u8>]) {
+        for (track, gcr) in self.tracks.iter_mut().zip(tracks) {
+            track.clone_from(gcr);
+        }
+
+        self.decode();
+    }
+}
@@ -35,7 +35,7 @@ impl IO {
     }
 
 
-    pub fn update(&mut self, window: &Window, cia1: &mut cia::CIAShared) {
+    pub fn update(&mut self, window: &Window, cia1: &mut cia::CIA) {
         // keyboard processing
         // iterating over all keys is crawling-slow, so check individual keys
         self.process_key(window.is_key_down(Key::Key0), Key::Key0, cia1);
@@ -129,10 +129,10 @@ impl IO {
             self.joy_port1 = !self.joy_port1;
 
             if self.joy_port1 {
-                cia1.borrow_mut().joystick_2 = 0xFF;
+                cia1.joystick_2 = 0xFF;
             }
             else {
-                cia1.borrow_mut().joystick_1 = 0xFF;
+                cia1.joystick_1 = 0xFF;
             }
             
             println!("Using joystick in port {}", if self.joy_port1 { "1" } else { "2" });
@@ -140,6 +140,18 @@ impl IO {
     }
 
 
+    // feed a key state change without a window (host key codes are mapped just as in update())
+    pub fn set_key(&mut self, keycode: Key, pressed: bool, cia1: &mut cia::CIA) {
+        self.process_key(pressed, keycode, cia1);
+    }
+
+
+    // feed a joystick state change without a window (num-pad directions and RightCtrl for fire)
+    pub fn set_joystick(&mut self, keycode: Key, pressed: bool, cia1: &mut cia::CIA) {
+        self.process_joystick(pressed, keycode, cia1);
+    }
+
+
     pub fn check_restore_key(&self, window: &Window) -> bool {
         // End will serve as the Restore key
         window.is_key_pressed(Key::End, KeyRepeat::No)
@@ -148,7 +160,7 @@ impl IO {
 
     // *** private functions *** //
 
-    fn process_key(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIAShared) {   
+    fn process_key(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIA) {   
         if key_pressed {
             self.on_key_press(keycode, cia1);
         }
@@ -158,7 +170,7 @@ impl IO {
     }    
 
 
-    fn on_key_press(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn on_key_press(&mut self, keycode: Key, cia1: &mut cia::CIA) {
         let c64_keycode = self.keycode_to_c64(keycode);
 
         if self.keyboard_state[c64_keycode as usize] || c64_keycode == 0xFF
@@ -174,16 +186,16 @@ impl IO {
         // key is shifted?
         if (c64_keycode & 0x80) != 0
         {
-            cia1.borrow_mut().key_matrix[6] &= 0xEF;
-            cia1.borrow_mut().rev_matrix[4] &= 0xBF;
+            cia1.key_matrix[6] &= 0xEF;
+            cia1.rev_matrix[4] &= 0xBF;
         }
         
-        cia1.borrow_mut().key_matrix[c64_byte as usize] &= !(1 << c64_bit);
-        cia1.borrow_mut().rev_matrix[c64_bit as usize]  &= !(1 << c64_byte);
+        cia1.key_matrix[c64_byte as usize] &= !(1 << c64_bit);
+        cia1.rev_matrix[c64_bit as usize]  &= !(1 << c64_byte);
     }
 
 
-    fn on_key_release(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn on_key_release(&mut self, keycode: Key, cia1: &mut cia::CIA) {
         let c64_keycode = self.keycode_to_c64(keycode);
 
         if !self.keyboard_state[c64_keycode as usize] || c64_keycode == 0xFF {
@@ -197,16 +209,16 @@ impl IO {
         
         // key is shifted?
         if (c64_keycode & 0x80) != 0 {
-            cia1.borrow_mut().key_matrix[6] |= 0x10;
-            cia1.borrow_mut().rev_matrix[4] |= 0x40;
+            cia1.key_matrix[6] |= 0x10;
+            cia1.rev_matrix[4] |= 0x40;
         }
         
-        cia1.borrow_mut().key_matrix[c64_byte as usize] |= 1 << c64_bit;
-        cia1.borrow_mut().rev_matrix[c64_bit as usize]  |= 1 << c64_byte;
+        cia1.key_matrix[c64_byte as usize] |= 1 << c64_bit;
+        cia1.rev_matrix[c64_bit as usize]  |= 1 << c64_byte;
     }
 
 
-    fn process_joystick(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIAShared) {
+    fn process_joystick(&mut self, key_pressed: bool, keycode: Key, cia1: &mut cia::CIA) {
         if key_pressed {
             self.on_joy_press(keycode, cia1);
         }
@@ -216,8 +228,8 @@ impl IO {
     }
 
 
-    fn on_joy_press(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
-        let mut joystate = if self.joy_port1 { cia1.borrow_mut().joystick_1 } else { cia1.borrow_mut().joystick_2 };
+    fn on_joy_press(&mut self, keycode: Key, cia1: &mut cia::CIA) {
+        let mut joystate = if self.joy_port1 { cia1.joystick_1 } else { cia1.joystick_2 };
 
         match keycode {
             // down-left
@@ -244,16 +256,16 @@ impl IO {
         }
 
         if self.joy_port1 {
-            cia1.borrow_mut().joystick_1 = joystate;
+            cia1.joystick_1 = joystate;
         }
         else {
-            cia1.borrow_mut().joystick_2 = joystate;
+            cia1.joystick_2 = joystate;
         }
     }
 
 
-    fn on_joy_release(&mut self, keycode: Key, cia1: &mut cia::CIAShared) {
-        let mut joystate = if self.joy_port1 { cia1.borrow_mut().joystick_1 } else { cia1.borrow_mut().joystick_2 };
+    fn on_joy_release(&mut self, keycode: Key, cia1: &mut cia::CIA) {
+        let mut joystate = if self.joy_port1 { cia1.joystick_1 } else { cia1.joystick_2 };
 
         if joystate == 0xFF {
             return;
@@ -284,12 +296,12 @@ impl IO {
         }
 
         if self.joy_port1 {
-            cia1.borrow_mut().joystick_1 = joystate;
-            cia1.borrow_mut().joystick_2 = 0xFF;
+            cia1.joystick_1 = joystate;
+            cia1.joystick_2 = 0xFF;
         }
         else {
-            cia1.borrow_mut().joystick_1 = 0xFF;
-            cia1.borrow_mut().joystick_2 = joystate;
+            cia1.joystick_1 = 0xFF;
+            cia1.joystick_2 = joystate;
         }
     }
 
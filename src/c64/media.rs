@@ -0,0 +1,55 @@
+// file types that can be attached to the machine, detected by content rather than extension
+use c64::crt;
+use c64::disk_image;
+use c64::t64;
+use c64::tap;
+use error::Error;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaType {
+    Prg,
+    Crt,
+    Disk, // D64, D71, D81 or G64
+    T64,
+    Tap,
+}
+
+impl MediaType {
+    pub fn detect(filename: &str) -> Result<MediaType, Error> {
+        let file = File::open(filename).map_err(|e| Error::Io(filename.to_string(), e))?;
+        let size = file.metadata().map_err(|e| Error::Io(filename.to_string(), e))?.len();
+        let mut header = Vec::<u8>::new();
+        file.take(64).read_to_end(&mut header).map_err(|e| Error::Io(filename.to_string(), e))?;
+
+        // sector based disk images have no header, but a size no program file can have
+        if disk_image::is_disk_image(size as usize, &header) {
+            return Ok(MediaType::Disk);
+        }
+
+        MediaType::from_header(&header).ok_or_else(|| Error::UnknownFileType(filename.to_string()))
+    }
+
+
+    pub fn from_header(data: &[u8]) -> Option<MediaType> {
+        if data.starts_with(crt::SIGNATURE) {
+            return Some(MediaType::Crt);
+        }
+
+        if data.starts_with(tap::SIGNATURE) {
+            return Some(MediaType::Tap);
+        }
+
+        if t64::is_t64(data) {
+            return Some(MediaType::T64);
+        }
+
+        // anything else starting with a load address is taken for a program file
+        if data.len() >= 2 {
+            return Some(MediaType::Prg);
+        }
+
+        None
+    }
+}
@@ -1,9 +1,8 @@
 // memory banks
-use std::cell::RefCell;
-use std::rc::Rc;
-use utils;
-
-pub type MemShared = Rc<RefCell<Memory>>;
+use c64::roms;
+use c64::snapshot;
+use std::io;
+use std::io::{Read, Write};
 
 pub enum MemType {
     Ram,
@@ -32,18 +31,9 @@ impl MemBank {
         };
 
         match mem_bank.bank_type {
-            MemType::Basic   => {
-                mem_bank.data = utils::open_file("rom/basic.rom", 0);
-                mem_bank.offset = 0xA000;
-            },
-            MemType::Chargen => {
-                mem_bank.data = utils::open_file("rom/chargen.rom", 0);
-                mem_bank.offset = 0xD000;
-            },
-            MemType::Kernal  => {
-                mem_bank.data = utils::open_file("rom/kernal.rom", 0);
-                mem_bank.offset = 0xE000;
-            },
+            MemType::Basic   => mem_bank.offset = 0xA000,
+            MemType::Chargen => mem_bank.offset = 0xD000,
+            MemType::Kernal  => mem_bank.offset = 0xE000,
             MemType::Ram => {
                 mem_bank.data = Vec::<u8>::with_capacity(0x10000);
                 for _ in 0..0x10000 {
@@ -67,7 +57,16 @@ impl MemBank {
     }
 
 
-    pub fn write(&mut self, addr: u16, val: u8) {
+    // ROM bank filled with a (validated) image
+    pub fn new_rom(mem_type: MemType, data: &[u8]) -> MemBank {
+        let mut mem_bank = MemBank::new(mem_type);
+        mem_bank.data = data.to_vec();
+        mem_bank
+    }
+
+
+    // returns false for ROM banks, which ignore writes (Memory::write_byte puts them into the RAM underneath)
+    pub fn write(&mut self, addr: u16, val: u8) -> bool {
         match self.bank_type {
             MemType::Ram => self.data[(addr - self.offset) as usize] = val,
             MemType::Io => {
@@ -78,13 +77,15 @@ impl MemBank {
                     //0xD01E..=0xD01F => (),  // cannot be written on real C64 but allow the VIC to do it anyway
                     0xD020..=0xD02E => self.data[(addr - self.offset) as usize] = 0xF0 | val,
                     0xD02F..=0xD03F => (),                             // write ignored
-                    0xD040..=0xD3FF => self.write(0xD000 + (addr % 0x0040), val), // same as 0xD000-0xD03F
+                    0xD040..=0xD3FF => { self.write(0xD000 + (addr % 0x0040), val); }, // same as 0xD000-0xD03F
                     _ => self.data[(addr - self.offset) as usize] = val
                 }
                 
             },
-            _ => panic!("Can't write to ROM!")
+            _ => return false
         }
+
+        true
     }
 
 
@@ -128,23 +129,27 @@ pub struct Memory {
     pub chargen_on: bool,
     pub io_on:      bool,
     pub kernal_on:  bool,
+
+    // datasette: a button is held down, which pulls bit 4 of the 6510 port low
+    pub cassette_sense: bool,
 }
 
 impl Memory {
-    pub fn new_shared() -> MemShared {
-        Rc::new(RefCell::new(Memory {
-            ram:     MemBank::new(MemType::Ram),     // 64k
-            basic:   MemBank::new(MemType::Basic),   // 8k
-            chargen: MemBank::new(MemType::Chargen), // 4k
-            io:      MemBank::new(MemType::Io),      // 4k (VIC, SID, CIA, Color RAM)
-            kernal:  MemBank::new(MemType::Kernal),  // 8k
+    pub fn new(roms: &roms::RomSet) -> Memory {
+        Memory {
+            ram:     MemBank::new(MemType::Ram),                         // 64k
+            basic:   MemBank::new_rom(MemType::Basic, &roms.basic),      // 8k
+            chargen: MemBank::new_rom(MemType::Chargen, &roms.chargen),  // 4k
+            io:      MemBank::new(MemType::Io),                          // 4k (VIC, SID, CIA, Color RAM)
+            kernal:  MemBank::new_rom(MemType::Kernal, &roms.kernal),    // 8k
             exrom:      true,
             game:       true,
             basic_on:   false,
             chargen_on: false,
             io_on:      false,
             kernal_on:  false,
-        }))
+            cassette_sense: false,
+        }
     }
     
 
@@ -218,13 +223,22 @@ impl Memory {
         if addr == 0x0001 {
             let ddr = self.ram.read(0x0000);
             let pr  = self.ram.read(0x0001);
-            return (ddr & pr) | (!ddr & 0x17);
+            let sense = if self.cassette_sense { 0x00 } else { 0x10 };
+            return (ddr & pr) | (!ddr & (0x07 | sense));
         }
         
         self.get_bank(addr).read(addr)
     }
 
 
+    // the datasette motor runs while bit 5 of the 6510 port is an output driven low
+    pub fn cassette_motor(&mut self) -> bool {
+        let ddr = self.ram.read(0x0000);
+        let pr  = self.ram.read(0x0001);
+        (ddr & !pr & 0x20) != 0
+    }
+
+
     // Read a word from memory (stored in little endian)
     pub fn read_word_le(&mut self, addr: u16) -> u16 {
         let bank = self.get_bank(addr);
@@ -236,6 +250,30 @@ impl Memory {
     }
 
 
+    // save RAM, IO area and banking flags (ROMs are reloaded from files and not part of the state)
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.ram.data)?;
+        w.write_all(&self.io.data)?;
+        snapshot::write_bools(w, &[self.exrom, self.game, self.basic_on, self.chargen_on, self.io_on, self.kernal_on])
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        r.read_exact(&mut self.ram.data)?;
+        r.read_exact(&mut self.io.data)?;
+
+        let mut flags = [false; 6];
+        snapshot::read_bools(r, &mut flags)?;
+        self.exrom      = flags[0];
+        self.game       = flags[1];
+        self.basic_on   = flags[2];
+        self.chargen_on = flags[3];
+        self.io_on      = flags[4];
+        self.kernal_on  = flags[5];
+        Ok(())
+    }
+
+
     // *** private functions *** //
 
     // update status of memory bank latches
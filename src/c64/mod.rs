@@ -1,202 +1,992 @@
 // main module for C64 updates
 extern crate minifb;
 
+pub mod binary_monitor;
+pub mod breakpoints;
+pub mod bus;
 pub mod cpu;
+pub mod d64;
+pub mod d71;
+pub mod d81;
+pub mod datasette;
+pub mod disasm;
+pub mod disk_image;
+pub mod drive1541;
+pub mod g64;
+pub mod media;
 pub mod memory;
+pub mod model;
+pub mod monitor;
 pub mod opcodes;
+pub mod prg;
+pub mod roms;
+pub mod t64;
+pub mod tap;
 pub mod vic;
+pub mod virtual_drive;
 pub mod crt;
 
 mod cia;
 mod clock;
 mod io;
+mod rewind;
 mod sid;
 mod sid_tables;
+mod snapshot;
 mod vic_tables;
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use debugger;
-use minifb::*;
+use error::Error;
+use std::fs::File;
+use std::io::{BufRead, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
 use utils;
 
 
 pub const SCREEN_WIDTH:  usize = 384; // extend 20 pixels left and right for the borders
 pub const SCREEN_HEIGHT: usize = 272; // extend 36 pixels top and down for the borders
 
-// PAL clock frequency in Hz
-const CLOCK_FREQ: f64 = 1.5 * 985248.0;
+// the wall clock is ticked at this multiple of the machine clock frequency
+const CLOCK_PACING: f64 = 1.5;
+
+// emulation speed limits in percent of the machine clock and the hotkey step
+pub const MIN_SPEED:  u32 = 10;
+pub const MAX_SPEED:  u32 = 1000;
+const SPEED_STEP: u32 = 10;
+
+// in warp mode only every n-th frame is presented
+const WARP_FRAME_SKIP: u32 = 25;
+
+// rewind buffer defaults: memory limit and number of frames between snapshots
+pub const DEFAULT_REWIND_LIMIT: usize = 32 * 1024 * 1024;
+const REWIND_INTERVAL: u32 = 5;
+
+// start of BASIC programs and the kernal keyboard buffer (used for autostarting programs)
+const BASIC_START: u16 = 0x0801;
+const KEYBOARD_BUFFER: u16 = 0x0277;
+const KEYBOARD_BUFFER_SIZE: usize = 10;
+
+// save state file used by the F9/F10 hotkeys
+const SNAPSHOT_FILE: &str = "rust64.sav";
 
 
 pub struct C64 {
-    pub main_window: minifb::Window,
-    pub file_to_load: String,
-    pub crt_to_load: String,
-    memory: memory::MemShared,
+    pub main_window: Option<minifb::Window>, // None when running headless
+    prg_to_load: Option<prg::Prg>, // loaded into memory once BASIC has started
+    crt_to_load: Option<crt::Crt>, // plugged in on power-on
     io:     io::IO,
     clock:  clock::Clock,
-    cpu:  cpu::CPUShared,
-    cia1: cia::CIAShared,
-    cia2: cia::CIAShared,
-    vic:  vic::VICShared,
-    sid:  sid::SIDShared,
+    cpu:    cpu::CPU,
+    bus:    bus::SystemBus, // memory (RAM, ROM, IO registers) and the chips
+    drive:  virtual_drive::VirtualDrive,
+    true_drive: Option<drive1541::Drive1541>, // a real 1541 on the serial bus instead of the virtual drive
+    datasette: datasette::Datasette,
+    tape_to_load: bool, // type LOAD once BASIC has started
 
     debugger: Option<debugger::Debugger>,
     powered_on: bool,
     boot_complete: bool,
     cycle_count: u32,
+    speed: u32,        // emulation speed in percent
+    warp: bool,        // run unthrottled
+    warp_frame: u32,   // frames since the last one presented in warp mode
+    rewind: rewind::RewindBuffer,
+    rewinding: bool,   // rewind key is being held
+    model: model::Model,
+    tod_cycles: u32,   // cycles left until the next TOD clock tick
+    autostart: bool,   // run the program once it's loaded
+    monitor: monitor::Monitor,
+    monitor_requested: bool,                       // pause and enter the monitor at the next instruction
+    monitor_input: Option<mpsc::Receiver<String>>, // console lines, if read in the background
+    monitor_line: Option<String>,                  // line typed while running, run as the first command
+    binary_monitor: Option<binary_monitor::BinaryMonitor>, // remote debugger connection, if listening
+    remote_requested: bool, // pause for the remote debugger at the next instruction
+    quit_requested: bool,   // the remote debugger asked to quit the emulator
 }
 
 impl C64 {
-    pub fn new(window_scale: Scale, debugger_on: bool, prg_to_load: &str, crt_to_load: &str) -> C64 {
-        let memory = memory::Memory::new_shared();
-        let vic    = vic::VIC::new_shared();
-        let cia1   = cia::CIA::new_shared(true);
-        let cia2   = cia::CIA::new_shared(false);
-        let cpu    = cpu::CPU::new_shared();
-        let sid    = sid::SID::new_shared();
+    // program and cartridge files are optional (empty name) and are validated right away
+    pub fn new(window_scale: Scale, debugger_on: bool, headless: bool, prg_to_load: &str, crt_to_load: &str, roms: &roms::RomSet) -> Result<C64, Error> {
+        let main_window = if headless { None } else {
+            Some(Window::new("Rust64", SCREEN_WIDTH, SCREEN_HEIGHT, WindowOptions { scale: window_scale, ..Default::default() }).map_err(|e| Error::Window(e.to_string()))?)
+        };
 
         let mut c64 = C64 {
-            main_window: Window::new("Rust64", SCREEN_WIDTH, SCREEN_HEIGHT, WindowOptions { scale: window_scale, ..Default::default() }).unwrap(),
-            file_to_load: String::from(prg_to_load),
-            crt_to_load: String::from(crt_to_load),
-            memory: memory.clone(), // shared system memory (RAM, ROM, IO registers)
+            main_window,
+            prg_to_load: if prg_to_load.is_empty() { None } else { Some(prg::Prg::from_filename(prg_to_load)?) },
+            crt_to_load: if crt_to_load.is_empty() { None } else { Some(crt::Crt::from_filename(crt_to_load)?) },
             io:     io::IO::new(),
-            clock:  clock::Clock::new(CLOCK_FREQ),
-            cpu:  cpu.clone(),
-            cia1: cia1.clone(),
-            cia2: cia2.clone(),
-            vic:  vic.clone(),
-            sid:  sid.clone(),
-            debugger: if debugger_on { Some(debugger::Debugger::new()) } else { None },
+            clock:  clock::Clock::new(CLOCK_PACING * model::Model::Pal.clock_freq()),
+            cpu:    cpu::CPU::new(),
+            bus:    bus::SystemBus::new(!headless, roms)?,
+            drive:  virtual_drive::VirtualDrive::new(),
+            true_drive: None,
+            datasette: datasette::Datasette::new(),
+            tape_to_load: false,
+            debugger: if debugger_on && !headless { Some(debugger::Debugger::new()?) } else { None },
             powered_on: false,
             boot_complete: false,
             cycle_count: 0,
+            speed: 100,
+            warp: false,
+            warp_frame: 0,
+            rewind: rewind::RewindBuffer::new(if headless { 0 } else { DEFAULT_REWIND_LIMIT }, REWIND_INTERVAL),
+            rewinding: false,
+            model: model::Model::Pal,
+            tod_cycles: 0,
+            autostart: false,
+            monitor: monitor::Monitor::new(),
+            monitor_requested: false,
+            monitor_input: None,
+            monitor_line: None,
+            binary_monitor: None,
+            remote_requested: false,
+            quit_requested: false,
         };
 
-        c64.main_window.set_position(75, 20);
+        if let Some(ref mut window) = c64.main_window {
+            window.set_position(75, 20);
+        }
+
+        Ok(c64)
+    }
+
 
-        // cyclic dependencies are not possible in Rust (yet?), so we have
-        // to resort to setting references manually
-        c64.cia1.borrow_mut().set_references(memory.clone(), cpu.clone(), vic.clone());
-        c64.cia2.borrow_mut().set_references(memory.clone(), cpu.clone(), vic.clone());
-        c64.vic.borrow_mut().set_references(memory.clone(), cpu.clone());
-        c64.sid.borrow_mut().set_references(memory.clone());
-        c64.cpu.borrow_mut().set_references(memory.clone(), vic.clone(), cia1.clone(), cia2.clone(), sid.clone());
+    pub fn reset(&mut self) {
+        self.bus.memory.reset();
+        self.cpu.reset(&mut self.bus);
+        self.bus.cia1.reset();
+        self.bus.cia2.reset();
+        self.bus.sid.reset();
+        self.drive.reset();
+        if let Some(ref mut drive) = self.true_drive {
+            drive.reset();
+        }
+    }
+
+
+    // a headless machine keeps running until the process decides to stop it
+    pub fn is_open(&self) -> bool {
+        if self.quit_requested {
+            return false;
+        }
 
-        drop(memory);
-        drop(cia1);
-        drop(cia2);
-        drop(vic);
-        drop(cpu);
-        drop(sid);
+        match self.main_window {
+            Some(ref window) => window.is_open(),
+            None => true,
+        }
+    }
 
-        c64
+
+    // current contents of the screen in 0x00RRGGBB format, SCREEN_WIDTH x SCREEN_HEIGHT
+    pub fn frame_buffer(&self) -> &[u32] {
+        &self.bus.vic.window_buffer[..]
     }
 
 
-    pub fn reset(&mut self) {
-        self.memory.borrow_mut().reset();
-        self.cpu.borrow_mut().reset();
-        self.cia1.borrow_mut().reset();
-        self.cia2.borrow_mut().reset();
-        self.sid.borrow_mut().reset();
+    // input injection, mostly useful in headless mode: keys use the same host mapping as the window
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        self.io.set_key(key, pressed, &mut self.bus.cia1);
+    }
+
+
+    pub fn set_joystick(&mut self, key: Key, pressed: bool) {
+        self.io.set_joystick(key, pressed, &mut self.bus.cia1);
+    }
+
+
+    pub fn press_restore(&mut self) {
+        self.cpu.set_nmi(true);
+    }
+
+
+    // read memory as currently seen by the CPU (RAM, ROM or IO depending on banking)
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.bus.memory.read_byte(addr)
+    }
+
+
+    // write to memory as the CPU would, bypassing chip register side effects
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let _ = self.bus.memory.write_byte(addr, value);
+    }
+
+
+    pub fn cpu(&self) -> &cpu::CPU {
+        &self.cpu
+    }
+
+
+    pub fn breakpoints(&mut self) -> &mut breakpoints::Breakpoints {
+        &mut self.cpu.breakpoints
+    }
+
+
+    // serialize the complete machine state into a versioned binary blob
+    pub fn save_snapshot(&mut self) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        // writing to memory can't fail
+        self.write_snapshot(&mut data).unwrap();
+        data
+    }
+
+
+    // restore a state created by save_snapshot(); the machine is reset if the data turns out to be corrupt
+    pub fn load_snapshot(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut reader = data;
+        let invalid = |e: std::io::Error| Error::InvalidSnapshot(format!("Corrupt save state: {}", e));
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(invalid)?;
+        if &magic != snapshot::MAGIC {
+            return Err(Error::InvalidSnapshot("Invalid save state signature".to_string()))
+        }
+
+        let version = reader.read_u16::<LittleEndian>().map_err(invalid)?;
+        if version != snapshot::VERSION {
+            return Err(Error::InvalidSnapshot(format!("Unsupported save state version: {} (expected {})", version, snapshot::VERSION)))
+        }
+
+        let model = reader.read_u8().map_err(invalid)?;
+        if model != self.model.to_u8() {
+            let name = model::Model::from_u8(model).map_or("unknown".to_string(), |m| m.to_string());
+            return Err(Error::InvalidSnapshot(format!("Save state is for a different machine model: {} (running {})", name, self.model)))
+        }
+
+        let result = self.read_snapshot(&mut reader);
+        if result.is_err() {
+            self.reset();
+        }
+
+        result.map_err(invalid)
+    }
+
+
+    pub fn save_snapshot_file(&mut self, filename: &str) -> Result<(), Error> {
+        let mut file = File::create(filename).map_err(|e| Error::Io(filename.to_string(), e))?;
+        file.write_all(&self.save_snapshot()).map_err(|e| Error::Io(filename.to_string(), e))
+    }
+
+
+    pub fn load_snapshot_file(&mut self, filename: &str) -> Result<(), Error> {
+        let data = utils::open_file(filename, 0)?;
+        self.load_snapshot(&data)
+    }
+
+
+    // set emulation speed in percent of real C64 speed
+    pub fn set_speed(&mut self, percent: u32) {
+        self.speed = percent.clamp(MIN_SPEED, MAX_SPEED);
+        self.clock.set_freq(CLOCK_PACING * self.model.clock_freq() * self.speed as f64 / 100.0);
+    }
+
+
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+
+    // switch between PAL and NTSC timing; the machine should be reset afterwards
+    pub fn set_model(&mut self, model: model::Model) {
+        self.model = model;
+        self.bus.vic.set_model(model);
+        self.bus.sid.set_model(model);
+        self.datasette.set_model(model);
+        self.tod_cycles = 0;
+        let speed = self.speed;
+        self.set_speed(speed);
+    }
+
+
+    pub fn model(&self) -> model::Model {
+        self.model
+    }
+
+
+    // chip-dependent constants used by the unstable ANE and LXA opcodes (cpu::ANE_MAGIC and cpu::LXA_MAGIC by default)
+    pub fn set_magic_constants(&mut self, ane: u8, lxa: u8) {
+        self.cpu.ane_magic = ane;
+        self.cpu.lxa_magic = lxa;
+    }
+
+
+    // start the program given on the command line right after loading it
+    pub fn set_autostart(&mut self, autostart: bool) {
+        self.autostart = autostart;
+    }
+
+
+    // warp mode runs the emulation as fast as possible and only shows some of the frames
+    pub fn set_warp(&mut self, warp: bool) {
+        self.warp = warp;
+        self.warp_frame = 0;
+    }
+
+
+    pub fn warp(&self) -> bool {
+        self.warp
+    }
+
+
+    // memory limit for the rewind buffer in bytes, 0 disables rewinding
+    pub fn set_rewind_limit(&mut self, max_bytes: usize) {
+        self.rewind.set_limit(max_bytes);
+    }
+
+
+    // step back to the most recent rewind snapshot; returns false if there's nothing left to rewind
+    pub fn rewind(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(data) => self.load_snapshot(&data).is_ok(),
+            None => false,
+        }
+    }
+
+
+    // put a disk image (D64, D71, D81 or G64) into drive 8, replacing the one in there
+    pub fn attach_disk(&mut self, filename: &str) -> Result<(), Error> {
+        let image = disk_image::open(filename)?;
+        self.insert_disk(image);
+        Ok(())
+    }
+
+
+    pub fn attach_disk_image<I: disk_image::DiskImage + 'static>(&mut self, image: I) {
+        self.insert_disk(Box::new(image));
+    }
+
+
+    // take the disk out of drive 8; returns it with whatever was saved to it
+    pub fn detach_disk(&mut self) -> Option<Box<dyn disk_image::DiskImage>> {
+        match self.true_drive {
+            Some(ref mut drive) => drive.detach(),
+            None => self.drive.detach(),
+        }
+    }
+
+
+    // replace the virtual drive 8 with an emulated 1541 running the given DOS ROM; the disk
+    // moves over to it. Loading is as slow as on the real thing, but fast loaders work.
+    pub fn enable_true_drive(&mut self, rom: Vec<u8>) -> Result<(), Error> {
+        roms::check_drive_rom(&rom)?;
+
+        let mut drive = drive1541::Drive1541::new(rom);
+        if let Some(image) = self.drive.detach() {
+            drive.attach(image);
+        }
+
+        self.true_drive = Some(drive);
+        Ok(())
+    }
+
+
+    pub fn true_drive(&mut self) -> Option<&mut drive1541::Drive1541> {
+        self.true_drive.as_mut()
+    }
+
+
+    // load the first file on the attached disk matching the pattern (e.g. "*"), as if it was given on the command line
+    pub fn load_disk_file(&mut self, pattern: &str) -> Result<(), Error> {
+        self.load_disk_program(pattern.as_bytes())
+    }
+
+
+    // load a file of a T64 archive (0 is the first) into memory, as if it was given on the command line
+    pub fn load_t64_entry(&mut self, filename: &str, index: usize) -> Result<(), Error> {
+        let archive = t64::T64::from_filename(filename)?;
+        let prg = archive.prg(index).ok_or_else(|| Error::InvalidTape(format!("{} has no file {}", filename, index)))?;
+        self.queue_prg(prg);
+        Ok(())
+    }
+
+
+    // put a TAP image into the datasette, rewound and stopped
+    pub fn attach_tape(&mut self, filename: &str) -> Result<(), Error> {
+        let tape = tap::Tap::from_filename(filename)?;
+        self.datasette.insert(tape);
+        Ok(())
+    }
+
+
+    pub fn attach_tape_image(&mut self, tape: tap::Tap) {
+        self.datasette.insert(tape);
+    }
+
+
+    pub fn detach_tape(&mut self) -> Option<tap::Tap> {
+        self.datasette.eject()
+    }
+
+
+    // buttons and position of the tape
+    pub fn datasette(&mut self) -> &mut datasette::Datasette {
+        &mut self.datasette
+    }
+
+
+    // press PLAY and type LOAD (and RUN with autostart) once BASIC has started, so the kernal loads the first file on the tape
+    pub fn load_tape(&mut self) -> Result<(), Error> {
+        if self.datasette.tape().is_none() {
+            return Err(Error::InvalidTape("No tape attached".to_string()))
+        }
+
+        self.datasette.press(datasette::Button::Play);
+        self.tape_to_load = true;
+
+        if self.boot_complete {
+            self.start_tape_load();
+        }
+
+        Ok(())
+    }
+
+
+    // pause the emulation and enter the monitor before the next instruction
+    pub fn enter_monitor(&mut self) {
+        self.monitor_requested = true;
+    }
+
+
+    // read monitor commands from stdin in the background, so that a line typed while running
+    // enters the monitor (and is run as its first command)
+    pub fn enable_monitor_input(&mut self) {
+        if self.monitor_input.is_some() {
+            return;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        self.monitor_input = Some(receiver);
+    }
+
+
+    // serve the VICE binary monitor protocol on a local TCP port (0 picks a free one); returns the port
+    pub fn enable_binary_monitor(&mut self, port: u16) -> Result<u16, Error> {
+        let server = binary_monitor::BinaryMonitor::bind(port)?;
+        let port = server.port();
+        self.binary_monitor = Some(server);
+        Ok(port)
+    }
+
+
+    // accept a remote debugger and, if it sent a command or a breakpoint was hit while it's connected,
+    // pause the emulation and serve it until it lets the machine continue
+    pub fn poll_binary_monitor(&mut self) {
+        let stop = match self.binary_monitor {
+            Some(ref mut server) => server.poll() || (server.is_connected() && self.cpu.breakpoints.hit.is_some()),
+            None => false,
+        };
+
+        if stop {
+            self.run_binary_monitor();
+        }
+    }
+
+
+    // real-time front end: paces the emulation with the wall clock, presents frames and handles host input;
+    // returns the number of cycles emulated, which is 0 if it's not time for the next one yet
+    pub fn run(&mut self) -> u32 {
+        let mut cycles = 0;
+
+        if self.remote_requested {
+            self.run_binary_monitor();
+        }
+
+        if self.monitor_requested {
+            self.run_monitor();
+        }
+
+        if self.warp {
+            cycles = 1;
+            if self.step() {
+                self.record_rewind();
+                self.warp_frame += 1;
+                if self.warp_frame >= WARP_FRAME_SKIP {
+                    self.warp_frame = 0;
+                    self.present_frame();
+                }
+            }
+        }
+        else if self.clock.tick() {
+            cycles = 1;
+            if self.step() {
+                self.record_rewind();
+                self.present_frame();
+            }
+        }
+
+        // update SDL2 audio buffers
+        self.bus.sid.update_audio();
+
+        // breakpoints stop in the remote debugger if one is connected
+        if self.cpu.breakpoints.hit.is_some() {
+            match self.binary_monitor {
+                Some(ref server) if server.is_connected() => self.remote_requested = true,
+                _ => self.monitor_requested = true,
+            }
+        }
+
+        cycles
+    }
+
+
+    // run exactly the given number of cycles; returns the number of frames completed on the way
+    pub fn run_cycles(&mut self, cycles: u32) -> u32 {
+        let mut frames = 0;
+
+        for _ in 0..cycles {
+            if self.step() {
+                frames += 1;
+            }
+        }
+
+        frames
+    }
+
+
+    // run up to and including the next VBlank cycle, stopping early if a breakpoint is hit;
+    // returns the number of cycles it took
+    pub fn run_until_vblank(&mut self) -> u32 {
+        let mut cycles = 1;
+
+        while !self.step() && self.cpu.breakpoints.hit.is_none() {
+            cycles += 1;
+        }
+
+        cycles
     }
 
 
-    pub fn run(&mut self) {
+    // run until the CPU is about to fetch the next opcode (or has jammed); returns the number of cycles it took
+    pub fn run_until_fetch(&mut self) -> u32 {
+        let mut cycles = 0;
+
+        loop {
+            self.step();
+            cycles += 1;
+
+            match self.cpu.state {
+                cpu::CPUState::FetchOp | cpu::CPUState::Jammed => return cycles,
+                _ => (),
+            }
+        }
+    }
+
+
+    // advance the machine by a single clock cycle regardless of wall time; returns true on VBlank.
+    // Emulation results depend only on the machine state and injected input, never on the host.
+    pub fn step(&mut self) -> bool {
         // attempt to load a program supplied with command line
         if !self.powered_on {
             // $FCE2 is the power-on reset routine, which searches for and starts
             // a cartridge amongst other things. The cartridge must be loaded here
-            self.powered_on = self.cpu.borrow_mut().pc == 0xFCE2;
+            self.powered_on = self.cpu.pc == 0xFCE2;
             if self.powered_on {
-                let crt_file = &self.crt_to_load.to_owned()[..];
-                if crt_file.len() > 0 {
-                    let crt = crt::Crt::from_filename(crt_file).unwrap();
+                if let Some(ref crt) = self.crt_to_load {
                     println!("{:?}", crt);
-                    crt.load_into_memory(self.memory.borrow_mut());
+                    crt.load_into_memory(&mut self.bus.memory);
                 }
             }
         }
 
         if !self.boot_complete {
             // $A480 is the BASIC warm start sequence - safe to assume we can load a cmdline program now
-            self.boot_complete = self.cpu.borrow_mut().pc == 0xA480;
+            self.boot_complete = self.cpu.pc == 0xA480;
 
             if self.boot_complete {
-                let prg_file = &self.file_to_load.to_owned()[..];
-
-                if prg_file.len() > 0 {
-                    self.boot_complete = true; self.load_prg(prg_file);
+                self.load_prg();
+                if self.tape_to_load {
+                    self.start_tape_load();
                 }
             }
         }
 
-        // main C64 update - use the clock to time all the operations
-        if self.clock.tick() {
-            let mut should_trigger_vblank = false;
+        let mut should_trigger_vblank = false;
 
-            if self.vic.borrow_mut().update(self.cycle_count, &mut should_trigger_vblank) {
-                self.sid.borrow_mut().update();
-            }
+        if self.bus.vic.update(self.cycle_count, &mut should_trigger_vblank, &mut self.bus.memory, &mut self.cpu) {
+            self.bus.sid.update();
+        }
 
-            self.cia1.borrow_mut().process_irq();
-            self.cia2.borrow_mut().process_irq();
-            self.cia1.borrow_mut().update();
-            self.cia2.borrow_mut().update();
+        self.bus.cia1.process_irq(&mut self.cpu);
+        self.bus.cia2.process_irq(&mut self.cpu);
+        self.bus.cia1.update();
+        self.bus.cia2.update();
 
-            self.cpu.borrow_mut().update(self.cycle_count);
+        // a pulse right after another still makes a falling edge on FLAG
+        let motor = self.bus.memory.cassette_motor();
+        if self.datasette.update(motor) {
+            self.bus.cia1.set_flag(true, &mut self.cpu);
+        }
+        self.bus.cia1.set_flag(self.datasette.read_line(), &mut self.cpu);
+        self.bus.memory.cassette_sense = self.datasette.is_playing();
 
-            // update the debugger window if it exists
-            match self.debugger {
-                Some(ref mut dbg) => {
-                    dbg.update_vic_window(&mut self.vic);
-                    if should_trigger_vblank {
-                        dbg.render(&mut self.cpu, &mut self.memory);
-                    }
-                },
-                None => (),
+        // the virtual drive answers the kernal's serial bus routines, unless an interrupt comes first
+        if let cpu::CPUState::FetchOp = self.cpu.state {
+            if !self.cpu.int_polled {
+                self.drive.trap(&mut self.cpu, &mut self.bus.memory);
             }
+        }
+
+        self.cpu.update(&mut self.bus);
 
-            // redraw the screen and process input on VBlank
-            if should_trigger_vblank {
-                let _ = self.main_window.update_with_buffer(&self.vic.borrow_mut().window_buffer, SCREEN_WIDTH, SCREEN_HEIGHT);
-                self.io.update(&self.main_window, &mut self.cia1);
-                self.cia1.borrow_mut().count_tod();
-                self.cia2.borrow_mut().count_tod();
+        if let Some(ref mut drive) = self.true_drive {
+            drive.update(&mut self.bus.cia2, self.model.clock_freq() as u32);
+        }
 
-                if self.io.check_restore_key(&self.main_window) {
-                    self.cpu.borrow_mut().set_nmi(true);
+        // update the debugger window if it exists
+        match self.debugger {
+            Some(ref mut dbg) => {
+                dbg.update_vic_window(&self.bus.vic);
+                if should_trigger_vblank {
+                    dbg.render(&mut self.cpu, &mut self.bus);
                 }
+            },
+            None => (),
+        }
+
+        // the TOD clocks are driven by the mains frequency
+        if self.tod_cycles == 0 {
+            self.tod_cycles = (self.model.clock_freq() as u32) / self.model.tod_freq();
+            self.bus.cia1.count_tod(&mut self.cpu);
+            self.bus.cia2.count_tod(&mut self.cpu);
+        }
+
+        self.tod_cycles -= 1;
+
+        self.cycle_count += 1;
+
+        should_trigger_vblank
+    }
+
+
+    // *** private functions *** //
+
+    // store a snapshot in the rewind buffer every few frames, unless going back in time right now
+    fn record_rewind(&mut self) {
+        if !self.rewinding && self.rewind.on_frame() {
+            let snapshot = self.save_snapshot();
+            self.rewind.push(snapshot);
+        }
+    }
+
+
+    // redraw the screen and process host input and special keys on VBlank
+    fn present_frame(&mut self) {
+        let mut reset_pressed = false;
+        let mut warp_pressed  = false;
+        let mut speed_up_pressed   = false;
+        let mut speed_down_pressed = false;
+        let mut save_pressed  = false;
+        let mut load_pressed  = false;
+
+        if let Some(ref mut window) = self.main_window {
+            let _ = window.update_with_buffer(&self.bus.vic.window_buffer, SCREEN_WIDTH, SCREEN_HEIGHT);
+            self.io.update(window, &mut self.bus.cia1);
+
+            if self.io.check_restore_key(window) {
+                self.cpu.set_nmi(true);
             }
 
-            // process special keys: console ASM output and reset switch
-            if self.main_window.is_key_pressed(Key::F11, KeyRepeat::No) {
-                let di = self.cpu.borrow_mut().debug_instr;
-                self.cpu.borrow_mut().debug_instr = !di;
+            // console ASM output and reset switch
+            if window.is_key_pressed(Key::F11, KeyRepeat::No) {
+                self.cpu.debug_instr = !self.cpu.debug_instr;
             }
 
-            if self.main_window.is_key_pressed(Key::F12, KeyRepeat::No) {
-                self.reset();
+            reset_pressed = window.is_key_pressed(Key::F12, KeyRepeat::No);
+            warp_pressed  = window.is_key_pressed(Key::NumPadAsterisk, KeyRepeat::No);
+            speed_up_pressed   = window.is_key_pressed(Key::NumPadPlus, KeyRepeat::Yes);
+            speed_down_pressed = window.is_key_pressed(Key::NumPadMinus, KeyRepeat::Yes);
+            save_pressed  = window.is_key_pressed(Key::F9, KeyRepeat::No);
+            load_pressed  = window.is_key_pressed(Key::F10, KeyRepeat::No);
+            self.monitor_requested |= window.is_key_pressed(Key::Pause, KeyRepeat::No);
+            self.rewinding = window.is_key_down(Key::PageUp);
+        }
+
+        if let Some(ref mut server) = self.binary_monitor {
+            self.remote_requested |= server.poll();
+        }
+
+        if let Some(ref input) = self.monitor_input {
+            if let Ok(line) = input.try_recv() {
+                self.monitor_line = Some(line);
+                self.monitor_requested = true;
             }
+        }
 
-            self.cycle_count += 1;
+        // hold the rewind key to go back in time, one snapshot per presented frame
+        if self.rewinding && !self.rewind() {
+            self.rewinding = false;
         }
 
-        // update SDL2 audio buffers
-        self.sid.borrow_mut().update_audio();
+        if reset_pressed {
+            self.reset();
+        }
+
+        // warp mode and emulation speed
+        if warp_pressed {
+            let warp = !self.warp;
+            self.set_warp(warp);
+            println!("Warp mode {}", if warp { "on" } else { "off" });
+        }
+
+        if speed_up_pressed || speed_down_pressed {
+            let speed = if speed_up_pressed { self.speed + SPEED_STEP } else { self.speed - SPEED_STEP };
+            self.set_speed(speed);
+            println!("Emulation speed: {}%", self.speed);
+        }
+
+        // save states: F9 saves, F10 restores
+        if save_pressed {
+            match self.save_snapshot_file(SNAPSHOT_FILE) {
+                Ok(_)  => println!("Saved state to {}", SNAPSHOT_FILE),
+                Err(e) => println!("Couldn't save state to {}: {}", SNAPSHOT_FILE, e),
+            }
+        }
+
+        if load_pressed {
+            match self.load_snapshot_file(SNAPSHOT_FILE) {
+                Ok(_)  => println!("Loaded state from {}", SNAPSHOT_FILE),
+                Err(e) => println!("Couldn't load state from {}: {}", SNAPSHOT_FILE, e),
+            }
+        }
     }
 
 
-    // *** private functions *** //
+    // console monitor session; the emulation stays paused until the monitor is left
+    fn run_monitor(&mut self) {
+        self.monitor_requested = false;
+
+        // registers are only consistent between instructions
+        if let cpu::CPUState::ExecuteOp = self.cpu.state {
+            self.run_until_fetch();
+        }
+
+        if let Some(hit) = self.cpu.breakpoints.hit.take() {
+            println!("{}", hit);
+        }
+
+        // show the state of the chips in the debugger window as well
+        if let Some(ref mut dbg) = self.debugger {
+            dbg.render(&mut self.cpu, &mut self.bus);
+        }
+
+        print!("{}", self.monitor.enter(&self.cpu));
+
+        loop {
+            let line = match self.monitor_line.take() {
+                Some(line) => line,
+                None => {
+                    print!("{}", self.monitor.prompt(&self.cpu));
+                    let _ = std::io::stdout().flush();
+
+                    match self.read_monitor_line() {
+                        Some(line) => line,
+                        None => break, // stdin closed
+                    }
+                }
+            };
+
+            let mut output = String::new();
+            let action = self.monitor.execute(&line, &mut self.cpu, &mut self.bus.memory, &mut output);
+            print!("{}", output);
+
+            match action {
+                monitor::Action::Resume => break,
+                monitor::Action::AttachDisk(filename) => match self.attach_disk(&filename) {
+                    Ok(_)  => println!("Attached {} to drive 8", filename),
+                    Err(e) => println!("{}", e),
+                },
+                monitor::Action::DetachDisk => {
+                    self.detach_disk();
+                },
+                monitor::Action::AttachTape(filename) => match self.attach_tape(&filename) {
+                    Ok(_)  => println!("Attached {} to the datasette", filename),
+                    Err(e) => println!("{}", e),
+                },
+                monitor::Action::DetachTape => {
+                    self.detach_tape();
+                },
+                monitor::Action::Tape(button) => {
+                    self.datasette.press(button);
+                    if button == datasette::Button::Play && !self.datasette.is_playing() {
+                        println!("No tape in the datasette");
+                    }
+                },
+                monitor::Action::Stay => (),
+            }
+        }
+    }
+
+
+    // remote debugger session; the emulation stays paused until the client lets it continue
+    fn run_binary_monitor(&mut self) {
+        self.remote_requested = false;
+
+        if let cpu::CPUState::ExecuteOp = self.cpu.state {
+            self.run_until_fetch();
+        }
+
+        let hit = self.cpu.breakpoints.hit.take();
+        if let Some(mut server) = self.binary_monitor.take() {
+            server.session(self, hit);
+            self.binary_monitor = Some(server);
+        }
+    }
+
+
+    fn read_monitor_line(&mut self) -> Option<String> {
+        if let Some(ref input) = self.monitor_input {
+            return input.recv().ok();
+        }
+
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+
+
+    fn write_snapshot<W: Write>(&mut self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(snapshot::MAGIC)?;
+        w.write_u16::<LittleEndian>(snapshot::VERSION)?;
+        w.write_u8(self.model.to_u8())?;
+        w.write_u32::<LittleEndian>(self.cycle_count)?;
+        w.write_u32::<LittleEndian>(self.tod_cycles)?;
+        snapshot::write_bools(w, &[self.powered_on, self.boot_complete])?;
+        self.cpu.save_state(w)?;
+        self.bus.memory.save_state(w)?;
+        self.bus.cia1.save_state(w)?;
+        self.bus.cia2.save_state(w)?;
+        self.bus.vic.save_state(w)?;
+        self.bus.sid.save_state(w)?;
+        self.datasette.save_state(w)?;
+
+        snapshot::write_bool(w, self.true_drive.is_some())?;
+        match self.true_drive {
+            Some(ref drive) => drive.save_state(w),
+            None => Ok(()),
+        }
+    }
+
+
+    fn read_snapshot<R: Read>(&mut self, r: &mut R) -> std::io::Result<()> {
+        self.cycle_count = r.read_u32::<LittleEndian>()?;
+        self.tod_cycles  = r.read_u32::<LittleEndian>()?;
+        let mut flags = [false; 2];
+        snapshot::read_bools(r, &mut flags)?;
+        self.powered_on    = flags[0];
+        self.boot_complete = flags[1];
+        self.cpu.load_state(r)?;
+        self.bus.memory.load_state(r)?;
+        self.bus.cia1.load_state(r)?;
+        self.bus.cia2.load_state(r)?;
+        self.bus.vic.load_state(r)?;
+        self.bus.sid.load_state(r)?;
+        self.datasette.load_state(r)?;
+
+        let true_drive = snapshot::read_bool(r)?;
+        match self.true_drive {
+            Some(ref mut drive) if true_drive => drive.load_state(r),
+            None if !true_drive => Ok(()),
+            _ => Err(snapshot::invalid_data("Save state is for a different drive setup")),
+        }
+    }
+
+
+    fn insert_disk(&mut self, image: Box<dyn disk_image::DiskImage>) {
+        match self.true_drive {
+            Some(ref mut drive) => drive.attach(image),
+            None => self.drive.attach(image),
+        }
+    }
 
-    // load a *.prg file
-    fn load_prg(&mut self, filename: &str) {
-        let prg_data = utils::open_file(filename, 0);
-        let start_address: u16 = ((prg_data[1] as u16) << 8) | (prg_data[0] as u16);
-        println!("Loading {} to start location at ${:04x} ({})", filename, start_address, start_address);
 
-        for i in 2..(prg_data.len()) {
-            self.memory.borrow_mut().write_byte(start_address + (i as u16) - 2, prg_data[i]);
+    // the disk in drive 8, whichever drive that is
+    fn disk_image(&self) -> Option<&dyn disk_image::DiskImage> {
+        self.true_drive.as_ref().map_or(self.drive.image(), |d| d.image())
+    }
+
+
+    fn load_disk_program(&mut self, pattern: &[u8]) -> Result<(), Error> {
+        let name = String::from_utf8_lossy(pattern).into_owned();
+        let data = {
+            let image = self.disk_image().ok_or_else(|| Error::InvalidDisk("No disk attached".to_string()))?;
+            let entry = image.find(pattern).ok_or_else(|| Error::InvalidDisk(format!("{} not found on the disk", name)))?;
+            image.read_file(&entry).map_err(|e| Error::InvalidDisk(format!("Couldn't read {}: DOS error {}", name, e.code())))?
+        };
+
+        let prg = prg::Prg::from_data(&data).map_err(|e| Error::InvalidPrg(format!("Invalid program file {}: {}", name, e)))?;
+        self.queue_prg(prg);
+        Ok(())
+    }
+
+
+    // loaded right away unless BASIC hasn't started yet
+    fn queue_prg(&mut self, prg: prg::Prg) {
+        self.prg_to_load = Some(prg);
+
+        if self.boot_complete {
+            self.load_prg();
         }
     }
+
+
+    // put the program supplied at startup into memory
+    fn load_prg(&mut self) {
+        let (start_address, end_address) = match self.prg_to_load {
+            Some(ref prg) => {
+                println!("Loading program to start location at ${:04x} ({})", prg.load_address, prg.load_address);
+
+                for (i, byte) in prg.data.iter().enumerate() {
+                    self.bus.memory.write_byte(prg.load_address + i as u16, *byte);
+                }
+
+                (prg.load_address, prg.end_address())
+            },
+            None => return,
+        };
+
+        // BASIC program: move the start of variables past its end, like LOAD does
+        if start_address == BASIC_START {
+            self.bus.memory.write_byte(0x002D, end_address as u8);
+            self.bus.memory.write_byte(0x002E, (end_address >> 8) as u8);
+        }
+
+        if self.autostart {
+            let command = if start_address == BASIC_START { "RUN\r".to_string() } else { format!("SYS{}\r", start_address) };
+            self.type_text(command.as_bytes());
+        }
+    }
+
+
+    fn start_tape_load(&mut self) {
+        self.tape_to_load = false;
+        let command: &[u8] = if self.autostart { b"LOAD\rRUN\r" } else { b"LOAD\r" };
+        self.type_text(command);
+    }
+
+
+    // put text into the kernal keyboard buffer, as if it was typed in
+    fn type_text(&mut self, text: &[u8]) {
+        let len = text.len().min(KEYBOARD_BUFFER_SIZE);
+
+        for (i, c) in text[..len].iter().enumerate() {
+            self.bus.memory.write_byte(KEYBOARD_BUFFER + i as u16, *c);
+        }
+
+        self.bus.memory.write_byte(0x00C6, len as u8);
+    }
 }
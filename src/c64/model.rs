@@ -0,0 +1,85 @@
+// machine models: the VIC-II revision determines the video timing and the system clock
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Pal,     // 6569, 312 lines, 63 cycles per line
+    Ntsc,    // 6567R8, 263 lines, 65 cycles per line
+    NtscOld, // 6567R56A, 262 lines, 64 cycles per line
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "pal"      => Some(Model::Pal),
+            "ntsc"     => Some(Model::Ntsc),
+            "ntsc-old" => Some(Model::NtscOld),
+            _ => None,
+        }
+    }
+
+
+    pub fn from_u8(value: u8) -> Option<Model> {
+        match value {
+            0 => Some(Model::Pal),
+            1 => Some(Model::Ntsc),
+            2 => Some(Model::NtscOld),
+            _ => None,
+        }
+    }
+
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Model::Pal     => 0,
+            Model::Ntsc    => 1,
+            Model::NtscOld => 2,
+        }
+    }
+
+
+    // system clock in Hz
+    pub fn clock_freq(self) -> f64 {
+        match self {
+            Model::Pal => 985248.0,
+            Model::Ntsc | Model::NtscOld => 1022727.0,
+        }
+    }
+
+
+    pub fn raster_lines(self) -> u16 {
+        match self {
+            Model::Pal     => 312,
+            Model::Ntsc    => 263,
+            Model::NtscOld => 262,
+        }
+    }
+
+
+    pub fn cycles_per_line(self) -> u8 {
+        match self {
+            Model::Pal     => 63,
+            Model::Ntsc    => 65,
+            Model::NtscOld => 64,
+        }
+    }
+
+
+    // mains frequency which drives the CIA TOD clocks
+    pub fn tod_freq(self) -> u32 {
+        match self {
+            Model::Pal => 50,
+            Model::Ntsc | Model::NtscOld => 60,
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Model::Pal     => write!(f, "PAL (6569)"),
+            Model::Ntsc    => write!(f, "NTSC (6567R8)"),
+            Model::NtscOld => write!(f, "old NTSC (6567R56A)"),
+        }
+    }
+}
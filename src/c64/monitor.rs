@@ -0,0 +1,625 @@
+// machine language monitor in the style of VICE and Action Replay: text commands which inspect and
+// change memory and CPU registers while the emulation is paused. Numbers are hexadecimal, '$' is optional.
+use c64::breakpoints;
+use c64::cpu;
+use c64::datasette;
+use c64::disasm;
+use c64::memory;
+use c64::opcodes::AddrMode;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem;
+
+const DUMP_BYTES_PER_LINE: u16 = 16;
+const DUMP_LINES: u16 = 8;
+const DISASM_LINES: u16 = 16;
+
+pub const HELP: &str = "m [start [end]]          memory dump
+d [start [end]]          disassemble
+a <addr> [instruction]   assemble (an empty line ends assembly mode)
+r [reg=value ...]        show or change registers (pc, a, x, y, sp, p)
+f <start> <end> <bytes>  fill memory with a byte pattern
+t <start> <end> <dest>   transfer memory
+c <start> <end> <dest>   compare memory
+h <start> <end> <bytes>  hunt for a byte sequence
+l \"file\" [addr]          load a .prg file (at its own address unless given)
+s \"file\" <start> <end>   save memory as a .prg file
+break [addr [end]] [if <cond>]
+                         list breakpoints or stop before executing an instruction in the range
+watch [load|store] <addr> [end] [if <cond>]
+                         stop after an instruction which reads and/or writes the range
+delete [id]              remove a breakpoint (all without an id)
+enable <id>, disable <id>
+ignore <id> [count]      don't stop on the next count hits
+condition <id> [<cond>]  set or remove the register condition, e.g. a == 01 or pc >= c000
+attach \"file\" [1|8]     put a disk image into drive 8, or a .tap tape image into the datasette (1)
+detach [1|8]             take the disk out of drive 8, or the tape out of the datasette
+tape <play|stop|rewind>  press a datasette button
+g [addr]                 go (continue at addr)
+x                        exit the monitor and continue";
+
+
+// what the emulator should do after a command
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Stay,
+    Resume,
+    AttachDisk(String), // left to the machine, which owns the drive
+    DetachDisk,
+    AttachTape(String), // and the datasette
+    DetachTape,
+    Tape(datasette::Button),
+}
+
+
+pub struct Monitor {
+    dump_addr: u16,             // where m continues without arguments
+    disasm_addr: u16,           // where d continues without arguments
+    assemble_addr: Option<u16>, // in assembly mode: address of the next instruction
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            dump_addr: 0,
+            disasm_addr: 0,
+            assemble_addr: None,
+        }
+    }
+
+
+    // called when the emulation is paused: returns the register display
+    pub fn enter(&mut self, cpu: &cpu::CPU) -> String {
+        self.dump_addr = cpu.pc;
+        self.disasm_addr = cpu.pc;
+        self.assemble_addr = None;
+        registers(cpu)
+    }
+
+
+    pub fn prompt(&self, cpu: &cpu::CPU) -> String {
+        match self.assemble_addr {
+            Some(addr) => format!("A ${:04X} ", addr),
+            None       => format!("(C:${:04X}) ", cpu.pc),
+        }
+    }
+
+
+    // run a single command line; its output is appended to out
+    pub fn execute(&mut self, line: &str, cpu: &mut cpu::CPU, memory: &mut memory::Memory, out: &mut String) -> Action {
+        let result = match self.assemble_addr {
+            Some(addr) => self.assemble_line(addr, line.trim(), memory),
+            None       => self.run_command(line.trim(), cpu, memory, out),
+        };
+
+        match result {
+            Ok(action) => action,
+            Err(e) => {
+                let _ = writeln!(out, "Error: {}", e);
+                Action::Stay
+            }
+        }
+    }
+
+
+    // *** private functions *** //
+
+    fn run_command(&mut self, line: &str, cpu: &mut cpu::CPU, memory: &mut memory::Memory, out: &mut String) -> Result<Action, String> {
+        let args = tokenize(line)?;
+        let (command, args) = match args.split_first() {
+            Some((command, args)) => (command.to_lowercase(), args),
+            None => return Ok(Action::Stay),
+        };
+
+        match &command[..] {
+            "m" => {
+                let (start, end) = optional_range(args, self.dump_addr)?;
+                let end = end.unwrap_or_else(|| start.saturating_add(DUMP_BYTES_PER_LINE * DUMP_LINES - 1));
+                self.dump_addr = dump(start, end, memory, out);
+            },
+            "d" => {
+                let (start, end) = optional_range(args, self.disasm_addr)?;
+                self.disasm_addr = disassemble(start, end, memory, out);
+            },
+            "a" => {
+                let addr = parse_word(arg(args, 0)?)?;
+                self.assemble_addr = Some(addr);
+                if args.len() > 1 {
+                    return self.assemble_line(addr, &args[1..].join(" "), memory);
+                }
+            },
+            "r" => {
+                if !args.is_empty() {
+                    set_registers(&args.join(" "), cpu)?;
+                }
+                out.push_str(&registers(cpu));
+            },
+            "f" => {
+                let (start, end) = range(args)?;
+                let pattern = parse_bytes(&args[2..])?;
+                for (i, addr) in (start..=end).enumerate() {
+                    memory.write_byte(addr, pattern[i % pattern.len()]);
+                }
+            },
+            "t" => {
+                let (start, end) = range(args)?;
+                let dest = parse_word(arg(args, 2)?)?;
+                // read everything first, the areas may overlap
+                let data: Vec<u8> = (start..=end).map(|addr| memory.read_byte(addr)).collect();
+                for (i, value) in data.iter().enumerate() {
+                    memory.write_byte(dest.wrapping_add(i as u16), *value);
+                }
+            },
+            "c" => {
+                let (start, end) = range(args)?;
+                let dest = parse_word(arg(args, 2)?)?;
+                for (i, addr) in (start..=end).enumerate() {
+                    let other = dest.wrapping_add(i as u16);
+                    let (a, b) = (memory.read_byte(addr), memory.read_byte(other));
+                    if a != b {
+                        let _ = writeln!(out, "${:04X} ${:02X}  ${:04X} ${:02X}", addr, a, other, b);
+                    }
+                }
+            },
+            "h" => {
+                let (start, end) = range(args)?;
+                let pattern = parse_bytes(&args[2..])?;
+                let data: Vec<u8> = (start..=end).map(|addr| memory.read_byte(addr)).collect();
+                for (i, window) in data.windows(pattern.len()).enumerate() {
+                    if window == pattern.as_slice() {
+                        let _ = writeln!(out, "${:04X}", start as usize + i);
+                    }
+                }
+            },
+            "l" => {
+                let filename = filename(arg(args, 0)?)?;
+                let data = read_file(filename)?;
+                if data.len() < 3 {
+                    return Err(format!("{} is too short for a program file", filename));
+                }
+
+                let start = match args.get(1) {
+                    Some(addr) => parse_word(addr)?,
+                    None => (data[1] as u16) << 8 | data[0] as u16,
+                };
+
+                for (i, value) in data[2..].iter().enumerate() {
+                    memory.write_byte(start.wrapping_add(i as u16), *value);
+                }
+
+                let _ = writeln!(out, "Loaded {} from ${:04X} to ${:04X}", filename, start, start.wrapping_add((data.len() - 3) as u16));
+            },
+            "s" => {
+                let filename = filename(arg(args, 0)?)?;
+                let (start, end) = range(&args[1..])?;
+                let mut data = vec![start as u8, (start >> 8) as u8];
+                data.extend((start..=end).map(|addr| memory.read_byte(addr)));
+
+                File::create(filename).and_then(|mut f| f.write_all(&data)).map_err(|e| format!("{}: {}", filename, e))?;
+                let _ = writeln!(out, "Saved {} from ${:04X} to ${:04X}", filename, start, end);
+            },
+            "break" | "bk" => {
+                let (args, condition) = split_condition(args)?;
+                if args.is_empty() && condition.is_none() {
+                    list_breakpoints(&cpu.breakpoints, out);
+                }
+                else {
+                    let (start, end) = address_range(args)?;
+                    let id = cpu.breakpoints.add(start, end, true, false, false, condition);
+                    let _ = writeln!(out, "Breakpoint #{} added", id);
+                }
+            },
+            "watch" | "w" => {
+                let (args, condition) = split_condition(args)?;
+                let (load, store, args) = match args.first().map(|a| a.to_lowercase()) {
+                    Some(ref kind) if kind == "load"  => (true, false, &args[1..]),
+                    Some(ref kind) if kind == "store" => (false, true, &args[1..]),
+                    _ => (true, true, args),
+                };
+
+                let (start, end) = address_range(args)?;
+                let id = cpu.breakpoints.add(start, end, false, load, store, condition);
+                let _ = writeln!(out, "Watchpoint #{} added", id);
+            },
+            "delete" | "del" => match args.first() {
+                Some(id) => {
+                    let id = parse_id(id)?;
+                    check_id(cpu.breakpoints.remove(id), id)?;
+                },
+                None => cpu.breakpoints.clear(),
+            },
+            "enable" | "disable" => {
+                let id = parse_id(arg(args, 0)?)?;
+                check_id(cpu.breakpoints.set_enabled(id, command == "enable"), id)?;
+            },
+            "ignore" => {
+                let id = parse_id(arg(args, 0)?)?;
+                let count = match args.get(1) {
+                    Some(count) => count.parse::<u32>().map_err(|_| format!("invalid count: {}", count))?,
+                    None => 1,
+                };
+                check_id(cpu.breakpoints.set_ignore_count(id, count), id)?;
+            },
+            "condition" | "cond" => {
+                let id = parse_id(arg(args, 0)?)?;
+                let text = args[1..].join(" ");
+                let text = text.trim_start_matches("if ").trim();
+                let condition = if text.is_empty() { None } else { Some(breakpoints::Condition::parse(text)?) };
+                check_id(cpu.breakpoints.set_condition(id, condition), id)?;
+            },
+            "attach" => {
+                let filename = filename(arg(args, 0)?)?.to_string();
+                return Ok(if parse_device(args.get(1))? == 1 { Action::AttachTape(filename) } else { Action::AttachDisk(filename) });
+            },
+            "detach" => {
+                return Ok(if parse_device(args.first())? == 1 { Action::DetachTape } else { Action::DetachDisk });
+            },
+            "tape" => {
+                let button = match &arg(args, 0)?.to_lowercase()[..] {
+                    "play"   => datasette::Button::Play,
+                    "stop"   => datasette::Button::Stop,
+                    "rewind" => datasette::Button::Rewind,
+                    other    => return Err(format!("no such button: {}", other)),
+                };
+                return Ok(Action::Tape(button));
+            },
+            "g" => {
+                if let Some(addr) = args.first() {
+                    cpu.pc = parse_word(addr)?;
+                }
+                return Ok(Action::Resume);
+            },
+            "x" => return Ok(Action::Resume),
+            "?" | "help" => {
+                out.push_str(HELP);
+                out.push('\n');
+            },
+            _ => return Err(format!("unknown command: {} (? for help)", command)),
+        }
+
+        Ok(Action::Stay)
+    }
+
+
+    fn assemble_line(&mut self, addr: u16, line: &str, memory: &mut memory::Memory) -> Result<Action, String> {
+        if line.is_empty() {
+            self.assemble_addr = None;
+            return Ok(Action::Stay);
+        }
+
+        let bytes = assemble(line, addr)?;
+        for (i, value) in bytes.iter().enumerate() {
+            memory.write_byte(addr.wrapping_add(i as u16), *value);
+        }
+
+        self.assemble_addr = Some(addr.wrapping_add(bytes.len() as u16));
+        Ok(Action::Stay)
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Monitor {
+        Monitor::new()
+    }
+}
+
+
+// register display, VICE style
+pub fn registers(cpu: &cpu::CPU) -> String {
+    format!("  ADDR A  X  Y  SP NV-BDIZC\n.;{:04X} {:02X} {:02X} {:02X} {:02X} {:08b}\n", cpu.pc, cpu.a, cpu.x, cpu.y, cpu.sp, cpu.p)
+}
+
+
+// assemble a single instruction in the disassembler's syntax (e.g. "LDA ($FB),Y") to be placed at addr;
+// undocumented opcodes are accepted too, documented ones are preferred where both exist
+pub fn assemble(text: &str, addr: u16) -> Result<Vec<u8>, String> {
+    let text = text.trim().to_uppercase();
+    let (mnemonic, operand) = match text.find(' ') {
+        Some(idx) => (&text[..idx], text[idx..].replace(' ', "")),
+        None      => (&text[..], String::new()),
+    };
+
+    let (modes, value) = operand_modes(&operand)?;
+    let mut found: Option<disasm::Instruction> = None;
+
+    // the first mode that exists for the mnemonic wins (zero page before absolute)
+    for mode in &modes {
+        for opcode in 0..=255u8 {
+            let instruction = disasm::Instruction::decode_with(addr, |a| if a == addr { opcode } else { 0 });
+            if instruction.op.to_string() != mnemonic || mem::discriminant(&instruction.addr_mode) != mem::discriminant(mode) {
+                continue;
+            }
+
+            let better = match found {
+                Some(ref f) => f.illegal && !instruction.illegal,
+                None => true,
+            };
+
+            if better {
+                found = Some(instruction);
+            }
+        }
+
+        if found.is_some() {
+            break;
+        }
+    }
+
+    let instruction = found.ok_or_else(|| format!("can't assemble {}", text))?;
+
+    let value = match instruction.addr_mode {
+        AddrMode::Relative => {
+            let offset = value as i32 - (addr as i32 + 2);
+            if !(-128..=127).contains(&offset) {
+                return Err(format!("branch target ${:04X} out of range", value));
+            }
+            offset as u8 as u16
+        },
+        _ => value,
+    };
+
+    let mut bytes = vec![instruction.opcode, value as u8, (value >> 8) as u8];
+    bytes.truncate(instruction.length as usize);
+    Ok(bytes)
+}
+
+
+// *** private functions *** //
+
+// candidate addressing modes for an operand, in order of preference, along with its value
+fn operand_modes(operand: &str) -> Result<(Vec<AddrMode>, u16), String> {
+    if operand.is_empty() {
+        return Ok((vec![AddrMode::Implied, AddrMode::Accumulator], 0));
+    }
+
+    if operand == "A" {
+        return Ok((vec![AddrMode::Accumulator], 0));
+    }
+
+    if let Some(value) = strip(operand, "#", "") {
+        return Ok((vec![AddrMode::Immediate], parse_byte(value)? as u16));
+    }
+
+    if let Some(inner) = strip(operand, "(", ",X)") {
+        return zeropage_operand(AddrMode::IndexedIndirectX, inner);
+    }
+
+    if let Some(inner) = strip(operand, "(", "),Y") {
+        return zeropage_operand(AddrMode::IndirectIndexedY(false), inner);
+    }
+
+    if let Some(inner) = strip(operand, "(", ")") {
+        return Ok((vec![AddrMode::Indirect], parse_word(inner)?));
+    }
+
+    let (value, zp_modes, abs_modes) = if let Some(value) = strip(operand, "", ",X") {
+        (value, vec![AddrMode::ZeropageIndexedX], vec![AddrMode::AbsoluteIndexedX(false)])
+    } else if let Some(value) = strip(operand, "", ",Y") {
+        (value, vec![AddrMode::ZeropageIndexedY], vec![AddrMode::AbsoluteIndexedY(false)])
+    } else {
+        (operand, vec![AddrMode::Zeropage], vec![AddrMode::Absolute, AddrMode::Relative])
+    };
+
+    let number = parse_word(value)?;
+    // explicitly written with four digits: keep it absolute
+    let zeropage = number <= 0xFF && value.trim_start_matches('$').len() <= 2;
+    let modes = if zeropage { zp_modes.into_iter().chain(abs_modes).collect() } else { abs_modes };
+
+    Ok((modes, number))
+}
+
+
+fn zeropage_operand(mode: AddrMode, text: &str) -> Result<(Vec<AddrMode>, u16), String> {
+    match parse_word(text)? {
+        value if value <= 0xFF => Ok((vec![mode], value)),
+        value => Err(format!("${:04X} is not a zero page address", value)),
+    }
+}
+
+
+fn strip<'a>(text: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    if text.len() > prefix.len() + suffix.len() && text.starts_with(prefix) && text.ends_with(suffix) {
+        Some(&text[prefix.len()..text.len() - suffix.len()])
+    } else {
+        None
+    }
+}
+
+
+// split a command line into words; a quoted file name counts as one word (quotes included)
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        let end = match rest.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(idx) => idx + 2,
+                None => return Err("missing closing quote".to_string()),
+            },
+            None => rest.find(char::is_whitespace).unwrap_or(rest.len()),
+        };
+
+        tokens.push(rest[..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+
+fn arg(args: &[String], idx: usize) -> Result<&str, String> {
+    args.get(idx).map(|a| &a[..]).ok_or_else(|| "missing argument".to_string())
+}
+
+
+fn filename(arg: &str) -> Result<&str, String> {
+    strip(arg, "\"", "\"").ok_or_else(|| format!("file name must be quoted: {}", arg))
+}
+
+
+// the datasette is device 1 and drive 8 the only disk drive there is; 8 if not given
+fn parse_device(device: Option<&String>) -> Result<u8, String> {
+    match device.map(|d| &d[..]) {
+        None | Some("8") => Ok(8),
+        Some("1") => Ok(1),
+        Some(device) => Err(format!("no device {}", device)),
+    }
+}
+
+
+fn parse_word(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16).map_err(|_| format!("invalid address: {}", text))
+}
+
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::from_str_radix(text.trim_start_matches('$'), 16).map_err(|_| format!("invalid byte: {}", text))
+}
+
+
+fn parse_bytes(args: &[String]) -> Result<Vec<u8>, String> {
+    if args.is_empty() {
+        return Err("missing byte values".to_string());
+    }
+
+    args.iter().map(|a| parse_byte(a)).collect()
+}
+
+
+// <start> <end>, inclusive
+fn range(args: &[String]) -> Result<(u16, u16), String> {
+    let start = parse_word(arg(args, 0)?)?;
+    let end = parse_word(arg(args, 1)?)?;
+
+    if end < start {
+        return Err(format!("end ${:04X} is before start ${:04X}", end, start));
+    }
+
+    Ok((start, end))
+}
+
+
+// <addr> [end]
+fn address_range(args: &[String]) -> Result<(u16, u16), String> {
+    match args.len() {
+        1 => parse_word(&args[0]).map(|addr| (addr, addr)),
+        2 => range(args),
+        0 => Err("missing address".to_string()),
+        _ => Err(format!("unexpected argument: {}", args[2])),
+    }
+}
+
+
+// arguments before "if" and the condition after it
+fn split_condition(args: &[String]) -> Result<(&[String], Option<breakpoints::Condition>), String> {
+    match args.iter().position(|a| a.eq_ignore_ascii_case("if")) {
+        Some(idx) => Ok((&args[..idx], Some(breakpoints::Condition::parse(&args[idx + 1..].join(" "))?))),
+        None => Ok((args, None)),
+    }
+}
+
+
+fn parse_id(text: &str) -> Result<u32, String> {
+    text.trim_start_matches('#').parse::<u32>().map_err(|_| format!("invalid breakpoint id: {}", text))
+}
+
+
+fn check_id(found: bool, id: u32) -> Result<(), String> {
+    if found { Ok(()) } else { Err(format!("no breakpoint #{}", id)) }
+}
+
+
+fn list_breakpoints(breakpoints: &breakpoints::Breakpoints, out: &mut String) {
+    if breakpoints.list().is_empty() {
+        out.push_str("No breakpoints\n");
+    }
+
+    for breakpoint in breakpoints.list() {
+        let _ = writeln!(out, "{}", breakpoint);
+    }
+}
+
+
+// [start [end]], both optional
+fn optional_range(args: &[String], default_start: u16) -> Result<(u16, Option<u16>), String> {
+    match args.len() {
+        0 => Ok((default_start, None)),
+        1 => Ok((parse_word(&args[0])?, None)),
+        _ => range(args).map(|(start, end)| (start, Some(end))),
+    }
+}
+
+
+// hex dump with printable characters; returns the address after the last byte shown
+fn dump(start: u16, end: u16, memory: &mut memory::Memory, out: &mut String) -> u16 {
+    let end = end as u32;
+    let mut addr = start as u32;
+
+    while addr <= end {
+        let line_end = (addr + DUMP_BYTES_PER_LINE as u32 - 1).min(end);
+        let bytes: Vec<u8> = (addr..=line_end).map(|a| memory.read_byte(a as u16)).collect();
+
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let text: String = bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+        let _ = writeln!(out, ">C:{:04X}  {:<48} {}", addr, hex.join(" "), text);
+
+        addr = line_end + 1;
+    }
+
+    addr as u16
+}
+
+
+// listing up to end, or a screenful; returns the address of the next instruction
+fn disassemble(start: u16, end: Option<u16>, memory: &mut memory::Memory, out: &mut String) -> u16 {
+    let mut addr = start;
+
+    for line in 0.. {
+        match end {
+            Some(end) if addr > end || addr < start => break,
+            None if line == DISASM_LINES => break,
+            _ => (),
+        }
+
+        let instruction = disasm::Instruction::decode_with(addr, |a| memory.read_byte(a));
+        let _ = writeln!(out, ".C:{:04X}  {:<8}  {}", addr, instruction.hex(), instruction);
+
+        addr = addr.wrapping_add(instruction.length as u16);
+    }
+
+    addr
+}
+
+
+// "pc=c000 a=01" etc.
+fn set_registers(text: &str, cpu: &mut cpu::CPU) -> Result<(), String> {
+    let text = text.replace(" =", "=").replace("= ", "=");
+
+    for assignment in text.split(|c: char| c.is_whitespace() || c == ',').filter(|s| !s.is_empty()) {
+        let (name, value) = match assignment.find('=') {
+            Some(idx) => (assignment[..idx].to_lowercase(), &assignment[idx + 1..]),
+            None => return Err(format!("expected register=value: {}", assignment)),
+        };
+
+        match &name[..] {
+            "pc" => cpu.pc = parse_word(value)?,
+            "a"  => cpu.a  = parse_byte(value)?,
+            "x"  => cpu.x  = parse_byte(value)?,
+            "y"  => cpu.y  = parse_byte(value)?,
+            "sp" => cpu.sp = parse_byte(value)?,
+            "p"  => cpu.p  = parse_byte(value)?,
+            _ => return Err(format!("unknown register: {}", name)),
+        }
+    }
+
+    Ok(())
+}
+
+
+fn read_file(filename: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    File::open(filename).and_then(|mut f| f.read_to_end(&mut data)).map_err(|e| format!("{}: {}", filename, e))?;
+    Ok(data)
+}
@@ -11,9 +11,11 @@
 // ind = ($0000)              // indirect
 // rel = $0000                // relative to PC/IP
 
+use c64::bus;
 use c64::cpu;
 use std::fmt;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddrMode {
     Implied,
     Accumulator,
@@ -30,6 +32,7 @@ pub enum AddrMode {
     IndirectIndexedY(bool)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Op {
     // Load/store
     LDA, LDX, LDY,
@@ -75,62 +78,73 @@ pub enum Op {
 }
 
 pub struct Instruction {
+    pub op_byte: u8,        // raw opcode value (addressing mode and op can be recovered from it)
     pub addr_mode: AddrMode,
     pub opcode: Op,
+    pub cycles: u8,         // number of cycles from the opcode table (without page crossing penalties)
+    pub cycle: u8,          // bus cycle currently being run - the opcode fetch is cycle 0
     pub operand_addr: u16,  // operand address for other modes
     pub index_addr: u16,    // additional address storage for indirect and indexed addressing modes
-    pub cycles_to_fetch: u8, // how many cycles to fetch the operand?
-    pub cycles_to_run: u8, // how many cycles to execute the operation?
-    pub cycles_to_rmw: u8, // how many cycles for additional 2 steps of read-move-write?
     pub is_rmw: bool,
-    pub rmw_buffer: u8,   // data read and stored during rmw stage and used as operand addr during execution for rmw instruction
-    pub zp_crossed: bool, // zero page crossed?
+    pub rmw_buffer: u8,   // data read during the rmw stage and written back unmodified before the result
+    pub zp_crossed: bool, // page crossed by indexing or a branch?
+    pub ba_stalled: bool, // did the VIC stop the CPU in the cycle before the last one?
+    pub interrupt: bool,  // BRK sequence forced by an IRQ or NMI instead of a fetched opcode
 }
 
 impl Instruction {
     pub fn new() -> Instruction {
         Instruction {
+            op_byte: 0,
             opcode: Op::BRK,
             addr_mode: AddrMode::Implied,
+            cycles: 0,
+            cycle: 0,
             operand_addr: 0,
             index_addr: 0,
-            cycles_to_fetch: 0,
-            cycles_to_run: 0,
-            cycles_to_rmw: 0,
             is_rmw: false,
             rmw_buffer: 0,
             zp_crossed: false,
+            ba_stalled: false,
+            interrupt: false,
         }
     }
 
 
-    // takes into account all factors (rmw, fetch cycles, etc) to calculate the number of execution cycles
-    pub fn calculate_cycles(&mut self, total_cycles: u8, is_rmw: bool) {
-        match self.addr_mode {
-            AddrMode::Absolute => self.cycles_to_fetch = 2,
-            AddrMode::Zeropage => self.cycles_to_fetch = 1,
-            AddrMode::Indirect => self.cycles_to_fetch = 2,
-            AddrMode::ZeropageIndexedX => self.cycles_to_fetch = 2,
-            AddrMode::ZeropageIndexedY => self.cycles_to_fetch = 2,
-            AddrMode::IndexedIndirectX => self.cycles_to_fetch = 4,
-            AddrMode::AbsoluteIndexedX(..) => self.cycles_to_fetch = 3,
-            AddrMode::AbsoluteIndexedY(..) => self.cycles_to_fetch = 3,
-            AddrMode::IndirectIndexedY(..) => self.cycles_to_fetch = 4,
-            _ => {}
-        }        
-        
-        self.cycles_to_rmw = if is_rmw { 2 } else { 0 };
-        self.cycles_to_run = total_cycles - self.cycles_to_fetch - self.cycles_to_rmw;
+    // set up the instruction decoded from an opcode; the opcode fetch itself was the first cycle
+    pub fn decode(&mut self, op_byte: u8) -> bool {
+        match get_instruction(op_byte) {
+            Some((opcode, cycles, is_rmw, addr_mode)) => {
+                self.op_byte = op_byte;
+                self.opcode = opcode;
+                self.cycles = cycles;
+                self.is_rmw = is_rmw;
+                self.addr_mode = addr_mode;
+                self.cycle = 1;
+                self.zp_crossed = false;
+                self.ba_stalled = false;
+                self.interrupt = false;
+                true
+            },
+            None => false
+        }
+    }
 
-        // subtract 1 to account for op fetching which already took 1 cycle
-        self.cycles_to_run -= 1;
+
+    // a taken branch which stays on the same page takes 3 cycles, but polls interrupts like a 2 cycle one
+    pub fn is_short_branch(&self) -> bool {
+        match self.opcode {
+            Op::BCC | Op::BCS | Op::BEQ | Op::BMI |
+            Op::BNE | Op::BPL | Op::BVC | Op::BVS => self.cycle == 2 && !self.zp_crossed,
+            _ => false,
+        }
     }
 }
 
-// debug display for opcodes
-impl fmt::Display for Instruction {
+// mnemonics
+impl fmt::Display for Op {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op_name = match self.opcode {
+        let op_name = match *self {
             Op::LDA => "LDA", Op::LDX => "LDX", Op::LDY => "LDY", Op::STA => "STA",
             Op::STX => "STX", Op::STY => "STY", Op::TAX => "TAX", Op::TAY => "TAY",
             Op::TXA => "TXA", Op::TYA => "TYA", Op::TSX => "TSX", Op::TXS => "TXS",
@@ -152,764 +166,700 @@ impl fmt::Display for Instruction {
             Op::DCP => "DCP", Op::AXS => "AXS", Op::ISC => "ISC",
         };
         
-        write!(f, "{}", op_name)
+        f.pad(op_name)
+    }
+}
+
+// debug display for opcodes
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode)
     }
 }
 
 
-pub fn fetch_operand_addr(cpu: &mut cpu::CPU) -> bool {
-    match cpu.instruction.addr_mode {
-        AddrMode::Absolute => {
-            match cpu.instruction.cycles_to_fetch {
-                2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                1 => {
-                    cpu.instruction.operand_addr = cpu.instruction.operand_addr | ((cpu.next_byte() as u16) << 8);
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
+// Every call runs exactly one bus cycle of the current instruction, with the same reads and writes as
+// the real 6510 - including the dummy reads of indexed addressing and the double write of RMW
+// instructions. While the VIC holds BA low the CPU can't read, so a read cycle is repeated until
+// the bus is free again; write cycles go through regardless. Returns true once the instruction is done.
+pub fn run_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> bool {
+    let result = match cpu.instruction.opcode {
+        Op::BRK => brk_cycle(cpu, bus),
+        Op::JSR => jsr_cycle(cpu, bus),
+        Op::RTS => rts_cycle(cpu, bus),
+        Op::RTI => rti_cycle(cpu, bus),
+        Op::JMP => jmp_cycle(cpu, bus),
+        Op::PHA | Op::PHP => push_cycle(cpu, bus),
+        Op::PLA | Op::PLP => pull_cycle(cpu, bus),
+        Op::BCC | Op::BCS | Op::BEQ | Op::BMI |
+        Op::BNE | Op::BPL | Op::BVC | Op::BVS => branch_cycle(cpu, bus),
+        Op::HLT => {
+            // the real CPU locks up until reset
+            cpu.state = cpu::CPUState::Jammed;
+            Cycle::Done
+        },
+        _ => match cpu.instruction.addr_mode {
+            AddrMode::Implied | AddrMode::Accumulator => implied_cycle(cpu, bus),
+            AddrMode::Immediate => immediate_cycle(cpu, bus),
+            _ => memory_cycle(cpu, bus),
+        }
+    };
+
+    match result {
+        Cycle::Stalled => {
+            // only the cycle right before a store can affect what gets written
+            if cpu.instruction.cycle + 2 == cpu.instruction.cycles {
+                cpu.instruction.ba_stalled = true;
             }
+            false
         },
-        AddrMode::AbsoluteIndexedX(extra_cycle) => {
-            match cpu.instruction.cycles_to_fetch {
-                3 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                2 => {
-                    let addr_lo = cpu.instruction.operand_addr;
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
-                    cpu.instruction.operand_addr = ((addr_lo + cpu.x as u16) & 0xFF) | (cpu.instruction.index_addr << 8);
-                    // page crossed?
-                    cpu.instruction.zp_crossed = addr_lo + (cpu.x as u16) >= 0x100;
-
-                    // if instruction has extra cycle on page crossing and it hasn't happened, we don't get
-                    // the extra cycle (finish fetching now)
-                    if !cpu.instruction.zp_crossed && extra_cycle {
-                        cpu.instruction.cycles_to_fetch = 1;
-                    }
-                },
-                1 => { // if page crossed - add 0x100 to operand address
-                    if cpu.instruction.zp_crossed {
-                        cpu.instruction.operand_addr += 0x100;
-                    }
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
+        Cycle::Next => {
+            cpu.instruction.cycle += 1;
+            false
         },
-        AddrMode::AbsoluteIndexedY(extra_cycle) => {
-            match cpu.instruction.cycles_to_fetch {
-                3 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                2 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
-                    let addr_lo = cpu.instruction.operand_addr;
-                    cpu.instruction.operand_addr = ((addr_lo + cpu.y as u16) & 0xFF) | (cpu.instruction.index_addr << 8);
-                    // page crossed?
-                    cpu.instruction.zp_crossed = addr_lo + (cpu.y as u16) >= 0x100;
-                    
-                    // if instruction has extra cycle on page crossing and it hasn't happened, we don't get
-                    // the extra cycle (finish fetching now)
-                    if !cpu.instruction.zp_crossed && extra_cycle {
-                        cpu.instruction.cycles_to_fetch = 1;
-                    }
-                },
-                1 => { // if page crossed - add 0x100 to operand address
-                    if cpu.instruction.zp_crossed { cpu.instruction.operand_addr += 0x100; }
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
+        Cycle::Done => true,
+    }
+}
+
+
+// outcome of a single bus cycle
+enum Cycle {
+    Stalled, // BA is low and this is a read cycle - try again
+    Next,
+    Done,
+}
+
+// how an instruction uses its effective address
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+
+fn access_type(instruction: &Instruction) -> Access {
+    if instruction.is_rmw {
+        return Access::ReadModifyWrite;
+    }
+
+    match instruction.opcode {
+        Op::STA | Op::STX | Op::STY | Op::SAX |
+        Op::AHX | Op::TAS | Op::SHY | Op::SHX => Access::Write,
+        _ => Access::Read,
+    }
+}
+
+
+// number of cycles after the opcode fetch until the effective address is known. Indexed modes need
+// an extra cycle to fix the high byte, which read instructions skip if no page boundary was crossed
+fn address_cycles(instruction: &Instruction, access: Access) -> u8 {
+    let fixup = if instruction.zp_crossed || access != Access::Read { 1 } else { 0 };
+
+    match instruction.addr_mode {
+        AddrMode::Zeropage => 1,
+        AddrMode::ZeropageIndexedX | AddrMode::ZeropageIndexedY => 2,
+        AddrMode::Absolute => 2,
+        AddrMode::AbsoluteIndexedX(..) | AddrMode::AbsoluteIndexedY(..) => 2 + fixup,
+        AddrMode::IndexedIndirectX => 4,
+        AddrMode::IndirectIndexedY(..) => 3 + fixup,
+        _ => 0,
+    }
+}
+
+
+// instructions with a memory operand: addressing cycles followed by the operand access
+fn memory_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    let access = access_type(&cpu.instruction);
+    let addr_cycles = address_cycles(&cpu.instruction, access);
+    let cycle = cpu.instruction.cycle;
+
+    if cycle <= addr_cycles {
+        return address_cycle(cpu, bus, cycle);
+    }
+
+    let addr = cpu.instruction.operand_addr;
+
+    match (access, cycle - addr_cycles) {
+        (Access::Read, 1) => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let value = cpu.read_byte(addr, bus);
+            execute_read(cpu, value);
+            Cycle::Done
+        },
+        (Access::Write, 1) => {
+            store(cpu, bus);
+            Cycle::Done
+        },
+        (Access::ReadModifyWrite, 1) => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            cpu.instruction.rmw_buffer = cpu.read_byte(addr, bus);
+            Cycle::Next
+        },
+        (Access::ReadModifyWrite, 2) => {
+            // the unmodified value is written back while the new one is calculated
+            let value = cpu.instruction.rmw_buffer;
+            cpu.write_byte(addr, value, bus);
+            cpu.instruction.rmw_buffer = execute_rmw(cpu, value);
+            Cycle::Next
+        },
+        (Access::ReadModifyWrite, 3) => {
+            let value = cpu.instruction.rmw_buffer;
+            cpu.write_byte(addr, value, bus);
+            Cycle::Done
+        },
+        _ => panic!("Too many cycles for {}: {}", cpu.instruction, cycle)
+    }
+}
+
+
+// builds up the effective address in operand_addr
+fn address_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B, cycle: u8) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; } // all addressing cycles are reads
+
+    match (cpu.instruction.addr_mode, cycle) {
+        (_, 1) => {
+            // zero page address, pointer or low byte of the absolute address
+            let value = cpu.next_byte(bus) as u16;
+            cpu.instruction.operand_addr = value;
+            cpu.instruction.index_addr = value;
+        },
+        (AddrMode::ZeropageIndexedX, 2) | (AddrMode::ZeropageIndexedY, 2) => {
+            let base = cpu.instruction.operand_addr;
+            cpu.read_byte(base, bus);
+            let index = if let AddrMode::ZeropageIndexedX = cpu.instruction.addr_mode { cpu.x } else { cpu.y };
+            cpu.instruction.operand_addr = (base + index as u16) & 0xFF;
+        },
+        (AddrMode::Absolute, 2) => {
+            let hi = cpu.next_byte(bus) as u16;
+            cpu.instruction.operand_addr |= hi << 8;
+        },
+        (AddrMode::AbsoluteIndexedX(..), 2) | (AddrMode::AbsoluteIndexedY(..), 2) => {
+            let hi = cpu.next_byte(bus) as u16;
+            let index = if let AddrMode::AbsoluteIndexedX(..) = cpu.instruction.addr_mode { cpu.x } else { cpu.y };
+            set_indexed_addr(cpu, hi, index);
+        },
+        (AddrMode::AbsoluteIndexedX(..), 3) | (AddrMode::AbsoluteIndexedY(..), 3) |
+        (AddrMode::IndirectIndexedY(..), 4) => {
+            // read from the address before the high byte is fixed
+            let addr = cpu.instruction.operand_addr;
+            cpu.read_byte(addr, bus);
+            if cpu.instruction.zp_crossed {
+                cpu.instruction.operand_addr = addr.wrapping_add(0x100);
             }
         },
-        AddrMode::Zeropage => {
-            cpu.instruction.operand_addr = cpu.next_byte() as u16;
+        (AddrMode::IndexedIndirectX, 2) => {
+            let ptr = cpu.instruction.index_addr;
+            cpu.read_byte(ptr, bus);
+            cpu.instruction.index_addr = (ptr + cpu.x as u16) & 0xFF;
         },
-        AddrMode::ZeropageIndexedX => {
-            match cpu.instruction.cycles_to_fetch {
-                2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                1 => {
-                    let x = cpu.x as u16;
-                    let base_addr = cpu.instruction.operand_addr;
-                    cpu.instruction.operand_addr = (base_addr.wrapping_add(x) as u16) & 0xFF;
-                }
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
+        (AddrMode::IndexedIndirectX, 3) => {
+            let ptr = cpu.instruction.index_addr;
+            cpu.instruction.operand_addr = cpu.read_byte(ptr, bus) as u16;
         },
-        AddrMode::ZeropageIndexedY => {
-            match cpu.instruction.cycles_to_fetch {
-                2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                1 => {
-                    let y = cpu.y as u16;
-                    let base_addr = cpu.instruction.operand_addr;
-                    cpu.instruction.operand_addr = (base_addr.wrapping_add(y) as u16) & 0xFF;
-                }
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
+        (AddrMode::IndexedIndirectX, 4) => {
+            let ptr = (cpu.instruction.index_addr + 1) & 0xFF;
+            let hi = cpu.read_byte(ptr, bus) as u16;
+            cpu.instruction.operand_addr |= hi << 8;
         },
-        AddrMode::IndexedIndirectX => {
-            match cpu.instruction.cycles_to_fetch {
-                4 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
-                },
-                3 => {
-                    cpu.instruction.index_addr = (cpu.instruction.index_addr + cpu.x as u16) & 0xFF;
-                },
-                2 => {
-                    let idx_addr = cpu.instruction.index_addr;
-                    cpu.instruction.operand_addr =  cpu.read_byte(idx_addr) as u16;
-                },
-                1 => {
-                    let idx = cpu.instruction.index_addr;
-                    let hi = cpu.read_byte((idx + 1) & 0xFF) as u16;
-                    cpu.instruction.operand_addr = cpu.instruction.operand_addr | (hi << 8);
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
+        (AddrMode::IndirectIndexedY(..), 2) => {
+            let ptr = cpu.instruction.index_addr;
+            cpu.instruction.operand_addr = cpu.read_byte(ptr, bus) as u16;
         },
-        AddrMode::IndirectIndexedY(extra_cycle) => {
-            match cpu.instruction.cycles_to_fetch {
-                4 => {
-                    cpu.instruction.index_addr = cpu.next_byte() as u16;
-                },
-                3 => {
-                    let base_addr = cpu.instruction.index_addr;
-                    cpu.instruction.operand_addr = cpu.read_byte(base_addr) as u16;
-                },
-                2 => {
-                    let idx = cpu.instruction.index_addr;
-                    let opaddr = cpu.instruction.operand_addr;
-                    cpu.instruction.index_addr =  cpu.read_byte((idx + 1) & 0xFF ) as u16;
-                    cpu.instruction.operand_addr = ((opaddr + cpu.y as u16) & 0x0FF) | (cpu.instruction.index_addr << 8);
-                    // page crossed?
-                    cpu.instruction.zp_crossed = opaddr + (cpu.y as u16) >= 0x100;
-
-                    // if instruction has extra cycle on page crossing and it hasn't happened, we don't get
-                    // the extra cycle (finish fetching now)
-                    if !cpu.instruction.zp_crossed && extra_cycle {
-                        cpu.instruction.cycles_to_fetch = 1;
-                    }
-                },
-                1 => { // if page crossed - add 0x100 to operand address
-                    if cpu.instruction.zp_crossed { cpu.instruction.operand_addr += 0x100; }
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
-        },
-        AddrMode::Indirect => {
-            match cpu.instruction.cycles_to_fetch {
-                2 => {
-                    cpu.instruction.operand_addr = cpu.next_byte() as u16;
-                },
-                1 => {
-                    let addr = cpu.instruction.operand_addr | ((cpu.next_byte() as u16) << 8);
-                    cpu.instruction.operand_addr = cpu.read_word_le(addr);
-                },
-                _ => panic!("Too many cycles for operand address fetch! ({}) ", cpu.instruction.cycles_to_fetch)
-            }
+        (AddrMode::IndirectIndexedY(..), 3) => {
+            let ptr = (cpu.instruction.index_addr + 1) & 0xFF;
+            let hi = cpu.read_byte(ptr, bus) as u16;
+            let y = cpu.y;
+            set_indexed_addr(cpu, hi, y);
         },
-        _ => {}
+        _ => panic!("Too many cycles for operand address fetch of {}: {}", cpu.instruction, cycle)
     }
 
-    cpu.instruction.cycles_to_fetch -= 1;
-    // fetch complete?
-    cpu.instruction.cycles_to_fetch == 0
+    Cycle::Next
 }
 
 
-// runs the instruction at its current cycles
-pub fn run(cpu: &mut cpu::CPU) -> bool {
+// add the index to the low byte only - the carry into the high byte takes another cycle
+fn set_indexed_addr(cpu: &mut cpu::CPU, hi: u16, index: u8) {
+    let lo = cpu.instruction.operand_addr + index as u16;
+    cpu.instruction.zp_crossed = lo > 0xFF;
+    cpu.instruction.index_addr = hi;
+    cpu.instruction.operand_addr = (hi << 8) | (lo & 0xFF);
+}
+
+
+// single byte instructions still read the byte after the opcode
+fn implied_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+    let pc = cpu.pc;
+    cpu.read_byte(pc, bus);
+
     match cpu.instruction.opcode {
-        Op::LDA => {
-            if cpu.ba_low { return false; }
-            let na = cpu.get_operand();
-            cpu.a = na;
-            cpu.set_zn_flags(na);
-        },
-        Op::LDX => {
-            if cpu.ba_low { return false; }
-            let nx = cpu.get_operand();
-            cpu.x = nx;
-            cpu.set_zn_flags(nx);
-        },
-        Op::LDY => {
-            if cpu.ba_low { return false; }
-            let ny = cpu.get_operand();
-            cpu.y = ny;
-            cpu.set_zn_flags(ny);
-        },
-        Op::STA => {
-            let a = cpu.a;
-            cpu.set_operand(a);
-        },
-        Op::STX => {
-            let x = cpu.x;
-            cpu.set_operand(x);
-        },
-        Op::STY => {
-            let y = cpu.y;
-            cpu.set_operand(y);
-        },
-        Op::TAX => {
-            if cpu.ba_low { return false; }
-            cpu.x = cpu.a;
-            let x = cpu.x;
-            cpu.set_zn_flags(x);
-        },
-        Op::TAY => {
-            if cpu.ba_low { return false; }
-            cpu.y = cpu.a;
-            let y = cpu.y;
-            cpu.set_zn_flags(y);
-        },
-        Op::TXA => {
-            if cpu.ba_low { return false; }
-            cpu.a = cpu.x;
-            let a = cpu.a;
-            cpu.set_zn_flags(a);
-        },
-        Op::TYA => {
-            if cpu.ba_low { return false; }
-            cpu.a = cpu.y;
+        Op::TAX => { cpu.x = cpu.a; let x = cpu.x; cpu.set_zn_flags(x); },
+        Op::TAY => { cpu.y = cpu.a; let y = cpu.y; cpu.set_zn_flags(y); },
+        Op::TXA => { cpu.a = cpu.x; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::TYA => { cpu.a = cpu.y; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::TSX => { cpu.x = cpu.sp; let x = cpu.x; cpu.set_zn_flags(x); },
+        Op::TXS => cpu.sp = cpu.x,
+        Op::INX => { cpu.x = cpu.x.wrapping_add(0x01); let x = cpu.x; cpu.set_zn_flags(x); },
+        Op::INY => { cpu.y = cpu.y.wrapping_add(0x01); let y = cpu.y; cpu.set_zn_flags(y); },
+        Op::DEX => { cpu.x = cpu.x.wrapping_sub(0x01); let x = cpu.x; cpu.set_zn_flags(x); },
+        Op::DEY => { cpu.y = cpu.y.wrapping_sub(0x01); let y = cpu.y; cpu.set_zn_flags(y); },
+        Op::CLC => cpu.set_status_flag(cpu::StatusFlag::Carry, false),
+        Op::CLD => cpu.set_status_flag(cpu::StatusFlag::DecimalMode, false),
+        Op::CLI => cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, false),
+        Op::CLV => cpu.set_status_flag(cpu::StatusFlag::Overflow, false),
+        Op::SEC => cpu.set_status_flag(cpu::StatusFlag::Carry, true),
+        Op::SED => cpu.set_status_flag(cpu::StatusFlag::DecimalMode, true),
+        Op::SEI => cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true),
+        Op::NOP => (),
+        // shifts and rotations of the accumulator
+        _ => {
             let a = cpu.a;
-            cpu.set_zn_flags(a);
-        },
-        Op::TSX => {
-            if cpu.ba_low { return false; }
-            cpu.x = cpu.sp;
-            let x = cpu.x;
-            cpu.set_zn_flags(x);
-        },
-        Op::TXS => {
-            if cpu.ba_low { return false; }
-            cpu.sp = cpu.x;
-        },
-        Op::PHA => {
-            match cpu.instruction.cycles_to_run {
-                2 => {
-                    if cpu.ba_low { return false; }
-                },
-                1 => {
-                    let a = cpu.a;
-                    cpu.push_byte(a);
-                },
-                _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
-        },
-        Op::PHP => {
-            match cpu.instruction.cycles_to_run {
-                2 => {
-                    if cpu.ba_low { return false; }
-                },
-                1 => {
-                    let p = cpu.p;
-                    // TODO: break flag?
-                    cpu.push_byte(p);
-                },
-                _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
-        },
-        Op::PLA => {
-            if cpu.ba_low { return false; }
-            match cpu.instruction.cycles_to_run {
-                3 | 2 => {},
-                1 => {
-                    let a = cpu.pop_byte();
-                    cpu.a = a;
-                    cpu.set_zn_flags(a);
-                },
-                _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
-        },
-        Op::PLP => {
-            if cpu.ba_low { return false; }
-            match cpu.instruction.cycles_to_run {
-                3 | 2 => {},
-                1 => {
-                    // TODO: opflags
-                    let p = cpu.pop_byte();
-                    cpu.p = p;
-                },
-                _ => panic!("Wrong number of cycles: {} {}", cpu.instruction, cpu.instruction.cycles_to_run)
+            cpu.a = execute_rmw(cpu, a);
+        }
+    }
+
+    Cycle::Done
+}
+
+
+fn immediate_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+    let value = cpu.next_byte(bus);
+    execute_read(cpu, value);
+    Cycle::Done
+}
+
+
+// BRK, and the same sequence forced by an IRQ or NMI, which doesn't skip the byte after the opcode
+fn brk_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    match cpu.instruction.cycle {
+        1 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            if !cpu.instruction.interrupt {
+                cpu.pc = pc.wrapping_add(1);
             }
         },
-        Op::AND => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let na = cpu.a & v;
-            cpu.a = na;
-            cpu.set_zn_flags(na);
-        },
-        Op::EOR => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let na = cpu.a ^ v;
-            cpu.a = na;
-            cpu.set_zn_flags(na);
-        },
-        Op::ORA => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let na = cpu.a | v;
-            cpu.a = na;
-            cpu.set_zn_flags(na);
-        },
-        Op::BIT => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let a = cpu.a;
-            cpu.set_status_flag(cpu::StatusFlag::Negative, (v as i8) < 0);
-            cpu.set_status_flag(cpu::StatusFlag::Overflow, (v & 0x40) != 0);
-            cpu.set_status_flag(cpu::StatusFlag::Zero,     (v & a)    == 0);
-        },
-        Op::ADC => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            cpu.adc(v);
-        },
-        Op::SBC => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            cpu.sbc(v);
-        },
-        Op::CMP => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let res = cpu.a as i16 - v as i16;
-            cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
-            cpu.set_zn_flags(res as u8);
-        },
-        Op::CPX => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let res = cpu.x as i16 - v as i16;
-            cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
-            cpu.set_zn_flags(res as u8);
-        },
-        Op::CPY => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let res = cpu.y as i16 - v as i16;
-            cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
-            cpu.set_zn_flags(res as u8);
+        2 => {
+            let pc_hi = (cpu.pc >> 8) as u8;
+            cpu.push_byte(pc_hi, bus);
         },
-        Op::INC => {
-            let v = cpu.instruction.rmw_buffer.wrapping_add(0x01);
-            let addr = cpu.instruction.operand_addr;
-            cpu.write_byte(addr, v);
-            cpu.set_zn_flags(v);
-        },
-        Op::INX => {
-            if cpu.ba_low { return false; }
-            cpu.x = cpu.x.wrapping_add(0x01);
-            let x = cpu.x;
-            cpu.set_zn_flags(x);
+        3 => {
+            let pc_lo = cpu.pc as u8;
+            cpu.push_byte(pc_lo, bus);
         },
-        Op::INY => {
-            if cpu.ba_low { return false; }
-            cpu.y = cpu.y.wrapping_add(0x01);
-            let y = cpu.y;
-            cpu.set_zn_flags(y);
-        },
-        Op::DEC => {
-            let v = cpu.instruction.rmw_buffer.wrapping_sub(0x01);
-            let addr = cpu.instruction.operand_addr;
-            cpu.write_byte(addr, v);
-            cpu.set_zn_flags(v);
-        },
-        Op::DEX => {
-            if cpu.ba_low { return false; }
-            cpu.x = cpu.x.wrapping_sub(0x01);
-            let x = cpu.x;
-            cpu.set_zn_flags(x);
-        },
-        Op::DEY => {
-            if cpu.ba_low { return false; }
-            cpu.y = cpu.y.wrapping_sub(0x01);
-            let y = cpu.y;
-            cpu.set_zn_flags(y);
-        },
-        Op::ASL => {
-            if cpu.ba_low {
-                match cpu.instruction.addr_mode {
-                    AddrMode::Accumulator => return false,
-                    _ => (),
-                }
+        4 => {
+            // B only exists on the stack - it tells BRK apart from an interrupt
+            let brk = if cpu.instruction.interrupt { 0 } else { cpu::StatusFlag::Break as u8 };
+            let p = (cpu.p & !(cpu::StatusFlag::Break as u8)) | brk | cpu::StatusFlag::Unused as u8;
+            cpu.push_byte(p, bus);
+
+            // an NMI occurring up to this point hijacks the sequence
+            cpu.instruction.index_addr = if cpu.nmi { cpu::NMI_VECTOR } else { cpu::IRQ_VECTOR };
+            if cpu.nmi {
+                cpu.nmi = false;
             }
-            let v = cpu.get_operand();
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x80) != 0);
-            let res = v << 1;
-            cpu.set_operand(res);
-            cpu.set_zn_flags(res);
         },
-        Op::LSR => {
-            if cpu.ba_low {
-                match cpu.instruction.addr_mode {
-                    AddrMode::Accumulator => return false,
-                    _ => (),
-                }
-            }
-            let v = cpu.get_operand();
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x01) != 0);
-            let res = v >> 1;
-            cpu.set_operand(res);
-            cpu.set_zn_flags(res);
+        5 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let vector = cpu.instruction.index_addr;
+            cpu.instruction.operand_addr = cpu.read_byte(vector, bus) as u16;
+            cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true);
         },
-        Op::ROL => {
-            if cpu.ba_low {
-                match cpu.instruction.addr_mode {
-                    AddrMode::Accumulator => return false,
-                    _ => (),
-                }
-            }
-            let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let v = cpu.get_operand();
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x80) != 0);
-            let mut res = v << 1;
-            if c {
-                res |= 0x01;
-            }
-            cpu.set_operand(res);
-            cpu.set_zn_flags(res);
+        6 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let vector = cpu.instruction.index_addr + 1;
+            let hi = cpu.read_byte(vector, bus) as u16;
+            cpu.pc = (hi << 8) | cpu.instruction.operand_addr;
+            return Cycle::Done;
         },
-        Op::ROR => {
-            if cpu.ba_low {
-                match cpu.instruction.addr_mode {
-                    AddrMode::Accumulator => return false,
-                    _ => (),
-                }
-            }
-            let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let v = cpu.get_operand();
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x01) != 0);
-            let mut res = v >> 1;
-            if c {
-                res |= 0x80;
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+
+    Cycle::Next
+}
+
+
+fn jsr_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    match cpu.instruction.cycle {
+        1 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            cpu.instruction.operand_addr = cpu.next_byte(bus) as u16;
+        },
+        2 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            read_stack(cpu, bus);
+        },
+        3 => {
+            // PC points at the high byte of the target address, which is what RTS expects
+            let pc_hi = (cpu.pc >> 8) as u8;
+            cpu.push_byte(pc_hi, bus);
+        },
+        4 => {
+            let pc_lo = cpu.pc as u8;
+            cpu.push_byte(pc_lo, bus);
+        },
+        5 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let hi = cpu.next_byte(bus) as u16;
+            cpu.pc = (hi << 8) | cpu.instruction.operand_addr;
+            return Cycle::Done;
+        },
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+
+    Cycle::Next
+}
+
+
+fn rts_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+
+    match cpu.instruction.cycle {
+        1 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+        },
+        2 => read_stack(cpu, bus),
+        3 => cpu.pc = cpu.pop_byte(bus) as u16,
+        4 => {
+            let hi = cpu.pop_byte(bus) as u16;
+            cpu.pc |= hi << 8;
+        },
+        5 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            cpu.pc = pc.wrapping_add(1);
+            return Cycle::Done;
+        },
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+
+    Cycle::Next
+}
+
+
+fn rti_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+
+    match cpu.instruction.cycle {
+        1 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+        },
+        2 => read_stack(cpu, bus),
+        3 => cpu.p = cpu.pop_byte(bus),
+        4 => cpu.pc = cpu.pop_byte(bus) as u16,
+        5 => {
+            let hi = cpu.pop_byte(bus) as u16;
+            cpu.pc |= hi << 8;
+            return Cycle::Done;
+        },
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+
+    Cycle::Next
+}
+
+
+fn jmp_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+
+    match cpu.instruction.cycle {
+        1 => cpu.instruction.operand_addr = cpu.next_byte(bus) as u16,
+        2 => {
+            let hi = cpu.next_byte(bus) as u16;
+            let addr = (hi << 8) | cpu.instruction.operand_addr;
+
+            if let AddrMode::Absolute = cpu.instruction.addr_mode {
+                cpu.pc = addr;
+                return Cycle::Done;
             }
-            cpu.set_operand(res);
-            cpu.set_zn_flags(res);
+
+            cpu.instruction.index_addr = addr;
         },
-        Op::JMP => { // TODO: is this ok?
-            if cpu.ba_low { return false; }
-            match cpu.instruction.cycles_to_run {
-                2 => {
-                    //cpu.instruction.cycles_to_run -= 1;
-                },
-                1 | 0 => {
-                    cpu.pc = cpu.instruction.operand_addr;
-                    cpu.instruction.cycles_to_run = 1;
-                },
-                _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
+        3 => {
+            let ptr = cpu.instruction.index_addr;
+            cpu.instruction.operand_addr = cpu.read_byte(ptr, bus) as u16;
         },
-        Op::JSR => { // TODO: is this ok?
-            match cpu.instruction.cycles_to_run {
-                3 => {
-                    // TODO: break down PC push to 2 byte instructions?
-                },
-                2 => {
-                    let pc = cpu.pc - 0x0001;
-                    cpu.push_word(pc);
-                },
-                1  => {
-                    if cpu.ba_low { return false; }
-                    cpu.pc = cpu.instruction.operand_addr;
-                },
-                _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
+        4 => {
+            // the pointer's high byte is read from the same page: JMP ($10FF) reads $10FF and $1000
+            let ptr = cpu.instruction.index_addr;
+            let hi = cpu.read_byte((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF), bus) as u16;
+            cpu.pc = (hi << 8) | cpu.instruction.operand_addr;
+            return Cycle::Done;
         },
-        Op::RTS => {
-            if cpu.ba_low { return false; }
-
-            match cpu.instruction.cycles_to_run {
-                5 | 4 => {},
-                3 => {
-                    let pc_lo = cpu.pop_byte() as u16;
-                    cpu.pc = pc_lo;
-                },
-                2 => {
-                    let pc_hi = cpu.pop_byte() as u16;
-                    cpu.pc |= pc_hi << 8;
-                },
-                1  => {
-                    cpu.pc += 1;
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+
+    Cycle::Next
+}
+
+
+fn push_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    match cpu.instruction.cycle {
+        1 => {
+            if cpu.ba_low { return Cycle::Stalled; }
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            Cycle::Next
+        },
+        2 => {
+            let value = match cpu.instruction.opcode {
+                Op::PHA => cpu.a,
+                // B and the unused bit don't exist in the register - they're always pushed as 1
+                _ => cpu.p | cpu::StatusFlag::Break as u8 | cpu::StatusFlag::Unused as u8,
+            };
+            cpu.push_byte(value, bus);
+            Cycle::Done
+        },
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+}
+
+
+fn pull_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+
+    match cpu.instruction.cycle {
+        1 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            Cycle::Next
+        },
+        2 => {
+            read_stack(cpu, bus);
+            Cycle::Next
+        },
+        3 => {
+            let value = cpu.pop_byte(bus);
+            match cpu.instruction.opcode {
+                Op::PLA => {
+                    cpu.a = value;
+                    cpu.set_zn_flags(value);
                 },
-                _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
-            }
-        },
-        // branching ops: (TODO: take into account forward/back branching?)
-        // take 2 cycles (fetch + execute) if no branch is taken
-        // 3 cycles if branch is taken, no page crossed
-        // 4 cycles if branch is taken, page crossed
-        Op::BCC => {
-            let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BCS => {
-            let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BEQ => {
-            let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Zero);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BNE => {
-            let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Zero);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BMI => {
-            let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Negative);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BPL => {
-            let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Negative);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
-            }
-        },
-        Op::BVC => {
-            let flag_condition = !cpu.get_status_flag(cpu::StatusFlag::Overflow);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
+                _ => cpu.p = value,
             }
+            Cycle::Done
         },
-        Op::BVS => {
-            let flag_condition = cpu.get_status_flag(cpu::StatusFlag::Overflow);
-            let cycle = cpu.instruction.cycles_to_run;
-            if !cpu.branch(flag_condition, cycle) {
-                // ba_low is set - no cycle advancement
-                return false;
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+}
+
+
+// 2 cycles if the branch isn't taken, 3 if it is and 4 if it goes to another page
+fn branch_cycle<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) -> Cycle {
+    if cpu.ba_low { return Cycle::Stalled; }
+
+    match cpu.instruction.cycle {
+        1 => {
+            let offset = cpu.next_byte(bus) as i8;
+            let taken = match cpu.instruction.opcode {
+                Op::BCC => !cpu.get_status_flag(cpu::StatusFlag::Carry),
+                Op::BCS =>  cpu.get_status_flag(cpu::StatusFlag::Carry),
+                Op::BNE => !cpu.get_status_flag(cpu::StatusFlag::Zero),
+                Op::BEQ =>  cpu.get_status_flag(cpu::StatusFlag::Zero),
+                Op::BPL => !cpu.get_status_flag(cpu::StatusFlag::Negative),
+                Op::BMI =>  cpu.get_status_flag(cpu::StatusFlag::Negative),
+                Op::BVC => !cpu.get_status_flag(cpu::StatusFlag::Overflow),
+                _       =>  cpu.get_status_flag(cpu::StatusFlag::Overflow),
+            };
+
+            if !taken {
+                return Cycle::Done;
             }
-        },
-        Op::CLC => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::Carry, false);
-        },
-        Op::CLD => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::DecimalMode, false);
-        },
-        Op::CLI => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, false);
-        },
-        Op::CLV => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::Overflow, false);
-        },
-        Op::SEC => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::Carry, true);
-        },
-        Op::SED => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::DecimalMode, true);
-        },
-        Op::SEI => {
-            if cpu.ba_low { return false; }
-            cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true);
-        },
-        Op::BRK => { // TODO: is this ok? do we have to break down new PC value to 2 cycles? read_word ok here?
-            match cpu.instruction.cycles_to_run {
-                6 => {
-                    if cpu.ba_low { return false; }
-                },
-                5 => {
-                    let pc = cpu.pc + 0x0001;
-                    cpu.push_byte(((pc >> 8) & 0xFF) as u8);
-                },
-                4 => {
-                    let pc = cpu.pc + 0x0001;
-                    cpu.push_byte((pc & 0xFF) as u8);
-                },
-                3 => {
-                    cpu.set_status_flag(cpu::StatusFlag::Break, true);
-                    let p = cpu.p;
-                    cpu.push_byte(p);
-                    cpu.set_status_flag(cpu::StatusFlag::InterruptDisable, true);
-                    if cpu.nmi {
-                        cpu.nmi_cycles_left = 7;
-                        cpu.state = cpu::CPUState::ProcessNMI;
-                    }
-                },
-                2 => {
-                    cpu.first_nmi_cycle += 1; // delay NMI
-                },
-                1  => {
-                    //println!("Received BRK instruction at ${:04X}", cpu.pc-1);
-                    cpu.pc = cpu.read_word_le(cpu::IRQ_VECTOR);
-                },
-                _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
+
+            let target = cpu.pc.wrapping_add(offset as u16);
+            cpu.instruction.operand_addr = target;
+            cpu.instruction.zp_crossed = (target >> 8) != (cpu.pc >> 8);
+            Cycle::Next
+        },
+        2 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            let target = cpu.instruction.operand_addr;
+
+            if cpu.instruction.zp_crossed {
+                // the low byte is updated first
+                cpu.pc = (pc & 0xFF00) | (target & 0x00FF);
+                Cycle::Next
             }
-        },
-        Op::NOP => {
-            if cpu.ba_low { return false; }
-        },
-        Op::RTI => { // TODO is this ok?
-            if cpu.ba_low { return false; }
-
-            match cpu.instruction.cycles_to_run {
-                5 | 4 => {},
-                3 => {
-                    let p = cpu.pop_byte();
-                    cpu.p = p;
-                },
-                2 => {
-                    let pc_lo = cpu.pop_byte() as u16;
-                    cpu.pc = pc_lo;
-                },
-                1  => {
-                    let pc_hi = cpu.pop_byte() as u16;
-                    cpu.pc |= pc_hi << 8;
-                },
-                _ => panic!("Wrong number of cycles: {} {} ", cpu.instruction, cpu.instruction.cycles_to_run)
+            else {
+                cpu.pc = target;
+                Cycle::Done
             }
         },
-        // forbidden ops
-        Op::HLT => {
-            panic!("Received HLT instruction at ${:04X}", cpu.pc-1);
-        },
-        Op::SLO => {
-            let mut v = cpu.instruction.rmw_buffer;
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x80) != 0);
-            v <<= 1;
-            cpu.instruction.rmw_buffer = v;
-            let na = cpu.a | v;
-            cpu.a = na;
-            cpu.set_zn_flags(na);
+        3 => {
+            let pc = cpu.pc;
+            cpu.read_byte(pc, bus);
+            cpu.pc = cpu.instruction.operand_addr;
+            Cycle::Done
         },
+        _ => panic!("Too many cycles for {}", cpu.instruction)
+    }
+}
+
+
+// the stack pointer is put on the bus without being changed
+fn read_stack<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) {
+    let addr = 0x0100 | cpu.sp as u16;
+    cpu.read_byte(addr, bus);
+}
+
+
+// operation of instructions which only read their operand
+fn execute_read(cpu: &mut cpu::CPU, value: u8) {
+    match cpu.instruction.opcode {
+        Op::LDA => { cpu.a = value; cpu.set_zn_flags(value); },
+        Op::LDX => { cpu.x = value; cpu.set_zn_flags(value); },
+        Op::LDY => { cpu.y = value; cpu.set_zn_flags(value); },
+        Op::AND => { cpu.a &= value; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::EOR => { cpu.a ^= value; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::ORA => { cpu.a |= value; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::BIT => {
+            let a = cpu.a;
+            cpu.set_status_flag(cpu::StatusFlag::Negative, (value as i8) < 0);
+            cpu.set_status_flag(cpu::StatusFlag::Overflow, (value & 0x40) != 0);
+            cpu.set_status_flag(cpu::StatusFlag::Zero,     (value & a)    == 0);
+        },
+        Op::ADC => cpu.adc(value),
+        Op::SBC => cpu.sbc(value),
+        Op::CMP => { let a = cpu.a; compare(cpu, a, value); },
+        Op::CPX => { let x = cpu.x; compare(cpu, x, value); },
+        Op::CPY => { let y = cpu.y; compare(cpu, y, value); },
+        Op::NOP => (),
         Op::ANC => {
-            let v = cpu.get_operand();
-            let na = cpu.a & v;
-            cpu.set_zn_flags(na);
-            let n = cpu.get_status_flag(cpu::StatusFlag::Negative);
-            cpu.set_status_flag(cpu::StatusFlag::Carry, n);
+            cpu.a &= value;
+            let a = cpu.a;
+            cpu.set_zn_flags(a);
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (a & 0x80) != 0);
         },
-        Op::RLA => {
-            let tmp = cpu.instruction.rmw_buffer & 0x80;
-            let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            let mut v = cpu.instruction.rmw_buffer << 1;
-            if c {
-                v |= 1;
-            }
-
-            cpu.set_status_flag(cpu::StatusFlag::Carry, tmp != 0);
-            cpu.instruction.rmw_buffer = v;
-            let na = cpu.a & v;
-            cpu.a = na;
-            cpu.set_zn_flags(na);
+        Op::ALR => {
+            let na = cpu.a & value;
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (na & 0x01) != 0);
+            cpu.a = na >> 1;
+            cpu.set_zn_flags(na >> 1);
         },
-        Op::SRE => {
-            let mut v = cpu.instruction.rmw_buffer;
-            cpu.set_status_flag(cpu::StatusFlag::Carry, (v & 0x01) != 0);
-            v >>= 1;
-            cpu.instruction.rmw_buffer = v;
-            let na = cpu.a ^ v;
+        Op::ARR => cpu.arr(value),
+        Op::XAA => { // also known as ANE
+            let na = (cpu.a | cpu.ane_magic) & cpu.x & value;
             cpu.a = na;
             cpu.set_zn_flags(na);
         },
-        Op::RRA => {
-            let mut v = cpu.instruction.rmw_buffer;
-            let tmp = v & 0x01;
-            let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
-            v >>= 1;
-            if c {
-                v |= 0x80;
-            }
-            cpu.set_status_flag(cpu::StatusFlag::Carry, tmp != 0);
-            cpu.adc(v);
-        },
-        Op::SAX => {
-            let v = cpu.a & cpu.x;
-            cpu.set_operand(v);
-        },
-        Op::AHX => {
-            let addr = cpu.instruction.operand_addr;
-            let addr_hi = cpu.instruction.index_addr as u8;
-            let y = cpu.y;
-            cpu.write_byte(addr, y & (addr_hi + 1));
-        },
-        Op::TAS => {
-            let addr = cpu.instruction.operand_addr;
-            let addr_hi = cpu.instruction.index_addr as u8;
-            let a = cpu.a;
-            let x = cpu.x;
-            cpu.sp = a & x;
-            cpu.write_byte(addr, (a & x) & (addr_hi + 1));
-        },
-        Op::SHY => {
-            let addr = cpu.instruction.operand_addr;
-            let addr_hi = cpu.instruction.index_addr as u8;
-            let a = cpu.a;
-            let x = cpu.x;
-            cpu.write_byte(addr, a & x & (addr_hi + 1));
-        },
-        Op::SHX => {
-            let addr = cpu.instruction.operand_addr;
-            let addr_hi = cpu.instruction.index_addr as u8;
-            let x = cpu.x;
-            cpu.write_byte(addr, x & (addr_hi + 1));
-        },
         Op::LAX => {
-            if cpu.ba_low { return false; }
-            let nv = cpu.get_operand();
+            let mut nv = value;
+            // the immediate version (LXA) mixes in a chip-dependent constant
+            if let AddrMode::Immediate = cpu.instruction.addr_mode {
+                nv &= cpu.a | cpu.lxa_magic;
+            }
             cpu.a = nv;
             cpu.x = nv;
             cpu.set_zn_flags(nv);
-        }, 
-        Op::DCP => {
-            let v = cpu.instruction.rmw_buffer.wrapping_sub(0x01);
-            cpu.set_operand(v);
-            let diff = cpu.a as i16 - v as i16;
-            cpu.set_zn_flags(diff as u8);
-            cpu.set_status_flag(cpu::StatusFlag::Carry, diff >= 0);
         },
-        Op::ISC => {
-            cpu.instruction.rmw_buffer += 1;
-            let v = cpu.instruction.rmw_buffer;
-            cpu.set_operand(v);
-            cpu.sbc(v);
+        Op::LAS => {
+            let nv = value & cpu.sp;
+            cpu.a = nv;
+            cpu.x = nv;
+            cpu.sp = nv;
+            cpu.set_zn_flags(nv);
         },
         Op::AXS => {
-            if cpu.ba_low { return false; }
-            let v = cpu.get_operand();
-            let res = (cpu.a & cpu.x) as i16 - v as i16;
+            let res = (cpu.a & cpu.x) as i16 - value as i16;
             cpu.x = res as u8;
             cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
             cpu.set_zn_flags(res as u8);
-        }
-        _ => panic!("Unknown instruction: {} at ${:04X}", cpu.instruction, cpu.pc)
+        },
+        _ => panic!("{} doesn't read its operand", cpu.instruction)
+    }
+}
+
+
+// operation of read-modify-write instructions (and of shifts on the accumulator): returns the new value
+fn execute_rmw(cpu: &mut cpu::CPU, value: u8) -> u8 {
+    let c = cpu.get_status_flag(cpu::StatusFlag::Carry);
+
+    let res = match cpu.instruction.opcode {
+        Op::ASL | Op::SLO => {
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (value & 0x80) != 0);
+            value << 1
+        },
+        Op::LSR | Op::SRE => {
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (value & 0x01) != 0);
+            value >> 1
+        },
+        Op::ROL | Op::RLA => {
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (value & 0x80) != 0);
+            (value << 1) | if c { 0x01 } else { 0x00 }
+        },
+        Op::ROR | Op::RRA => {
+            cpu.set_status_flag(cpu::StatusFlag::Carry, (value & 0x01) != 0);
+            (value >> 1) | if c { 0x80 } else { 0x00 }
+        },
+        Op::INC | Op::ISC => value.wrapping_add(0x01),
+        Op::DEC | Op::DCP => value.wrapping_sub(0x01),
+        _ => panic!("{} isn't a read-modify-write instruction", cpu.instruction)
+    };
+
+    // the undocumented ones combine the result with the accumulator
+    match cpu.instruction.opcode {
+        Op::SLO => { cpu.a |= res; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::RLA => { cpu.a &= res; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::SRE => { cpu.a ^= res; let a = cpu.a; cpu.set_zn_flags(a); },
+        Op::RRA => cpu.adc(res),
+        Op::ISC => cpu.sbc(res),
+        Op::DCP => { let a = cpu.a; compare(cpu, a, res); },
+        _ => cpu.set_zn_flags(res),
+    }
+
+    res
+}
+
+
+fn compare(cpu: &mut cpu::CPU, reg: u8, value: u8) {
+    let res = reg as i16 - value as i16;
+    cpu.set_status_flag(cpu::StatusFlag::Carry, res >= 0);
+    cpu.set_zn_flags(res as u8);
+}
+
+
+// write cycle of the store instructions
+fn store<B: bus::Bus>(cpu: &mut cpu::CPU, bus: &mut B) {
+    let addr = cpu.instruction.operand_addr;
+
+    match cpu.instruction.opcode {
+        Op::STA => { let a = cpu.a; cpu.write_byte(addr, a, bus); },
+        Op::STX => { let x = cpu.x; cpu.write_byte(addr, x, bus); },
+        Op::STY => { let y = cpu.y; cpu.write_byte(addr, y, bus); },
+        Op::SAX => { let v = cpu.a & cpu.x; cpu.write_byte(addr, v, bus); },
+        Op::AHX => { let v = cpu.a & cpu.x; store_unstable(cpu, v, bus); }, // also known as SHA
+        Op::TAS => {
+            let v = cpu.a & cpu.x;
+            cpu.sp = v;
+            store_unstable(cpu, v, bus);
+        },
+        Op::SHY => { let v = cpu.y; store_unstable(cpu, v, bus); },
+        Op::SHX => { let v = cpu.x; store_unstable(cpu, v, bus); },
+        _ => panic!("{} isn't a store instruction", cpu.instruction)
+    }
+}
+
+
+// SHA, SHX, SHY and TAS store the value ANDed with the high byte of the base address + 1.
+// The AND drops out if the VIC stopped the CPU in the cycle before the write, and if the index crossed
+// a page boundary the stored value becomes the high byte of the target address as well
+fn store_unstable<B: bus::Bus>(cpu: &mut cpu::CPU, value: u8, bus: &mut B) {
+    let addr_hi = cpu.instruction.index_addr as u8;
+    let v = if cpu.instruction.ba_stalled { value } else { value & addr_hi.wrapping_add(1) };
+    let mut addr = cpu.instruction.operand_addr;
+
+    if cpu.instruction.zp_crossed {
+        addr = (addr & 0x00FF) | ((v as u16) << 8);
     }
 
-    cpu.instruction.cycles_to_run -= 1;
-    // instruction finished execution?
-    cpu.instruction.cycles_to_run == 0
+    cpu.write_byte(addr, v, bus);
 }
 
 
@@ -1100,7 +1050,7 @@ pub fn get_instruction(opcode: u8) -> Option<(Op, u8, bool, AddrMode)> {
         /* RLA_abx */ 0x3F => (Op::RLA, 7,  true, AddrMode::AbsoluteIndexedX(false)),
         /* HLT     */ 0x42 => (Op::HLT, 1, false, AddrMode::Implied),
         /* SRE_izx */ 0x43 => (Op::SRE, 8,  true, AddrMode::IndexedIndirectX),
-        /* NOP     */ 0x44 => (Op::NOP, 3, false, AddrMode::Implied),
+        /* NOP_zp  */ 0x44 => (Op::NOP, 3, false, AddrMode::Zeropage),
         /* SRE_zp  */ 0x47 => (Op::SRE, 5,  true, AddrMode::Zeropage),
         /* ALR_imm */ 0x4B => (Op::ALR, 2, false, AddrMode::Immediate),
         /* SRE_abs */ 0x4F => (Op::SRE, 6,  true, AddrMode::Absolute),
@@ -1116,10 +1066,10 @@ pub fn get_instruction(opcode: u8) -> Option<(Op, u8, bool, AddrMode)> {
         /* RRA_izx */ 0x63 => (Op::RRA, 8,  true, AddrMode::IndexedIndirectX),
         /* NOP_zp  */ 0x64 => (Op::NOP, 3, false, AddrMode::Zeropage),
         /* RRA_zp  */ 0x67 => (Op::RRA, 5,  true, AddrMode::Zeropage),
-        /* ARR     */ 0x6B => (Op::ARR, 2, false, AddrMode::Implied),
+        /* ARR_imm */ 0x6B => (Op::ARR, 2, false, AddrMode::Immediate),
         /* RRA_abs */ 0x6F => (Op::RRA, 6,  true, AddrMode::Absolute),
         /* HLT     */ 0x72 => (Op::HLT, 1, false, AddrMode::Implied),
-        /* RRA_izy */ 0x73 => (Op::RRA, 8, false, AddrMode::IndirectIndexedY(false)),
+        /* RRA_izy */ 0x73 => (Op::RRA, 8,  true, AddrMode::IndirectIndexedY(false)),
         /* NOP_zpx */ 0x74 => (Op::NOP, 4, false, AddrMode::ZeropageIndexedX),
         /* RRA_zpx */ 0x77 => (Op::RRA, 6,  true, AddrMode::ZeropageIndexedX),
         /* NOP     */ 0x7A => (Op::NOP, 2, false, AddrMode::Implied),
@@ -0,0 +1,40 @@
+// program files: a little-endian load address followed by the data to put there
+use error::Error;
+use utils;
+
+pub struct Prg {
+    pub load_address: u16,
+    pub data: Vec<u8>,
+}
+
+impl Prg {
+    pub fn from_filename(filename: &str) -> Result<Prg, Error> {
+        let prg_data = utils::open_file(filename, 0)?;
+        Prg::from_data(&prg_data).map_err(|e| Error::InvalidPrg(format!("Invalid program file {}: {}", filename, e)))
+    }
+
+
+    pub fn from_data(prg_data: &[u8]) -> Result<Prg, String> {
+        if prg_data.len() < 3 {
+            return Err(format!("file is truncated ({} bytes)", prg_data.len()))
+        }
+
+        let load_address = ((prg_data[1] as u16) << 8) | (prg_data[0] as u16);
+        let data = prg_data[2..].to_vec();
+
+        if load_address as usize + data.len() > 0x10000 {
+            return Err(format!("{} bytes at ${:04x} don't fit in memory", data.len(), load_address))
+        }
+
+        Ok(Prg {
+            load_address,
+            data,
+        })
+    }
+
+
+    // address right after the last byte loaded
+    pub fn end_address(&self) -> u16 {
+        (self.load_address as usize + self.data.len()) as u16
+    }
+}
@@ -0,0 +1,88 @@
+// rewind buffer: a memory-bounded ring of periodic machine snapshots
+use std::collections::VecDeque;
+
+pub struct RewindBuffer {
+    snapshots: VecDeque<Vec<u8>>,
+    max_bytes:  usize, // memory limit for all stored snapshots (0 disables rewinding)
+    used_bytes: usize,
+    interval:  u32,    // number of frames between snapshots
+    frame_cnt: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(max_bytes: usize, interval: u32) -> RewindBuffer {
+        RewindBuffer {
+            snapshots: VecDeque::new(),
+            max_bytes,
+            used_bytes: 0,
+            interval,
+            frame_cnt: 0,
+        }
+    }
+
+
+    pub fn set_limit(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.evict(0);
+    }
+
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+
+    // called once per frame - returns true if a snapshot should be taken now
+    pub fn on_frame(&mut self) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        self.frame_cnt += 1;
+        if self.frame_cnt >= self.interval {
+            self.frame_cnt = 0;
+            return true;
+        }
+
+        false
+    }
+
+
+    pub fn push(&mut self, mut snapshot: Vec<u8>) {
+        if snapshot.len() > self.max_bytes {
+            return;
+        }
+
+        // a snapshot is written into a growing vector, which would leave up to twice the counted size allocated
+        snapshot.shrink_to_fit();
+
+        self.evict(snapshot.len());
+        self.used_bytes += snapshot.len();
+        self.snapshots.push_back(snapshot);
+    }
+
+
+    // take the most recent snapshot off the buffer
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.snapshots.pop_back();
+
+        if let Some(ref data) = snapshot {
+            self.used_bytes -= data.len();
+        }
+
+        self.frame_cnt = 0;
+        snapshot
+    }
+
+
+    // *** private functions *** //
+
+    // drop the oldest snapshots until there's room for extra_bytes
+    fn evict(&mut self, extra_bytes: usize) {
+        while !self.snapshots.is_empty() && self.used_bytes + extra_bytes > self.max_bytes {
+            if let Some(data) = self.snapshots.pop_front() {
+                self.used_bytes -= data.len();
+            }
+        }
+    }
+}
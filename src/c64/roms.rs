@@ -0,0 +1,203 @@
+// system ROM set: loading, validation and identification of BASIC, chargen and kernal images, plus the 1541 DOS ROM
+use error::Error;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use utils;
+
+pub const BASIC_SIZE:   usize = 0x2000;
+pub const CHARGEN_SIZE: usize = 0x1000;
+pub const KERNAL_SIZE:  usize = 0x2000;
+pub const DRIVE_SIZE:   usize = 0x4000; // 1541 DOS, $C000-$FFFF in the drive
+
+// environment variable overriding the default ROM directory
+pub const ROM_DIR_ENV: &str = "RUST64_ROM_DIR";
+const DEFAULT_ROM_DIR: &str = "rom";
+
+// CRC32 checksums of the stock ROMs
+const BASIC_CRC:   u32 = 0xF833D117; // 901226-01
+const CHARGEN_CRC: u32 = 0xEC4272EE; // 901225-01
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernalRevision {
+    R1,
+    R2,
+    R3,
+    Sx64,
+    JiffyDos,
+    Unknown(u32), // CRC32 of an unrecognized image
+}
+
+impl KernalRevision {
+    pub fn identify(data: &[u8]) -> KernalRevision {
+        match utils::crc32(data) {
+            0xDCE782FA => KernalRevision::R1, // 901227-01
+            0xA5C687B3 => KernalRevision::R2, // 901227-02
+            0xDBE3E7C7 => KernalRevision::R3, // 901227-03
+            0x2C5965D4 => KernalRevision::Sx64, // 251104-04
+            // JiffyDOS comes in many versions - recognize it by its power-on banner
+            crc => if data.windows(8).any(|w| w == b"JIFFYDOS") { KernalRevision::JiffyDos } else { KernalRevision::Unknown(crc) }
+        }
+    }
+}
+
+impl fmt::Display for KernalRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KernalRevision::R1       => write!(f, "r1 (901227-01)"),
+            KernalRevision::R2       => write!(f, "r2 (901227-02)"),
+            KernalRevision::R3       => write!(f, "r3 (901227-03)"),
+            KernalRevision::Sx64     => write!(f, "SX-64 (251104-04)"),
+            KernalRevision::JiffyDos => write!(f, "JiffyDOS"),
+            KernalRevision::Unknown(crc) => write!(f, "unknown (CRC32 {:08X})", crc),
+        }
+    }
+}
+
+
+// where to find the ROM images: a file set explicitly wins over the ROM directory
+#[derive(Clone, Debug, Default)]
+pub struct RomPaths {
+    pub dir:     Option<PathBuf>,
+    pub basic:   Option<PathBuf>,
+    pub chargen: Option<PathBuf>,
+    pub kernal:  Option<PathBuf>,
+    pub drive:   Option<PathBuf>,
+}
+
+impl RomPaths {
+    // ROM directory: set explicitly, then $RUST64_ROM_DIR, then ./rom and finally rom/ next to the executable
+    pub fn rom_dir(&self) -> PathBuf {
+        if let Some(ref dir) = self.dir {
+            return dir.clone();
+        }
+
+        if let Some(dir) = env::var_os(ROM_DIR_ENV) {
+            return PathBuf::from(dir);
+        }
+
+        let local_dir = PathBuf::from(DEFAULT_ROM_DIR);
+        if local_dir.is_dir() {
+            return local_dir;
+        }
+
+        if let Ok(exe) = env::current_exe() {
+            if let Some(exe_dir) = exe.parent() {
+                let exe_rom_dir = exe_dir.join(DEFAULT_ROM_DIR);
+                if exe_rom_dir.is_dir() {
+                    return exe_rom_dir;
+                }
+            }
+        }
+
+        local_dir
+    }
+
+
+    // *** private functions *** //
+
+    fn resolve(&self, path: &Option<PathBuf>, file_name: &str) -> PathBuf {
+        match *path {
+            Some(ref path) => path.clone(),
+            None => self.rom_dir().join(file_name),
+        }
+    }
+}
+
+
+pub struct RomSet {
+    pub basic:   Vec<u8>,
+    pub chargen: Vec<u8>,
+    pub kernal:  Vec<u8>,
+}
+
+impl RomSet {
+    pub fn load(paths: &RomPaths) -> Result<RomSet, Error> {
+        let basic   = read_rom(&paths.resolve(&paths.basic, "basic.rom"), "BASIC", BASIC_SIZE)?;
+        let chargen = read_rom(&paths.resolve(&paths.chargen, "chargen.rom"), "chargen", CHARGEN_SIZE)?;
+        let kernal  = read_rom(&paths.resolve(&paths.kernal, "kernal.rom"), "kernal", KERNAL_SIZE)?;
+
+        Ok(RomSet {
+            basic,
+            chargen,
+            kernal,
+        })
+    }
+
+
+    // build a ROM set from images already in memory; only the sizes must match,
+    // since alternative ROMs are allowed
+    pub fn from_data(basic: Vec<u8>, chargen: Vec<u8>, kernal: Vec<u8>) -> Result<RomSet, Error> {
+        check_size(&basic, BASIC_SIZE, "BASIC ROM")?;
+        check_size(&chargen, CHARGEN_SIZE, "chargen ROM")?;
+        check_size(&kernal, KERNAL_SIZE, "kernal ROM")?;
+
+        Ok(RomSet {
+            basic,
+            chargen,
+            kernal,
+        })
+    }
+
+
+    pub fn kernal_revision(&self) -> KernalRevision {
+        KernalRevision::identify(&self.kernal)
+    }
+
+
+    pub fn is_stock_basic(&self) -> bool {
+        utils::crc32(&self.basic) == BASIC_CRC
+    }
+
+
+    pub fn is_stock_chargen(&self) -> bool {
+        utils::crc32(&self.chargen) == CHARGEN_CRC
+    }
+
+
+    // print what's been loaded, with a warning for non-standard images
+    pub fn print_info(&self) {
+        println!("Kernal ROM: {}", self.kernal_revision());
+
+        if !self.is_stock_basic() {
+            println!("Warning: unknown BASIC ROM (CRC32 {:08X})", utils::crc32(&self.basic));
+        }
+
+        if !self.is_stock_chargen() {
+            println!("Warning: unknown chargen ROM (CRC32 {:08X})", utils::crc32(&self.chargen));
+        }
+    }
+}
+
+
+// the 1541 DOS ROM is only needed for true drive emulation, so it's not part of the ROM set
+pub fn load_drive_rom(paths: &RomPaths) -> Result<Vec<u8>, Error> {
+    read_rom(&paths.resolve(&paths.drive, "dos1541.rom"), "1541 DOS", DRIVE_SIZE)
+}
+
+
+pub fn check_drive_rom(data: &[u8]) -> Result<(), Error> {
+    check_size(data, DRIVE_SIZE, "1541 DOS ROM")
+}
+
+
+fn read_rom(path: &Path, name: &str, size: usize) -> Result<Vec<u8>, Error> {
+    let what = format!("{} ROM {}", name, path.display());
+    let mut file = File::open(path).map_err(|e| Error::Io(what.clone(), e))?;
+    let mut data = Vec::<u8>::new();
+    file.read_to_end(&mut data).map_err(|e| Error::Io(what.clone(), e))?;
+    check_size(&data, size, &what)?;
+    Ok(data)
+}
+
+
+fn check_size(data: &[u8], size: usize, what: &str) -> Result<(), Error> {
+    if data.len() != size {
+        return Err(Error::InvalidRom(format!("Invalid {}: expected {} bytes, got {}", what, size, data.len())))
+    }
+
+    Ok(())
+}
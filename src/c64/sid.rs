@@ -1,53 +1,62 @@
 // SID chip
-extern crate rand;
 #[cfg(not(target_os = "redox"))]
 extern crate sdl2;
 
 #[cfg(not(target_os = "redox"))]
 use self::sdl2::audio::{ AudioCallback, AudioSpecDesired };
 use c64::memory;
+use c64::model;
 use c64::sid_tables::*;
-use std::cell::RefCell;
+use c64::snapshot;
+use error::Error;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use enum_primitive::FromPrimitive;
 use std::f32;
-use std::rc::Rc;
-
-pub type SIDShared = Rc<RefCell<SID>>;
+use std::io;
+use std::io::{Read, Write};
 
 const SAMPLE_FREQ: u32 = 44100;  // output frequency
-const SID_FREQ:    u32 = 985248; // SID frequency in Hz
-pub const SID_CYCLES:  u32 = SID_FREQ / SAMPLE_FREQ;  // SID clocks/sample frame
 const NUM_SAMPLES: usize = 624; // size of buffer for sampled voice
-
-
-enum WaveForm {
-    None,
-    Triangle,
-    Saw,
-    TriSaw,
-    Pulse,
-    TriPulse,
-    SawPulse,
-    TriSawPulse,
-    Noise
+const LFSR_SEED:   u32 = 0x7FFFF8; // SID noise shift register value after reset
+
+
+enum_from_primitive! {
+    #[derive(Clone, Copy)]
+    enum WaveForm {
+        None,
+        Triangle,
+        Saw,
+        TriSaw,
+        Pulse,
+        TriPulse,
+        SawPulse,
+        TriSawPulse,
+        Noise
+    }
 }
 
-enum VoiceState {
-    Idle,
-    Attack,
-    Decay,
-    Release
+enum_from_primitive! {
+    #[derive(Clone, Copy)]
+    enum VoiceState {
+        Idle,
+        Attack,
+        Decay,
+        Release
+    }
 }
 
-#[derive(PartialEq)]
-enum FilterType {
-    None,
-    Lowpass,
-    Bandpass,
-    LowBandpass,
-    Highpass,
-    Notch,
-    HighBandpass,
-    All
+enum_from_primitive! {
+    #[derive(Clone, Copy, PartialEq)]
+    enum FilterType {
+        None,
+        Lowpass,
+        Bandpass,
+        LowBandpass,
+        Highpass,
+        Notch,
+        HighBandpass,
+        All
+    }
 }
 
 
@@ -76,7 +85,7 @@ struct SIDVoice {
 }
 
 impl SIDVoice {
-    fn new() -> SIDVoice {
+    fn new(eg_rate: u32) -> SIDVoice {
         SIDVoice {
             wave: WaveForm::None,
             state: VoiceState::Idle,
@@ -86,9 +95,9 @@ impl SIDVoice {
             wf_add: 0,
             freq: 0,
             pw_val: 0,
-            attack_add: EG_TABLE[0],
-            decay_sub: EG_TABLE[0],
-            release_sub: EG_TABLE[0],
+            attack_add: eg_rate,
+            decay_sub: eg_rate,
+            release_sub: eg_rate,
             sustain_level: 0,
             level: 0,
             noise: 0,
@@ -102,16 +111,56 @@ impl SIDVoice {
     }
 
 
-    fn reset(&mut self) {
+    fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self.wave as u8, self.state as u8])?;
+        for v in &[self.wf_cnt, self.wf_add, self.attack_add, self.decay_sub, self.release_sub, self.sustain_level, self.level, self.noise] {
+            w.write_u32::<LittleEndian>(*v)?;
+        }
+
+        snapshot::write_u16s(w, &[self.freq, self.pw_val])?;
+        snapshot::write_bools(w, &[self.gate, self.ring, self.test, self.filter, self.sync, self.mute])
+    }
+
+
+    fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.wave  = WaveForm::from_u8(r.read_u8()?).ok_or(snapshot::invalid_data("Invalid SID waveform"))?;
+        self.state = VoiceState::from_u8(r.read_u8()?).ok_or(snapshot::invalid_data("Invalid SID voice state"))?;
+        self.wf_cnt = r.read_u32::<LittleEndian>()?;
+        self.wf_add = r.read_u32::<LittleEndian>()?;
+        self.attack_add  = r.read_u32::<LittleEndian>()?;
+        self.decay_sub   = r.read_u32::<LittleEndian>()?;
+        self.release_sub = r.read_u32::<LittleEndian>()?;
+        self.sustain_level = r.read_u32::<LittleEndian>()?;
+        self.level = r.read_u32::<LittleEndian>()?;
+        self.noise = r.read_u32::<LittleEndian>()?;
+
+        let mut regs = [0; 2];
+        snapshot::read_u16s(r, &mut regs)?;
+        self.freq   = regs[0];
+        self.pw_val = regs[1];
+
+        let mut flags = [false; 6];
+        snapshot::read_bools(r, &mut flags)?;
+        self.gate   = flags[0];
+        self.ring   = flags[1];
+        self.test   = flags[2];
+        self.filter = flags[3];
+        self.sync   = flags[4];
+        self.mute   = flags[5];
+        Ok(())
+    }
+
+
+    fn reset(&mut self, eg_rate: u32) {
         self.wave  = WaveForm::None;
         self.state = VoiceState::Idle;
         self.wf_cnt = 0;
         self.wf_add = 0;
         self.freq   = 0;
         self.pw_val = 0;
-        self.attack_add  = EG_TABLE[0];
-        self.decay_sub   = EG_TABLE[0];
-        self.release_sub = EG_TABLE[0];
+        self.attack_add  = eg_rate;
+        self.decay_sub   = eg_rate;
+        self.release_sub = eg_rate;
         self.sustain_level = 0;
         self.level = 0;
         self.noise = 0;
@@ -125,69 +174,57 @@ impl SIDVoice {
 }
 
 
-// the SID chip with associated SDL2 audio device
-pub struct SID {
-    mem_ref: Option<memory::MemShared>,
+// where the SID samples end up: an SDL2 audio device or nowhere (headless mode)
+enum SIDOutput {
     #[cfg(not(target_os = "redox"))]
-    audio_device: sdl2::audio::AudioDevice<SIDAudioDevice>,
+    Audio(sdl2::audio::AudioDevice<SIDAudioDevice>),
+    Silent(Box<SIDAudioDevice>),
 }
 
-#[cfg(not(target_os = "redox"))]
-impl SID {
-    pub fn new_shared() -> SIDShared {
-        let sdl_context = sdl2::init().unwrap();
-        let audio_subsystem = sdl_context.audio().unwrap();
 
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),  // mono
-            samples: Some(512), // default sample size
-        };
+// the SID chip with associated SDL2 audio device
+pub struct SID {
+    output: SIDOutput,
+}
 
-        Rc::new(RefCell::new(SID {
-            mem_ref: None,
-            audio_device: audio_subsystem.open_playback(None, &desired_spec, |spec| {
-                println!("{:?}", spec);
-                SIDAudioDevice::new()
-                }).unwrap()
-        }))
+impl SID {
+    pub fn new(audio_on: bool) -> Result<SID, Error> {
+        Ok(SID {
+            output: if audio_on { SID::open_audio()? } else { SIDOutput::Silent(Box::new(SIDAudioDevice::new())) }
+        })
     }
 
 
-    pub fn set_references(&mut self, memref: memory::MemShared) {
-        self.mem_ref = Some(memref);
+    pub fn reset(&mut self) {
+        self.with_device(|device| device.reset());
     }
 
 
-    pub fn reset(&mut self) {
-        let mut lock = self.audio_device.lock();
-        (*lock).reset();
+    pub fn update(&mut self) {
+        self.with_device(|device| device.update());
     }
 
 
-    pub fn update(&mut self) {
-        let mut lock = self.audio_device.lock();
-        (*lock).update();
+    pub fn set_model(&mut self, model: model::Model) {
+        self.with_device(|device| device.set_model(model));
     }
 
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
+    pub fn read_register(&mut self, addr: u16, mem: &mut memory::Memory) -> u8 {
         let mut rval = 0;
 
         match addr {
             0xD419..=0xD41A => {
-                let mut lock = self.audio_device.lock();
-                rval = (*lock).read_register(addr);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
+                rval = self.with_device(|device| device.read_register(addr));
+                mem.get_ram_bank(memory::MemType::Io).write(addr, rval);
             },
             0xD41B..=0xD41C => {
-                let mut lock = self.audio_device.lock();
-                rval = (*lock).read_register(addr);
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
+                rval = self.with_device(|device| device.read_register(addr));
+                mem.get_ram_bank(memory::MemType::Io).write(addr, rval);
             },
-            0xD420..=0xD7FF =>  { rval = self.read_register(0xD400 + (addr % 0x0020)); },
+            0xD420..=0xD7FF =>  { rval = self.read_register(0xD400 + (addr % 0x0020), mem); },
             _               =>  {
-                as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, rval);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, rval);
             }
         }
 
@@ -195,48 +232,70 @@ impl SID {
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8) {
-        let mut lock = self.audio_device.lock();
-        (*lock).write_register(addr, value);
-        as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+    pub fn write_register(&mut self, addr: u16, value: u8, mem: &mut memory::Memory) {
+        self.with_device(|device| device.write_register(addr, value));
+        mem.get_ram_bank(memory::MemType::Io).write(addr, value);
     }
 
 
-    pub fn update_audio(&mut self) {
-        self.audio_device.resume();
+    pub fn save_state<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.with_device(|device| device.save_state(w))
     }
-}
 
 
-#[cfg(target_os = "redox")]
-impl SID {
-    pub fn new_shared() -> SIDShared {
-        Rc::new(RefCell::new(SID {
-            mem_ref: None,
-        }))
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.with_device(|device| device.load_state(r))
     }
 
 
-    pub fn set_references(&mut self, memref: memory::MemShared) {
-        self.mem_ref = Some(memref);
+    pub fn update_audio(&mut self) {
+        match self.output {
+            #[cfg(not(target_os = "redox"))]
+            SIDOutput::Audio(ref mut audio_device) => audio_device.resume(),
+            SIDOutput::Silent(_) => (),
+        }
     }
 
 
-    pub fn reset(&mut self) {}
+    // *** private functions *** //
 
+    #[cfg(not(target_os = "redox"))]
+    fn open_audio() -> Result<SIDOutput, Error> {
+        let sdl_context = sdl2::init().map_err(Error::Audio)?;
+        let audio_subsystem = sdl_context.audio().map_err(Error::Audio)?;
 
-    pub fn update(&mut self) {}
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),  // mono
+            samples: Some(512), // default sample size
+        };
 
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            println!("{:?}", spec);
+            SIDAudioDevice::new()
+            }).map_err(Error::Audio)?;
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
-        0
+        Ok(SIDOutput::Audio(device))
     }
 
 
-    pub fn write_register(&mut self, addr: u16, value: u8) {}
+    #[cfg(target_os = "redox")]
+    fn open_audio() -> Result<SIDOutput, Error> {
+        Ok(SIDOutput::Silent(Box::new(SIDAudioDevice::new())))
+    }
 
 
-    pub fn update_audio(&mut self) {}
+    // run an operation on the SID state, locking the audio device if there is one
+    fn with_device<F, R>(&mut self, f: F) -> R where F: FnOnce(&mut SIDAudioDevice) -> R {
+        match self.output {
+            #[cfg(not(target_os = "redox"))]
+            SIDOutput::Audio(ref mut audio_device) => {
+                let mut lock = audio_device.lock();
+                f(&mut lock)
+            },
+            SIDOutput::Silent(ref mut device) => f(device),
+        }
+    }
 }
 
 
@@ -260,16 +319,28 @@ struct SIDAudioDevice {
     yn1: f32,
     yn2: f32,
 
+    // clock dependent values for the current machine model
+    sid_cycles: u32,          // SID clocks per output sample
+    eg_table:   [u32; 16],    // envelope rates
+    line_freq:  usize,        // raster lines per second - the volume register is sampled once per line
+
     voices: Vec<SIDVoice>,
     sample_buffer: [u8; NUM_SAMPLES],
-    sample_idx: usize
+    sample_idx: usize,
+
+    // noise shift registers: one advanced by register reads, one by audio output which runs on host time
+    osc3_lfsr:  u32,
+    noise_lfsr: u32,
 }
 
 impl SIDAudioDevice {
     pub fn new() -> SIDAudioDevice {
         let mut sid_audio_device = SIDAudioDevice {
             last_sid_byte: 0,
-            voices: vec![SIDVoice::new(), SIDVoice::new(), SIDVoice::new()],
+            sid_cycles: 0,
+            eg_table:   [0; 16],
+            line_freq:  0,
+            voices: vec![SIDVoice::new(0), SIDVoice::new(0), SIDVoice::new(0)],
             volume: 0,
             filter_type: FilterType::None,
             filter_freq: 0,
@@ -284,7 +355,9 @@ impl SIDAudioDevice {
             yn1: 0.0,
             yn2: 0.0,
             sample_buffer: [0; NUM_SAMPLES],
-            sample_idx: 0
+            sample_idx: 0,
+            osc3_lfsr:  LFSR_SEED,
+            noise_lfsr: LFSR_SEED,
         };
 
         // calculate triangle table values
@@ -303,15 +376,29 @@ impl SIDAudioDevice {
         sid_audio_device.voices[2].modulator = 1;
         sid_audio_device.voices[2].modulatee = 0;
 
+        sid_audio_device.set_model(model::Model::Pal);
         sid_audio_device
     }
 
 
+    // derive the clock dependent values from the machine model; resets the chip
+    pub fn set_model(&mut self, model: model::Model) {
+        self.sid_cycles = model.clock_freq() as u32 / SAMPLE_FREQ;
+        self.line_freq  = (model.clock_freq() / model.cycles_per_line() as f64) as usize;
+
+        for (rate, divisor) in self.eg_table.iter_mut().zip(EG_DR_RATES.iter()) {
+            *rate = (self.sid_cycles << 16) / divisor;
+        }
+
+        self.reset();
+    }
+
+
     pub fn reset(&mut self) {
         self.last_sid_byte = 0;
 
         for i in 0..self.voices.len() {
-            self.voices[i].reset();
+            self.voices[i].reset(self.eg_table[0]);
         }
 
         self.volume = 0;
@@ -328,6 +415,8 @@ impl SIDAudioDevice {
         self.yn1 = 0.0;
         self.yn2 = 0.0;
         self.sample_idx = 0;
+        self.osc3_lfsr  = LFSR_SEED;
+        self.noise_lfsr = LFSR_SEED;
         self.calculate_filter();
 
         for i in 0..NUM_SAMPLES {
@@ -336,6 +425,56 @@ impl SIDAudioDevice {
     }
 
 
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self.last_sid_byte, self.volume, self.filter_type as u8, self.filter_freq, self.filter_resonance])?;
+        for v in &[self.iir_att, self.d1, self.d2, self.g1, self.g2, self.xn1, self.xn2, self.yn1, self.yn2] {
+            w.write_f32::<LittleEndian>(*v)?;
+        }
+
+        for voice in &self.voices {
+            voice.save_state(w)?;
+        }
+
+        w.write_all(&self.sample_buffer)?;
+        w.write_u32::<LittleEndian>(self.sample_idx as u32)?;
+        w.write_u32::<LittleEndian>(self.osc3_lfsr)?;
+        w.write_u32::<LittleEndian>(self.noise_lfsr)
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.last_sid_byte = r.read_u8()?;
+        self.volume = r.read_u8()?;
+        self.filter_type = FilterType::from_u8(r.read_u8()?).ok_or(snapshot::invalid_data("Invalid SID filter type"))?;
+        self.filter_freq = r.read_u8()?;
+        self.filter_resonance = r.read_u8()?;
+        self.iir_att = r.read_f32::<LittleEndian>()?;
+        self.d1  = r.read_f32::<LittleEndian>()?;
+        self.d2  = r.read_f32::<LittleEndian>()?;
+        self.g1  = r.read_f32::<LittleEndian>()?;
+        self.g2  = r.read_f32::<LittleEndian>()?;
+        self.xn1 = r.read_f32::<LittleEndian>()?;
+        self.xn2 = r.read_f32::<LittleEndian>()?;
+        self.yn1 = r.read_f32::<LittleEndian>()?;
+        self.yn2 = r.read_f32::<LittleEndian>()?;
+
+        for voice in self.voices.iter_mut() {
+            voice.load_state(r)?;
+        }
+
+        r.read_exact(&mut self.sample_buffer)?;
+        self.sample_idx = r.read_u32::<LittleEndian>()? as usize;
+        if self.sample_idx >= NUM_SAMPLES {
+            return Err(snapshot::invalid_data("Invalid SID sample index"));
+        }
+
+        self.osc3_lfsr  = r.read_u32::<LittleEndian>()?;
+        self.noise_lfsr = r.read_u32::<LittleEndian>()?;
+
+        Ok(())
+    }
+
+
     pub fn update(&mut self) {
         let idx = self.sample_idx;
         self.sample_buffer[idx] = self.volume;
@@ -354,7 +493,7 @@ impl SIDAudioDevice {
             },
             0xD41B..=0xD41C => {
                 self.last_sid_byte = 0;
-                let rval = rand::random::<u8>();
+                let rval = next_noise(&mut self.osc3_lfsr);
                 rval
             },
             0xD420..=0xD7FF => self.read_register(0xD400 + (addr % 0x0020)),
@@ -372,11 +511,11 @@ impl SIDAudioDevice {
         match addr {
             0xD400 => {
                 self.voices[0].freq = (self.voices[0].freq & 0xFF00) | value as u16;
-                self.voices[0].wf_add = SID_CYCLES * self.voices[0].freq as u32;
+                self.voices[0].wf_add = self.sid_cycles * self.voices[0].freq as u32;
             },
             0xD401 => {
                 self.voices[0].freq = (self.voices[0].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[0].wf_add = SID_CYCLES * self.voices[0].freq as u32;
+                self.voices[0].wf_add = self.sid_cycles * self.voices[0].freq as u32;
             },
             0xD402 => {
                 self.voices[0].pw_val = (self.voices[0].pw_val & 0x0F00) | value as u16;
@@ -388,20 +527,20 @@ impl SIDAudioDevice {
                 self.set_control_register(0, value);
             },
             0xD405 => {
-                self.voices[0].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[0].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[0].attack_add = self.eg_table[ (value >> 4) as usize ];
+                self.voices[0].decay_sub  = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD406 => {
                 self.voices[0].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[0].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[0].release_sub   = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD407 => {
                 self.voices[1].freq = (self.voices[1].freq & 0xFF00) | value as u16;
-                self.voices[1].wf_add = SID_CYCLES * self.voices[1].freq as u32;
+                self.voices[1].wf_add = self.sid_cycles * self.voices[1].freq as u32;
             },
             0xD408 => {
                 self.voices[1].freq = (self.voices[1].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[1].wf_add = SID_CYCLES * self.voices[1].freq as u32;
+                self.voices[1].wf_add = self.sid_cycles * self.voices[1].freq as u32;
             },
             0xD409 => {
                 self.voices[1].pw_val = (self.voices[1].pw_val & 0x0F00) | value as u16;
@@ -413,20 +552,20 @@ impl SIDAudioDevice {
                 self.set_control_register(1, value);
             },
             0xD40C => {
-                self.voices[1].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[1].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[1].attack_add = self.eg_table[ (value >> 4) as usize ];
+                self.voices[1].decay_sub  = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD40D => {
                 self.voices[1].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[1].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[1].release_sub   = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD40E => {
                 self.voices[2].freq = (self.voices[2].freq & 0xFF00) | value as u16;
-                self.voices[2].wf_add = SID_CYCLES * self.voices[2].freq as u32;
+                self.voices[2].wf_add = self.sid_cycles * self.voices[2].freq as u32;
             },
             0xD40F => {
                 self.voices[2].freq = (self.voices[2].freq & 0x00FF) | ((value as u16) << 8);
-                self.voices[2].wf_add = SID_CYCLES * self.voices[2].freq as u32;
+                self.voices[2].wf_add = self.sid_cycles * self.voices[2].freq as u32;
             },
             0xD410 => {
                 self.voices[2].pw_val = (self.voices[2].pw_val & 0x0F00) | value as u16;
@@ -438,12 +577,12 @@ impl SIDAudioDevice {
                 self.set_control_register(2, value);
             },
             0xD413 => {
-                self.voices[2].attack_add = EG_TABLE[ (value >> 4) as usize ];
-                self.voices[2].decay_sub  = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[2].attack_add = self.eg_table[ (value >> 4) as usize ];
+                self.voices[2].decay_sub  = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD414 => {
                 self.voices[2].sustain_level = 0x111111 * (value >> 4) as u32;
-                self.voices[2].release_sub   = EG_TABLE[ (value & 0x0F) as usize ];
+                self.voices[2].release_sub   = self.eg_table[ (value & 0x0F) as usize ];
             },
             0xD416 => {
                 if self.filter_freq != value {
@@ -624,6 +763,16 @@ impl SIDAudioDevice {
     }
 }
 
+// clock the 23-bit noise shift register and return the 8 bits that feed the waveform output
+fn next_noise(lfsr: &mut u32) -> u8 {
+    let bit0 = ((*lfsr >> 22) ^ (*lfsr >> 17)) & 1;
+    *lfsr = ((*lfsr << 1) | bit0) & 0x7FFFFF;
+
+    (((*lfsr >> 15) & 0x80) | ((*lfsr >> 14) & 0x40) | ((*lfsr >> 11) & 0x20) | ((*lfsr >> 9) & 0x10) |
+     ((*lfsr >> 8)  & 0x08) | ((*lfsr >> 5)  & 0x04) | ((*lfsr >> 3)  & 0x02) | ((*lfsr >> 2) & 0x01)) as u8
+}
+
+
 // SDL2 audio callback implementation - this is where the samples are being converted to output sound
 #[cfg(not(target_os = "redox"))]
 impl AudioCallback for SIDAudioDevice {
@@ -641,7 +790,7 @@ impl AudioCallback for SIDAudioDevice {
         for x in out.iter_mut() {
             let master_volume: u8 = self.sample_buffer[(sample_count >> 16) % NUM_SAMPLES];
 
-            sample_count += ((50 * NUM_SAMPLES/2) << 16) / SAMPLE_FREQ as usize;
+            sample_count += (self.line_freq << 16) / SAMPLE_FREQ as usize;
             let mut total_output: i32 = (SAMPLE_TABLE[master_volume as usize] as i32) << 8;
             let mut total_output_filter: i32 = 0;
 
@@ -738,7 +887,7 @@ impl AudioCallback for SIDAudioDevice {
                     },
                     WaveForm::Noise => {
                         if self.voices[i].wf_cnt > 0x100000 {
-                            let rnd_noise = rand::random::<u16>() << 8;
+                            let rnd_noise = (next_noise(&mut self.noise_lfsr) as u16) << 8;
                             self.voices[i].noise = rnd_noise as u32;
                             output = rnd_noise;
                             self.voices[i].wf_cnt &= 0xFFFFF;
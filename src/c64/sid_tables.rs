@@ -1,6 +1,4 @@
 // SID data and precalculated sample tables - as found in Frodo emulator 4.1b
-use c64::sid;
-
 pub static mut TRI_TABLE: [u16; 8192] = [0; 8192];
 
 
@@ -168,13 +166,8 @@ pub const SAMPLE_TABLE: [u16; 16] = [
     0x0888, 0x1999, 0x2aaa, 0x3bbb, 0x4ccc, 0x5ddd, 0x6eee, 0x7fff,
     ];
 
-pub const EG_TABLE: [u32; 16] = [
-    (sid::SID_CYCLES << 16) / 9, (sid::SID_CYCLES << 16) / 32,
-    (sid::SID_CYCLES << 16) / 63, (sid::SID_CYCLES << 16) / 95,
-    (sid::SID_CYCLES << 16) / 149, (sid::SID_CYCLES << 16) / 220,
-    (sid::SID_CYCLES << 16) / 267, (sid::SID_CYCLES << 16) / 313,
-    (sid::SID_CYCLES << 16) / 392, (sid::SID_CYCLES << 16) / 977,
-    (sid::SID_CYCLES << 16) / 1954, (sid::SID_CYCLES << 16) / 3126,
-    (sid::SID_CYCLES << 16) / 3906, (sid::SID_CYCLES << 16) / 11720,
-    (sid::SID_CYCLES << 16) / 19531, (sid::SID_CYCLES << 16) / 31251
-        ];
+// envelope attack/decay/release rate divisors, scaled by the SID clock rate
+pub const EG_DR_RATES: [u32; 16] = [
+    9, 32, 63, 95, 149, 220, 267, 313,
+    392, 977, 1954, 3126, 3906, 11720, 19531, 31251
+    ];
@@ -0,0 +1,58 @@
+// save state helpers - every chip serializes itself in a fixed order using these
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"RUST64SS";
+pub const VERSION: u16 = 7;
+
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+pub fn write_bool<W: Write>(w: &mut W, value: bool) -> io::Result<()> {
+    w.write_u8(value as u8)
+}
+
+
+pub fn read_bool<R: Read>(r: &mut R) -> io::Result<bool> {
+    Ok(r.read_u8()? != 0)
+}
+
+
+pub fn write_u16s<W: Write>(w: &mut W, values: &[u16]) -> io::Result<()> {
+    for v in values {
+        w.write_u16::<LittleEndian>(*v)?;
+    }
+
+    Ok(())
+}
+
+
+pub fn read_u16s<R: Read>(r: &mut R, values: &mut [u16]) -> io::Result<()> {
+    for v in values.iter_mut() {
+        *v = r.read_u16::<LittleEndian>()?;
+    }
+
+    Ok(())
+}
+
+
+pub fn write_bools<W: Write>(w: &mut W, values: &[bool]) -> io::Result<()> {
+    for v in values {
+        write_bool(w, *v)?;
+    }
+
+    Ok(())
+}
+
+
+pub fn read_bools<R: Read>(r: &mut R, values: &mut [bool]) -> io::Result<()> {
+    for v in values.iter_mut() {
+        *v = read_bool(r)?;
+    }
+
+    Ok(())
+}
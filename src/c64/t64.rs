@@ -0,0 +1,155 @@
+// T64 tape archives: a directory of program files in one container, as made for the C64S emulator.
+// Many archives out there were written by broken tools, so the directory isn't taken at its word.
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use c64::disk_image;
+use c64::prg;
+use error::Error;
+use utils;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+pub const SIGNATURE: &[u8; 3] = b"C64"; // followed by "S tape file", " tape image file" and the like
+const HEADER_SIZE: u64 = 0x40;
+const ENTRY_SIZE: u64 = 0x20;
+const NAME_LEN: usize = 16;
+const BROKEN_END_ADDRESS: u16 = 0xC3C6;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: Vec<u8>,      // PETSCII, without padding
+    pub file_type: u8,      // as in a disk directory, $82 for a program file
+    pub start_address: u16,
+    pub end_address: u16,   // right after the last byte, fixed up where the directory is wrong
+    offset: usize,          // of the data in the archive
+}
+
+
+pub struct T64 {
+    pub name: Vec<u8>,
+    entries: Vec<Entry>,
+    data: Vec<u8>,
+}
+
+impl T64 {
+    pub fn from_filename(filename: &str) -> Result<T64, Error> {
+        let data = utils::open_file(filename, 0)?;
+        T64::from_data(data).map_err(|e| Error::InvalidTape(format!("Invalid tape archive {}: {}", filename, e)))
+    }
+
+
+    pub fn from_data(data: Vec<u8>) -> Result<T64, String> {
+        if !is_t64(&data) {
+            return Err("invalid signature".to_string())
+        }
+
+        let mut file = Cursor::new(&data[..]);
+        // the whole file is in memory already, so a failed read means it's cut short
+        let truncated = |_| "file is truncated".to_string();
+
+        file.seek(SeekFrom::Start(0x22)).map_err(truncated)?;
+        let max_entries = file.read_u16::<LittleEndian>().map_err(truncated)?;
+        let used_entries = file.read_u16::<LittleEndian>().map_err(truncated)?;
+        file.seek(SeekFrom::Start(0x28)).map_err(truncated)?;
+        let mut name = [0u8; 24];
+        file.read_exact(&mut name).map_err(truncated)?;
+
+        // some tools leave the entry counts at 0, so the directory is read as far as it goes
+        let slots = max_entries.max(used_entries).max(1) as u64;
+        let mut entries = Vec::new();
+        for i in 0..slots {
+            file.seek(SeekFrom::Start(HEADER_SIZE + i * ENTRY_SIZE)).map_err(truncated)?;
+            let mut entry = [0u8; ENTRY_SIZE as usize];
+            if file.read_exact(&mut entry).is_err() {
+                break;
+            }
+
+            // 0 is a free slot; 1 is a normal file, and anything else is taken for one as well
+            let offset = (&entry[8..12]).read_u32::<LittleEndian>().map_err(truncated)? as usize;
+            if entry[0] == 0 || offset >= data.len() {
+                continue;
+            }
+
+            entries.push(Entry {
+                name: unpad_name(&entry[16..16 + NAME_LEN]),
+                file_type: entry[1],
+                start_address: (&entry[2..4]).read_u16::<LittleEndian>().map_err(truncated)?,
+                end_address: (&entry[4..6]).read_u16::<LittleEndian>().map_err(truncated)?,
+                offset,
+            });
+        }
+
+        if entries.is_empty() {
+            return Err("no files in the archive".to_string())
+        }
+
+        fix_end_addresses(&mut entries, data.len());
+
+        Ok(T64 {
+            name: unpad_name(&name),
+            entries,
+            data,
+        })
+    }
+
+
+    // the files in the order of the directory
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+
+    // index of the first file with a name matching the pattern, in which '?' stands for any character and '*' for the rest of the name
+    pub fn find(&self, pattern: &[u8]) -> Option<usize> {
+        self.entries.iter().position(|e| disk_image::matches_pattern(&e.name, pattern))
+    }
+
+
+    // an entry as a program file, to be loaded like one
+    pub fn prg(&self, index: usize) -> Option<prg::Prg> {
+        let entry = self.entries.get(index)?;
+        let len = entry.end_address.wrapping_sub(entry.start_address) as usize;
+        let end = (entry.offset + len).min(self.data.len());
+
+        Some(prg::Prg {
+            load_address: entry.start_address,
+            data: self.data[entry.offset..end].to_vec(),
+        })
+    }
+}
+
+
+// whether a file starting with this header is a T64 archive
+pub fn is_t64(header: &[u8]) -> bool {
+    let text: Vec<u8> = header.iter().take(0x20).map(|c| c.to_ascii_lowercase()).collect();
+    header.starts_with(SIGNATURE) && text.windows(4).any(|w| w == b"tape")
+}
+
+
+// *** private functions *** //
+
+// a file can't go beyond where the next one in the archive starts, or the end of the archive; an end address
+// which does, comes before the start or is the $C3C6 a well known converter put into every entry is replaced
+// by the one the data gives
+fn fix_end_addresses(entries: &mut [Entry], archive_len: usize) {
+    let mut offsets: Vec<usize> = entries.iter().map(|e| e.offset).collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    for entry in entries.iter_mut() {
+        let next = offsets.iter().cloned().find(|o| *o > entry.offset).unwrap_or(archive_len);
+        let available = (next - entry.offset).min(0x10000 - entry.start_address as usize);
+        let len = entry.end_address.wrapping_sub(entry.start_address) as usize;
+
+        if (entry.end_address <= entry.start_address && entry.end_address != 0) || len > available || entry.end_address == BROKEN_END_ADDRESS {
+            entry.end_address = (entry.start_address as usize + available) as u16;
+        }
+    }
+}
+
+
+// names are padded with spaces, or with shifted spaces like on disk
+fn unpad_name(name: &[u8]) -> Vec<u8> {
+    let len = name.iter().rposition(|c| *c != 0x20 && *c != 0xA0 && *c != 0x00).map_or(0, |i| i + 1);
+    name[..len].to_vec()
+}
@@ -0,0 +1,90 @@
+// TAP tape images: the lengths of the pulses recorded on a tape, which the datasette passes on to
+// the FLAG input of CIA1. Loaders tell the bits apart by the time between the pulses.
+use std::io::{Cursor, Read};
+use c64::model;
+use error::Error;
+use utils;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+pub const SIGNATURE: &[u8; 12] = b"C64-TAPE-RAW";
+const HEADER_SIZE: usize = 0x14;
+const OVERFLOW_CYCLES: u32 = 256 * 8; // a 0 in a version 0 image: a pause of unknown length
+
+
+pub struct Tap {
+    pub version: u8, // 0 and 1: full waves, 2: half waves (from a C16)
+    pub model: model::Model, // the machine it was recorded on, which the cycles are counted in
+    pulses: Vec<u32>, // in cycles
+}
+
+impl Tap {
+    pub fn from_filename(filename: &str) -> Result<Tap, Error> {
+        let data = utils::open_file(filename, 0)?;
+        Tap::from_data(&data).map_err(|e| Error::InvalidTape(format!("Invalid tape image {}: {}", filename, e)))
+    }
+
+
+    pub fn from_data(data: &[u8]) -> Result<Tap, String> {
+        let mut file = Cursor::new(data);
+        // the whole file is in memory already, so a failed read means it's cut short
+        let truncated = |_| "file is truncated".to_string();
+
+        let mut signature = [0u8; 12];
+        file.read_exact(&mut signature).map_err(truncated)?;
+        if &signature != SIGNATURE {
+            return Err("invalid signature".to_string())
+        }
+        let version = file.read_u8().map_err(truncated)?;
+        if version > 2 {
+            return Err(format!("unsupported version {}", version))
+        }
+
+        // the video standard is coded like model::Model; some tools leave it out, which makes it PAL
+        let mut platform = [0u8; 3];
+        file.read_exact(&mut platform).map_err(truncated)?;
+        let model = model::Model::from_u8(platform[1]).unwrap_or(model::Model::Pal);
+
+        // some tools get the data size wrong, so the file size has the last word
+        let size = file.read_u32::<LittleEndian>().map_err(truncated)? as usize;
+        let data = &data[HEADER_SIZE..(HEADER_SIZE + size).min(data.len())];
+
+        let mut values = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            match data[i] {
+                0 if version == 0 => values.push(OVERFLOW_CYCLES),
+                // longer pulses are given in cycles, with 3 more bytes
+                0 => {
+                    if i + 4 > data.len() {
+                        break;
+                    }
+                    values.push(data[i + 1] as u32 | (data[i + 2] as u32) << 8 | (data[i + 3] as u32) << 16);
+                    i += 3;
+                },
+                value => values.push(value as u32 * 8),
+            }
+            i += 1;
+        }
+
+        // the FLAG input only sees one edge per full wave
+        let pulses = if version == 2 { values.chunks(2).map(|w| w.iter().sum()).collect() } else { values };
+
+        Ok(Tap {
+            version,
+            model,
+            pulses,
+        })
+    }
+
+
+    pub fn pulses(&self) -> &[u32] {
+        &self.pulses
+    }
+
+
+    // playing time in cycles of the machine it was recorded on
+    pub fn len_cycles(&self) -> u64 {
+        self.pulses.iter().map(|p| *p as u64).sum()
+    }
+}
@@ -1,16 +1,16 @@
 // VIC-II chip
 use c64;
 use c64::memory;
+use c64::model;
 use c64::cpu;
+use c64::snapshot;
 use c64::vic_tables::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, Write};
 use utils;
 
-pub type VICShared = Rc<RefCell<VIC>>;
-
 const SKIP_FRAMES:     u16 = 2;
-const NUM_RASTERLINES: u16 = 312;  // number of rasterlines for PAL (0x138)
 const FIRST_DISP_LINE: u16 = 0x10;
 const LAST_DISP_LINE:  u16 = 0x11f;
 const ROW25_YSTART: u16 = 0x33;
@@ -22,6 +22,9 @@ const ROW24_YSTOP:  u16 = 0xF7;
 const FIRST_BADLINE: u16 = 0x30;
 const LAST_BADLINE:  u16 = 0xF7;
 
+// NTSC chips insert their extra idle cycles right before cycle 58 (sprite 0 pointer fetch)
+const EXTRA_CYCLES_AT: u8 = 58;
+
 
 pub struct VIC {
     pub window_buffer: Vec<u32>,
@@ -33,11 +36,13 @@ pub struct VIC {
     pub is_bad_line: bool,
     pub dbg_reg_changed: bool,  // has the VIC register changed? (use in visual debugger)
 
-    mem_ref: Option<memory::MemShared>,
-    cpu_ref: Option<cpu::CPUShared>,
-
     irq_flag: u8,
     irq_mask: u8,
+
+    raster_lines:   u16, // number of raster lines for the current model
+    last_disp_line: u16,
+    extra_cycles:   u8,  // idle cycles per line on top of the 63 of a PAL chip
+    extra_cycles_left: u8,
     
     matrix_line: [u8; 40], // video line buffer, read in bad lines
     color_line:  [u8; 40], // color line buffer, read in bad lines
@@ -95,20 +100,22 @@ pub struct VIC {
 }
 
 impl VIC {
-    pub fn new_shared() -> VICShared {
-        Rc::new(RefCell::new(VIC {
+    pub fn new() -> VIC {
+        VIC {
             window_buffer: vec![0; c64::SCREEN_WIDTH * c64::SCREEN_HEIGHT],
             last_byte: 0,
-            raster_cnt: NUM_RASTERLINES - 1,
+            raster_cnt: model::Model::Pal.raster_lines() - 1,
             raster_irq: 0,
             curr_cycle: 1,
             border_on:   false,
             is_bad_line: false,
             dbg_reg_changed: false,
-            mem_ref: None,
-            cpu_ref: None,
             irq_flag: 0,
             irq_mask: 0,
+            raster_lines:   model::Model::Pal.raster_lines(),
+            last_disp_line: LAST_DISP_LINE,
+            extra_cycles:   0,
+            extra_cycles_left: 0,
             matrix_line: [0; 40],
             color_line:  [0; 40],
             screen_chunk_offset: 0,
@@ -157,17 +164,22 @@ impl VIC {
             sprite_data:      [[0; 4]; 8],
             sprite_draw_data: [[0; 4]; 8],
             first_ba_cycle: 0
-        }))
+        }
     }
-    
 
-    pub fn set_references(&mut self, memref: memory::MemShared, cpuref: cpu::CPUShared) {
-        self.mem_ref = Some(memref);
-        self.cpu_ref = Some(cpuref);
+
+    // switch video timing to the given chip revision; the raster restarts at the end of a frame
+    pub fn set_model(&mut self, model: model::Model) {
+        self.raster_lines   = model.raster_lines();
+        self.last_disp_line = LAST_DISP_LINE.min(self.raster_lines - 1);
+        self.extra_cycles   = model.cycles_per_line() - model::Model::Pal.cycles_per_line();
+        self.extra_cycles_left = self.extra_cycles;
+        self.raster_cnt = self.raster_lines - 1;
+        self.curr_cycle = 1;
     }
     
 
-    pub fn read_register(&self, addr: u16) -> u8 {
+    pub fn read_register(&self, addr: u16, mem: &mut memory::Memory) -> u8 {
         match addr {
             0xD000..=0xD00F => {
                 let idx = ((addr & 0x000F) >> 1) as usize;
@@ -179,29 +191,29 @@ impl VIC {
                 }
             },
             0xD011 => {
-                let curr_val = as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr);
+                let curr_val = mem.get_ram_bank(memory::MemType::Io).read(addr);
                 // bit 7 in $d011 is bit 8 of $d012
                 (curr_val & 0x7F) | ((self.raster_cnt & 0x100) >> 1) as u8
             },
             0xD012          => self.raster_cnt as u8,
             0xD019          => self.irq_flag | 0x70,
             0xD01A          => self.irq_mask | 0xF0,
-            0xD040..=0xD3FF => self.read_register(0xD000 + (addr % 0x0040)),
-            _               => as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr)
+            0xD040..=0xD3FF => self.read_register(0xD000 + (addr % 0x0040), mem),
+            _               => mem.get_ram_bank(memory::MemType::Io).read(addr)
         }
     }
 
 
     // write to register - ignore callback to CPU
-    pub fn write_register_nc(&mut self, addr: u16, value: u8) {
+    pub fn write_register_nc(&mut self, addr: u16, value: u8, mem: &mut memory::Memory) {
         let mut ca = cpu::Callback::None;
-        self.write_register(addr, value, &mut ca);
+        self.write_register(addr, value, &mut ca, mem);
     }
    
 
     // write to register - perform callback action on CPU
-    pub fn write_register(&mut self, addr: u16, value: u8, on_vic_write: &mut cpu::Callback) {
-        self.dbg_check_regs(addr, value);
+    pub fn write_register(&mut self, addr: u16, value: u8, on_vic_write: &mut cpu::Callback, mem: &mut memory::Memory) {
+        self.dbg_check_regs(addr, value, mem);
         
         match addr {
             0xD000..=0xD00F => {
@@ -209,11 +221,11 @@ impl VIC {
                 
                 if (addr % 2) == 0 {
                     self.mx[idx] = (self.mx[idx] & 0xFF00) | value as u16;
-                    as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, self.mx[idx] as u8);
+                    mem.get_ram_bank(memory::MemType::Io).write(addr, self.mx[idx] as u8);
                 }
                 else {
                     self.my[idx] = value;
-                    as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                    mem.get_ram_bank(memory::MemType::Io).write(addr, value);
                 }
             },
             0xD010 => {
@@ -230,7 +242,7 @@ impl VIC {
                     j <<= 1;
                 }
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD011 => {
                 self.y_scroll = (value & 7) as u16;
@@ -258,10 +270,10 @@ impl VIC {
                 self.is_bad_line = (self.raster_cnt >= FIRST_BADLINE) &&
                                    (self.raster_cnt <= LAST_BADLINE) &&
                                    ((self.raster_cnt & 7) == self.y_scroll) && self.bad_lines_on;
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(0xD016, mem);
                 self.display_mode = (((value & 0x60) | (ctrl2 & 0x10)) >> 4) as u16;
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD012 => {
                 let new_raster_irq = (self.raster_irq & 0xFF00) | value as u16;
@@ -271,25 +283,25 @@ impl VIC {
                 }
 
                 self.raster_irq = new_raster_irq;
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD016 => {
-                let ctrl1 = self.read_register(0xD011);
+                let ctrl1 = self.read_register(0xD011, mem);
                 self.x_scroll = (value & 7) as u16;
                 self.display_mode = (((ctrl1 & 0x60) | (value & 0x10)) >> 4) as u16;
 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD017 => {
                 self.sprite_y_exp |= !value;
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD018 => {
                 self.matrix_base = ((value & 0xF0) as u16) << 6;
                 self.char_base   = ((value & 0x0E) as u16) << 10;
                 self.bitmap_base = ((value & 0x08) as u16) << 10;
                 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD019 => {
                 self.irq_flag = self.irq_flag & (!value & 0x0F);
@@ -302,7 +314,7 @@ impl VIC {
                     // it's not possible due to RefCell already being borrowed (call by CPU)
                     *on_vic_write = cpu::Callback::ClearVICIrq;
                 }
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
             0xD01A => {
                 self.irq_mask = value & 0x0F;
@@ -316,15 +328,17 @@ impl VIC {
                     *on_vic_write = cpu::Callback::ClearVICIrq;
                 }
 
-                as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value);
+                mem.get_ram_bank(memory::MemType::Io).write(addr, value);
             },
-            0xD040..=0xD3FF => { self.write_register(0xD000 + (addr % 0x0040), value, on_vic_write); },
-            _ => as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).write(addr, value),
+            0xD040..=0xD3FF => { self.write_register(0xD000 + (addr % 0x0040), value, on_vic_write, mem); },
+            _ => { mem.get_ram_bank(memory::MemType::Io).write(addr, value); },
         }
     }
     
 
-    pub fn trigger_lp_irq(&mut self) {
+    pub fn trigger_lp_irq(&mut self, mem: &mut memory::Memory) -> cpu::Callback {
+        let mut callback = cpu::Callback::None;
+
         // lightpen triggers only once per frame
         if !self.lp_triggered {
             self.lp_triggered = true;
@@ -335,19 +349,21 @@ impl VIC {
             self.irq_flag |= 0x08;
             if (self.irq_mask & 0x08) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                callback = cpu::Callback::TriggerVICIrq;
             }
 
-            self.write_register_nc(0xD013, lpx as u8);
-            self.write_register_nc(0xD014, lpy as u8);
+            self.write_register_nc(0xD013, lpx as u8, mem);
+            self.write_register_nc(0xD014, lpy as u8, mem);
         }
+
+        callback
     }
 
 
-    pub fn on_va_change(&mut self, new_va: u8) {
+    pub fn on_va_change(&mut self, new_va: u8, mem: &mut memory::Memory) {
         self.cia_vabase = (new_va as u16) << 14;
-        let vbase = self.read_register(0xD018);
-        self.write_register_nc(0xD018, vbase);
+        let vbase = self.read_register(0xD018, mem);
+        self.write_register_nc(0xD018, vbase, mem);
     }
 
 
@@ -365,15 +381,15 @@ impl VIC {
     }
 
 
-    pub fn read_byte(&mut self, addr: u16) -> u8 {
+    pub fn read_byte(&mut self, addr: u16, mem: &mut memory::Memory) -> u8 {
         let va = addr | self.cia_vabase;
 
         if (va & 0x7000) == 0x1000 {
             let addr = 0xD000 + (va & 0x0FFF);
-            self.last_byte = as_mut!(self.mem_ref).get_rom_bank(memory::MemType::Chargen).read(addr);
+            self.last_byte = mem.get_rom_bank(memory::MemType::Chargen).read(addr);
         }
         else {
-            self.last_byte = as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Ram).read(va);
+            self.last_byte = mem.get_ram_bank(memory::MemType::Ram).read(va);
         }
 
         self.last_byte
@@ -382,16 +398,24 @@ impl VIC {
 
     // *** main VIC-II loop ***
     // returns true if VBlank is to be triggered
-    pub fn update(&mut self, c64_cycle_cnt: u32, should_trigger_vblank: &mut bool) -> bool {
+    pub fn update(&mut self, c64_cycle_cnt: u32, should_trigger_vblank: &mut bool, mem: &mut memory::Memory, cpu: &mut cpu::CPU) -> bool {
         let mut mask: u8;
         let mut line_finished = false;
         self.dbg_reg_changed = false;
 
+        // NTSC: nothing but idle accesses in the extra cycles
+        if self.curr_cycle == EXTRA_CYCLES_AT && self.extra_cycles_left > 0 {
+            self.extra_cycles_left -= 1;
+            self.idle_access(mem);
+            self.raster_x = self.raster_x.wrapping_add(0x08);
+            return false;
+        }
+
         match self.curr_cycle {
             // fetch sprite pointer 3, inc raster counter, trigger raster irq,
             // test for bad line, reset BA if sprites 3 and 4 are off, read data of sprite 3
             1 => {
-                if self.raster_cnt == (NUM_RASTERLINES - 1) {
+                if self.raster_cnt == (self.raster_lines - 1) {
                     self.trigger_vblank = true;
                 }
                 else {
@@ -399,13 +423,13 @@ impl VIC {
 
                     if self.raster_cnt == self.raster_irq {
                         match self.raster_irq() {
-                            cpu::Callback::TriggerVICIrq => as_mut!(self.cpu_ref).set_vic_irq(true),
+                            cpu::Callback::TriggerVICIrq => cpu.set_vic_irq(true),
                             _ => (),
                         }
                     }
                     
                     if self.raster_cnt == 0x30 {
-                        self.bad_lines_on = (self.read_register(0xD011) & 0x10) != 0;
+                        self.bad_lines_on = (self.read_register(0xD011, mem) & 0x10) != 0;
                     }
 
                     self.is_bad_line = (self.raster_cnt >= FIRST_BADLINE) &&
@@ -414,17 +438,17 @@ impl VIC {
                                         self.bad_lines_on;
 
                     self.draw_this_line = (self.raster_cnt >= FIRST_DISP_LINE) &&
-                                          (self.raster_cnt <= LAST_DISP_LINE ) && !self.frame_skipped;
+                                          (self.raster_cnt <= self.last_disp_line) && !self.frame_skipped;
                 }
 
                 self.border_on_sample[0] = self.border_on;
 
-                self.sprite_ptr_access(3);
-                self.sprite_data_access(3, 0);
+                self.sprite_ptr_access(3, mem);
+                self.sprite_data_access(3, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x18) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // set BA for sprite 5, read data of sprite 3
@@ -451,7 +475,7 @@ impl VIC {
                     
                     if self.raster_irq == 0 {
                         match self.raster_irq() {
-                            cpu::Callback::TriggerVICIrq => as_mut!(self.cpu_ref).set_vic_irq(true),
+                            cpu::Callback::TriggerVICIrq => cpu.set_vic_irq(true),
                             _ => (),
                         }
                     }
@@ -461,119 +485,119 @@ impl VIC {
                 self.fg_mask_offset = 0;
                 self.fg_mask_buffer = [0; c64::SCREEN_WIDTH / 8];
                 
-                self.sprite_data_access(3, 1);
-                self.sprite_data_access(3, 2);
+                self.sprite_data_access(3, 1, mem);
+                self.sprite_data_access(3, 2, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x20) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // fetch sprite pointer 4, reset BA if sprite 4 and 5 are off
             3 => {
-                self.sprite_ptr_access(4);
-                self.sprite_data_access(4, 0);
+                self.sprite_ptr_access(4, mem);
+                self.sprite_data_access(4, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x30) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // set BA for sprite 6, read data of sprite 4
             4 => {
-                self.sprite_data_access(4, 1);
-                self.sprite_data_access(4, 2);
+                self.sprite_data_access(4, 1, mem);
+                self.sprite_data_access(4, 2, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x40) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // fetch sprite pointer 5, reset BA if sprite 5 and 6 are off
             5 => {
-                self.sprite_ptr_access(5);
-                self.sprite_data_access(5, 0);
+                self.sprite_ptr_access(5, mem);
+                self.sprite_data_access(5, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x60) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // set BA for sprite 7, read data of sprite 5
             6 => {
-                self.sprite_data_access(5, 1);
-                self.sprite_data_access(5, 2);
+                self.sprite_data_access(5, 1, mem);
+                self.sprite_data_access(5, 2, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x80) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // fetch sprite pointer 6, reset BA if sprite 6 and 7 are off
             7 => {
-                self.sprite_ptr_access(6);
-                self.sprite_data_access(6, 0);
+                self.sprite_ptr_access(6, mem);
+                self.sprite_data_access(6, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0xC0) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // read data of sprite 6
             8 => {
-                self.sprite_data_access(6, 1);
-                self.sprite_data_access(6, 2);
+                self.sprite_data_access(6, 1, mem);
+                self.sprite_data_access(6, 2, mem);
                 self.display_if_bad_line();
             },
             // fetch sprite pointer 7, reset BA if sprite 7 are off
             9 => {
-                self.sprite_ptr_access(7);
-                self.sprite_data_access(7, 0);
+                self.sprite_ptr_access(7, mem);
+                self.sprite_data_access(7, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x80) == 0 {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // read data of sprite 7
             10 => {
-                self.sprite_data_access(7, 1);
-                self.sprite_data_access(7, 2);
+                self.sprite_data_access(7, 1, mem);
+                self.sprite_data_access(7, 2, mem);
                 self.display_if_bad_line();
             },
             // refresh, reset BA
             11 => {
-                self.refresh_access();
+                self.refresh_access(mem);
                 self.display_if_bad_line();
-                as_mut!(self.cpu_ref).ba_low = false;
+                cpu.ba_low = false;
             },
             // refresh, turn on matrix access if bad line
             12 => {
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
             },
             // refresh, turn on matrix access if bad line, reset raster_x, graphics display starts here
             13 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
                 self.raster_x = 0xFFFC;
             },
             // refresh, reset video counter, turn on matrix access and reset row counter if bad line
             14 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.rc_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.rc_if_bad_line(c64_cycle_cnt, cpu);
                 self.video_cnt = self.video_cnt_base;
             },
             // refresh, matrix access, inc mc_base by if if y expansion is set
             15 => {
-                self.draw_background();
-                self.sample_border();
-                self.refresh_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.refresh_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
 
                 for i in 0..8 {
                     if (self.sprite_y_exp & (1 << i)) != 0 {
@@ -582,14 +606,14 @@ impl VIC {
                 }
                 
                 self.ml_idx = 0;
-                self.matrix_access(c64_cycle_cnt);
+                self.matrix_access(c64_cycle_cnt, mem, cpu);
             },
             // graphics access, matrix access, inc mc_base by 1 if y expansion is set
             16 => {
-                self.draw_background();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
 
                 mask = 1;
 
@@ -605,13 +629,13 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.matrix_access(c64_cycle_cnt);
+                self.matrix_access(c64_cycle_cnt, mem, cpu);
             },
             // graphics access, matrix access, turn off border in 40 column mode,
             // display window starts here
             17 => {
-                let ctrl1 = self.read_register(0xD011);
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl1 = self.read_register(0xD011, mem);
+                let ctrl2 = self.read_register(0xD016, mem);
 
                 if (ctrl2 & 8) != 0 {
                     if self.raster_cnt == self.dy_stop {
@@ -639,17 +663,17 @@ impl VIC {
 
                 self.border_on_sample[1] = self.border_on;
 
-                self.draw_background();
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_background(mem);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
+                self.matrix_access(c64_cycle_cnt, mem, cpu);
             },
             // turn off border in 38 column mode
             18 => {
-                let ctrl1 = self.read_register(0xD011);
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl1 = self.read_register(0xD011, mem);
+                let ctrl2 = self.read_register(0xD016, mem);
 
                 if (ctrl2 & 8) == 0 {
                     if self.raster_cnt == self.dy_stop {
@@ -676,32 +700,32 @@ impl VIC {
                 }
 
                 self.border_on_sample[2] = self.border_on;
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
+                self.matrix_access(c64_cycle_cnt, mem, cpu);
                 self.last_char_data = self.char_data;
             },
             // graphics and matrix access
             19..=54 => {
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
-                self.fetch_if_bad_line(c64_cycle_cnt);
-                self.matrix_access(c64_cycle_cnt);
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
+                self.fetch_if_bad_line(c64_cycle_cnt, cpu);
+                self.matrix_access(c64_cycle_cnt, mem, cpu);
                 self.last_char_data = self.char_data;
             },
             // lastr graphics access, turn off matrix access,
             // turn on sprite DMA if y cooord is rightr and sprite enabled,
             // handle sprite y expansion, set BA for sprite 0
             55 => {
-                self.draw_graphics();
-                self.sample_border();
-                self.graphics_access();
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.graphics_access(mem);
                 self.display_if_bad_line();
 
-                let mye = self.read_register(0xD017);
+                let mye = self.read_register(0xD017, mem);
                 
                 mask = 1;
                 for _ in 0..8 {
@@ -712,19 +736,19 @@ impl VIC {
                     mask <<= 1;
                 }
                 
-                self.check_sprite_dma();
+                self.check_sprite_dma(mem);
 
                 if (self.sprite_dma_on & 0x01) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
                 else {
-                    as_mut!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // turn on border in 38 column mode, turn on sprite DMA if Y is right and sprite enabled,
             // set BA for sprite 0, display window ends here
             56 => {
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(0xD016, mem);
 
                 if (ctrl2 & 8) == 0 {
                     self.border_on = true;
@@ -732,19 +756,19 @@ impl VIC {
 
                 self.border_on_sample[3] = self.border_on;
 
-                self.draw_graphics();
-                self.sample_border();
-                self.idle_access();
+                self.draw_graphics(mem);
+                self.sample_border(mem);
+                self.idle_access(mem);
                 self.display_if_bad_line();
-                self.check_sprite_dma();
+                self.check_sprite_dma(mem);
 
                 if (self.sprite_dma_on & 0x01) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // turn on border in 40 column mode, set BA for sprite 1, paint sprites
             57 => {
-                let ctrl2 = self.read_register(0xD016);
+                let ctrl2 = self.read_register(0xD016, mem);
 
                 if (ctrl2 & 8) != 0 {
                     self.border_on = true;
@@ -767,20 +791,20 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.draw_background();
-                self.sample_border();
-                self.idle_access();
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.idle_access(mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x02) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // fetch sprite pointer 0, reset mc, turn on sprite display if needed,
             // turn off display if row_cnt == 7, read data of sprite 0
             58 => {
-                self.draw_background();
-                self.sample_border();
+                self.draw_background(mem);
+                self.sample_border(mem);
 
                 mask = 1;
 
@@ -795,8 +819,8 @@ impl VIC {
                     mask <<= 1;
                 }
 
-                self.sprite_ptr_access(0);
-                self.sprite_data_access(0, 0);
+                self.sprite_ptr_access(0, mem);
+                self.sprite_data_access(0, 0, mem);
 
                 if self.row_cnt == 7 {
                     self.video_cnt_base = self.video_cnt;
@@ -810,25 +834,25 @@ impl VIC {
             },
             // set BA for sprite 2, read data of sprite 0
             59 => {
-                self.draw_background();
-                self.sample_border();
-                self.sprite_data_access(0, 1);
-                self.sprite_data_access(0, 2);
+                self.draw_background(mem);
+                self.sample_border(mem);
+                self.sprite_data_access(0, 1, mem);
+                self.sprite_data_access(0, 2, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x04) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // fetch sprite pointer 1, reset BA if sprite 1 and 2 are off
             // graphics display ends here
             60 => {
-                self.draw_background();
-                self.sample_border();
+                self.draw_background(mem);
+                self.sample_border(mem);
 
                 if self.draw_this_line {
                     if self.sprite_draw != 0 {
-                        self.draw_sprites();
+                        self.draw_sprites(mem, cpu);
                     }
 
                     // left border01
@@ -870,45 +894,45 @@ impl VIC {
                     self.line_start_offset += c64::SCREEN_WIDTH;
                 }
 
-                self.sprite_ptr_access(1);
-                self.sprite_data_access(1, 0);
+                self.sprite_ptr_access(1, mem);
+                self.sprite_data_access(1, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x06) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // set BA for sprite 3, read data of sprite 1
             61 => {
-                self.sprite_data_access(1, 1);
-                self.sprite_data_access(1, 2);
+                self.sprite_data_access(1, 1, mem);
+                self.sprite_data_access(1, 2, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x08) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
             },
             // read sprite pointer 2, reset BA if sprite 2 and 3 are off, read data of sprite 2
             62 => {
-                self.sprite_ptr_access(2);
-                self.sprite_data_access(2, 0);
+                self.sprite_ptr_access(2, mem);
+                self.sprite_data_access(2, 0, mem);
                 self.display_if_bad_line();
 
                 if (self.sprite_dma_on & 0x0C) == 0 {
-                    as_ref!(self.cpu_ref).ba_low = false;
+                    cpu.ba_low = false;
                 }
             },
             // set BA for sprite 4, read data of sprite 2
             63 => {
-                self.sprite_data_access(2, 1);
-                self.sprite_data_access(2, 2);
+                self.sprite_data_access(2, 1, mem);
+                self.sprite_data_access(2, 2, mem);
                 self.display_if_bad_line();
 
                 if self.raster_cnt == self.dy_stop {
                     self.ud_border_on = true;
                 }
                 else {
-                    let ctrl1 = self.read_register(0xD011);
+                    let ctrl1 = self.read_register(0xD011, mem);
 
                     if ((ctrl1 & 0x10) != 0) && (self.raster_cnt == self.dy_start) {
                         self.ud_border_on = false;
@@ -916,7 +940,7 @@ impl VIC {
                 }
                 
                 if (self.sprite_dma_on & 0x10) != 0 {
-                    self.set_ba_low(c64_cycle_cnt);
+                    self.set_ba_low(c64_cycle_cnt, cpu);
                 }
 
                 line_finished = true;
@@ -929,6 +953,7 @@ impl VIC {
 
         if line_finished {
             self.curr_cycle = 1;
+            self.extra_cycles_left = self.extra_cycles;
         }
         else {
             self.curr_cycle += 1;
@@ -938,33 +963,163 @@ impl VIC {
     }
 
 
+    // raster and sprite state; the screen itself gets redrawn within the next frame
+    pub fn save_state<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self.last_byte, self.curr_cycle, self.irq_flag, self.irq_mask, self.extra_cycles_left])?;
+        snapshot::write_u16s(w, &[self.raster_cnt, self.raster_irq, self.raster_x, self.dy_start, self.dy_stop,
+                                  self.row_cnt, self.video_cnt, self.video_cnt_base, self.x_scroll, self.y_scroll,
+                                  self.cia_vabase, self.display_mode, self.skip_cnt,
+                                  self.matrix_base, self.char_base, self.bitmap_base])?;
+        snapshot::write_bools(w, &[self.border_on, self.is_bad_line, self.bad_lines_on, self.lp_triggered,
+                                   self.display_state, self.ud_border_on, self.frame_skipped, self.draw_this_line,
+                                   self.trigger_vblank])?;
+        snapshot::write_bools(w, &self.border_on_sample)?;
+        w.write_all(&self.matrix_line)?;
+        w.write_all(&self.color_line)?;
+        for offset in &[self.screen_chunk_offset, self.line_start_offset, self.fg_mask_offset, self.ml_idx] {
+            w.write_u32::<LittleEndian>(*offset as u32)?;
+        }
+
+        snapshot::write_u16s(w, &self.mc)?;
+        snapshot::write_u16s(w, &self.mc_base)?;
+        snapshot::write_u16s(w, &self.mx)?;
+        w.write_all(&self.my)?;
+        snapshot::write_u16s(w, &self.sprite_ptr)?;
+        w.write_all(&self.fg_mask_buffer)?;
+        w.write_all(&self.border_color_sample)?;
+        w.write_all(&self.sprite_coll_buffer)?;
+        w.write_all(&[self.refresh_cnt, self.sprite_y_exp, self.sprite_dma_on, self.sprite_display_on, self.sprite_draw,
+                      self.gfx_data, self.char_data, self.color_data, self.last_char_data])?;
+        for i in 0..8 {
+            w.write_all(&self.sprite_data[i])?;
+            w.write_all(&self.sprite_draw_data[i])?;
+        }
+
+        w.write_u32::<LittleEndian>(self.first_ba_cycle)
+    }
+
+
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut regs = [0; 5];
+        r.read_exact(&mut regs)?;
+        self.last_byte  = regs[0];
+        self.curr_cycle = regs[1];
+        self.irq_flag   = regs[2];
+        self.irq_mask   = regs[3];
+        self.extra_cycles_left = regs[4];
+
+        let mut counters = [0; 16];
+        snapshot::read_u16s(r, &mut counters)?;
+        self.raster_cnt = counters[0];
+        self.raster_irq = counters[1];
+        self.raster_x   = counters[2];
+        self.dy_start   = counters[3];
+        self.dy_stop    = counters[4];
+        self.row_cnt    = counters[5];
+        self.video_cnt  = counters[6];
+        self.video_cnt_base = counters[7];
+        self.x_scroll   = counters[8];
+        self.y_scroll   = counters[9];
+        self.cia_vabase = counters[10];
+        self.display_mode = counters[11];
+        self.skip_cnt     = counters[12];
+        self.matrix_base  = counters[13];
+        self.char_base    = counters[14];
+        self.bitmap_base  = counters[15];
+
+        // the mode picks the drawing routine and the scroll value is a shift count
+        if self.display_mode > 7 || self.x_scroll > 7 {
+            return Err(snapshot::invalid_data("Invalid VIC display mode"));
+        }
+
+        let mut flags = [false; 9];
+        snapshot::read_bools(r, &mut flags)?;
+        self.border_on      = flags[0];
+        self.is_bad_line    = flags[1];
+        self.bad_lines_on   = flags[2];
+        self.lp_triggered   = flags[3];
+        self.display_state  = flags[4];
+        self.ud_border_on   = flags[5];
+        self.frame_skipped  = flags[6];
+        self.draw_this_line = flags[7];
+        self.trigger_vblank = flags[8];
+        snapshot::read_bools(r, &mut self.border_on_sample)?;
+        r.read_exact(&mut self.matrix_line)?;
+        r.read_exact(&mut self.color_line)?;
+
+        let mut offsets = [0; 4];
+        for offset in offsets.iter_mut() {
+            *offset = r.read_u32::<LittleEndian>()? as usize;
+        }
+
+        // offsets index into fixed size buffers - don't let a corrupt state cause out of bounds access later
+        if offsets[0] > self.window_buffer.len() || offsets[1] > self.window_buffer.len() ||
+           offsets[2] > self.fg_mask_buffer.len() || offsets[3] > self.matrix_line.len() {
+            return Err(snapshot::invalid_data("Invalid VIC buffer offset"));
+        }
+
+        self.screen_chunk_offset = offsets[0];
+        self.line_start_offset   = offsets[1];
+        self.fg_mask_offset      = offsets[2];
+        self.ml_idx              = offsets[3];
+
+        snapshot::read_u16s(r, &mut self.mc)?;
+        snapshot::read_u16s(r, &mut self.mc_base)?;
+        snapshot::read_u16s(r, &mut self.mx)?;
+        r.read_exact(&mut self.my)?;
+        snapshot::read_u16s(r, &mut self.sprite_ptr)?;
+        r.read_exact(&mut self.fg_mask_buffer)?;
+        r.read_exact(&mut self.border_color_sample)?;
+        r.read_exact(&mut self.sprite_coll_buffer)?;
+
+        let mut sprite_regs = [0; 9];
+        r.read_exact(&mut sprite_regs)?;
+        self.refresh_cnt       = sprite_regs[0];
+        self.sprite_y_exp      = sprite_regs[1];
+        self.sprite_dma_on     = sprite_regs[2];
+        self.sprite_display_on = sprite_regs[3];
+        self.sprite_draw       = sprite_regs[4];
+        self.gfx_data          = sprite_regs[5];
+        self.char_data         = sprite_regs[6];
+        self.color_data        = sprite_regs[7];
+        self.last_char_data    = sprite_regs[8];
+        for i in 0..8 {
+            r.read_exact(&mut self.sprite_data[i])?;
+            r.read_exact(&mut self.sprite_draw_data[i])?;
+        }
+
+        self.first_ba_cycle = r.read_u32::<LittleEndian>()?;
+        Ok(())
+    }
+
+
     // *** private functions *** //
 
     // check if register status has changed - used for visual debugger
-    fn dbg_check_regs(&mut self, addr: u16, value: u8) {
-        self.dbg_reg_changed = as_mut!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(addr) != value;
+    fn dbg_check_regs(&mut self, addr: u16, value: u8, mem: &mut memory::Memory) {
+        self.dbg_reg_changed = mem.get_ram_bank(memory::MemType::Io).read(addr) != value;
     }
 
 
-    fn matrix_access(&mut self, c64_cycle_cnt: u32) {
-        if as_ref!(self.cpu_ref).ba_low {
+    fn matrix_access(&mut self, c64_cycle_cnt: u32, mem: &mut memory::Memory, cpu: &mut cpu::CPU) {
+        if cpu.ba_low {
             if (c64_cycle_cnt - self.first_ba_cycle) < 3 {
                 self.color_line[self.ml_idx]  = 0xFF;
                 self.matrix_line[self.ml_idx] = 0xFF;
             }
             else {
                 let addr = (self.video_cnt & 0x03FF) | self.matrix_base;
-                self.matrix_line[self.ml_idx] = self.read_byte(addr);
+                self.matrix_line[self.ml_idx] = self.read_byte(addr, mem);
 
                 // assign value from color ram
-                self.color_line[self.ml_idx] = as_ref!(self.mem_ref).get_ram_bank(memory::MemType::Io).read(0xD800 + (addr & 0x03FF));
+                self.color_line[self.ml_idx] = mem.get_ram_bank(memory::MemType::Io).read(0xD800 + (addr & 0x03FF));
             }
         }
     }
 
 
-    fn graphics_access(&mut self) {
-        let ctrl1 = self.read_register(0xD011);
+    fn graphics_access(&mut self, mem: &mut memory::Memory) {
+        let ctrl1 = self.read_register(0xD011, mem);
         
         if self.display_state {
             let mut addr: u16;
@@ -980,7 +1135,7 @@ impl VIC {
                 addr &= 0xF9FF;
             }
 
-            self.gfx_data = self.read_byte(addr);
+            self.gfx_data = self.read_byte(addr, mem);
             self.char_data = self.matrix_line[self.ml_idx];
             self.color_data = self.color_line[self.ml_idx];
 
@@ -989,14 +1144,14 @@ impl VIC {
         }
         else {
             // display is off
-            self.gfx_data = self.read_byte(if (ctrl1 & 0x40) != 0 { 0x39FF } else { 0x3FFF });
+            self.gfx_data = self.read_byte(if (ctrl1 & 0x40) != 0 { 0x39FF } else { 0x3FFF }, mem);
             self.char_data = 0;
             self.color_data = 0;
         }
     }
 
 
-    fn draw_background(&mut self) {
+    fn draw_background(&mut self, mem: &mut memory::Memory) {
         let dst_color: u8;
 
         if !self.draw_this_line {
@@ -1006,7 +1161,7 @@ impl VIC {
         match self.display_mode {
             // standard text, multicolor text, multicolor bitmap
             0 | 1 | 3 => {
-                dst_color = self.read_register(0xD021);
+                dst_color = self.read_register(0xD021, mem);
             },
             // standard bitmap
             2 => {
@@ -1016,18 +1171,18 @@ impl VIC {
             4 => {
                 if (self.last_char_data & 0x80) != 0 {
                     if (self.last_char_data & 0x40) != 0 {
-                        dst_color = self.read_register(0xD024);
+                        dst_color = self.read_register(0xD024, mem);
                     }
                     else {
-                        dst_color = self.read_register(0xD023);
+                        dst_color = self.read_register(0xD023, mem);
                     }
                 }
                 else {
                     if (self.last_char_data & 0x40) != 0 {
-                        dst_color = self.read_register(0xD022);
+                        dst_color = self.read_register(0xD022, mem);
                     }
                     else {
-                        dst_color = self.read_register(0xD021);
+                        dst_color = self.read_register(0xD021, mem);
                     }
                 }
             },
@@ -1039,13 +1194,13 @@ impl VIC {
     }
     
 
-    fn draw_graphics(&mut self) {
+    fn draw_graphics(&mut self, mem: &mut memory::Memory) {
         if !self.draw_this_line {
             return;
         }
         
         if self.ud_border_on {
-            self.draw_background();
+            self.draw_background(mem);
             return;
         }
 
@@ -1053,20 +1208,20 @@ impl VIC {
 
         match self.display_mode {
             0 => { // standard text
-                dst_color[0] = self.read_register(0xD021);
+                dst_color[0] = self.read_register(0xD021, mem);
                 dst_color[1] = self.color_data;
                 self.draw_std(&dst_color);
             },
             1 => { // multicolor text
                 if (self.color_data & 8) != 0 {
-                    dst_color[0] = self.read_register(0xD021);
-                    dst_color[1] = self.read_register(0xD022);
-                    dst_color[2] = self.read_register(0xD023);
+                    dst_color[0] = self.read_register(0xD021, mem);
+                    dst_color[1] = self.read_register(0xD022, mem);
+                    dst_color[2] = self.read_register(0xD023, mem);
                     dst_color[3] = self.color_data & 7;
                     self.draw_multi(&dst_color);
                 }
                 else {
-                    dst_color[0] = self.read_register(0xD021);
+                    dst_color[0] = self.read_register(0xD021, mem);
                     dst_color[1] = self.color_data;
                     self.draw_std(&dst_color);
                 }
@@ -1077,7 +1232,7 @@ impl VIC {
                 self.draw_std(&dst_color);
             },
             3 => { // multicolor bitmap
-                dst_color[0] = self.read_register(0xD021);
+                dst_color[0] = self.read_register(0xD021, mem);
                 dst_color[1] = self.char_data >> 4;
                 dst_color[2] = self.char_data;
                 dst_color[3] = self.color_data;
@@ -1086,18 +1241,18 @@ impl VIC {
             4 => { // ECM text
                 if (self.char_data & 0x80) != 0 {
                     if (self.char_data & 0x40) != 0 {
-                        dst_color[0] = self.read_register(0xD024);
+                        dst_color[0] = self.read_register(0xD024, mem);
                     }
                     else {
-                        dst_color[0] = self.read_register(0xD023);
+                        dst_color[0] = self.read_register(0xD023, mem);
                     }
                 }
                 else {
                     if (self.char_data & 0x40) != 0 {
-                        dst_color[0] = self.read_register(0xD022);
+                        dst_color[0] = self.read_register(0xD022, mem);
                     }
                     else {
-                        dst_color[0] = self.read_register(0xD021);
+                        dst_color[0] = self.read_register(0xD021, mem);
                     }
                 }
 
@@ -1167,7 +1322,7 @@ impl VIC {
     }
     
 
-    fn draw_sprites(&mut self) {
+    fn draw_sprites(&mut self, mem: &mut memory::Memory, cpu: &mut cpu::CPU) {
         let mut sbit = 1;
         let mut spr_coll = 0;
         let mut gfx_coll = 0;
@@ -1181,7 +1336,7 @@ impl VIC {
             if ((self.sprite_draw & sbit) != 0) && (self.mx[snum] < (c64::SCREEN_WIDTH as u16)-32) {
                 let p = self.line_start_offset as u32 + (self.mx[snum] + 8) as u32;
                 let q = self.mx[snum] + 8;
-                let color = self.read_register(0xD027 + snum as u16);
+                let color = self.read_register(0xD027 + snum as u16, mem);
 
                 // fetch sprite data and mask
                 let mut sdata: u32 = ((self.sprite_draw_data[snum][0] as u32) << 24) | 
@@ -1201,7 +1356,7 @@ impl VIC {
                 }
 
                 // is sprite X-expanded?
-                let mxe = self.read_register(0xD01D);
+                let mxe = self.read_register(0xD01D, mem);
                 if (mxe & sbit) != 0 {
                     if self.mx[snum] > ((c64::SCREEN_WIDTH as u16)-56) {
                         sbit <<= 1;
@@ -1227,7 +1382,7 @@ impl VIC {
                     }
 
                     // multicolor?
-                    let mmc = self.read_register(0xD01C);
+                    let mmc = self.read_register(0xD01C, mem);
                     if (mmc & sbit) != 0 {
                         // expand sprite data
                         sdata_l = ((MULTI_EXP_TABLE[((sdata >> 24) & 0xFF) as usize] as u32) << 16) |
@@ -1244,7 +1399,7 @@ impl VIC {
                         if ((fg_mask & (plane0_l | plane1_l)) != 0) || ((fg_mask_r & (plane0_r | plane1_r)) != 0) {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(0xD01B, mem);
                             if (mdp & sbit) != 0 {
                                 plane0_l &= !fg_mask; // mask sprite if in background
                                 plane1_l &= !fg_mask;
@@ -1260,7 +1415,7 @@ impl VIC {
 
                             if (plane1_l & 0x80000000) != 0 {
                                 if (plane0_l & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(0xD026, mem);
                                 }
                                 else {
                                     col = color;
@@ -1268,7 +1423,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0_l & 0x80000000) != 0 {
-                                   col = self.read_register(0xD025);
+                                   col = self.read_register(0xD025, mem);
                                 }
                                 else {
                                     i += 1;
@@ -1295,7 +1450,7 @@ impl VIC {
                             
                             if (plane1_r & 0x80000000) != 0 {
                                 if (plane0_r & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(0xD026, mem);
                                 }
                                 else {
                                     col = color;
@@ -1303,7 +1458,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0_r & 0x80000000) != 0 {
-                                   col = self.read_register(0xD025);
+                                   col = self.read_register(0xD025, mem);
                                 }
                                 else {
                                     i += 1;
@@ -1336,7 +1491,7 @@ impl VIC {
                         if ((fg_mask & sdata_l) != 0) || ((fg_mask_r & sdata_r) != 0) {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(0xD01B, mem);
                             if (mdp & sbit) != 0 {
                                 sdata_l &= !fg_mask; // mask sprite if in background
                             }
@@ -1382,7 +1537,7 @@ impl VIC {
                 else {
                     // unexpanded
                     // multicolor?
-                    let mmc = self.read_register(0xD01C);
+                    let mmc = self.read_register(0xD01C, mem);
                     if (mmc & sbit) != 0 {
                         // convert sprite chunky pixels to bitplanes
                         let mut plane0: u32 = (sdata & 0x55555555) | ((sdata & 0x55555555) << 1);
@@ -1392,7 +1547,7 @@ impl VIC {
                         if (fg_mask & (plane0 | plane1)) != 0 {
                             gfx_coll |= sbit;
                             
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(0xD01B, mem);
                             if (mdp & sbit) != 0 {
                                 plane0 &= !fg_mask; // mask sprite if in background
                                 plane1 &= !fg_mask;
@@ -1404,7 +1559,7 @@ impl VIC {
                             let col: u8;
                             if (plane1 & 0x80000000) != 0 {
                                 if (plane0 & 0x80000000) != 0 {
-                                    col = self.read_register(0xD026);
+                                    col = self.read_register(0xD026, mem);
                                 }
                                 else {
                                     col = color;
@@ -1412,7 +1567,7 @@ impl VIC {
                             }
                             else {
                                 if (plane0 & 0x80000000) != 0 {
-                                    col = self.read_register(0xD025);
+                                    col = self.read_register(0xD025, mem);
                                 }
                                 else {
                                     plane0 <<= 1;
@@ -1439,7 +1594,7 @@ impl VIC {
                         if (fg_mask & sdata) != 0 {
                             gfx_coll |= sbit;
 
-                            let mdp = self.read_register(0xD01B);
+                            let mdp = self.read_register(0xD01B, mem);
                             if (mdp & sbit) != 0 {
                                 sdata &= !fg_mask; // mask sprite if in background
                             }
@@ -1469,34 +1624,34 @@ impl VIC {
         // explicitly allows the VIC to perform writes there.
         
         // sprite-sprite collisions
-        let clx_spr = self.read_register(0xD01E) | spr_coll;
-        self.write_register_nc(0xD01E, clx_spr);
+        let clx_spr = self.read_register(0xD01E, mem) | spr_coll;
+        self.write_register_nc(0xD01E, clx_spr, mem);
         if clx_spr == 0 {
             self.irq_flag |= 0x04;
             if (self.irq_mask & 0x04) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                cpu.set_vic_irq(true);
             }
         }
         
         // sprite-background collisions
-        let clx_bgr = self.read_register(0xD01F) | gfx_coll;
-        self.write_register_nc(0xD01F, clx_bgr);
+        let clx_bgr = self.read_register(0xD01F, mem) | gfx_coll;
+        self.write_register_nc(0xD01F, clx_bgr, mem);
         if clx_bgr == 0 {
             self.irq_flag |= 0x02;
             if (self.irq_mask & 0x02) != 0 {
                 self.irq_flag |= 0x80;
-                as_mut!(self.cpu_ref).set_vic_irq(true);
+                cpu.set_vic_irq(true);
             }
         }
     }
 
 
     // ***helper functions ***
-    fn set_ba_low(&mut self, c64_cycle_cnt: u32) {
-        if !as_mut!(self.cpu_ref).ba_low {
+    fn set_ba_low(&mut self, c64_cycle_cnt: u32, cpu: &mut cpu::CPU) {
+        if !cpu.ba_low {
             self.first_ba_cycle = c64_cycle_cnt;
-            as_mut!(self.cpu_ref).ba_low = true;
+            cpu.ba_low = true;
         }   
     }
 
@@ -1508,39 +1663,39 @@ impl VIC {
     }
 
 
-    fn fetch_if_bad_line(&mut self, c64_cycle_cnt: u32) {
+    fn fetch_if_bad_line(&mut self, c64_cycle_cnt: u32, cpu: &mut cpu::CPU) {
         if self.is_bad_line {
             self.display_state = true;
-            self.set_ba_low(c64_cycle_cnt);
+            self.set_ba_low(c64_cycle_cnt, cpu);
         }
     }
 
 
-    fn rc_if_bad_line(&mut self, c64_cycle_cnt: u32) {
+    fn rc_if_bad_line(&mut self, c64_cycle_cnt: u32, cpu: &mut cpu::CPU) {
         if self.is_bad_line {
             self.display_state = true;
             self.row_cnt = 0;
-            self.set_ba_low(c64_cycle_cnt);
+            self.set_ba_low(c64_cycle_cnt, cpu);
         }
     }
 
 
-    fn idle_access(&mut self) {
-        self.read_byte(0x3FFF);
+    fn idle_access(&mut self, mem: &mut memory::Memory) {
+        self.read_byte(0x3FFF, mem);
     }
 
 
-    fn refresh_access(&mut self){
+    fn refresh_access(&mut self, mem: &mut memory::Memory){
         let ref_cnt = self.refresh_cnt as u16;
-        self.read_byte(0x3F00 | ref_cnt);
+        self.read_byte(0x3F00 | ref_cnt, mem);
         self.refresh_cnt = self.refresh_cnt.wrapping_sub(0x01);
     }
 
 
-    fn check_sprite_dma(&mut self){
+    fn check_sprite_dma(&mut self, mem: &mut memory::Memory){
         let mut mask = 1;
-        let me = self.read_register(0xD015);
-        let mye = self.read_register(0xD017);
+        let me = self.read_register(0xD015, mem);
+        let mye = self.read_register(0xD017, mem);
         for i in 0..8 {
             if ((me & mask) != 0) && ((self.raster_cnt & 0xFF) == self.my[i] as u16) {
                 self.sprite_dma_on |= mask;
@@ -1555,28 +1710,28 @@ impl VIC {
     }
 
 
-    fn sprite_ptr_access(&mut self, num: usize) {
+    fn sprite_ptr_access(&mut self, num: usize, mem: &mut memory::Memory) {
         let addr = self.matrix_base | 0x03F8 | num as u16;
-        self.sprite_ptr[num] = (self.read_byte(addr) as u16) << 6;
+        self.sprite_ptr[num] = (self.read_byte(addr, mem) as u16) << 6;
     }
 
 
-    fn sprite_data_access(&mut self, num: usize, bytenum: usize) {
+    fn sprite_data_access(&mut self, num: usize, bytenum: usize, mem: &mut memory::Memory) {
         if (self.sprite_dma_on & (1 << num as u8)) != 0 {
             let addr = self.mc[num] & 0x3F | self.sprite_ptr[num];
-            self.sprite_data[num][bytenum] = self.read_byte(addr);
+            self.sprite_data[num][bytenum] = self.read_byte(addr, mem);
             self.mc[num] += 1;
         }
         else if bytenum == 1 {
-            self.idle_access();
+            self.idle_access(mem);
         }
     }
 
 
-    fn sample_border(&mut self) {
+    fn sample_border(&mut self, mem: &mut memory::Memory) {
         if self.draw_this_line {
             if self.border_on {
-                self.border_color_sample[(self.curr_cycle-13) as usize] = self.read_register(0xD020);
+                self.border_color_sample[(self.curr_cycle-13) as usize] = self.read_register(0xD020, mem);
             }
             
             self.screen_chunk_offset += 8;
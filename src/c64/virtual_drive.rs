@@ -0,0 +1,382 @@
+// virtual drive 8: serves a disk image to the kernal by trapping its serial bus routines, so that LOAD, SAVE,
+// OPEN and the like work without emulating the drive itself. Fast loaders which talk to the drive directly don't.
+use c64::cpu;
+use c64::disk_image::{DiskImage, FileType};
+use c64::memory;
+use std::mem;
+
+pub const DEVICE: u8 = 8;
+const COMMAND_CHANNEL: usize = 15;
+const STATUS: u16 = 0x0090; // kernal I/O status byte
+const STATUS_TIMEOUT: u8 = 0x02;
+const STATUS_EOI: u8 = 0x40;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Routine {
+    Talk, Listen, Second, Tksa, Ciout, Acptr, Untlk, Unlsn,
+}
+
+// kernal serial routines and their first bytes, which have to match for the trap to fire
+// (replacement kernals like JiffyDOS have routines of their own)
+const TRAPS: [(u16, [u8; 2], Routine); 8] = [
+    (0xED09, [0x09, 0x40], Routine::Talk),
+    (0xED0C, [0x09, 0x20], Routine::Listen),
+    (0xEDB9, [0x85, 0x95], Routine::Second),
+    (0xEDC7, [0x85, 0x95], Routine::Tksa),
+    (0xEDDD, [0x24, 0x94], Routine::Ciout),
+    (0xEE13, [0x78, 0xA9], Routine::Acptr),
+    (0xEDEF, [0x78, 0x20], Routine::Untlk),
+    (0xEDFE, [0xA9, 0x3F], Routine::Unlsn),
+];
+
+// DOS status codes reported on the command channel
+const OK: u8 = 0;
+const FILES_SCRATCHED: u8 = 1;
+const WRITE_PROTECT_ON: u8 = 26;
+const SYNTAX_ERROR: u8 = 31;
+const NO_FILE_NAME: u8 = 34;
+const FILE_NOT_OPEN: u8 = 61;
+const FILE_NOT_FOUND: u8 = 62;
+const FILE_TYPE_MISMATCH: u8 = 64;
+const DOS_VERSION: u8 = 73;
+
+
+// open file: read from the disk as a whole on open, or written to it as a whole on close
+struct Channel {
+    data: Vec<u8>,
+    pos: usize,                                   // next byte to read
+    write: Option<(Vec<u8>, FileType, bool)>, // name, type and whether to replace an existing file
+}
+
+
+pub struct VirtualDrive {
+    image: Option<Box<dyn DiskImage>>,
+    channels: Vec<Option<Channel>>,
+    listening: bool,
+    talking: bool,
+    channel: usize,    // secondary address of the current transfer
+    opening: bool,     // receiving the name of a file to open on the channel
+    name: Vec<u8>,
+    command: Vec<u8>,  // written to the command channel
+    error: (u8, u8, u8), // status code, track and sector
+    status: Vec<u8>,   // rest of the status message being read from the command channel
+}
+
+impl VirtualDrive {
+    pub fn new() -> VirtualDrive {
+        VirtualDrive {
+            image: None,
+            channels: (0..COMMAND_CHANNEL).map(|_| None).collect(),
+            listening: false,
+            talking: false,
+            channel: 0,
+            opening: false,
+            name: Vec::new(),
+            command: Vec::new(),
+            error: (DOS_VERSION, 0, 0),
+            status: Vec::new(),
+        }
+    }
+
+
+    // power cycle: closes all files, the disk stays in
+    pub fn reset(&mut self) {
+        let image = self.image.take();
+        *self = VirtualDrive::new();
+        self.image = image;
+    }
+
+
+    pub fn attach(&mut self, image: Box<dyn DiskImage>) {
+        self.reset();
+        self.image = Some(image);
+    }
+
+
+    pub fn detach(&mut self) -> Option<Box<dyn DiskImage>> {
+        self.reset();
+        self.image.take()
+    }
+
+
+    pub fn image(&self) -> Option<&dyn DiskImage> {
+        self.image.as_deref()
+    }
+
+
+    // called when the CPU is about to run the instruction at PC: runs a kernal serial routine on behalf
+    // of the drive if it's addressed, and returns to the caller. The drive is only present with a disk in it.
+    pub fn trap(&mut self, cpu: &mut cpu::CPU, memory: &mut memory::Memory) -> bool {
+        if self.image.is_none() {
+            return false;
+        }
+
+        let pc = cpu.pc;
+        let routine = match TRAPS.iter().find(|t| t.0 == pc) {
+            Some(&(_, signature, routine)) if memory.read_byte(pc) == signature[0] && memory.read_byte(pc + 1) == signature[1] => routine,
+            _ => return false,
+        };
+
+        match routine {
+            Routine::Listen | Routine::Talk => {
+                self.listening = routine == Routine::Listen && cpu.a == DEVICE;
+                self.talking = routine == Routine::Talk && cpu.a == DEVICE;
+                if cpu.a != DEVICE {
+                    return false;
+                }
+            },
+            Routine::Second if self.listening => self.second(cpu.a),
+            Routine::Tksa if self.talking => self.channel = (cpu.a & 0x0F) as usize,
+            Routine::Ciout if self.listening => self.receive(cpu.a),
+            Routine::Acptr if self.talking => {
+                let (value, status) = self.send();
+                cpu.a = value;
+                cpu.set_zn_flags(value);
+                if status != 0 {
+                    let st = memory.read_byte(STATUS);
+                    memory.write_byte(STATUS, st | status);
+                }
+            },
+            Routine::Unlsn if self.listening => self.unlisten(),
+            Routine::Untlk if self.talking => self.talking = false,
+            _ => return false,
+        }
+
+        // RTS
+        let lo = memory.read_byte(0x0100 + cpu.sp.wrapping_add(1) as u16);
+        let hi = memory.read_byte(0x0100 + cpu.sp.wrapping_add(2) as u16);
+        cpu.sp = cpu.sp.wrapping_add(2);
+        cpu.pc = ((hi as u16) << 8 | lo as u16).wrapping_add(1);
+        cpu.set_status_flag(cpu::StatusFlag::Carry, false);
+        true
+    }
+
+
+    // *** private functions *** //
+
+    // secondary address after LISTEN: open, close or data
+    fn second(&mut self, value: u8) {
+        let channel = (value & 0x0F) as usize;
+
+        match value & 0xF0 {
+            0xF0 => {
+                self.opening = true;
+                self.name.clear();
+                self.channel = channel;
+            },
+            0xE0 => self.close(channel),
+            _ => self.channel = channel,
+        }
+    }
+
+
+    fn receive(&mut self, value: u8) {
+        if self.opening {
+            self.name.push(value);
+        }
+        else if self.channel == COMMAND_CHANNEL {
+            self.command.push(value);
+        }
+        else {
+            match self.channels[self.channel] {
+                Some(Channel { ref mut data, write: Some(_), .. }) => data.push(value),
+                _ => self.set_error(FILE_NOT_OPEN, 0, 0),
+            }
+        }
+    }
+
+
+    // next byte for the computer and the status bits to go with it
+    fn send(&mut self) -> (u8, u8) {
+        if self.channel == COMMAND_CHANNEL {
+            if self.status.is_empty() {
+                let (code, track, sector) = self.error;
+                self.status = format!("{:02}, {},{:02},{:02}\r", code, message(code), track, sector).into_bytes();
+                self.error = (OK, 0, 0);
+            }
+
+            let value = self.status.remove(0);
+            return (value, if self.status.is_empty() { STATUS_EOI } else { 0 });
+        }
+
+        match self.channels[self.channel] {
+            Some(Channel { ref data, ref mut pos, write: None }) if *pos < data.len() => {
+                *pos += 1;
+                (data[*pos - 1], if *pos == data.len() { STATUS_EOI } else { 0 })
+            },
+            // nothing to send, which the kernal sees as a timeout (file not found)
+            _ => (0x0D, STATUS_TIMEOUT | STATUS_EOI),
+        }
+    }
+
+
+    fn unlisten(&mut self) {
+        self.listening = false;
+
+        if self.opening {
+            self.opening = false;
+            let name = mem::take(&mut self.name);
+            self.open(self.channel, &name);
+        }
+        else if self.channel == COMMAND_CHANNEL && !self.command.is_empty() {
+            let command = mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+
+    // name: [[@][drive]:]name[,type[,mode]], "$" for the directory or a command on the command channel
+    fn open(&mut self, channel: usize, name: &[u8]) {
+        if channel == COMMAND_CHANNEL {
+            if !name.is_empty() {
+                self.execute(name);
+            }
+            return;
+        }
+
+        self.channels[channel] = None;
+        let image = self.image.as_ref().unwrap();
+
+        if name.first() == Some(&b'$') {
+            let pattern = name.iter().position(|c| *c == b':').map_or(&b"*"[..], |i| &name[i + 1..]);
+            self.channels[channel] = Some(Channel { data: image.listing(pattern), pos: 0, write: None });
+            self.set_error(OK, 0, 0);
+            return;
+        }
+
+        let replace = name.first() == Some(&b'@');
+        let name = &name[replace as usize..];
+        let name = match name.iter().position(|c| *c == b':') {
+            Some(i) => &name[i + 1..],
+            None => name,
+        };
+
+        let mut parts = name.split(|c| *c == b',');
+        let name = parts.next().unwrap_or(&[]);
+        let file_type = match parts.next().and_then(|t| t.first()) {
+            Some(b'P') => Some(FileType::Prg),
+            Some(b'S') => Some(FileType::Seq),
+            Some(b'U') => Some(FileType::Usr),
+            Some(b'L') => Some(FileType::Rel),
+            _ => None,
+        };
+        let write = channel == 1 || matches!(parts.next().and_then(|m| m.first()), Some(b'W'));
+
+        if name.is_empty() {
+            self.set_error(NO_FILE_NAME, 0, 0);
+        }
+        else if write {
+            let file_type = file_type.unwrap_or(if channel <= 1 { FileType::Prg } else { FileType::Seq });
+            self.channels[channel] = Some(Channel { data: Vec::new(), pos: 0, write: Some((name.to_vec(), file_type, replace)) });
+            self.set_error(OK, 0, 0);
+        }
+        else {
+            let result = match image.find(name) {
+                Some(ref entry) if file_type.is_some() && file_type != Some(entry.file_type) => Err(FILE_TYPE_MISMATCH),
+                Some(ref entry) => image.read_file(entry).map_err(|e| e.code()),
+                None => Err(FILE_NOT_FOUND),
+            };
+
+            match result {
+                Ok(data) => {
+                    self.channels[channel] = Some(Channel { data, pos: 0, write: None });
+                    self.set_error(OK, 0, 0);
+                },
+                Err(code) => self.set_error(code, 0, 0),
+            }
+        }
+    }
+
+
+    // files written to are stored on the disk; closing the command channel closes all of them
+    fn close(&mut self, channel: usize) {
+        if channel == COMMAND_CHANNEL {
+            for channel in 0..COMMAND_CHANNEL {
+                self.close(channel);
+            }
+            return;
+        }
+
+        if let Some(Channel { data, write: Some((name, file_type, replace)), .. }) = self.channels[channel].take() {
+            let result = self.image.as_mut().unwrap().write_file(&name, file_type, &data, replace);
+            match result {
+                Ok(_) => self.save(),
+                Err(e) => self.set_error(e.code(), 0, 0),
+            }
+        }
+    }
+
+
+    // DOS commands: I(nitialize), S(cratch):pattern, N(ew):name[,id] and UJ/UI (reset)
+    fn execute(&mut self, command: &[u8]) {
+        let len = command.iter().rposition(|c| *c != b'\r').map_or(0, |i| i + 1);
+        let command = &command[..len];
+        let argument = command.iter().position(|c| *c == b':').map(|i| &command[i + 1..]);
+
+        match (command.first(), argument) {
+            (Some(b'I'), _) => self.set_error(OK, 0, 0),
+            (Some(b'U'), _) if matches!(command.get(1), Some(b'J') | Some(b'I') | Some(b':')) => self.reset(),
+            (Some(b'S'), Some(pattern)) => {
+                let result = self.image.as_mut().unwrap().scratch(pattern);
+                match result {
+                    Ok(count) => {
+                        self.set_error(FILES_SCRATCHED, count, 0);
+                        self.save();
+                    },
+                    Err(e) => self.set_error(e.code(), 0, 0),
+                }
+            },
+            (Some(b'N'), Some(argument)) => {
+                let mut parts = argument.split(|c| *c == b',');
+                let name = parts.next().unwrap_or(&[]);
+                let id = parts.next();
+                self.image.as_mut().unwrap().format(name, id);
+                self.set_error(OK, 0, 0);
+                self.save();
+            },
+            _ => self.set_error(SYNTAX_ERROR, 0, 0),
+        }
+    }
+
+
+    // write the changed disk back to its file
+    fn save(&mut self) {
+        if self.image.as_ref().unwrap().save().is_err() {
+            self.set_error(WRITE_PROTECT_ON, 0, 0);
+        }
+    }
+
+
+    fn set_error(&mut self, code: u8, track: u8, sector: u8) {
+        self.error = (code, track, sector);
+        self.status.clear();
+    }
+}
+
+impl Default for VirtualDrive {
+    fn default() -> VirtualDrive {
+        VirtualDrive::new()
+    }
+}
+
+
+// *** private functions *** //
+
+fn message(code: u8) -> &'static str {
+    match code {
+        0           => "OK",
+        1           => "FILES SCRATCHED",
+        20..=24 | 27 | 29 => "READ ERROR",
+        25          => "WRITE ERROR",
+        26          => "WRITE PROTECT ON",
+        30..=34     => "SYNTAX ERROR",
+        61          => "FILE NOT OPEN",
+        62          => "FILE NOT FOUND",
+        63          => "FILE EXISTS",
+        64          => "FILE TYPE MISMATCH",
+        66          => "ILLEGAL TRACK OR SECTOR",
+        72          => "DISK FULL",
+        73          => "CBM DOS V2.6 1541",
+        _           => "DRIVE NOT READY",
+    }
+}
@@ -0,0 +1,297 @@
+// command line options of the emulator executable
+use minifb::Scale;
+use rust64::c64;
+use rust64::c64::model;
+use rust64::c64::roms;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: rust64 [OPTIONS] [FILE]
+       rust64 disasm <FILE>
+
+Loads FILE (a .prg program, a .t64 tape archive or a .crt cartridge, detected by content) once the C64 has booted.
+A .d64, .d71, .d81 or .g64 disk image is put into drive 8, and --autostart loads its first file.
+A .tap tape image is put into the datasette, and --autostart presses PLAY and loads its first file.
+The disasm command prints a listing of the .prg program FILE instead.
+
+Options:
+  --autostart            run FILE right away once it's loaded
+  --scale <1|2|4|8>      window scale (default: 1)
+  --debugger             open the debugger windows
+  --model <MODEL>        machine model: pal, ntsc or ntsc-old (default: pal)
+  --rom-dir <DIR>        directory with basic.rom, chargen.rom and kernal.rom
+  --basic <FILE>         BASIC ROM image to use instead of the one in the ROM directory
+  --chargen <FILE>       character ROM image to use instead of the one in the ROM directory
+  --kernal <FILE>        kernal ROM image to use instead of the one in the ROM directory
+  --t64-entry <N>        file of a .t64 archive to load, 0 is the first (default: 0)
+  --true-drive           emulate a real 1541 as drive 8, which needs dos1541.rom in the ROM directory
+  --drive-rom <FILE>     1541 DOS ROM image to use instead of the one in the ROM directory
+  --speed <PERCENT>      emulation speed, 10-1000 (default: 100)
+  --warp                 run as fast as possible
+  --rewind <MB>          rewind buffer size, 0 disables rewinding (default: 32)
+  --headless             run without a window or audio device
+  --monitor              start in the machine language monitor; a line typed on the console enters it again
+  --binary-monitor <PORT>
+                         serve the VICE binary monitor protocol for remote debuggers on localhost:PORT
+  --limit-cycles <N>     exit after N clock cycles
+  -h, --help             print this help";
+
+// options followed by a value
+const VALUE_OPTIONS: [&str; 12] = ["--scale", "--model", "--rom-dir", "--basic", "--chargen", "--kernal", "--drive-rom", "--speed",
+                                   "--rewind", "--binary-monitor", "--limit-cycles", "--t64-entry"];
+
+
+pub struct Options {
+    pub file:         Option<String>,
+    pub autostart:    bool,
+    pub scale:        Scale,
+    pub debugger:     bool,
+    pub headless:     bool,
+    pub monitor:      bool,
+    pub binary_monitor_port: Option<u16>,
+    pub true_drive:   bool,
+    pub t64_entry:    usize,
+    pub warp:         bool,
+    pub speed:        u32,
+    pub rewind_limit: usize,
+    pub model:        model::Model,
+    pub rom_paths:    roms::RomPaths,
+    pub limit_cycles: Option<u64>,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            file:         None,
+            autostart:    false,
+            scale:        Scale::X1,
+            debugger:     false,
+            headless:     false,
+            monitor:      false,
+            binary_monitor_port: None,
+            true_drive:   false,
+            t64_entry:    0,
+            warp:         false,
+            speed:        100,
+            rewind_limit: c64::DEFAULT_REWIND_LIMIT,
+            model:        model::Model::Pal,
+            rom_paths:    roms::RomPaths::default(),
+            limit_cycles: None,
+        }
+    }
+}
+
+
+pub enum Command {
+    Run(Options),
+    Disasm(String),
+    Help,
+}
+
+
+// parse the arguments (without the program name); options take their value either as the next argument or after '='
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    if !args.is_empty() && args[0] == "disasm" {
+        return parse_disasm(&args[1..]);
+    }
+
+    let mut options = Options::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            set_file(&mut options, arg)?;
+            continue;
+        }
+
+        let (name, inline_value) = match arg.find('=') {
+            Some(idx) => (&arg[..idx], Some(arg[idx + 1..].to_string())),
+            None      => (&arg[..], None),
+        };
+
+        match name {
+            "-h" | "--help" | "--autostart" | "--debugger" | "--headless" | "--monitor" | "--true-drive" | "--warp" if inline_value.is_some() => {
+                return Err(format!("{} doesn't take a value", name))
+            },
+            "-h" | "--help" => return Ok(Command::Help),
+            "--autostart"   => options.autostart = true,
+            "--debugger"    => options.debugger = true,
+            "--headless"    => options.headless = true,
+            "--monitor"     => options.monitor = true,
+            "--true-drive"  => options.true_drive = true,
+            "--warp"        => options.warp = true,
+            _ if !VALUE_OPTIONS.contains(&name) => return Err(format!("Unknown option: {}", name)),
+            _ => {
+                // everything else requires a value
+                let value = match inline_value {
+                    Some(value) => value,
+                    None => match args.next() {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("Missing value for {}", name)),
+                    }
+                };
+
+                match name {
+                    "--scale" => options.scale = match &value[..] {
+                        "1" => Scale::X1,
+                        "2" => Scale::X2,
+                        "4" => Scale::X4,
+                        "8" => Scale::X8,
+                        _ => return Err(format!("Invalid scale: {} (expected 1, 2, 4 or 8)", value)),
+                    },
+                    "--model" => match model::Model::from_name(&value) {
+                        Some(m) => options.model = m,
+                        None    => return Err(format!("Unknown machine model: {} (expected pal, ntsc or ntsc-old)", value)),
+                    },
+                    "--rom-dir" => options.rom_paths.dir     = Some(PathBuf::from(value)),
+                    "--basic"   => options.rom_paths.basic   = Some(PathBuf::from(value)),
+                    "--chargen" => options.rom_paths.chargen = Some(PathBuf::from(value)),
+                    "--kernal"  => options.rom_paths.kernal  = Some(PathBuf::from(value)),
+                    "--drive-rom" => options.rom_paths.drive = Some(PathBuf::from(value)),
+                    "--speed" => match value.parse::<u32>() {
+                        Ok(speed) if (c64::MIN_SPEED..=c64::MAX_SPEED).contains(&speed) => options.speed = speed,
+                        _ => return Err(format!("Invalid speed: {} (expected {}-{})", value, c64::MIN_SPEED, c64::MAX_SPEED)),
+                    },
+                    "--rewind" => match value.parse::<usize>().ok().and_then(|size| size.checked_mul(1024 * 1024)) {
+                        Some(limit) => options.rewind_limit = limit,
+                        None        => return Err(format!("Invalid rewind buffer size: {}", value)),
+                    },
+                    "--binary-monitor" => match value.parse::<u16>() {
+                        Ok(port) => options.binary_monitor_port = Some(port),
+                        Err(_)   => return Err(format!("Invalid port: {}", value)),
+                    },
+                    "--t64-entry" => match value.parse::<usize>() {
+                        Ok(index) => options.t64_entry = index,
+                        Err(_)    => return Err(format!("Invalid T64 entry: {}", value)),
+                    },
+                    "--limit-cycles" => match value.parse::<u64>() {
+                        Ok(cycles) => options.limit_cycles = Some(cycles),
+                        Err(_)     => return Err(format!("Invalid cycle limit: {}", value)),
+                    },
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    Ok(Command::Run(options))
+}
+
+
+// disasm <FILE>
+fn parse_disasm(args: &[String]) -> Result<Command, String> {
+    match args {
+        [arg] if arg == "-h" || arg == "--help" => Ok(Command::Help),
+        [arg] if arg.starts_with('-') => Err(format!("Unknown option: {}", arg)),
+        [file] => Ok(Command::Disasm(file.clone())),
+        []     => Err("Missing file to disassemble".to_string()),
+        _      => Err("Only one file can be disassembled".to_string()),
+    }
+}
+
+
+fn set_file(options: &mut Options, file: &str) -> Result<(), String> {
+    if let Some(ref previous) = options.file {
+        return Err(format!("Only one file can be loaded, got {} and {}", previous, file));
+    }
+
+    options.file = Some(file.to_string());
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+
+    fn run_options(line: &str) -> Options {
+        match parse(&args(line)) {
+            Ok(Command::Run(options)) => options,
+            Ok(_)  => panic!("{} isn't a run command", line),
+            Err(e) => panic!("{} was rejected: {}", line, e),
+        }
+    }
+
+
+    fn error(line: &str) -> String {
+        match parse(&args(line)) {
+            Err(e) => e,
+            Ok(_)  => panic!("{} was accepted", line),
+        }
+    }
+
+
+    #[test]
+    fn unknown_options() {
+        assert_eq!(error("--fast game.prg"), "Unknown option: --fast");
+        assert_eq!(error("--fast=1"), "Unknown option: --fast");
+        assert_eq!(error("disasm --fast"), "Unknown option: --fast");
+    }
+
+
+    #[test]
+    fn values_inline_or_next() {
+        let options = run_options("--speed=200 --model ntsc --limit-cycles=1000");
+        assert_eq!((options.speed, options.model, options.limit_cycles), (200, model::Model::Ntsc, Some(1000)));
+
+        assert_eq!(error("--warp=1"), "--warp doesn't take a value");
+        assert_eq!(error("--autostart=game.prg"), "--autostart doesn't take a value");
+    }
+
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(error("game.prg --speed"), "Missing value for --speed");
+        assert_eq!(error("--t64-entry"), "Missing value for --t64-entry");
+    }
+
+
+    #[test]
+    fn one_file_only() {
+        assert_eq!(error("a.prg b.prg"), "Only one file can be loaded, got a.prg and b.prg");
+        assert!(parse(&args("disasm")).is_err());
+        assert!(parse(&args("disasm a.prg b.prg")).is_err());
+    }
+
+
+    #[test]
+    fn speed_range() {
+        assert_eq!(run_options("--speed 10").speed, 10);
+        assert_eq!(run_options("--speed 1000").speed, 1000);
+        assert!(error("--speed 9").starts_with("Invalid speed: 9"));
+        assert!(error("--speed 1001").starts_with("Invalid speed: 1001"));
+        assert!(error("--speed fast").starts_with("Invalid speed: fast"));
+    }
+
+
+    #[test]
+    fn rewind_size() {
+        assert_eq!(run_options("--rewind 0").rewind_limit, 0);
+        assert_eq!(run_options("--rewind 2").rewind_limit, 2 * 1024 * 1024);
+        assert_eq!(error("--rewind 99999999999999999"), "Invalid rewind buffer size: 99999999999999999");
+        assert_eq!(error("--rewind -1"), "Invalid rewind buffer size: -1");
+    }
+
+
+    #[test]
+    fn options_before_and_after_file() {
+        for line in &["--autostart --warp game.prg", "--warp game.prg --autostart", "game.prg --autostart --warp"] {
+            let options = run_options(line);
+            assert_eq!(options.file, Some("game.prg".to_string()), "{}", line);
+            assert!(options.autostart && options.warp, "{}", line);
+        }
+
+        // as in the README
+        let options = run_options("--autostart --t64-entry 2 games.t64");
+        assert_eq!((options.file, options.autostart, options.t64_entry), (Some("games.t64".to_string()), true, 2));
+
+        // a value is never taken for the file
+        let options = run_options("--scale 2 game.prg");
+        assert_eq!(options.file, Some("game.prg".to_string()));
+        assert!(!options.autostart);
+    }
+}
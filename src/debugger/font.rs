@@ -1,4 +1,5 @@
 // bitmap font used in debugger window
+use error::Error;
 use utils;
 
 pub struct SysFont {
@@ -7,13 +8,13 @@ pub struct SysFont {
 
 
 impl SysFont {
-    pub fn new() -> SysFont {
+    pub fn new() -> Result<SysFont, Error> {
         let mut font = SysFont {
             data: Vec::<u8>::new(),
         };
 
         // lazily skipping the BMP header to actual data
-        let bmp_data = utils::open_file("res/font.bmp", 54);
+        let bmp_data = utils::open_file("res/font.bmp", 54)?;
 
         let mut j: i32 = 256*63*3;
         let mut i;
@@ -29,7 +30,7 @@ impl SysFont {
             j -= 256 * 3;
         }
         
-        font
+        Ok(font)
     }
 
     pub fn draw_text_rgb(&self, window_buffer: &mut Vec<u32>, window_w: usize, x: usize, y: usize, text: &str, color: u32) {
@@ -4,7 +4,8 @@ extern crate minifb;
 mod font;
 
 use c64;
-use minifb::*;
+use error::Error;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
 use std::io::Write;
 use utils;
 
@@ -32,11 +33,11 @@ pub struct Debugger {
 }
 
 impl Debugger {
-    pub fn new() -> Debugger {
+    pub fn new() -> Result<Debugger, Error> {
         let mut dbg = Debugger {
-            debug_window: Window::new("Debug window", DEBUG_W, DEBUG_H, WindowOptions { scale: Scale::X2, ..Default::default() }).unwrap(),
-            vic_window: Window::new("VIC", RASTER_DEBUG_W, RASTER_DEBUG_H, WindowOptions::default()).unwrap(),
-            font: font::SysFont::new(),
+            debug_window: Window::new("Debug window", DEBUG_W, DEBUG_H, WindowOptions { scale: Scale::X2, ..Default::default() }).map_err(|e| Error::Window(e.to_string()))?,
+            vic_window: Window::new("VIC", RASTER_DEBUG_W, RASTER_DEBUG_H, WindowOptions::default()).map_err(|e| Error::Window(e.to_string()))?,
+            font: font::SysFont::new()?,
             window_buffer: vec![0; DEBUG_W * DEBUG_H],
             vic_buffer: vec![0; RASTER_DEBUG_W * RASTER_DEBUG_H],
             mempage_offset: 0,
@@ -53,11 +54,11 @@ impl Debugger {
         }
 
         dbg.draw_vic_window_txt();        
-        dbg
+        Ok(dbg)
     }
 
 
-    pub fn render(&mut self, cpu: &mut c64::cpu::CPUShared, memory: &mut c64::memory::MemShared) {
+    pub fn render(&mut self, cpu: &mut c64::cpu::CPU, bus: &mut c64::bus::SystemBus) {
         if self.debug_window.is_open() {
             self.draw_border();
 
@@ -96,17 +97,17 @@ impl Debugger {
             }
 
             match self.draw_mode {
-                0 => self.draw_ram(memory),
-                1 => self.draw_vic(memory),
-                2 => self.draw_cia(cpu),
-                3 => self.draw_color_ram(memory),
-                4 => self.draw_sid(memory),
+                0 => self.draw_ram(&mut bus.memory),
+                1 => self.draw_vic(&mut bus.memory),
+                2 => self.draw_cia(cpu, bus),
+                3 => self.draw_color_ram(&mut bus.memory),
+                4 => self.draw_sid(&mut bus.memory),
                 _ => ()
             }
 
-            self.draw_gfx_mode(memory);
-            self.draw_latch_status(memory);
-            self.draw_data(memory);
+            self.draw_gfx_mode(&mut bus.memory);
+            self.draw_latch_status(&mut bus.memory);
+            self.draw_data(&mut bus.memory);
             self.draw_cpu(cpu);
 
             let _ = self.debug_window.update_with_buffer(&self.window_buffer, DEBUG_W, DEBUG_H);
@@ -117,17 +118,17 @@ impl Debugger {
     }
 
     
-    pub fn update_vic_window(&mut self, vic: &mut c64::vic::VICShared) {
+    pub fn update_vic_window(&mut self, vic: &c64::vic::VIC) {
         if !self.vic_window.is_open() {
             return;
         }
 
-        let x = vic.borrow_mut().curr_cycle;
-        let y = vic.borrow_mut().raster_cnt;
-        let is_bad_line = vic.borrow_mut().is_bad_line;
-        let is_raster_irq = vic.borrow_mut().raster_irq == y;
-        let is_border = vic.borrow_mut().border_on;
-        let is_state_changed = vic.borrow_mut().dbg_reg_changed;
+        let x = vic.curr_cycle;
+        let y = vic.raster_cnt;
+        let is_bad_line = vic.is_bad_line;
+        let is_raster_irq = vic.raster_irq == y;
+        let is_border = vic.border_on;
+        let is_state_changed = vic.dbg_reg_changed;
         
         let mut dst_color = if is_border { BORDER_COLOR } else { BG_COLOR };
         dst_color = if is_state_changed { self.mix_colors(VIC_WRITE_COLOR, dst_color, 0.8) } else { dst_color };
@@ -141,7 +142,7 @@ impl Debugger {
     // *** private functions *** //
 
     // dump RAM page to screen
-    fn draw_ram(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_ram(&mut self, memory: &mut c64::memory::Memory) {
         if self.debug_window.is_key_pressed(Key::PageUp, KeyRepeat::Yes) {
             self.mempage_offset += 0x400;
 
@@ -165,7 +166,7 @@ impl Debugger {
 
         for y in 0..26 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Ram).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Ram).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -180,7 +181,7 @@ impl Debugger {
 
 
     // VIC registers
-    fn draw_vic(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_vic(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD000;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -190,7 +191,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
                 hex_offset_x += 1;
@@ -206,7 +207,7 @@ impl Debugger {
 
 
     // CIA registers
-    fn draw_cia(&mut self, cpu: &mut c64::cpu::CPUShared) {
+    fn draw_cia(&mut self, cpu: &mut c64::cpu::CPU, bus: &mut c64::bus::SystemBus) {
         let mut start = 0xDC00;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -221,7 +222,7 @@ impl Debugger {
                     start += 1;
                     continue;
                 }
-                let byte = cpu.borrow_mut().read_byte(start);
+                let byte = cpu.read_byte(start, bus);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
                 hex_offset_x += 1;
@@ -237,7 +238,7 @@ impl Debugger {
 
 
     // SID registers
-    fn draw_sid(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_sid(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD400;
         let mut title = Vec::new();
         let mut hex_offset_x = 0;
@@ -247,7 +248,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -264,7 +265,7 @@ impl Debugger {
 
 
     // Color RAM
-    fn draw_color_ram(&mut self, memory: &mut c64::memory::MemShared) {
+    fn draw_color_ram(&mut self, memory: &mut c64::memory::Memory) {
         let mut start = 0xD800;
 
         let mut title = Vec::new();
@@ -276,7 +277,7 @@ impl Debugger {
 
         for y in 0..25 {
             for x in 0..40 {
-                let byte = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(start);
+                let byte = memory.get_ram_bank(c64::memory::MemType::Io).read(start);
                 self.font.draw_char(&mut self.window_buffer, DEBUG_W, 8*x as usize, 8 + 8*y as usize, byte, 0x05);
 
                 self.draw_hex(hex_offset_x + x as usize, 28 + y as usize, byte);
@@ -313,9 +314,9 @@ impl Debugger {
 
 
     // basic C64 settings
-    fn draw_data(&mut self, memory: &mut c64::memory::MemShared) {
-        let d018 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD018);
-        let dd00 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xDD00);
+    fn draw_data(&mut self, memory: &mut c64::memory::Memory) {
+        let d018 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD018);
+        let dd00 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xDD00);
         
         let mut vmatrix_txt = Vec::new();
         let mut char_txt = Vec::new();
@@ -337,9 +338,9 @@ impl Debugger {
 
 
     // current graphics mode tags
-    fn draw_gfx_mode(&mut self, memory: &mut c64::memory::MemShared) {
-        let d011 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD011);
-        let d016 = memory.borrow_mut().get_ram_bank(c64::memory::MemType::Io).read(0xD016);
+    fn draw_gfx_mode(&mut self, memory: &mut c64::memory::Memory) {
+        let d011 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD011);
+        let d016 = memory.get_ram_bank(c64::memory::MemType::Io).read(0xD016);
         let ecm_on = (d011 & 0x40) != 0;
         let mcm_on = (d016 & 0x10) != 0;
         let bmp_on = (d011 & 0x20) != 0;
@@ -352,11 +353,11 @@ impl Debugger {
 
 
     // active memory banks
-    fn draw_latch_status(&mut self, memory: &mut c64::memory::MemShared) {
-        let basic_on = memory.borrow_mut().basic_on;
-        let chargen_on = memory.borrow_mut().chargen_on;
-        let io_on = memory.borrow_mut().io_on;
-        let kernal_on = memory.borrow_mut().kernal_on;
+    fn draw_latch_status(&mut self, memory: &mut c64::memory::Memory) {
+        let basic_on = memory.basic_on;
+        let chargen_on = memory.chargen_on;
+        let io_on = memory.io_on;
+        let kernal_on = memory.kernal_on;
         
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 48, 25, "BASIC", if basic_on { 0x0A } else { 0x0B });
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 55, 25, "CHARGEN", if chargen_on { 0x0A } else { 0x0B });
@@ -366,19 +367,19 @@ impl Debugger {
 
 
     // draw CPU flags and registers
-    fn draw_cpu(&mut self, cpu: &mut c64::cpu::CPUShared) {
+    fn draw_cpu(&mut self, cpu: &c64::cpu::CPU) {
         let mut pc_txt = Vec::new();
         let mut a_txt = Vec::new();
         let mut x_txt = Vec::new();
         let mut y_txt = Vec::new();
         let mut sp_txt = Vec::new();
         let mut p_txt = Vec::new();
-        let _ = write!(&mut pc_txt, "${:04X}", cpu.borrow_mut().pc);
-        let _ = write!(&mut a_txt, "${:02X}", cpu.borrow_mut().a);
-        let _ = write!(&mut x_txt, "${:02X}", cpu.borrow_mut().x);
-        let _ = write!(&mut y_txt, "${:02X}", cpu.borrow_mut().y);
-        let _ = write!(&mut sp_txt, "${:02X}", cpu.borrow_mut().sp);
-        let _ = write!(&mut p_txt, "[{:08b}]", cpu.borrow_mut().p);
+        let _ = write!(&mut pc_txt, "${:04X}", cpu.pc);
+        let _ = write!(&mut a_txt, "${:02X}", cpu.a);
+        let _ = write!(&mut x_txt, "${:02X}", cpu.x);
+        let _ = write!(&mut y_txt, "${:02X}", cpu.y);
+        let _ = write!(&mut sp_txt, "${:02X}", cpu.sp);
+        let _ = write!(&mut p_txt, "[{:08b}]", cpu.p);
         
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 44, 22, "PC:", 0x0F);
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 47, 22, &String::from_utf8(pc_txt).unwrap().to_owned()[..], 0x0E);
@@ -392,6 +393,15 @@ impl Debugger {
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 74, 22, &String::from_utf8(sp_txt).unwrap().to_owned()[..], 0x0E);
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 51, 23, "NV-BDIZC:", 0x0F);
         self.font.draw_text(&mut self.window_buffer, DEBUG_W, 61, 23, &String::from_utf8(p_txt).unwrap().to_owned()[..], 0x0E);
+
+        match cpu.jam_address() {
+            Some(addr) => {
+                let mut jam_txt = Vec::new();
+                let _ = write!(&mut jam_txt, "CPU JAMMED AT ${:04X}", addr);
+                self.font.draw_text(&mut self.window_buffer, DEBUG_W, 51, 24, &String::from_utf8(jam_txt).unwrap().to_owned()[..], 0x02);
+            },
+            None => self.font.draw_text(&mut self.window_buffer, DEBUG_W, 51, 24, "                    ", 0x02),
+        }
     }
 
 
@@ -0,0 +1,44 @@
+// errors reported to the caller instead of bringing the whole emulator down
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(String, io::Error), // file that couldn't be read or written and the reason
+    InvalidRom(String),
+    InvalidPrg(String),
+    InvalidCrt(String),
+    InvalidDisk(String),
+    InvalidTape(String),
+    InvalidSnapshot(String),
+    UnknownFileType(String),
+    Window(String),        // host window couldn't be created
+    Audio(String),         // nor the audio device
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref file, ref e)       => write!(f, "Couldn't access {}: {}", file, e),
+            Error::InvalidRom(ref msg)       => write!(f, "{}", msg),
+            Error::InvalidPrg(ref msg)       => write!(f, "{}", msg),
+            Error::InvalidCrt(ref msg)       => write!(f, "{}", msg),
+            Error::InvalidDisk(ref msg)      => write!(f, "{}", msg),
+            Error::InvalidTape(ref msg)      => write!(f, "{}", msg),
+            Error::InvalidSnapshot(ref msg)  => write!(f, "{}", msg),
+            Error::UnknownFileType(ref file) => write!(f, "Unrecognized file type: {}", file),
+            Error::Window(ref msg)           => write!(f, "Couldn't create window: {}", msg),
+            Error::Audio(ref msg)            => write!(f, "Couldn't open audio device: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+// rust64 as a library: the emulator core along with a simple machine facade
+extern crate minifb;
+extern crate byteorder;
+extern crate num;
+
+#[macro_use]
+extern crate enum_primitive;
+
+#[macro_use]
+mod utils;
+mod debugger;
+mod error;
+mod machine;
+pub mod c64;
+pub mod lorenz;
+
+pub use error::Error;
+pub use machine::{ Machine, Registers };
@@ -0,0 +1,99 @@
+// runner for Wolfgang Lorenz's C64 test suite: every test is a BASIC program which prints its
+// name, checks a set of opcodes or chip features and then LOADs the next test in the chain.
+// On a mismatch the expected and actual values are printed and the test waits for a key press.
+use c64::roms::RomSet;
+use error::Error;
+use machine::Machine;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+// kernal jump table entries the tests go through
+const CHROUT: u16 = 0xFFD2; // print a character
+const LOAD:   u16 = 0xFFD5; // chain to the next test - the current one has passed
+const GETIN:  u16 = 0xFFE4; // wait for a key after reporting an error
+
+// the tests run from RAM between the BASIC start and the BASIC ROM
+const PROGRAM_AREA: RangeInclusive<u16> = 0x0801..=0x9FFF;
+
+// roughly two minutes of C64 time, enough for the slowest tests
+pub const DEFAULT_CYCLE_LIMIT: u64 = 120_000_000;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,       // the test reported an error
+    Jammed(u16),  // address of the KIL opcode
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            Outcome::Passed       => "ok".to_string(),
+            Outcome::Failed       => "FAILED".to_string(),
+            Outcome::Jammed(addr) => format!("JAMMED at ${:04X}", addr),
+            Outcome::Timeout      => "TIMEOUT".to_string(),
+        };
+
+        f.pad(&text)
+    }
+}
+
+
+pub struct TestResult {
+    pub outcome: Outcome,
+    pub output:  String, // everything the test printed
+    pub cycles:  u64,
+}
+
+
+// boot a fresh machine, run the test program and wait until it either passes, fails or gets stuck
+pub fn run_test(roms: &RomSet, filename: &str, cycle_limit: u64) -> Result<TestResult, Error> {
+    let mut machine = Machine::with_roms(roms, filename, "")?;
+    machine.set_autostart(true);
+
+    let mut output = String::new();
+    let mut prev_pc = 0;
+    let mut started = false; // ignore what's printed during boot
+    let mut cycles = 0;
+
+    while cycles < cycle_limit {
+        cycles += machine.step_instruction() as u64;
+
+        if let Some(addr) = machine.jam_address() {
+            return Ok(TestResult { outcome: Outcome::Jammed(addr), output, cycles });
+        }
+
+        // the CPU may be stalled by the VIC before the fetch - only act when it gets there
+        let regs = machine.registers();
+        if regs.pc == prev_pc {
+            continue;
+        }
+
+        prev_pc = regs.pc;
+        started = started || PROGRAM_AREA.contains(&regs.pc);
+
+        match regs.pc {
+            CHROUT if started => output.push(petscii_to_ascii(regs.a)),
+            LOAD   => return Ok(TestResult { outcome: Outcome::Passed, output, cycles }),
+            GETIN  => return Ok(TestResult { outcome: Outcome::Failed, output, cycles }),
+            _ => (),
+        }
+    }
+
+    Ok(TestResult { outcome: Outcome::Timeout, output, cycles })
+}
+
+
+// printable approximation of what went to the screen
+fn petscii_to_ascii(c: u8) -> char {
+    match c {
+        0x0D        => '\n',
+        0x20..=0x40 => c as char,
+        0x41..=0x5A => (c + 0x20) as char, // unshifted letters show as lower case
+        0x5B..=0x5F => c as char,
+        0xC1..=0xDA => (c - 0x80) as char,
+        _ => '?',
+    }
+}
@@ -0,0 +1,256 @@
+// public API for driving the emulator from other crates
+use c64;
+use error::Error;
+use minifb::{ Key, Scale };
+
+
+// snapshot of the CPU registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub sp: u8,
+    pub p:  u8,
+    pub a:  u8,
+    pub x:  u8,
+    pub y:  u8,
+}
+
+
+// a headless C64 that is stepped explicitly instead of being timed by the wall clock,
+// so the same sequence of calls always gives bit-identical results
+pub struct Machine {
+    c64: c64::C64,
+}
+
+impl Machine {
+    // uses the ROM set from the default location (see roms::RomPaths)
+    pub fn new(prg_to_load: &str, crt_to_load: &str) -> Result<Machine, Error> {
+        let roms = c64::roms::RomSet::load(&c64::roms::RomPaths::default())?;
+        Machine::with_roms(&roms, prg_to_load, crt_to_load)
+    }
+
+
+    pub fn with_roms(roms: &c64::roms::RomSet, prg_to_load: &str, crt_to_load: &str) -> Result<Machine, Error> {
+        let mut machine = Machine {
+            c64: c64::C64::new(Scale::X1, false, true, prg_to_load, crt_to_load, roms)?,
+        };
+
+        machine.reset();
+        Ok(machine)
+    }
+
+
+    pub fn reset(&mut self) {
+        self.c64.reset();
+    }
+
+
+    // switch between PAL and NTSC timing; the machine is reset
+    pub fn set_model(&mut self, model: c64::model::Model) {
+        self.c64.set_model(model);
+        self.reset();
+    }
+
+
+    // chip-dependent constants used by the unstable ANE and LXA opcodes
+    pub fn set_magic_constants(&mut self, ane: u8, lxa: u8) {
+        self.c64.set_magic_constants(ane, lxa);
+    }
+
+
+    // type RUN (or SYS for machine code) once the program has been loaded
+    pub fn set_autostart(&mut self, autostart: bool) {
+        self.c64.set_autostart(autostart);
+    }
+
+
+    // run the given number of clock cycles; returns the number of frames completed on the way
+    pub fn step(&mut self, cycles: u32) -> u32 {
+        self.c64.run_cycles(cycles)
+    }
+
+
+    // run up to the start of the next instruction; returns the number of cycles it took
+    pub fn step_instruction(&mut self) -> u32 {
+        self.c64.run_until_fetch()
+    }
+
+
+    // run until the VIC reaches VBlank or a breakpoint is hit; returns the number of cycles it took
+    pub fn run_frame(&mut self) -> u32 {
+        self.c64.run_until_vblank()
+    }
+
+
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.c64.peek(addr)
+    }
+
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.c64.poke(addr, value);
+    }
+
+
+    pub fn registers(&self) -> Registers {
+        let cpu = self.c64.cpu();
+
+        Registers {
+            pc: cpu.pc,
+            sp: cpu.sp,
+            p:  cpu.p,
+            a:  cpu.a,
+            x:  cpu.x,
+            y:  cpu.y,
+        }
+    }
+
+
+    // address of the KIL opcode if the CPU has locked up; only a reset recovers from that
+    pub fn jam_address(&self) -> Option<u16> {
+        self.c64.cpu().jam_address()
+    }
+
+
+    pub fn breakpoints(&mut self) -> &mut c64::breakpoints::Breakpoints {
+        self.c64.breakpoints()
+    }
+
+
+    // run whole instructions until a breakpoint or watchpoint stops the machine or max_cycles have passed;
+    // returns the hit, if any
+    pub fn run_until_break(&mut self, max_cycles: u64) -> Option<c64::breakpoints::Hit> {
+        let mut cycles = 0;
+
+        while cycles < max_cycles {
+            cycles += self.c64.run_until_fetch() as u64;
+
+            if let Some(hit) = self.c64.breakpoints().hit.take() {
+                return Some(hit);
+            }
+        }
+
+        None
+    }
+
+
+    // put a disk image (D64, D71, D81 or G64) into drive 8, which serves the kernal's LOAD, SAVE and OPEN
+    pub fn attach_disk(&mut self, filename: &str) -> Result<(), Error> {
+        self.c64.attach_disk(filename)
+    }
+
+
+    pub fn attach_disk_image<I: c64::disk_image::DiskImage + 'static>(&mut self, image: I) {
+        self.c64.attach_disk_image(image);
+    }
+
+
+    // take the disk out of drive 8; returns it with whatever was saved to it
+    pub fn detach_disk(&mut self) -> Option<Box<dyn c64::disk_image::DiskImage>> {
+        self.c64.detach_disk()
+    }
+
+
+    // emulate a real 1541 on the serial bus with the given DOS ROM instead of the virtual drive
+    pub fn enable_true_drive(&mut self, rom: Vec<u8>) -> Result<(), Error> {
+        self.c64.enable_true_drive(rom)
+    }
+
+
+    // the emulated 1541, if enabled
+    pub fn true_drive(&mut self) -> Option<&mut c64::drive1541::Drive1541> {
+        self.c64.true_drive()
+    }
+
+
+    // load the first file on the disk matching the pattern (e.g. "*") into memory, once BASIC has started
+    pub fn load_disk_file(&mut self, pattern: &str) -> Result<(), Error> {
+        self.c64.load_disk_file(pattern)
+    }
+
+
+    // load a file of a T64 archive (0 is the first) into memory, once BASIC has started
+    pub fn load_t64_entry(&mut self, filename: &str, index: usize) -> Result<(), Error> {
+        self.c64.load_t64_entry(filename, index)
+    }
+
+
+    // put a TAP image into the datasette, rewound and stopped
+    pub fn attach_tape(&mut self, filename: &str) -> Result<(), Error> {
+        self.c64.attach_tape(filename)
+    }
+
+
+    pub fn attach_tape_image(&mut self, tape: c64::tap::Tap) {
+        self.c64.attach_tape_image(tape);
+    }
+
+
+    pub fn detach_tape(&mut self) -> Option<c64::tap::Tap> {
+        self.c64.detach_tape()
+    }
+
+
+    // PLAY, STOP and REWIND; the motor is under the control of the 6510 port like on a real C64
+    pub fn datasette(&mut self) -> &mut c64::datasette::Datasette {
+        self.c64.datasette()
+    }
+
+
+    // press PLAY and type LOAD (RUN as well with autostart) once BASIC has started
+    pub fn load_tape(&mut self) -> Result<(), Error> {
+        self.c64.load_tape()
+    }
+
+
+    // serve the VICE binary monitor protocol on localhost (port 0 picks a free one); returns the port
+    pub fn enable_binary_monitor(&mut self, port: u16) -> Result<u16, Error> {
+        self.c64.enable_binary_monitor(port)
+    }
+
+
+    // serve the remote debugger if it sent a command or a breakpoint was hit; the machine stays paused
+    // until the client lets it continue. Call it regularly, e.g. after every frame.
+    pub fn poll_binary_monitor(&mut self) {
+        self.c64.poll_binary_monitor();
+    }
+
+
+    // complete machine state as a versioned binary blob
+    pub fn save_state(&mut self) -> Vec<u8> {
+        self.c64.save_snapshot()
+    }
+
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.c64.load_snapshot(data)
+    }
+
+
+    // screen contents in 0x00RRGGBB format
+    pub fn frame_buffer(&self) -> &[u32] {
+        self.c64.frame_buffer()
+    }
+
+
+    pub fn frame_size(&self) -> (usize, usize) {
+        (c64::SCREEN_WIDTH, c64::SCREEN_HEIGHT)
+    }
+
+
+    // keys use the same host key mapping as the emulator window
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        self.c64.set_key(key, pressed);
+    }
+
+
+    // num-pad keys for directions and RightCtrl for fire
+    pub fn set_joystick(&mut self, key: Key, pressed: bool) {
+        self.c64.set_joystick(key, pressed);
+    }
+
+
+    pub fn press_restore(&mut self) {
+        self.c64.press_restore();
+    }
+}
@@ -1,47 +1,175 @@
 extern crate minifb;
-extern crate byteorder;
-extern crate num;
+extern crate rust64;
 
-#[macro_use]
-extern crate enum_primitive;
+mod cli;
 
-#[macro_use]
-mod utils;
-mod c64;
-mod debugger;
-
-use minifb::*;
+use rust64::c64;
+use rust64::c64::disasm;
+use rust64::c64::media::MediaType;
+use rust64::c64::prg::Prg;
+use rust64::c64::roms;
+use rust64::Error;
 use std::env;
+use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let mut prg_to_load  = String::new();
-    let mut crt_to_load  = String::new();
-    let mut debugger_on  = false;
-    let mut window_scale = Scale::X1;
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    // process cmd line params
-    for i in 1..args.len() {
-        if args[i] == "debugger" {
-            debugger_on = true;
+    let options = match cli::parse(&args) {
+        Ok(cli::Command::Run(options)) => options,
+        Ok(cli::Command::Disasm(file)) => {
+            if let Err(e) = print_listing(&file) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return;
+        },
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("{}\nTry 'rust64 --help' for more information.", e);
+            process::exit(2);
         }
-        else if args[i] == "x2" {
-            window_scale = Scale::X2;
+    };
+
+    // figure out what kind of file we've been given
+    let mut prg_to_load = String::new();
+    let mut crt_to_load = String::new();
+    let mut disk_to_attach = String::new();
+    let mut t64_to_load = String::new();
+    let mut tape_to_attach = String::new();
+
+    if let Some(ref file) = options.file {
+        match MediaType::detect(file) {
+            Ok(MediaType::Prg) => prg_to_load = file.clone(),
+            Ok(MediaType::Crt) => crt_to_load = file.clone(),
+            Ok(MediaType::Disk) => disk_to_attach = file.clone(),
+            Ok(MediaType::T64) => t64_to_load = file.clone(),
+            Ok(MediaType::Tap) => tape_to_attach = file.clone(),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
         }
-        else if args[i].ends_with(".prg") {
-            prg_to_load = args[i].clone();
+    }
+
+    let rom_set = match roms::RomSet::load(&options.rom_paths) {
+        Ok(rom_set) => rom_set,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
-        else if args[i].ends_with(".crt") {
-            crt_to_load = args[i].clone();
+    };
+    rom_set.print_info();
+
+    let mut c64 = match c64::C64::new(options.scale, options.debugger, options.headless, &prg_to_load, &crt_to_load, &rom_set) {
+        Ok(c64) => c64,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
+    };
+    c64.set_model(options.model);
+    c64.set_speed(options.speed);
+    c64.set_warp(options.warp);
+    c64.set_autostart(options.autostart);
+    if !options.headless {
+        c64.set_rewind_limit(options.rewind_limit);
     }
-    
-    let mut c64 = c64::C64::new(window_scale, debugger_on, &prg_to_load, &crt_to_load);
     c64.reset();
 
+    if options.true_drive {
+        let result = roms::load_drive_rom(&options.rom_paths).and_then(|rom| c64.enable_true_drive(rom));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // disks go into drive 8; autostart loads the first file on them
+    if !disk_to_attach.is_empty() {
+        let result = c64.attach_disk(&disk_to_attach).and_then(|_| if options.autostart { c64.load_disk_file("*") } else { Ok(()) });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // a file of a tape archive is loaded like a program file
+    if !t64_to_load.is_empty() {
+        if let Err(e) = c64.load_t64_entry(&t64_to_load, options.t64_entry) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // tapes go into the datasette; autostart presses PLAY and loads the first file on them
+    if !tape_to_attach.is_empty() {
+        let result = c64.attach_tape(&tape_to_attach).and_then(|_| if options.autostart { c64.load_tape() } else { Ok(()) });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    if options.monitor {
+        c64.enable_monitor_input();
+        c64.enter_monitor();
+    }
+
+    if let Some(port) = options.binary_monitor_port {
+        match c64.enable_binary_monitor(port) {
+            Ok(port) => println!("Binary monitor listening on 127.0.0.1:{}", port),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+
     // main update loop
-    while c64.main_window.is_open() {
-        c64.run();
+    let mut cycles: u64 = 0;
+    let cycle_limit = options.limit_cycles.unwrap_or(u64::MAX);
+    let mut jammed = false;
+
+    while c64.is_open() {
+        cycles += c64.run() as u64;
+
+        // reported once; a reset gets the CPU going again, and only the window has a key for that
+        let jam_address = c64.cpu().jam_address();
+        if !jammed {
+            if let Some(addr) = jam_address {
+                let hint = if options.headless { "" } else { " - press F12 to reset" };
+                println!("CPU jammed by ${:02X} at ${:04X}{}", c64.cpu().instruction.op_byte, addr, hint);
+            }
+        }
+        jammed = jam_address.is_some();
+
+        if cycles >= cycle_limit {
+            break;
+        }
     }
 }
+
+
+// disassembly of a whole program file; a cut off instruction at the end is shown as plain bytes
+fn print_listing(filename: &str) -> Result<(), Error> {
+    let prg = Prg::from_filename(filename)?;
+    let mut address = prg.load_address;
+
+    for instruction in disasm::disassemble(&prg.data, prg.load_address) {
+        let illegal_mark = if instruction.illegal { "; illegal" } else { "" };
+        let line = format!("${:04X}  {:<8}  {:<12}{}", instruction.address, instruction.hex(), instruction, illegal_mark);
+        println!("{}", line.trim_end());
+        address = address.wrapping_add(instruction.length as u16);
+    }
+
+    for byte in &prg.data[address.wrapping_sub(prg.load_address) as usize..] {
+        println!("${:04X}  {:02X}        .BYTE ${:02X}", address, byte, byte);
+        address = address.wrapping_add(1);
+    }
+
+    Ok(())
+}
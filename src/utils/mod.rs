@@ -1,42 +1,43 @@
 // helper utility functions and macros
+use c64::bus;
 use c64::cpu;
+use c64::disasm;
 use c64::opcodes;
+use error::Error;
 use std::io::prelude::*;
 use std::fs::File;
 use std::io::SeekFrom;
 use std::path::Path;
 
 
-// helper macros to easily extract references from Option<RefCell<...>>
-macro_rules! as_ref {
-    ($x:expr) => ($x.as_ref().unwrap().borrow_mut())
-}
-
-macro_rules! as_mut {
-    ($x:expr) => ($x.as_mut().unwrap().borrow_mut())
-}
-
-
 // common helper functions
-pub fn open_file(filename: &str, offset: u64) -> Vec<u8> {
+pub fn open_file(filename: &str, offset: u64) -> Result<Vec<u8>, Error> {
     let path = Path::new(&filename);
-    
-    let mut file = match File::open(&path) {
-        Err(why) => panic!("Couldn't open {}: {}", path.display(), why.to_string()),
-        Ok(file) => file,
-    };
+    let io_error = |e| Error::Io(path.display().to_string(), e);
 
+    let mut file = File::open(&path).map_err(io_error)?;
     let mut file_data = Vec::<u8>::new();
 
-    let _ = file.seek(SeekFrom::Start(offset));
-    let result = file.read_to_end(&mut file_data);
-    
-    match result {
-        Err(why)   => panic!("Error reading file: {}", why.to_string()),
-        Ok(result) => println!("Read {}: {} bytes", path.display(), result),
-    };    
+    file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+    let result = file.read_to_end(&mut file_data).map_err(io_error)?;
+    println!("Read {}: {} bytes", path.display(), result);
+
+    Ok(file_data)
+}
 
-    file_data
+
+// standard CRC-32 (IEEE) checksum, used to identify ROM images
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
 }
 
 
@@ -93,13 +94,10 @@ impl OpDebugger {
 
 
 // output current instruction and CPU register status in a neat, readable fashion
-pub fn debug_instruction(opcode: u8, cpu: &mut cpu::CPU) {
+pub fn debug_instruction<B: bus::Bus>(opcode: u8, cpu: &mut cpu::CPU, bus: &mut B) {
     cpu.prev_pc = cpu.pc;
     let prev_pc = cpu.prev_pc;
     
-    let operand_hex: String;
-    let operand: String;
-    let mut extra_cycle = false;
     let debug_loops = true; // if true, every loop will be unrolled in the debug output
 
     // RTS? pop from queue to continue logging
@@ -124,83 +122,17 @@ pub fn debug_instruction(opcode: u8, cpu: &mut cpu::CPU) {
         }
     }
 
-    // instruction opcode and arglist formatting based on addressing mode
-    match cpu.instruction.addr_mode {
-        opcodes::AddrMode::Implied => {
-            operand_hex = format!("       ");
-            operand = format!("       ");
-        },
-        opcodes::AddrMode::Accumulator => {
-            operand_hex = format!("       ");
-            operand = format!("A      ");
-        },
-        opcodes::AddrMode::Immediate => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("#${:02X}   ", cpu.read_byte(prev_pc)); 
-        },
-        opcodes::AddrMode::Absolute => {
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X}  ", cpu.read_word_le(cpu.prev_pc));
-        },
-        opcodes::AddrMode::AbsoluteIndexedX(ec) => {
-            extra_cycle = ec;
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X},X", cpu.read_word_le(cpu.prev_pc));
-        },
-        opcodes::AddrMode::AbsoluteIndexedY(ec) => {
-            extra_cycle = ec;
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("${:04X},Y", cpu.read_word_le(cpu.prev_pc));
-        },
-        opcodes::AddrMode::Zeropage => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X}    ", cpu.read_byte(prev_pc));
-        }, 
-        opcodes::AddrMode::ZeropageIndexedX => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X},X  ", cpu.read_byte(prev_pc));
-        },
-        opcodes::AddrMode::ZeropageIndexedY => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("${:02X},Y  ", cpu.read_byte(prev_pc));
-        },
-        opcodes::AddrMode::Relative => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            let b: i8 = cpu.read_byte(prev_pc) as i8;
-            operand = format!("${:04X}  ", ((cpu.prev_pc + 1) as i16 + b as i16) as u16);
-        },
-        opcodes::AddrMode::Indirect => {
-            operand_hex = format!(" {:02X} {:02X} ", cpu.read_byte(prev_pc), cpu.read_byte(prev_pc + 0x01));
-            operand = format!("(${:04X})", cpu.read_word_le(cpu.prev_pc));
-        },
-        opcodes::AddrMode::IndexedIndirectX => {
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("(${:02X},X)", cpu.read_byte(prev_pc));
-        },
-        opcodes::AddrMode::IndirectIndexedY(ec) => {
-            extra_cycle = ec;
-            operand_hex = format!(" {:02X}    ", cpu.read_byte(prev_pc));
-            operand = format!("(${:02X}),Y", cpu.read_byte(prev_pc));
-        },
-    }
+    let decoded = disasm::Instruction::decode_with(prev_pc.wrapping_sub(1), |addr| cpu.read_byte(addr, bus));
 
     // control latch bytes' status
-    let byte0 = cpu.read_byte(0x0000);
-    let byte1 = cpu.read_byte(0x0001);
-
-    let mut total_cycles = cpu.instruction.cycles_to_fetch + cpu.instruction.cycles_to_run + cpu.instruction.cycles_to_rmw;
-    let mut fetch_cycles = cpu.instruction.cycles_to_fetch;
-    let mut extra_cycle_mark = "*";
+    let byte0 = cpu.read_byte(0x0000, bus);
+    let byte1 = cpu.read_byte(0x0001, bus);
 
-    if !extra_cycle {
-        extra_cycle_mark = " ";
-        total_cycles += 1;
-        fetch_cycles += 1;
-    }
-    
-    let rmw_mark = if cpu.instruction.cycles_to_rmw > 0 { "+" } else { " " };
+    // extra cycle on page crossing, read-modify-write
+    let extra_cycle_mark = if decoded.extra_cycles { "*" } else { " " };
+    let rmw_mark = if cpu.instruction.is_rmw { "+" } else { " " };
 
-    println!("${:04X}: {:02X}{}{}{} {}  {}<- A: {:02X} X: {:02X} Y: {:02X} SP: {:02X} 00: {:02X} 01: {:02X} NV-BDIZC: [{:08b}] ({} cls, f: {}, r: {})", cpu.prev_pc - 1, opcode, operand_hex, extra_cycle_mark, cpu.instruction, operand,rmw_mark, cpu.a, cpu.x, cpu.y, cpu.sp, byte0, byte1, cpu.p, total_cycles, fetch_cycles, cpu.instruction.cycles_to_run);
+    println!("${:04X}: {:<9}{}{} {:<9}{}<- A: {:02X} X: {:02X} Y: {:02X} SP: {:02X} 00: {:02X} 01: {:02X} NV-BDIZC: [{:08b}] ({} cls)", decoded.address, decoded.hex(), extra_cycle_mark, decoded.op, decoded.operand_text(), rmw_mark, cpu.a, cpu.x, cpu.y, cpu.sp, byte0, byte1, cpu.p, cpu.instruction.cycles);
 
     // JSR? push on queue to supress logging
     if !debug_loops {
@@ -0,0 +1,182 @@
+extern crate rust64;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+
+const EVENT_ID: u32 = 0xFFFF_FFFF;
+
+
+struct Response {
+    kind: u8,
+    error: u8,
+    request_id: u32,
+    body: Vec<u8>,
+}
+
+
+struct Client {
+    stream: TcpStream,
+    next_id: u32,
+}
+
+impl Client {
+    fn request(&mut self, kind: u8, body: &[u8]) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut packet = vec![0x02, 0x02];
+        packet.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.push(kind);
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet).unwrap();
+        id
+    }
+
+
+    fn receive(&mut self) -> Response {
+        let mut header = [0; 12];
+        self.stream.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..2], &[0x02, 0x02]);
+
+        let mut body = vec![0; u32_at(&header, 2) as usize];
+        self.stream.read_exact(&mut body).unwrap();
+
+        Response { kind: header[6], error: header[7], request_id: u32_at(&header, 8), body }
+    }
+
+
+    // send a command and wait for its response, skipping events sent in the meantime
+    fn call(&mut self, kind: u8, body: &[u8]) -> Response {
+        let id = self.request(kind, body);
+
+        loop {
+            let response = self.receive();
+            if response.request_id == id {
+                return response;
+            }
+            assert_eq!(response.request_id, EVENT_ID);
+        }
+    }
+
+
+    // wait for the machine to stop, returning the events up to and including the stop event
+    fn wait_for_stop(&mut self) -> Vec<Response> {
+        let mut events = Vec::new();
+
+        loop {
+            let event = self.receive();
+            let kind = event.kind;
+            events.push(event);
+            if kind == 0x62 {
+                return events;
+            }
+        }
+    }
+}
+
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+
+// value of a register in a registers response
+fn register(body: &[u8], id: u8) -> u16 {
+    let count = u16_at(body, 0) as usize;
+    (0..count).map(|i| 2 + i * 4)
+              .find(|&offset| body[offset + 1] == id)
+              .map(|offset| u16_at(body, offset + 2))
+              .unwrap()
+}
+
+
+// machine running in the background, serving the protocol on a free port
+fn connect() -> Client {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut machine = rust64::Machine::new("", "").unwrap();
+        sender.send(machine.enable_binary_monitor(0).unwrap()).unwrap();
+
+        loop {
+            machine.run_frame();
+            machine.poll_binary_monitor();
+        }
+    });
+
+    let port = receiver.recv().unwrap();
+    Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap(), next_id: 1 }
+}
+
+
+#[test]
+fn memory_registers_checkpoints_and_stepping() {
+    let mut client = connect();
+
+    // any command stops the machine
+    let ping = client.call(0x81, &[]);
+    assert_eq!((ping.kind, ping.error), (0x81, 0x00));
+
+    // SEI / LDA #$05 / STA $D020 / loop: INC $C100 / JMP loop
+    let program = [0x78, 0xA9, 0x05, 0x8D, 0x20, 0xD0, 0xEE, 0x00, 0xC1, 0x4C, 0x06, 0xC0];
+    let mut set = vec![0x00, 0x00, 0xC0, 0x0B, 0xC0, 0x00, 0x00, 0x00];
+    set.extend_from_slice(&program);
+    assert_eq!(client.call(0x02, &set).error, 0x00);
+
+    let get = client.call(0x01, &[0x00, 0x00, 0xC0, 0x0B, 0xC0, 0x00, 0x00, 0x00]);
+    assert_eq!(get.error, 0x00);
+    assert_eq!(u16_at(&get.body, 0), 12);
+    assert_eq!(&get.body[2..], &program);
+
+    // PC = $C000
+    let registers = client.call(0x32, &[0x00, 0x01, 0x00, 0x03, 0x03, 0x00, 0xC0]);
+    assert_eq!((registers.kind, registers.error), (0x31, 0x00));
+    assert_eq!(register(&registers.body, 0x03), 0xC000);
+
+    // exec checkpoint at the loop, then continue
+    let checkpoint = client.call(0x12, &[0x06, 0xC0, 0x06, 0xC0, 0x01, 0x01, 0x04, 0x00]);
+    assert_eq!((checkpoint.kind, checkpoint.error), (0x11, 0x00));
+    let number = u32_at(&checkpoint.body, 0);
+    assert_eq!(client.call(0xAA, &[]).error, 0x00);
+
+    let events = client.wait_for_stop();
+    let hit = events.iter().find(|e| e.kind == 0x11).unwrap();
+    assert_eq!(u32_at(&hit.body, 0), number);
+    assert_eq!(hit.body[4], 1); // currently hit
+    let registers = events.iter().find(|e| e.kind == 0x31).unwrap();
+    assert_eq!(register(&registers.body, 0x00), 0x05);
+    assert_eq!(u16_at(&events.last().unwrap().body, 0), 0xC006);
+
+    // the store went to the VIC, reading the border color without side effects
+    let border = client.call(0x01, &[0x00, 0x20, 0xD0, 0x20, 0xD0, 0x00, 0x00, 0x00]);
+    assert_eq!(border.body[2] & 0x0F, 0x05);
+
+    // step one instruction
+    assert_eq!(client.call(0x71, &[0x00, 0x01, 0x00]).error, 0x00);
+    let events = client.wait_for_stop();
+    assert_eq!(u16_at(&events.last().unwrap().body, 0), 0xC009);
+
+    // checkpoint list: info for each one, then the count
+    let info = client.call(0x14, &[]);
+    assert_eq!((info.kind, u32_at(&info.body, 0)), (0x11, number));
+    let list = client.receive();
+    assert_eq!((list.kind, u32_at(&list.body, 0)), (0x14, 1));
+    assert_eq!(client.call(0x13, &number.to_le_bytes()).error, 0x00);
+    assert_eq!(client.call(0x13, &number.to_le_bytes()).error, 0x01);
+
+    // reset goes back to the kernal's reset routine
+    assert_eq!(client.call(0xCC, &[0x00]).error, 0x00);
+    let registers = client.call(0x31, &[0x00]);
+    assert_eq!(register(&registers.body, 0x03), 0xFCE2);
+
+    assert_eq!(client.call(0x99, &[]).error, 0x83);
+    assert_eq!(client.call(0xAA, &[]).error, 0x00);
+}
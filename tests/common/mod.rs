@@ -0,0 +1,31 @@
+// fixtures shared by the integration tests which run a whole C64; not every test uses all of them
+#![allow(dead_code)]
+
+use rust64::Machine;
+
+// from power-on to BASIC's READY prompt, with some to spare
+const BOOT_FRAMES: u32 = 150;
+
+
+pub fn booted_machine() -> Machine {
+    let mut machine = Machine::new("", "").unwrap();
+    boot(&mut machine);
+    machine
+}
+
+
+// for machines which need setting up before they start
+pub fn boot(machine: &mut Machine) {
+    for _ in 0..BOOT_FRAMES {
+        machine.run_frame();
+    }
+}
+
+
+// put text into the kernal keyboard buffer, which holds up to 10 characters
+pub fn type_text(machine: &mut Machine, text: &[u8]) {
+    for (i, c) in text.iter().enumerate() {
+        machine.poke(0x0277 + i as u16, *c);
+    }
+    machine.poke(0x00C6, text.len() as u8);
+}
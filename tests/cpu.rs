@@ -0,0 +1,522 @@
+// 6502 test suites run on the CPU core with a flat 64K RAM bus
+extern crate rust64;
+
+use rust64::c64::breakpoints::{Access, Condition, Hit};
+use rust64::c64::bus::{BusCycle, RamBus};
+use rust64::c64::cpu::{CPU, CPUState, StatusFlag};
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+// Klaus Dormann's suite: https://github.com/Klaus2m5/6502_65C02_functional_tests
+const FUNCTIONAL_TEST:    &str = "6502_functional_test.bin";
+const FUNCTIONAL_START:   u16  = 0x0400;
+const FUNCTIONAL_SUCCESS: u16  = 0x3469;
+const DECIMAL_TEST:       &str = "6502_decimal_test.bin";
+const DECIMAL_START:      u16  = 0x0200;
+const DECIMAL_ERROR:      u16  = 0x000B; // 0 if all results matched
+
+// bail out if a suite neither passes nor traps
+const MAX_CYCLES: u64 = 200_000_000;
+
+const ADC_IMM: u8 = 0x69;
+const SBC_IMM: u8 = 0xE9;
+const JMP_ABS: u8 = 0x4C;
+
+
+struct Runner {
+    cpu:   CPU,
+    bus:   RamBus,
+    cycle: u64,
+}
+
+impl Runner {
+    fn new() -> Runner {
+        Runner {
+            cpu:   CPU::new(),
+            bus:   RamBus::new(),
+            cycle: 0,
+        }
+    }
+
+
+    // run a single instruction
+    fn step(&mut self) {
+        loop {
+            self.cpu.update(&mut self.bus);
+            self.cycle += 1;
+
+            match self.cpu.state {
+                CPUState::FetchOp | CPUState::Jammed => break,
+                _ => (),
+            }
+        }
+    }
+
+
+    // run a single instruction at addr and return its bus cycles, opcode fetch included
+    fn trace(&mut self, addr: u16) -> Vec<BusCycle> {
+        self.cpu.pc = addr;
+        self.bus.trace = Some(Vec::new());
+        self.step();
+        self.bus.trace.take().unwrap()
+    }
+
+
+    // run from addr until the code gets stuck on a single instruction; returns its address
+    fn run_until_trap(&mut self, addr: u16) -> Result<u16, String> {
+        self.cpu.pc = addr;
+        self.cpu.state = CPUState::FetchOp;
+
+        while self.cycle < MAX_CYCLES {
+            let pc = self.cpu.pc;
+            self.step();
+
+            if self.cpu.pc == pc {
+                return Ok(pc);
+            }
+
+            if let CPUState::Jammed = self.cpu.state {
+                return Err(format!("CPU jammed at ${:04X}", pc));
+            }
+        }
+
+        Err(format!("no trap after {} cycles, PC at ${:04X}", MAX_CYCLES, self.cpu.pc))
+    }
+}
+
+
+// the GPL test binaries can't be distributed with the emulator - they're looked up in tests/roms, and the tests
+// using them only run when asked for with --ignored
+fn load_test_binary(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms").join(name);
+    let mut data = Vec::new();
+
+    match File::open(&path).and_then(|mut file| file.read_to_end(&mut data)) {
+        Ok(_) => data,
+        Err(e) => panic!("couldn't read {}: {} (see tests/roms/README.md)", path.display(), e),
+    }
+}
+
+
+#[test]
+#[ignore = "needs tests/roms/*.bin, see README"]
+fn dormann_functional_test() {
+    let binary = load_test_binary(FUNCTIONAL_TEST);
+
+    let mut runner = Runner::new();
+    runner.bus.load(0x0000, &binary);
+
+    match runner.run_until_trap(FUNCTIONAL_START) {
+        Ok(FUNCTIONAL_SUCCESS) => (),
+        Ok(trap) => panic!("functional test trapped at ${:04X}", trap),
+        Err(e) => panic!("functional test failed: {}", e),
+    }
+}
+
+
+#[test]
+#[ignore = "needs tests/roms/*.bin, see README"]
+fn dormann_decimal_test() {
+    let binary = load_test_binary(DECIMAL_TEST);
+
+    let mut runner = Runner::new();
+    runner.bus.load(DECIMAL_START, &binary);
+
+    match runner.run_until_trap(DECIMAL_START) {
+        Ok(trap) => assert_eq!(runner.bus.ram[DECIMAL_ERROR as usize], 0, "decimal test failed, trapped at ${:04X}", trap),
+        Err(e) => panic!("decimal test failed: {}", e),
+    }
+}
+
+
+// NMOS 6502 decimal mode as described in Bruce Clark's "Decimal Mode" tutorial (appendix B)
+fn reference_adc(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let c = carry as i32;
+
+    // accumulator and carry
+    let mut al = (a as i32 & 0x0F) + (b as i32 & 0x0F) + c;
+    if al >= 0x0A {
+        al = ((al + 0x06) & 0x0F) + 0x10;
+    }
+
+    let mut sum = (a as i32 & 0xF0) + (b as i32 & 0xF0) + al;
+    if sum >= 0xA0 {
+        sum += 0x60;
+    }
+
+    // N and V come from the intermediate result, Z from the binary sum
+    let signed = (a & 0xF0) as i8 as i32 + (b & 0xF0) as i8 as i32 + al;
+    let binary = (a as i32 + b as i32 + c) & 0xFF;
+
+    let mut p = 0;
+    if sum >= 0x100                  { p |= StatusFlag::Carry as u8; }
+    if binary == 0                   { p |= StatusFlag::Zero as u8; }
+    if !(-128..=127).contains(&signed) { p |= StatusFlag::Overflow as u8; }
+    if signed & 0x80 != 0            { p |= StatusFlag::Negative as u8; }
+
+    (sum as u8, p)
+}
+
+
+fn reference_sbc(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let borrow = 1 - carry as i32;
+
+    let mut al = (a as i32 & 0x0F) - (b as i32 & 0x0F) - borrow;
+    if al < 0 {
+        al = ((al - 0x06) & 0x0F) - 0x10;
+    }
+
+    let mut diff = (a as i32 & 0xF0) - (b as i32 & 0xF0) + al;
+    if diff < 0 {
+        diff -= 0x60;
+    }
+
+    // all flags are the same as in binary mode
+    let binary = a as i32 - b as i32 - borrow;
+    let signed = a as i8 as i32 - b as i8 as i32 - borrow;
+
+    let mut p = 0;
+    if binary >= 0                   { p |= StatusFlag::Carry as u8; }
+    if binary & 0xFF == 0            { p |= StatusFlag::Zero as u8; }
+    if !(-128..=127).contains(&signed) { p |= StatusFlag::Overflow as u8; }
+    if binary & 0x80 != 0            { p |= StatusFlag::Negative as u8; }
+
+    (diff as u8, p)
+}
+
+
+// run the instruction for every accumulator, operand and carry combination and compare with the reference
+fn check_decimal_mode(opcode: u8, reference: fn(u8, u8, bool) -> (u8, u8)) {
+    let flags_mask = StatusFlag::Carry as u8 | StatusFlag::Zero as u8 | StatusFlag::Overflow as u8 | StatusFlag::Negative as u8;
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[opcode, 0x00, JMP_ABS, 0x02, 0x02]);
+
+    for a in 0..=255u8 {
+        for b in 0..=255u8 {
+            for &carry in &[false, true] {
+                runner.bus.ram[0x0201] = b;
+                runner.cpu.pc = 0x0200;
+                runner.cpu.a  = a;
+                runner.cpu.p  = StatusFlag::DecimalMode as u8 | if carry { StatusFlag::Carry as u8 } else { 0 };
+                runner.step();
+
+                let (expected_a, expected_p) = reference(a, b, carry);
+                assert_eq!((runner.cpu.a, runner.cpu.p & flags_mask), (expected_a, expected_p),
+                           "opcode ${:02X} with A=${:02X}, operand ${:02X}, C={}", opcode, a, b, carry as u8);
+            }
+        }
+    }
+}
+
+
+#[test]
+fn adc_decimal_mode() {
+    check_decimal_mode(ADC_IMM, reference_adc);
+}
+
+
+#[test]
+fn sbc_decimal_mode() {
+    check_decimal_mode(SBC_IMM, reference_sbc);
+}
+
+
+// PHP and BRK push B and the unused bit set, interrupts push B clear
+#[test]
+fn pushed_status_flags() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0x08, 0x00, 0x00]); // PHP, BRK
+    runner.bus.load(0xFFFE, &[0x00, 0x03]);
+    runner.cpu.pc = 0x0200;
+    runner.cpu.sp = 0xFF;
+    runner.cpu.p  = StatusFlag::Carry as u8;
+
+    runner.step();
+    assert_eq!(runner.bus.ram[0x01FF], 0x31);
+
+    runner.step();
+    assert_eq!(runner.bus.ram[0x01FC], 0x31);
+    assert_eq!(runner.cpu.pc, 0x0300);
+}
+
+
+#[test]
+fn rmw_writes_twice() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0xFE, 0xF0, 0x10]); // INC $10F0,X
+    runner.bus.ram[0x1110] = 0x41;
+    runner.cpu.x = 0x20;
+
+    assert_eq!(runner.trace(0x0200), vec![BusCycle::Read(0x0200), BusCycle::Read(0x0201), BusCycle::Read(0x0202),
+                                          BusCycle::Read(0x1010), BusCycle::Read(0x1110),
+                                          BusCycle::Write(0x1110, 0x41), BusCycle::Write(0x1110, 0x42)]);
+}
+
+
+#[test]
+fn indexed_read_fixes_page_in_extra_cycle() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0xBD, 0xF0, 0x10]); // LDA $10F0,X
+    runner.cpu.x = 0x01;
+
+    assert_eq!(runner.trace(0x0200), vec![BusCycle::Read(0x0200), BusCycle::Read(0x0201), BusCycle::Read(0x0202),
+                                          BusCycle::Read(0x10F1)]);
+
+    runner.cpu.x = 0x20;
+    assert_eq!(runner.trace(0x0200), vec![BusCycle::Read(0x0200), BusCycle::Read(0x0201), BusCycle::Read(0x0202),
+                                          BusCycle::Read(0x1010), BusCycle::Read(0x1110)]);
+}
+
+
+#[test]
+fn jsr_and_rts_cycles() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0x20, 0x00, 0x03]); // JSR $0300
+    runner.bus.load(0x0300, &[0x60]);             // RTS
+    runner.cpu.sp = 0xFF;
+
+    assert_eq!(runner.trace(0x0200), vec![BusCycle::Read(0x0200), BusCycle::Read(0x0201), BusCycle::Read(0x01FF),
+                                          BusCycle::Write(0x01FF, 0x02), BusCycle::Write(0x01FE, 0x02),
+                                          BusCycle::Read(0x0202)]);
+    assert_eq!(runner.cpu.pc, 0x0300);
+
+    assert_eq!(runner.trace(0x0300), vec![BusCycle::Read(0x0300), BusCycle::Read(0x0301), BusCycle::Read(0x01FD),
+                                          BusCycle::Read(0x01FE), BusCycle::Read(0x01FF), BusCycle::Read(0x0202)]);
+    assert_eq!(runner.cpu.pc, 0x0203);
+}
+
+
+#[test]
+fn branch_across_page_cycles() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x02FD, &[0xD0, 0x10]); // BNE *+$12
+    runner.cpu.p = 0;
+
+    assert_eq!(runner.trace(0x02FD), vec![BusCycle::Read(0x02FD), BusCycle::Read(0x02FE), BusCycle::Read(0x02FF),
+                                          BusCycle::Read(0x020F)]);
+    assert_eq!(runner.cpu.pc, 0x030F);
+}
+
+
+#[test]
+fn jmp_indirect_wraps_within_page() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0x6C, 0xFF, 0x10]); // JMP ($10FF)
+    runner.bus.ram[0x10FF] = 0x34;
+    runner.bus.ram[0x1000] = 0x12;
+
+    runner.trace(0x0200);
+    assert_eq!(runner.cpu.pc, 0x1234);
+}
+
+
+// while BA is low the CPU stops on its next read cycle, but writes go through
+#[test]
+fn ba_low_stalls_reads_only() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0x8D, 0x34, 0x12]); // STA $1234
+    runner.cpu.pc = 0x0200;
+    runner.cpu.a  = 0x55;
+    runner.bus.trace = Some(Vec::new());
+
+    runner.cpu.update(&mut runner.bus);
+    runner.cpu.ba_low = true;
+    for _ in 0..3 {
+        runner.cpu.update(&mut runner.bus);
+    }
+    assert_eq!(runner.bus.trace.as_ref().unwrap().len(), 1);
+
+    runner.cpu.ba_low = false;
+    runner.cpu.update(&mut runner.bus);
+    runner.cpu.update(&mut runner.bus);
+    runner.cpu.ba_low = true;
+    runner.cpu.update(&mut runner.bus);
+
+    assert_eq!(runner.bus.trace.unwrap().len(), 4);
+    assert_eq!(runner.bus.ram[0x1234], 0x55);
+}
+
+
+// run the SHA, SHX, SHY or TAS at $0200 with $0F as the value to store, indexed off $12F0, and BA low in one
+// cycle (counted from the opcode fetch); returns the write and the stack pointer
+fn unstable_store(code: &[u8], index: u8, stall_at: Option<u32>) -> (BusCycle, u8) {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, code);
+    runner.bus.load(0x0010, &[0xF0, 0x12]);
+    runner.cpu.sp = 0xFF;
+    match code[0] {
+        0x9E => { runner.cpu.x = 0x0F; runner.cpu.y = index; }, // SHX $12F0,Y
+        0x9C => { runner.cpu.y = 0x0F; runner.cpu.x = index; }, // SHY $12F0,X
+        _    => { runner.cpu.a = 0x0F; runner.cpu.x = 0xFF; runner.cpu.y = index; },
+    }
+
+    runner.cpu.pc = 0x0200;
+    runner.bus.trace = Some(Vec::new());
+    for cycle in 0.. {
+        runner.cpu.ba_low = stall_at == Some(cycle);
+        runner.cpu.update(&mut runner.bus);
+        if let CPUState::FetchOp = runner.cpu.state {
+            break;
+        }
+    }
+
+    (*runner.bus.trace.unwrap().last().unwrap(), runner.cpu.sp)
+}
+
+
+// the value is ANDed with the high byte of the base address + 1 and replaces the high byte of the target
+// on a page cross; BA going low in the cycle right before the write leaves out the AND
+#[test]
+fn unstable_stores() {
+    let stores: [(&[u8], u32); 5] = [
+        (&[0x9F, 0xF0, 0x12], 3), // SHA $12F0,Y
+        (&[0x93, 0x10], 4),       // SHA ($10),Y
+        (&[0x9E, 0xF0, 0x12], 3), // SHX $12F0,Y
+        (&[0x9C, 0xF0, 0x12], 3), // SHY $12F0,X
+        (&[0x9B, 0xF0, 0x12], 3), // TAS $12F0,Y
+    ];
+
+    for &(code, before_write) in &stores {
+        assert_eq!(unstable_store(code, 0x01, None).0, BusCycle::Write(0x12F1, 0x03), "{:02X?}", code);
+        assert_eq!(unstable_store(code, 0x20, None).0, BusCycle::Write(0x0310, 0x03), "{:02X?}", code);
+
+        assert_eq!(unstable_store(code, 0x01, Some(before_write)).0, BusCycle::Write(0x12F1, 0x0F), "{:02X?}", code);
+        assert_eq!(unstable_store(code, 0x20, Some(before_write)).0, BusCycle::Write(0x0F10, 0x0F), "{:02X?}", code);
+
+        // a stall in any earlier cycle doesn't
+        for cycle in 1..before_write {
+            assert_eq!(unstable_store(code, 0x20, Some(cycle)).0, BusCycle::Write(0x0310, 0x03), "{:02X?}", code);
+        }
+    }
+
+    // TAS puts A & X in the stack pointer as well
+    assert_eq!(unstable_store(&[0x9B, 0xF0, 0x12], 0x01, None).1, 0x0F);
+    assert_eq!(unstable_store(&[0x9F, 0xF0, 0x12], 0x01, None).1, 0xFF);
+}
+
+
+// ANE ANDs X and the operand with A ORed with a chip-dependent constant, LXA just the operand
+#[test]
+fn ane_and_lxa_magic_constants() {
+    let mut runner = Runner::new();
+    runner.bus.load(0x0200, &[0x8B, 0xFF]); // ANE #$FF
+    runner.bus.load(0x0300, &[0xAB, 0xFF]); // LXA #$FF
+    assert_eq!((runner.cpu.ane_magic, runner.cpu.lxa_magic), (0xEF, 0xEE));
+
+    runner.cpu.a = 0x00;
+    runner.cpu.x = 0xFF;
+    runner.trace(0x0200);
+    assert_eq!(runner.cpu.a, 0xEF);
+    assert!(runner.cpu.get_status_flag(StatusFlag::Negative));
+
+    runner.cpu.a = 0x10;
+    runner.cpu.x = 0x5A;
+    runner.trace(0x0200);
+    assert_eq!(runner.cpu.a, 0x5A);
+
+    runner.cpu.a = 0x01;
+    runner.cpu.x = 0x00;
+    runner.trace(0x0300);
+    assert_eq!((runner.cpu.a, runner.cpu.x), (0xEF, 0xEF));
+
+    runner.cpu.a = 0x10;
+    runner.trace(0x0300);
+    assert_eq!((runner.cpu.a, runner.cpu.x), (0xFE, 0xFE));
+
+    // other chips
+    runner.cpu.ane_magic = 0x00;
+    runner.cpu.lxa_magic = 0xFF;
+    runner.cpu.a = 0x10;
+    runner.cpu.x = 0x5A;
+    runner.trace(0x0200);
+    assert_eq!(runner.cpu.a, 0x10);
+    runner.cpu.a = 0x00;
+    runner.trace(0x0300);
+    assert_eq!((runner.cpu.a, runner.cpu.x), (0xFF, 0xFF));
+}
+
+
+// the I flag is polled before CLI and SEI change it, so the instruction after CLI still runs
+// before a pending IRQ and SEI doesn't block one
+#[test]
+fn irq_polling_around_cli_and_sei() {
+    for &(opcode, irq_disabled, return_addr) in &[(0x58, true, 0x0202), (0x78, false, 0x0201)] {
+        let mut runner = Runner::new();
+        runner.bus.load(0x0200, &[opcode, 0xEA, 0xEA, 0xEA]); // CLI/SEI, NOP, NOP, NOP
+        runner.bus.load(0xFFFE, &[0x00, 0x03]);
+        runner.cpu.pc = 0x0200;
+        runner.cpu.sp = 0xFF;
+        runner.cpu.p  = if irq_disabled { StatusFlag::InterruptDisable as u8 } else { 0 };
+        runner.cpu.cia_irq = true;
+
+        while runner.cpu.pc != 0x0300 && runner.cycle < 100 {
+            runner.step();
+        }
+
+        assert_eq!(runner.cpu.pc, 0x0300);
+        assert_eq!((runner.bus.ram[0x01FF], runner.bus.ram[0x01FE]), ((return_addr >> 8) as u8, return_addr as u8));
+        assert_eq!(runner.bus.ram[0x01FD] & StatusFlag::Break as u8, 0);
+    }
+}
+
+
+// exec breakpoints stop before the instruction, watchpoints report the access of the instruction
+#[test]
+fn breakpoints_and_watchpoints() {
+    let mut runner = Runner::new();
+    // LDX #$03 / loop: STX $D020 / DEX / BNE loop / NOP
+    runner.bus.load(0x0200, &[0xA2, 0x03, 0x8E, 0x20, 0xD0, 0xCA, 0xD0, 0xFA, 0xEA]);
+    runner.cpu.pc = 0x0200;
+
+    let watch = runner.cpu.breakpoints.add(0xD000, 0xD3FF, false, false, true, Some(Condition::parse("x < 3").unwrap()));
+    let exec = runner.cpu.breakpoints.add(0x0208, 0x0208, true, false, false, None);
+    runner.cpu.breakpoints.set_ignore_count(watch, 1);
+
+    let mut hits = Vec::new();
+    while runner.cycle < 100 && hits.len() < 2 {
+        runner.step();
+        if let Some(hit) = runner.cpu.breakpoints.hit.take() {
+            hits.push((hit, runner.cpu.pc));
+        }
+    }
+
+    assert_eq!(hits, vec![(Hit { id: watch, access: Access::Store, addr: 0xD020, value: 0x01 }, 0x0205),
+                          (Hit { id: exec, access: Access::Exec, addr: 0x0208, value: 0 }, 0x0208)]);
+    assert_eq!(runner.bus.ram[0x0208], 0xEA);
+    assert_eq!(runner.cpu.breakpoints.list()[0].hits, 2);
+}
+
+
+// the 12 KIL opcodes lock the CPU up with the program counter past the opcode; neither bus cycles
+// nor interrupts happen until a reset
+#[test]
+fn kil_jams_until_reset() {
+    for &opcode in &[0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2] {
+        let mut runner = Runner::new();
+        runner.bus.load(0x0200, &[0xEA, opcode, 0xEA]); // NOP, KIL, NOP
+        runner.bus.load(0xFFFC, &[0x00, 0x03]);
+        runner.bus.load(0x0300, &[0xEA]);
+        runner.cpu.pc = 0x0200;
+
+        runner.step();
+        assert_eq!(runner.cpu.jam_address(), None);
+        runner.step();
+        assert!(matches!(runner.cpu.state, CPUState::Jammed), "opcode ${:02X}", opcode);
+        assert_eq!(runner.cpu.jam_address(), Some(0x0201), "opcode ${:02X}", opcode);
+
+        runner.bus.trace = Some(Vec::new());
+        runner.cpu.set_nmi(true);
+        for _ in 0..20 {
+            runner.cpu.update(&mut runner.bus);
+        }
+        assert_eq!(runner.bus.trace.take().unwrap(), vec![]);
+        assert_eq!(runner.cpu.jam_address(), Some(0x0201));
+
+        runner.cpu.reset(&mut runner.bus);
+        runner.cpu.set_nmi(false);
+        assert_eq!((runner.cpu.jam_address(), runner.cpu.pc), (None, 0x0300));
+        runner.step();
+        assert_eq!(runner.cpu.pc, 0x0301);
+    }
+}
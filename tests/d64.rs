@@ -0,0 +1,87 @@
+extern crate rust64;
+
+mod common;
+
+use rust64::c64::d64::{D64, DosError, FileType};
+use rust64::c64::disk_image::DiskImage;
+
+// 10 PRINT"HI"
+const PROGRAM: [u8; 14] = [0x01, 0x08, 0x0B, 0x08, 0x0A, 0x00, 0x99, 0x22, 0x48, 0x49, 0x22, 0x00, 0x00, 0x00];
+
+
+// type a line and give the command time to finish
+fn type_line(machine: &mut rust64::Machine, text: &[u8]) {
+    common::type_text(machine, text);
+
+    for _ in 0..100 {
+        machine.run_frame();
+    }
+}
+
+
+#[test]
+fn files_directory_and_bam() {
+    let mut disk = D64::blank(b"TEST DISK", b"AB");
+    assert_eq!(disk.free_blocks(), 664);
+
+    // 600 bytes take 3 blocks, interleaved on the track next to the directory
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    disk.write_file(b"DATA", FileType::Seq, &data, false).unwrap();
+    disk.write_file(b"HELLO", FileType::Prg, &PROGRAM, false).unwrap();
+    assert_eq!(disk.write_file(b"HELLO", FileType::Prg, &PROGRAM, false), Err(DosError::FileExists));
+    assert_eq!(disk.free_blocks(), 660);
+
+    let directory = disk.directory();
+    assert_eq!(directory.len(), 2);
+    assert_eq!((&directory[0].name[..], directory[0].file_type, directory[0].blocks), (&b"DATA"[..], FileType::Seq, 3));
+    assert_eq!((directory[0].track, directory[0].sector), (17, 0));
+    assert_eq!(disk.read_file(&directory[0]), Ok(data));
+
+    let hello = disk.find(b"H?LL*").unwrap();
+    assert_eq!(disk.read_file(&hello), Ok(PROGRAM.to_vec()));
+    assert!(disk.find(b"HELLO2").is_none());
+
+    // the "$" file is a BASIC program at $0401
+    let listing = disk.listing(b"*");
+    assert_eq!(&listing[..2], &[0x01, 0x04]);
+    assert!(listing.windows(9).any(|w| w == b"\"HELLO\"  "));
+    assert!(listing.windows(12).any(|w| w == b"BLOCKS FREE."));
+
+    assert_eq!(disk.scratch(b"D*"), Ok(1));
+    assert_eq!(disk.free_blocks(), 663);
+    assert_eq!(disk.directory().len(), 1);
+
+    // sizes tell 35 and 40 track images with and without error bytes apart
+    assert!(D64::from_data(vec![0; 174848]).is_ok());
+    assert_eq!(D64::from_data(vec![0; 197376]).map(|d| d.tracks()), Ok(40));
+    assert!(D64::from_data(vec![0; 1000]).is_err());
+}
+
+
+#[test]
+fn kernal_load_and_save_through_drive_8() {
+    let mut disk = D64::blank(b"TEST DISK", b"AB");
+    disk.write_file(b"HELLO", FileType::Prg, &PROGRAM, false).unwrap();
+
+    let mut machine = common::booted_machine();
+    machine.attach_disk_image(disk);
+
+    type_line(&mut machine, b"LOAD\"H*\",8\r");
+    let loaded: Vec<u8> = (0x0801..0x080D).map(|addr| machine.peek(addr)).collect();
+    assert_eq!(&loaded[..], &PROGRAM[2..]);
+
+    type_line(&mut machine, b"SAVE\"X\",8\r");
+
+    // the directory replaces the program
+    type_line(&mut machine, b"LOAD\"$\",8\r");
+    assert_eq!(machine.peek(0x0805), 0x12); // reverse video header
+
+    let disk = machine.detach_disk().unwrap();
+    let saved = disk.find(b"X").unwrap();
+    assert_eq!(disk.read_file(&saved), Ok(PROGRAM.to_vec()));
+
+    // without a disk there's no drive
+    type_line(&mut machine, b"LOAD\"H*\",8\r");
+    let message: Vec<u8> = (0..40 * 25).map(|i| machine.peek(0x0400 + i)).collect();
+    assert!(message.windows(7).any(|w| w == [4, 5, 22, 9, 3, 5, 32])); // "DEVICE "
+}
@@ -0,0 +1,50 @@
+extern crate rust64;
+
+use rust64::c64::disasm::{self, Instruction};
+use rust64::c64::opcodes::{AddrMode, Op};
+
+
+#[test]
+fn decodes_operands_and_timing() {
+    let instruction = Instruction::decode(&[0xBD, 0x00, 0x04], 0xC000).unwrap();
+
+    assert_eq!(instruction.op, Op::LDA);
+    assert_eq!(instruction.addr_mode, AddrMode::AbsoluteIndexedX(true));
+    assert_eq!(instruction.operand, 0x0400);
+    assert_eq!(instruction.length, 3);
+    assert_eq!(instruction.cycles, 4);
+    assert!(instruction.extra_cycles);
+    assert!(!instruction.illegal);
+    assert_eq!(instruction.hex(), "BD 00 04");
+    assert_eq!(instruction.to_string(), "LDA $0400,X");
+}
+
+
+#[test]
+fn resolves_branch_targets() {
+    let instructions = disasm::disassemble(&[0xD0, 0xFE, 0x10, 0x7F], 0xFFFC);
+
+    assert_eq!(instructions[0].target(), Some(0xFFFC));
+    assert_eq!(instructions[0].cycles, 2);
+    assert_eq!(instructions[1].to_string(), "BPL $007F");
+}
+
+
+#[test]
+fn marks_undocumented_opcodes() {
+    let illegal: Vec<bool> = disasm::disassemble(&[0xEA, 0x1A, 0xE9, 0x00, 0xEB, 0x00, 0xA7, 0x10, 0x02], 0x1000)
+                                 .iter()
+                                 .map(|i| i.illegal)
+                                 .collect();
+
+    assert_eq!(illegal, vec![false, true, false, true, true, true]);
+}
+
+
+#[test]
+fn stops_before_a_cut_off_instruction() {
+    let instructions = disasm::disassemble(&[0xEA, 0x4C, 0x00], 0x1000);
+
+    assert_eq!(instructions.len(), 1);
+    assert!(Instruction::decode(&[], 0x1000).is_none());
+}
@@ -0,0 +1,94 @@
+extern crate rust64;
+
+use rust64::c64::d64::D64;
+use rust64::c64::d71::D71;
+use rust64::c64::d81::D81;
+use rust64::c64::disk_image::{DiskImage, FileType};
+use rust64::c64::g64::G64;
+
+
+#[test]
+fn d71_spills_over_to_the_second_side() {
+    let mut disk = D71::blank(b"DOUBLE", b"71");
+    assert_eq!(disk.tracks(), 70);
+    assert_eq!(disk.free_blocks(), 1328);
+    assert_eq!(disk.disk_id(), [b'7', b'1']);
+
+    // more than one side holds
+    let data: Vec<u8> = (0..700 * 254).map(|i| (i % 251) as u8).collect();
+    disk.write_file(b"BIG", FileType::Seq, &data, false).unwrap();
+    assert_eq!(disk.free_blocks(), 628);
+    let entry = disk.find(b"BIG").unwrap();
+    assert_eq!(entry.blocks, 700);
+    assert_eq!(disk.read_file(&entry), Ok(data.clone()));
+
+    let copy = D71::from_data(image_data(&disk)).unwrap();
+    assert_eq!(copy.read_file(&copy.find(b"B*").unwrap()), Ok(data));
+
+    disk.scratch(b"BIG").unwrap();
+    assert_eq!(disk.free_blocks(), 1328);
+}
+
+
+#[test]
+fn d81_directory_and_files() {
+    let mut disk = D81::blank(b"THREE AND A HALF", b"81");
+    assert_eq!(disk.tracks(), 80);
+    assert_eq!(disk.free_blocks(), 3160);
+    assert_eq!(disk.disk_name(), b"THREE AND A HALF".to_vec());
+
+    for i in 0..20u8 {
+        disk.write_file(&[b'F', b'0' + i / 10, b'0' + i % 10], FileType::Prg, &[0x01, 0x08, i], false).unwrap();
+    }
+
+    // the directory grows beyond its first sector on track 40
+    let directory = disk.directory();
+    assert_eq!(directory.len(), 20);
+    assert!(directory.iter().all(|e| e.track != 40));
+    assert_eq!(disk.free_blocks(), 3140);
+    assert_eq!(disk.read_file(&disk.find(b"F17").unwrap()), Ok(vec![0x01, 0x08, 17]));
+
+    let listing = disk.listing(b"*");
+    assert_eq!(&listing[6..9], &[0x12, b'"', b'T']);
+    assert_eq!(&listing[24..31], b"\" 81 3D");
+
+    assert!(D81::from_data(vec![0; 819200]).is_ok());
+    assert!(D81::from_data(vec![0; 819201]).is_err());
+}
+
+
+#[test]
+fn g64_keeps_the_gcr_tracks() {
+    let mut d64 = D64::blank(b"GCR DISK", b"64");
+    let data: Vec<u8> = (0..3000).map(|i| (i * 3) as u8).collect();
+    d64.write_file(b"PROGRAM", FileType::Prg, &data, false).unwrap();
+
+    let g64 = G64::from_disk(&d64).unwrap();
+    let mut g64 = G64::from_data(&g64.to_data()).unwrap();
+    assert_eq!(g64.disk_name(), b"GCR DISK".to_vec());
+    assert_eq!(g64.read_file(&g64.find(b"PROGRAM").unwrap()), Ok(data));
+    assert_eq!(g64.free_blocks(), d64.free_blocks());
+
+    // writes go into the GCR data, and tracks without sectors read as damaged
+    g64.write_file(b"MORE", FileType::Seq, b"HELLO", false).unwrap();
+    let g64 = G64::from_data(&g64.to_data()).unwrap();
+    assert_eq!(g64.read_file(&g64.find(b"MORE").unwrap()), Ok(b"HELLO".to_vec()));
+    assert!(g64.read_sector(36, 0).is_err());
+    assert_eq!(g64.gcr_tracks().unwrap()[0].len(), 7692);
+
+    assert!(G64::from_data(b"GCR-1541\x01").is_err());
+    assert!(D71::blank(b"", b"").gcr_tracks().is_none());
+}
+
+
+// the image file contents, sector by sector
+fn image_data<I: DiskImage>(image: &I) -> Vec<u8> {
+    let layout = image.layout();
+    let mut data = Vec::new();
+    for track in 1..=image.tracks() {
+        for sector in 0..layout.sectors_per_track(track) {
+            data.extend_from_slice(image.raw_sector(track, sector).unwrap());
+        }
+    }
+    data
+}
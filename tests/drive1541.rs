@@ -0,0 +1,145 @@
+extern crate rust64;
+
+mod common;
+
+use rust64::c64::d64::{D64, FileType};
+use rust64::c64::disk_image::DiskImage;
+use rust64::c64::drive1541::{gcr, via};
+
+// stands in for the DOS ROM: releases the serial bus, turns the motor on, waits for a sync mark
+// and stores the next 10 bytes read from the disk at $0300
+const DRIVE_PROGRAM: [u8; 59] = [
+    0xA9, 0x00, 0x8D, 0x00, 0x18, // LDA #$00 / STA $1800    serial bus lines released, ATN acknowledged
+    0xA9, 0x1A, 0x8D, 0x02, 0x18, // LDA #$1A / STA $1802    DATA, CLK and ATNA are outputs
+    0xA9, 0x01, 0x8D, 0x0C, 0x18, // LDA #$01 / STA $180C    ATN interrupt flag on its rising edge
+    0xA9, 0x6F, 0x8D, 0x02, 0x1C, // LDA #$6F / STA $1C02
+    0xA9, 0x4C, 0x8D, 0x00, 0x1C, // LDA #$4C / STA $1C00    motor and LED on, speed zone 2
+    0xA9, 0xEE, 0x8D, 0x0C, 0x1C, // LDA #$EE / STA $1C0C    byte ready on the overflow flag, read mode
+    0x2C, 0x00, 0x1C, 0x30, 0xFB, // BIT $1C00 / BMI *-3     wait for a sync mark...
+    0x2C, 0x00, 0x1C, 0x10, 0xFB, // BIT $1C00 / BPL *-3     ...and its end
+    0xA2, 0x00,                   // LDX #$00
+    0x50, 0xFE,                   // BVC *
+    0xB8,                         // CLV
+    0xAD, 0x01, 0x1C,             // LDA $1C01
+    0x9D, 0x00, 0x03,             // STA $0300,X
+    0xE8,                         // INX
+    0xE0, 0x0A,                   // CPX #$0A
+    0xD0, 0xF2,                   // BNE *-12
+    0x4C, 0x38, 0xC0,             // JMP *
+];
+
+// asserts ATN for a while, storing $DD00 at $C100 while it's asserted and at $C101 after releasing it
+const C64_PROGRAM: [u8; 37] = [
+    0xAD, 0x00, 0xDD, 0x09, 0x08, 0x8D, 0x00, 0xDD, // LDA $DD00 / ORA #$08 / STA $DD00
+    0xA2, 0x00, 0xCA, 0xD0, 0xFD,                   // LDX #$00 / DEX / BNE *-1
+    0xAD, 0x00, 0xDD, 0x8D, 0x00, 0xC1,             // LDA $DD00 / STA $C100
+    0xAD, 0x00, 0xDD, 0x29, 0xF7, 0x8D, 0x00, 0xDD, // LDA $DD00 / AND #$F7 / STA $DD00
+    0xCA, 0xD0, 0xFD,                               // DEX / BNE *-1
+    0xAD, 0x00, 0xDD, 0x8D, 0x01, 0xC1, 0x60,       // LDA $DD00 / STA $C101 / RTS
+];
+
+
+#[test]
+fn gcr_tracks_decode_back_into_sectors() {
+    // the header ID $08 always starts with $52
+    assert_eq!(gcr::encode(&[0x08, 0x00, 0x00, 0x00])[0], 0x52);
+    assert_eq!(gcr::decode(&gcr::encode(&[0x12, 0x34, 0xAB, 0xFF])), Some([0x12, 0x34, 0xAB, 0xFF]));
+    assert_eq!(gcr::decode(&[0x00; 5]), None);
+
+    let mut disk = D64::blank(b"GCR", b"ZZ");
+    let data: Vec<u8> = (0..5000).map(|i| (i * 7) as u8).collect();
+    disk.write_file(b"FILE", FileType::Prg, &data, false).unwrap();
+
+    let tracks = gcr::encode_disk(&disk);
+    assert_eq!(tracks.len(), gcr::HALF_TRACKS);
+    assert_eq!((tracks[0].len(), tracks[1].len(), tracks[34].len()), (7692, 0, 7142));
+
+    let mut copy = D64::blank(b"", b"00");
+    for track in 1..=35u8 {
+        let sectors = gcr::decode_track(&tracks[(track as usize - 1) * 2], track, &mut copy);
+        assert_eq!(sectors, rust64::c64::d64::sectors_per_track(track) as usize);
+    }
+
+    assert_eq!(copy.disk_name(), b"GCR".to_vec());
+    let entry = copy.find(b"FILE").unwrap();
+    assert_eq!(copy.read_file(&entry), Ok(data));
+}
+
+
+#[test]
+fn via_timers_and_interrupts() {
+    let mut via = via::VIA::new();
+
+    // one-shot timer 1 with its interrupt enabled
+    via.write_register(0x0E, 0x80 | via::IRQ_T1);
+    via.write_register(0x04, 0x03);
+    via.write_register(0x05, 0x00);
+    for _ in 0..3 {
+        via.update();
+    }
+    assert!(!via.irq());
+    via.update();
+    assert!(via.irq());
+    assert_eq!(via.read_register(0x0D), 0x80 | via::IRQ_T1);
+
+    // reading the low counter byte acknowledges it, and it doesn't fire again
+    via.read_register(0x04);
+    for _ in 0..0x10000 {
+        via.update();
+    }
+    assert!(!via.irq());
+
+    // port pins set up as inputs read what's driven onto them and are pulled high on the outside
+    via.port_b_in = 0x05;
+    via.write_register(0x02, 0xF0);
+    via.write_register(0x00, 0xA3);
+    assert_eq!(via.read_register(0x00), 0xA5);
+    assert_eq!(via.port_b_out(), 0xAF);
+
+    // CA1 edge selected by the PCR
+    via.write_register(0x0C, 0x01);
+    via.set_ca1(false);
+    assert_eq!(via.read_register(0x0D) & via::IRQ_CA1, 0);
+    via.set_ca1(true);
+    assert_eq!(via.read_register(0x0D) & via::IRQ_CA1, via::IRQ_CA1);
+}
+
+
+#[test]
+fn drive_reads_the_disk_and_answers_atn() {
+    let mut rom = vec![0xEA; 0x4000];
+    rom[..DRIVE_PROGRAM.len()].copy_from_slice(&DRIVE_PROGRAM);
+    rom[0x3FFC] = 0x00;
+    rom[0x3FFD] = 0xC0;
+
+    let mut machine = rust64::Machine::new("", "").unwrap();
+    assert!(machine.enable_true_drive(vec![0; 100]).is_err());
+    machine.enable_true_drive(rom).unwrap();
+    machine.attach_disk_image(D64::blank(b"TRUE DRIVE", b"42"));
+    common::boot(&mut machine);
+
+    // the head sits on track 18, where the first block found is a sector header
+    let drive = machine.true_drive().unwrap();
+    assert_eq!(drive.track(), 18.0);
+    assert!(drive.led_on());
+    let ram: Vec<u8> = (0x0300..0x030A).map(|i| drive.bus().ram[i]).collect();
+    let first = gcr::decode(&[ram[0], ram[1], ram[2], ram[3], ram[4]]).unwrap();
+    let second = gcr::decode(&[ram[5], ram[6], ram[7], ram[8], ram[9]]).unwrap();
+    assert_eq!((first[0], first[3], second[0], second[1]), (0x08, 18, b'2', b'4'));
+    assert_eq!(first[1], first[2] ^ 18 ^ b'2' ^ b'4');
+
+    for (i, byte) in C64_PROGRAM.iter().enumerate() {
+        machine.poke(0xC000 + i as u16, *byte);
+    }
+
+    common::type_text(&mut machine, b"SYS49152\r");
+    for _ in 0..50 {
+        machine.run_frame();
+    }
+
+    // the drive pulls DATA by itself while ATN is asserted, and the ATN edge is flagged in VIA1
+    assert_eq!(machine.peek(0xC100) & 0x80, 0x00);
+    assert_eq!(machine.peek(0xC101) & 0x80, 0x80);
+    let drive = machine.true_drive().unwrap();
+    assert_eq!(drive.bus().via1.read_register(0x0D) & via::IRQ_CA1, via::IRQ_CA1);
+}
@@ -0,0 +1,79 @@
+extern crate rust64;
+
+use rust64::c64::roms::{RomPaths, RomSet};
+use rust64::lorenz::{self, Outcome, TestResult};
+use std::env;
+use std::fs;
+
+// 10 SYS2061, followed by the machine code
+const SYS_2061: [u8; 14] = [0x01, 0x08, 0x0B, 0x08, 0x0A, 0x00, 0x9E, 0x32, 0x30, 0x36, 0x31, 0x00, 0x00, 0x00];
+
+const PRINT_HI: [u8; 10] = [
+    0xA9, 0x48,       // LDA #'H'
+    0x20, 0xD2, 0xFF, // JSR CHROUT
+    0xA9, 0x49,       // LDA #'I'
+    0x20, 0xD2, 0xFF, // JSR CHROUT
+];
+
+// long enough to boot and start the program
+const LOOP_CYCLE_LIMIT: u64 = 10_000_000;
+
+
+// run a test program made up of the given machine code at $080D
+fn run(name: &str, code: &[u8], cycle_limit: u64) -> TestResult {
+    let mut prg = SYS_2061.to_vec();
+    prg.extend_from_slice(code);
+
+    let filename = env::temp_dir().join(format!("rust64-test-{}-{}.prg", std::process::id(), name));
+    fs::write(&filename, prg).unwrap();
+
+    let roms = RomSet::load(&RomPaths::default()).unwrap();
+    let result = lorenz::run_test(&roms, filename.to_str().unwrap(), cycle_limit).unwrap();
+    fs::remove_file(&filename).unwrap();
+    result
+}
+
+
+// the next test is LOADed once this one has passed
+#[test]
+fn load_passes() {
+    let mut code = PRINT_HI.to_vec();
+    code.extend_from_slice(&[0x20, 0xD5, 0xFF]); // JSR LOAD
+
+    let result = run("pass", &code, lorenz::DEFAULT_CYCLE_LIMIT);
+    assert_eq!(result.outcome, Outcome::Passed);
+    assert_eq!(result.output, "hi");
+    assert!(result.cycles < LOOP_CYCLE_LIMIT);
+}
+
+
+// after printing the mismatch, a failed test waits for a key
+#[test]
+fn getin_fails() {
+    let mut code = PRINT_HI.to_vec();
+    code.extend_from_slice(&[0x20, 0xE4, 0xFF]); // JSR GETIN
+
+    let result = run("fail", &code, lorenz::DEFAULT_CYCLE_LIMIT);
+    assert_eq!(result.outcome, Outcome::Failed);
+    assert_eq!(result.output, "hi");
+}
+
+
+#[test]
+fn kil_jams() {
+    let mut code = PRINT_HI.to_vec();
+    code.push(0x02); // KIL
+
+    let result = run("jam", &code, lorenz::DEFAULT_CYCLE_LIMIT);
+    assert_eq!(result.outcome, Outcome::Jammed(0x0817));
+    assert_eq!(result.output, "hi");
+}
+
+
+#[test]
+fn endless_loop_times_out() {
+    let result = run("loop", &[0x4C, 0x0D, 0x08], LOOP_CYCLE_LIMIT); // JMP $080D
+    assert_eq!(result.outcome, Outcome::Timeout);
+    assert!(result.cycles >= LOOP_CYCLE_LIMIT);
+    assert_eq!(result.output, "");
+}
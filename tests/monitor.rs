@@ -0,0 +1,44 @@
+extern crate rust64;
+
+use rust64::c64::disasm::Instruction;
+use rust64::c64::monitor;
+
+
+// every opcode's disassembly assembles back to an instruction with the same text and length
+// (undocumented duplicates of documented opcodes turn into the documented one)
+#[test]
+fn assembles_disassembler_output() {
+    for opcode in 0..=255u8 {
+        let bytes = [opcode, 0x12, 0x34];
+        let instruction = Instruction::decode(&bytes, 0x1000).unwrap();
+        let text = instruction.to_string();
+
+        let assembled = monitor::assemble(&text, 0x1000).unwrap();
+        let reassembled = Instruction::decode(&assembled, 0x1000).unwrap();
+
+        assert_eq!(reassembled.to_string(), text, "opcode ${:02X}", opcode);
+        assert_eq!(assembled.len(), instruction.length as usize, "opcode ${:02X}", opcode);
+        if !instruction.illegal {
+            assert_eq!(assembled, &bytes[..assembled.len()], "opcode ${:02X}", opcode);
+        }
+    }
+}
+
+
+#[test]
+fn picks_zero_page_unless_written_as_absolute() {
+    assert_eq!(monitor::assemble("lda $12", 0xC000), Ok(vec![0xA5, 0x12]));
+    assert_eq!(monitor::assemble("lda $0012", 0xC000), Ok(vec![0xAD, 0x12, 0x00]));
+    assert_eq!(monitor::assemble("lda 12,y", 0xC000), Ok(vec![0xB9, 0x12, 0x00]));
+    assert_eq!(monitor::assemble("ldx 12,y", 0xC000), Ok(vec![0xB6, 0x12]));
+}
+
+
+#[test]
+fn checks_branch_range() {
+    assert_eq!(monitor::assemble("bne $c000", 0xC000), Ok(vec![0xD0, 0xFE]));
+    assert_eq!(monitor::assemble("bcc $c081", 0xC000), Ok(vec![0x90, 0x7F]));
+    assert!(monitor::assemble("bcc $c082", 0xC000).is_err());
+    assert!(monitor::assemble("lda ($1234),y", 0xC000).is_err());
+    assert!(monitor::assemble("foo", 0xC000).is_err());
+}
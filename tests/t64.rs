@@ -0,0 +1,86 @@
+extern crate rust64;
+
+mod common;
+
+use rust64::c64::media::MediaType;
+use rust64::c64::t64::{self, T64};
+use std::env;
+use std::fs;
+
+const BASIC_PROGRAM: [u8; 12] = [0x0B, 0x08, 0x0A, 0x00, 0x99, 0x22, 0x48, 0x49, 0x22, 0x00, 0x00, 0x00]; // 10 PRINT"HI"
+
+
+// three files in four slots, the way a broken converter writes them: no entry count,
+// and the same wrong end address everywhere
+fn archive() -> Vec<u8> {
+    let mut data = vec![0; 0x40 + 4 * 0x20];
+    data[..20].copy_from_slice(b"C64S tape image file");
+    data[0x20..0x28].copy_from_slice(&[0x01, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    data[0x28..0x30].copy_from_slice(b"ARCHIVE ");
+
+    let files: [(&[u8], u16, &[u8]); 3] = [(b"BASIC", 0x0801, &BASIC_PROGRAM), (b"DATA", 0xC000, &[1, 2, 3, 4, 5]), (b"SCREEN", 0x0400, &[8, 9])];
+    for (i, &(name, start, contents)) in files.iter().enumerate() {
+        let slot = 0x40 + [0, 2, 3][i] * 0x20;
+        let offset = data.len();
+        data[slot] = 1;
+        data[slot + 1] = 0x82;
+        data[slot + 2..slot + 4].copy_from_slice(&[start as u8, (start >> 8) as u8]);
+        data[slot + 4..slot + 6].copy_from_slice(&[0xC6, 0xC3]);
+        data[slot + 8..slot + 12].copy_from_slice(&[offset as u8, (offset >> 8) as u8, 0, 0]);
+        data[slot + 16..slot + 32].copy_from_slice(&[0x20; 16]);
+        data[slot + 16..slot + 16 + name.len()].copy_from_slice(name);
+        data.extend_from_slice(contents);
+    }
+
+    data
+}
+
+
+#[test]
+fn directory_and_end_address_fixes() {
+    let data = archive();
+    assert!(t64::is_t64(&data));
+    assert_eq!(MediaType::from_header(&data[..64]), Some(MediaType::T64));
+    assert!(T64::from_data(data[..0x40].to_vec()).is_err());
+
+    let archive = T64::from_data(data).unwrap();
+    assert_eq!(archive.name, b"ARCHIVE".to_vec());
+
+    let entries = archive.entries();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[1].name, b"DATA".to_vec());
+    assert_eq!((entries[0].start_address, entries[0].end_address), (0x0801, 0x080D));
+    assert_eq!((entries[1].start_address, entries[1].end_address), (0xC000, 0xC005));
+    assert_eq!((entries[2].start_address, entries[2].end_address), (0x0400, 0x0402));
+
+    assert_eq!(archive.find(b"SC*"), Some(2));
+    assert_eq!(archive.find(b"NONE"), None);
+    let prg = archive.prg(1).unwrap();
+    assert_eq!((prg.load_address, prg.data), (0xC000, vec![1, 2, 3, 4, 5]));
+    assert!(archive.prg(3).is_none());
+}
+
+
+#[test]
+fn entries_load_like_program_files() {
+    let filename = env::temp_dir().join(format!("rust64-test-{}.t64", std::process::id()));
+    fs::write(&filename, archive()).unwrap();
+    let filename = filename.to_str().unwrap().to_string();
+
+    let mut machine = rust64::Machine::new("", "").unwrap();
+    assert!(machine.load_t64_entry(&filename, 5).is_err());
+    machine.load_t64_entry(&filename, 0).unwrap();
+    common::boot(&mut machine);
+
+    // a BASIC program moves the start of variables past its end
+    let loaded: Vec<u8> = (0x0801..0x080D).map(|addr| machine.peek(addr)).collect();
+    assert_eq!(&loaded[..], &BASIC_PROGRAM);
+    assert_eq!((machine.peek(0x002D), machine.peek(0x002E)), (0x0D, 0x08));
+
+    // once BASIC is running, files are loaded right away
+    machine.load_t64_entry(&filename, 1).unwrap();
+    let loaded: Vec<u8> = (0xC000..0xC005).map(|addr| machine.peek(addr)).collect();
+    assert_eq!(loaded, vec![1, 2, 3, 4, 5]);
+
+    fs::remove_file(&filename).unwrap();
+}
@@ -0,0 +1,197 @@
+extern crate rust64;
+
+mod common;
+
+use rust64::c64::datasette::{Button, Datasette};
+use rust64::c64::media::MediaType;
+use rust64::c64::model::Model;
+use rust64::c64::tap::Tap;
+
+const SHORT: u8 = 0x30; // 384 cycles
+const LONG: u8 = 0x60;  // 768 cycles
+const DATA: [u8; 4] = [0xA5, 0x3C, 0x01, 0xFF];
+
+// a minimal turbo loader: times the pulses on FLAG with a one-shot timer A restarted on each one, so a pulse longer
+// than $0240 cycles lets it underflow and reads as a 1. Four bytes, MSB first, are stored at $C100.
+const LOADER: [u8; 77] = [
+    0x78,                   // C000: SEI
+    0xA9, 0x7F,             // C001: LDA #$7F
+    0x8D, 0x0D, 0xDC,       // C003: STA $DC0D   ; no CIA1 interrupts
+    0xAD, 0x0D, 0xDC,       // C006: LDA $DC0D
+    0xA5, 0x01,             // C009: LDA $01
+    0x29, 0xDF,             // C00B: AND #$DF
+    0x85, 0x01,             // C00D: STA $01     ; motor on
+    0xA9, 0x40,             // C00F: LDA #$40
+    0x8D, 0x04, 0xDC,       // C011: STA $DC04
+    0xA9, 0x02,             // C014: LDA #$02
+    0x8D, 0x05, 0xDC,       // C016: STA $DC05
+    0xAD, 0x0D, 0xDC,       // C019: LDA $DC0D   ; wait for the first pulse
+    0x29, 0x10,             // C01C: AND #$10
+    0xF0, 0xF9,             // C01E: BEQ $C019
+    0xA2, 0x00,             // C020: LDX #$00
+    0xA0, 0x08,             // C022: LDY #$08
+    0xA9, 0x19,             // C024: LDA #$19
+    0x8D, 0x0E, 0xDC,       // C026: STA $DC0E   ; one-shot, force load, start
+    0xA9, 0x00,             // C029: LDA #$00
+    0x85, 0xFB,             // C02B: STA $FB
+    0xAD, 0x0D, 0xDC,       // C02D: LDA $DC0D   ; collect the ICR up to the next pulse
+    0x05, 0xFB,             // C030: ORA $FB
+    0x85, 0xFB,             // C032: STA $FB
+    0x29, 0x10,             // C034: AND #$10
+    0xF0, 0xF5,             // C036: BEQ $C02D
+    0xA5, 0xFB,             // C038: LDA $FB
+    0x4A,                   // C03A: LSR         ; timer A underflow
+    0x26, 0xFC,             // C03B: ROL $FC
+    0x88,                   // C03D: DEY
+    0xD0, 0xE4,             // C03E: BNE $C024
+    0xA5, 0xFC,             // C040: LDA $FC
+    0x9D, 0x00, 0xC1,       // C042: STA $C100,X
+    0xE8,                   // C045: INX
+    0xE0, 0x04,             // C046: CPX #$04
+    0xD0, 0xD8,             // C048: BNE $C022
+    0x4C, 0x4A, 0xC0,       // C04A: JMP $C04A
+];
+
+
+fn tap(version: u8, data: &[u8]) -> Vec<u8> {
+    let mut file = b"C64-TAPE-RAW".to_vec();
+    file.extend_from_slice(&[version, 0, 0, 0]);
+    file.extend_from_slice(&[data.len() as u8, (data.len() >> 8) as u8, 0, 0]);
+    file.extend_from_slice(data);
+    file
+}
+
+
+#[test]
+fn pulses_of_all_versions() {
+    let v0 = tap(0, &[SHORT, 0x00, LONG]);
+    assert_eq!(MediaType::from_header(&v0), Some(MediaType::Tap));
+    let tape = Tap::from_data(&v0).unwrap();
+    assert_eq!(tape.pulses(), &[384, 2048, 768]);
+    assert_eq!(tape.len_cycles(), 3200);
+
+    // longer pulses in cycles, with a cut off one at the end
+    let tape = Tap::from_data(&tap(1, &[SHORT, 0x00, 0x10, 0x27, 0x00, LONG, 0x00, 0x01])).unwrap();
+    assert_eq!(tape.pulses(), &[384, 10000, 768]);
+
+    // half waves make up one pulse
+    let tape = Tap::from_data(&tap(2, &[0x18, 0x18, 0x30, 0x30])).unwrap();
+    assert_eq!((tape.version, tape.pulses()), (2, &[384, 768][..]));
+
+    // cycles of an NTSC machine
+    let mut ntsc = tap(1, &[SHORT]);
+    ntsc[0x0E] = 1;
+    assert_eq!(Tap::from_data(&tap(1, &[SHORT])).unwrap().model, Model::Pal);
+    assert_eq!(Tap::from_data(&ntsc).unwrap().model, Model::Ntsc);
+
+    assert!(Tap::from_data(&tap(3, &[SHORT])).is_err());
+    assert!(Tap::from_data(&v0[..0x10]).is_err());
+    assert!(Tap::from_data(b"C64-TAPE-RAX\x01\x00\x00\x00\x00\x00\x00\x00").is_err());
+}
+
+
+// cycles in which a pulse starts, until the tape ends
+fn play(datasette: &mut Datasette, max_cycles: u32) -> Vec<u32> {
+    let mut edges = Vec::new();
+    datasette.press(Button::Play);
+
+    for cycle in 0..max_cycles {
+        let high = datasette.read_line();
+        if datasette.update(true) {
+            // the line has to come up in between for FLAG to see an edge, in the same cycle at the latest
+            assert!(high || cycle == edges.last().map_or(0, |c| c + 1), "no edge in cycle {}", cycle);
+            edges.push(cycle);
+        }
+        if !datasette.is_playing() {
+            break;
+        }
+    }
+
+    edges
+}
+
+
+#[test]
+fn every_pulse_is_an_edge() {
+    let mut datasette = Datasette::new();
+    datasette.insert(Tap::from_data(&tap(1, &[0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01])).unwrap());
+
+    // the motor has to run as well
+    datasette.press(Button::Play);
+    for _ in 0..100 {
+        assert!(!datasette.update(false));
+    }
+    assert_eq!(datasette.position(), 0);
+
+    assert_eq!(play(&mut datasette, 100), vec![0, 1, 3, 5, 6]);
+    assert_eq!(datasette.position(), 5);
+    assert!(datasette.read_line());
+}
+
+
+#[test]
+fn pal_tape_on_ntsc() {
+    let mut pal = Datasette::new();
+    pal.insert(Tap::from_data(&tap(1, &[SHORT, LONG, SHORT])).unwrap());
+    assert_eq!(play(&mut pal, 10000), vec![0, 384, 1152]);
+
+    // NTSC runs at 1022727 Hz, the recording at 985248 Hz
+    let mut ntsc = Datasette::new();
+    ntsc.set_model(Model::Ntsc);
+    ntsc.insert(Tap::from_data(&tap(1, &[SHORT, LONG, SHORT])).unwrap());
+    assert_eq!(play(&mut ntsc, 10000), vec![0, 399, 1196]);
+
+    let mut ntsc_tape = tap(1, &[SHORT, LONG, SHORT]);
+    ntsc_tape[0x0E] = 1;
+    ntsc.insert(Tap::from_data(&ntsc_tape).unwrap());
+    assert_eq!(play(&mut ntsc, 10000), vec![0, 384, 1152]);
+}
+
+
+#[test]
+fn turbo_loader_reads_the_tape() {
+    let mut pulses = Vec::new();
+    for byte in DATA.iter() {
+        for bit in (0..8).rev() {
+            pulses.push(if byte & (1 << bit) != 0 { LONG } else { SHORT });
+        }
+    }
+    pulses.push(SHORT); // the last bit ends with the next pulse
+
+    let mut machine = common::booted_machine();
+    machine.attach_tape_image(Tap::from_data(&tap(1, &pulses)).unwrap());
+
+    // the loader waits with the motor on; the kernal would start it as well as soon as PLAY is pressed
+    for (i, byte) in LOADER.iter().enumerate() {
+        machine.poke(0xC000 + i as u16, *byte);
+    }
+
+    common::type_text(&mut machine, b"SYS49152\r");
+    for _ in 0..10 {
+        machine.run_frame();
+    }
+
+    // the buttons are sensed on bit 4 of the 6510 port
+    assert_eq!(machine.peek(0x0001) & 0x30, 0x10);
+    machine.datasette().press(Button::Play);
+    machine.step(1);
+    assert_eq!(machine.peek(0x0001) & 0x10, 0x00);
+    assert!(machine.datasette().is_playing());
+    for _ in 0..50 {
+        machine.run_frame();
+    }
+
+    let loaded: Vec<u8> = (0xC100..0xC104).map(|addr| machine.peek(addr)).collect();
+    assert_eq!(loaded, DATA.to_vec());
+
+    // PLAY comes up at the end of the tape
+    assert!(!machine.datasette().is_playing());
+    assert_eq!(machine.datasette().position(), 33);
+    assert_eq!(machine.peek(0x0001) & 0x10, 0x10);
+
+    machine.datasette().press(Button::Rewind);
+    assert_eq!(machine.datasette().position(), 0);
+    assert!(machine.detach_tape().is_some());
+    machine.datasette().press(Button::Play);
+    assert!(!machine.datasette().is_playing());
+}